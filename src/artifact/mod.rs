@@ -0,0 +1,344 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::store::{put_json, read_manifest, BlobStore, StoreError};
+use crate::v1::{
+    Descriptor, Digest, Manifest, ParseError, VerifyingReader, ANNOTATION_TITLE, EMPTY_JSON,
+    MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+pub const DEFAULT_FILE_MEDIA_TYPE: &str = "application/octet-stream";
+
+/// Files packaged together under an artifact type, such as a Helm chart or an ML model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    pub artifact_type: String,
+    pub files: Vec<ArtifactFile>,
+    /// Replaces the empty config when set.
+    pub config: Option<ArtifactConfig>,
+    pub subject: Option<Descriptor>,
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactFile {
+    pub path: PathBuf,
+    /// The name the file is extracted as, recorded in `org.opencontainers.image.title`.
+    pub title: String,
+    pub media_type: String,
+}
+
+impl ArtifactFile {
+    /// Uses the file's name as its title and `application/octet-stream` as its media type.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        ArtifactFile {
+            title: path
+                .file_name()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            path,
+            media_type: DEFAULT_FILE_MEDIA_TYPE.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtifactConfig {
+    pub media_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Stores every file of `artifact` as a layer along with a manifest for them, returning the
+/// manifest's descriptor. Tag it to make it reachable in a layout. An artifact without files
+/// gets the empty descriptor as its only layer, as image-spec v1.1 recommends.
+pub fn package_artifact<S: BlobStore + ?Sized>(
+    store: &S,
+    artifact: &Artifact,
+) -> Result<(Descriptor, Manifest), StoreError> {
+    let mut titles = BTreeSet::new();
+    let mut layers = Vec::with_capacity(artifact.files.len());
+    for file in &artifact.files {
+        check_title(&file.title)?;
+        if !titles.insert(file.title.as_str()) {
+            return Err(StoreError::Invalid(format!(
+                "more than one file is titled `{}`",
+                file.title
+            )));
+        }
+
+        let mut writer = store.writer()?;
+        std::io::copy(&mut BufReader::new(File::open(&file.path)?), &mut writer)?;
+        let (digest, size) = writer.commit()?;
+        let mut annotations = HashMap::new();
+        annotations.insert(ANNOTATION_TITLE.to_string(), file.title.clone());
+        layers.push(Descriptor {
+            media_type: file.media_type.clone(),
+            digest,
+            size,
+            urls: None,
+            artifact_type: None,
+            annotations: Some(annotations),
+            platform: None,
+        });
+    }
+
+    if layers.is_empty() {
+        store.put(EMPTY_JSON)?;
+        layers.push(Descriptor::empty());
+    }
+
+    let config = match &artifact.config {
+        Some(config) => Descriptor {
+            media_type: config.media_type.clone(),
+            digest: store.put(&config.data)?,
+            size: config.data.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        },
+        None => {
+            store.put(EMPTY_JSON)?;
+            Descriptor::empty()
+        }
+    };
+    let manifest = Manifest {
+        schema_version: 2,
+        media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+        artifact_type: Some(artifact.artifact_type.clone()),
+        config,
+        layers,
+        subject: artifact.subject.clone(),
+        annotations: artifact.annotations.clone(),
+    };
+    let mut descriptor = put_json(store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)?;
+    descriptor.artifact_type = manifest.artifact_type.clone();
+    Ok((descriptor, manifest))
+}
+
+/// Writes every titled layer of the artifact manifest `digest` into `dir`, returning the paths
+/// of the extracted files. Layers without a title aren't files and are skipped.
+pub fn extract_artifact<S: BlobStore + ?Sized, P: AsRef<Path>>(
+    store: &S,
+    digest: &Digest,
+    dir: P,
+) -> Result<Vec<PathBuf>, StoreError> {
+    let dir = dir.as_ref();
+    let manifest = read_manifest(store, digest)?;
+    fs::create_dir_all(dir)?;
+
+    let mut extracted = Vec::new();
+    for layer in &manifest.layers {
+        let title = match layer
+            .annotations
+            .as_ref()
+            .and_then(|x| x.get(ANNOTATION_TITLE))
+        {
+            Some(title) => title,
+            None => continue,
+        };
+        check_title(title)?;
+
+        // nothing shows up under `title` unless it matches its descriptor
+        let path = dir.join(title);
+        let partial = dir.join(format!(".{}.partial", title));
+        let mut reader = VerifyingReader::for_descriptor(store.reader(&layer.digest)?, layer)
+            .map_err(ParseError::from)?;
+        let mut file = File::create(&partial)?;
+        let copied = std::io::copy(&mut reader, &mut file).and_then(|_| file.flush());
+        if let Err(error) = copied {
+            let _ = fs::remove_file(&partial);
+            return Err(ParseError::from(error).into());
+        }
+        fs::rename(&partial, &path)?;
+        extracted.push(path);
+    }
+    Ok(extracted)
+}
+
+// titles come from manifests, which shouldn't be able to write outside the target directory
+fn check_title(title: &str) -> Result<(), StoreError> {
+    let mut components = Path::new(title).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => Ok(()),
+        _ => Err(StoreError::Invalid(format!(
+            "`{}` isn't a valid file name for an artifact",
+            title
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::OciLayout;
+    use crate::v1::{VerificationError, MEDIA_TYPE_EMPTY_JSON};
+
+    const MODEL: &str = "application/vnd.example.model";
+
+    fn write_files(dir: &Path) -> Vec<ArtifactFile> {
+        fs::write(dir.join("weights.bin"), b"weights").unwrap();
+        fs::write(dir.join("README.md"), b"# model").unwrap();
+        let mut readme = ArtifactFile::new(dir.join("README.md"));
+        readme.media_type = "text/markdown".to_string();
+        vec![ArtifactFile::new(dir.join("weights.bin")), readme]
+    }
+
+    fn artifact(files: Vec<ArtifactFile>) -> Artifact {
+        Artifact {
+            artifact_type: MODEL.to_string(),
+            files,
+            config: None,
+            subject: None,
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn packages_files_as_layers() {
+        let source = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+
+        let (descriptor, manifest) =
+            package_artifact(&layout, &artifact(write_files(source.path()))).unwrap();
+        assert_eq!(descriptor.artifact_type.as_deref(), Some(MODEL));
+        assert_eq!(manifest.config, Descriptor::empty());
+        assert_eq!(manifest.config.media_type, MEDIA_TYPE_EMPTY_JSON);
+        assert_eq!(layout.get(&manifest.config.digest).unwrap(), b"{}");
+
+        let titles: Vec<(&str, &str)> = manifest
+            .layers
+            .iter()
+            .map(|x| {
+                (
+                    x.annotations.as_ref().unwrap()[ANNOTATION_TITLE].as_str(),
+                    x.media_type.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            titles,
+            vec![
+                ("weights.bin", DEFAULT_FILE_MEDIA_TYPE),
+                ("README.md", "text/markdown")
+            ]
+        );
+        assert_eq!(layout.get(&manifest.layers[0].digest).unwrap(), b"weights");
+        assert_eq!(
+            read_manifest(&layout, &descriptor.digest).unwrap(),
+            manifest
+        );
+    }
+
+    #[test]
+    fn packages_no_files_as_the_empty_descriptor() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+
+        let (descriptor, manifest) = package_artifact(&layout, &artifact(vec![])).unwrap();
+        assert_eq!(manifest.layers, vec![Descriptor::empty()]);
+        assert_eq!(layout.get(&manifest.layers[0].digest).unwrap(), EMPTY_JSON);
+        // it isn't a file, so there's nothing to extract
+        let extracted = extract_artifact(&layout, &descriptor.digest, target.path()).unwrap();
+        assert!(extracted.is_empty());
+    }
+
+    #[test]
+    fn uses_custom_config() {
+        let source = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let mut artifact = artifact(write_files(source.path()));
+        artifact.config = Some(ArtifactConfig {
+            media_type: "application/vnd.example.model.config+json".to_string(),
+            data: br#"{"framework":"onnx"}"#.to_vec(),
+        });
+
+        let (_, manifest) = package_artifact(&layout, &artifact).unwrap();
+        assert_eq!(
+            manifest.config.media_type,
+            "application/vnd.example.model.config+json"
+        );
+        assert_eq!(
+            layout.get(&manifest.config.digest).unwrap(),
+            br#"{"framework":"onnx"}"#
+        );
+    }
+
+    #[test]
+    fn extracts_files_back() {
+        let source = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (descriptor, _) =
+            package_artifact(&layout, &artifact(write_files(source.path()))).unwrap();
+        layout.tag("model", descriptor.clone()).unwrap();
+
+        let extracted = extract_artifact(&layout, &descriptor.digest, target.path()).unwrap();
+        assert_eq!(
+            extracted,
+            vec![
+                target.path().join("weights.bin"),
+                target.path().join("README.md")
+            ]
+        );
+        assert_eq!(fs::read(&extracted[0]).unwrap(), b"weights");
+        assert_eq!(fs::read(&extracted[1]).unwrap(), b"# model");
+    }
+
+    #[test]
+    fn refuses_to_extract_corrupt_files() {
+        let source = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (descriptor, manifest) =
+            package_artifact(&layout, &artifact(write_files(source.path()))).unwrap();
+        fs::write(
+            layout.blobs().blob_path(&manifest.layers[0].digest),
+            b"WEIGHTS",
+        )
+        .unwrap();
+
+        match extract_artifact(&layout, &descriptor.digest, target.path()) {
+            Err(StoreError::Parse(ParseError::Verification(
+                VerificationError::DigestMismatch { .. },
+            ))) => {}
+            result => panic!("Received unexpected result: {:?}", result),
+        }
+        assert_eq!(fs::read_dir(target.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn rejects_unsafe_and_duplicate_titles() {
+        let source = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+
+        for title in &["../escape", "/etc/passwd", "a/b", "", "."] {
+            let mut files = write_files(source.path());
+            files[0].title = title.to_string();
+            assert!(
+                matches!(
+                    package_artifact(&layout, &artifact(files)),
+                    Err(StoreError::Invalid(_))
+                ),
+                "{}",
+                title
+            );
+        }
+
+        let mut files = write_files(source.path());
+        files[1].title = files[0].title.clone();
+        assert!(matches!(
+            package_artifact(&layout, &artifact(files)),
+            Err(StoreError::Invalid(_))
+        ));
+    }
+}
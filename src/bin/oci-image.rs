@@ -0,0 +1,810 @@
+//! `oci-image`: inspects, validates and converts images from the command line.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process;
+
+use oci_image_spec_rs::layer::explain_difference;
+use oci_image_spec_rs::layout::OciLayout;
+use oci_image_spec_rs::store::resolve_image_config;
+use oci_image_spec_rs::v1::{
+    diff_image_configs, parse_image_config, parse_image_index, parse_image_manifest, Descriptor,
+    Digest, Digester, ImageConfig, JsonPath, ALGORITHM_SHA256, ANNOTATION_REF_NAME,
+    MEDIA_TYPE_IMAGE_INDEX, MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+const USAGE: &str = "\
+Usage: oci-image [--json] <command> [<args>]
+
+Commands:
+  config show <image>               Show an image config
+  config get <path> <image>         Print the value at a JSON path of an image config
+  validate [--kind <kind>] <input>  Validate a config, manifest, index or image layout
+  digest [--algorithm <alg>] [<file>]
+                                    Print the digest of a file, or of stdin
+  diff-config <old> <new>           Compare two image configs
+  explain <image> <image>           Explain why the digests of two images in layouts differ
+  layout ls <dir>                   List the entries of an image layout
+  convert <source> <destination>    Convert between docker-archive:<file> and oci:<dir>[:<ref>]
+
+An <image> is a config file, `-` for stdin, or oci:<dir>[:<ref>] for an image in a layout.
+Kinds are config, manifest, index and layout; by default the kind is worked out from the input.
+
+Options:
+  --json      Print JSON instead of human-readable output
+  -h, --help  Print this help";
+
+#[derive(Debug, PartialEq)]
+enum CliError {
+    // exits with 2 and prints the usage
+    Usage(String),
+    Failed(String),
+}
+
+impl<E: std::error::Error> From<E> for CliError {
+    fn from(e: E) -> Self {
+        CliError::Failed(e.to_string())
+    }
+}
+
+fn usage<T>(message: impl Display) -> Result<T, CliError> {
+    Err(CliError::Usage(message.to_string()))
+}
+
+fn failed<T>(message: impl Display) -> Result<T, CliError> {
+    Err(CliError::Failed(message.to_string()))
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    json: bool,
+    help: bool,
+    kind: Option<String>,
+    algorithm: Option<String>,
+    arguments: Vec<String>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, CliError> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = |name: &str| match inline.clone().or_else(|| args.next().cloned()) {
+            Some(value) => Ok(value),
+            None => usage(format!("`{}` needs a value", name)),
+        };
+        match flag {
+            "--json" => options.json = true,
+            "-h" | "--help" => options.help = true,
+            "--kind" => options.kind = Some(value("--kind")?),
+            "--algorithm" => options.algorithm = Some(value("--algorithm")?),
+            "--" => options.arguments.extend(args.by_ref().cloned()),
+            _ if flag.starts_with('-') && flag != "-" => {
+                return usage(format!("unknown option `{}`", flag))
+            }
+            _ => options.arguments.push(arg.clone()),
+        }
+    }
+    Ok(options)
+}
+
+/// Where an image comes from.
+#[derive(Debug, PartialEq)]
+enum Source {
+    // `-` is stdin
+    File(String),
+    Layout {
+        dir: String,
+        reference: Option<String>,
+    },
+    DockerArchive(String),
+}
+
+fn parse_source(value: &str) -> Source {
+    if let Some(rest) = value.strip_prefix("oci:") {
+        match rest.split_once(':') {
+            Some((dir, reference)) => Source::Layout {
+                dir: dir.to_string(),
+                reference: Some(reference.to_string()),
+            },
+            None => Source::Layout {
+                dir: rest.to_string(),
+                reference: None,
+            },
+        }
+    } else if let Some(file) = value.strip_prefix("docker-archive:") {
+        Source::DockerArchive(file.to_string())
+    } else {
+        Source::File(value.to_string())
+    }
+}
+
+fn read_input(path: &str) -> Result<Vec<u8>, CliError> {
+    let mut data = vec![];
+    if path == "-" {
+        io::stdin().read_to_end(&mut data)?;
+    } else {
+        File::open(path)
+            .and_then(|mut x| x.read_to_end(&mut data))
+            .map_err(|e| CliError::Failed(format!("can't read `{}`: {}", path, e)))?;
+    }
+    Ok(data)
+}
+
+// the image manifest a reference names, or the only one in the layout
+fn select_image(layout: &OciLayout, reference: Option<&str>) -> Result<Descriptor, CliError> {
+    let descriptor = match reference {
+        Some(reference) => match layout.resolve(reference)? {
+            Some(descriptor) => descriptor,
+            None => return failed(format!("no image named `{}` in the layout", reference)),
+        },
+        None => {
+            let mut images: Vec<Descriptor> = layout
+                .index()?
+                .manifests
+                .into_iter()
+                .filter(|x| x.media_type == MEDIA_TYPE_IMAGE_MANIFEST)
+                .collect();
+            if images.len() != 1 {
+                return failed(format!(
+                    "the layout has {} images; pick one with oci:<dir>:<ref>",
+                    images.len()
+                ));
+            }
+            images.remove(0)
+        }
+    };
+    if descriptor.media_type != MEDIA_TYPE_IMAGE_MANIFEST {
+        return failed(format!(
+            "`{}` is a {}, not an image manifest",
+            descriptor.digest, descriptor.media_type
+        ));
+    }
+    Ok(descriptor)
+}
+
+fn load_config(value: &str) -> Result<ImageConfig, CliError> {
+    match parse_source(value) {
+        Source::File(path) => Ok(parse_image_config(&mut read_input(&path)?.as_slice())?),
+        Source::Layout { dir, reference } => {
+            let layout = OciLayout::open(&dir)?;
+            let descriptor = select_image(&layout, reference.as_deref())?;
+            let (_, config) = resolve_image_config(&layout, &descriptor.digest)?;
+            Ok(config)
+        }
+        Source::DockerArchive(_) => usage("image configs can't be read from docker archives"),
+    }
+}
+
+fn print_json<T: Serialize>(out: &mut dyn Write, value: &T) -> Result<(), CliError> {
+    serde_json::to_writer_pretty(&mut *out, value)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+// strings as they are, string lists one per line and sets (like `ExposedPorts`) as their keys
+fn human(value: &Value) -> String {
+    match value {
+        Value::String(x) => x.clone(),
+        Value::Array(items) if items.iter().all(|x| !x.is_object() && !x.is_array()) => {
+            items.iter().map(human).collect::<Vec<_>>().join("\n")
+        }
+        Value::Object(map)
+            if map
+                .values()
+                .all(|x| x.as_object().is_some_and(|x| x.is_empty())) =>
+        {
+            map.keys().cloned().collect::<Vec<_>>().join("\n")
+        }
+        _ => serde_json::to_string_pretty(value).unwrap_or_default(),
+    }
+}
+
+fn print_field(out: &mut dyn Write, name: &str, value: &str) -> io::Result<()> {
+    let mut lines = value.lines();
+    writeln!(
+        out,
+        "{:<14}{}",
+        format!("{}:", name),
+        lines.next().unwrap_or("")
+    )?;
+    for line in lines {
+        writeln!(out, "{:<14}{}", "", line)?;
+    }
+    Ok(())
+}
+
+fn config_show(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let config = match options.arguments.as_slice() {
+        [_, _, image] => load_config(image)?,
+        _ => return usage("`config show` takes an image"),
+    };
+    if options.json {
+        print_json(out, &config)?;
+        return Ok(0);
+    }
+
+    let mut platform = format!("{}/{}", config.os, config.architecture);
+    if let Some(variant) = &config.variant {
+        platform = format!("{}/{}", platform, variant);
+    }
+    print_field(out, "Platform", &platform)?;
+    if let Some(created) = &config.created {
+        print_field(out, "Created", &created.to_rfc3339())?;
+    }
+    if let Some(author) = &config.author {
+        print_field(out, "Author", author)?;
+    }
+    if let Value::Object(runtime) = serde_json::to_value(&config.config)? {
+        for (name, value) in &runtime {
+            print_field(out, name, &human(value))?;
+        }
+    }
+    print_field(out, "Layers", &config.rootfs.diff_ids.len().to_string())?;
+    let history = config.history.as_ref().map_or(0, |x| x.len());
+    print_field(out, "History", &history.to_string())?;
+    Ok(0)
+}
+
+fn config_get(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let (path, config) = match options.arguments.as_slice() {
+        [_, _, path, image] => (path.parse::<JsonPath>()?, load_config(image)?),
+        _ => return usage("`config get` takes a JSON path and an image"),
+    };
+    let config = serde_json::to_value(&config)?;
+    let value = match path.lookup(&config) {
+        Some(value) => value,
+        None => return failed(format!("nothing at `{}`", path)),
+    };
+    if options.json {
+        print_json(out, value)?;
+    } else {
+        writeln!(out, "{}", human(value))?;
+    }
+    Ok(0)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Validation {
+    kind: String,
+    valid: bool,
+    issues: Vec<String>,
+}
+
+fn detect_kind(data: &[u8]) -> &'static str {
+    let value: Value = serde_json::from_slice(data).unwrap_or_default();
+    match value.get("mediaType").and_then(|x| x.as_str()) {
+        Some(MEDIA_TYPE_IMAGE_MANIFEST) => "manifest",
+        Some(MEDIA_TYPE_IMAGE_INDEX) => "index",
+        _ if value.get("rootfs").is_some() => "config",
+        _ if value.get("manifests").is_some() => "index",
+        _ if value.get("layers").is_some() => "manifest",
+        _ => "config",
+    }
+}
+
+fn validate(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let input = match options.arguments.as_slice() {
+        [_, input] => input,
+        _ => return usage("`validate` takes a file or an image layout"),
+    };
+    let path = match parse_source(input) {
+        Source::Layout { dir, .. } => dir,
+        Source::File(path) => path,
+        Source::DockerArchive(_) => return usage("docker archives can't be validated"),
+    };
+
+    let is_layout = path != "-" && Path::new(&path).is_dir();
+    let kind = match options.kind.as_deref() {
+        Some(kind @ ("config" | "manifest" | "index" | "layout")) => kind,
+        Some(kind) => return usage(format!("unknown kind `{}`", kind)),
+        None if is_layout => "layout",
+        None => "",
+    };
+    let validation = if kind == "layout" {
+        let report = OciLayout::open(&path).and_then(|x| x.fsck());
+        let issues = match report {
+            Ok(report) => report.issues.iter().map(|x| x.to_string()).collect(),
+            Err(e) => vec![e.to_string()],
+        };
+        Validation {
+            kind: kind.to_string(),
+            valid: issues.is_empty(),
+            issues,
+        }
+    } else {
+        let data = read_input(&path)?;
+        let kind = if kind.is_empty() {
+            detect_kind(&data)
+        } else {
+            kind
+        };
+        let result = match kind {
+            "manifest" => parse_image_manifest(&mut data.as_slice()).and_then(|x| x.validate()),
+            "index" => parse_image_index(&mut data.as_slice()).and_then(|x| x.validate()),
+            _ => parse_image_config(&mut data.as_slice()).and_then(|x| x.validate()),
+        };
+        Validation {
+            kind: kind.to_string(),
+            valid: result.is_ok(),
+            issues: result.err().map(|x| x.to_string()).into_iter().collect(),
+        }
+    };
+
+    if options.json {
+        print_json(out, &validation)?;
+    } else if validation.valid {
+        writeln!(out, "valid {}", validation.kind)?;
+    } else {
+        writeln!(out, "invalid {}:", validation.kind)?;
+        for issue in &validation.issues {
+            writeln!(out, "  {}", issue)?;
+        }
+    }
+    Ok(if validation.valid { 0 } else { 1 })
+}
+
+fn digest(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let path = match options.arguments.as_slice() {
+        [_] => "-",
+        [_, path] => path.as_str(),
+        _ => return usage("`digest` takes at most one file"),
+    };
+    let algorithm = options.algorithm.as_deref().unwrap_or(ALGORITHM_SHA256);
+    let mut digester = Digester::new(algorithm)?;
+    let size = if path == "-" {
+        io::copy(&mut io::stdin(), &mut digester)?
+    } else {
+        let mut file = File::open(path)
+            .map_err(|e| CliError::Failed(format!("can't read `{}`: {}", path, e)))?;
+        io::copy(&mut file, &mut digester)?
+    };
+    let digest = digester.finalize();
+
+    if options.json {
+        print_json(
+            out,
+            &serde_json::json!({ "digest": digest.to_string(), "size": size }),
+        )?;
+    } else {
+        writeln!(out, "{}", digest)?;
+    }
+    Ok(0)
+}
+
+fn diff_config(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let (old, new) = match options.arguments.as_slice() {
+        [_, old, new] => (load_config(old)?, load_config(new)?),
+        _ => return usage("`diff-config` takes two images"),
+    };
+    let diff = diff_image_configs(&old, &new);
+    if options.json {
+        print_json(out, &diff)?;
+    } else {
+        write!(out, "{}", diff)?;
+    }
+    Ok(0)
+}
+
+fn explain(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let (left, right) = match options.arguments.as_slice() {
+        [_, left, right] => (open_image(left)?, open_image(right)?),
+        _ => return usage("`explain` takes two images"),
+    };
+    let explanation = explain_difference(&left.0, &left.1, &right.0, &right.1)?;
+    if options.json {
+        print_json(out, &explanation)?;
+    } else {
+        write!(out, "{}", explanation)?;
+    }
+    Ok(0)
+}
+
+// the layout an image is in and the digest of its manifest
+fn open_image(value: &str) -> Result<(OciLayout, Digest), CliError> {
+    match parse_source(value) {
+        Source::Layout { dir, reference } => {
+            let layout = OciLayout::open(&dir)?;
+            let descriptor = select_image(&layout, reference.as_deref())?;
+            Ok((layout, descriptor.digest))
+        }
+        _ => usage(format!(
+            "`{}` isn't an image in a layout, oci:<dir>[:<ref>]",
+            value
+        )),
+    }
+}
+
+fn layout_ls(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let layout = match options.arguments.as_slice() {
+        [_, _, dir] => OciLayout::open(dir)?,
+        _ => return usage("`layout ls` takes an image layout"),
+    };
+    let index = layout.index()?;
+    if options.json {
+        print_json(out, &index.manifests)?;
+        return Ok(0);
+    }
+
+    writeln!(
+        out,
+        "{:<24} {:<20} {:>10}  {:<46} PLATFORM",
+        "NAME", "DIGEST", "SIZE", "MEDIA TYPE"
+    )?;
+    for descriptor in &index.manifests {
+        let name = descriptor
+            .annotations
+            .as_ref()
+            .and_then(|x| x.get(ANNOTATION_REF_NAME))
+            .map_or("<none>", |x| x.as_str());
+        let platform = descriptor
+            .platform
+            .as_ref()
+            .map_or(String::new(), |x| format!("{}/{}", x.os, x.architecture));
+        let digest = descriptor.digest.to_string();
+        writeln!(
+            out,
+            "{:<24} {:<20} {:>10}  {:<46} {}",
+            name,
+            &digest[..digest.len().min(19)],
+            descriptor.size,
+            descriptor.media_type,
+            platform
+        )?;
+    }
+    Ok(0)
+}
+
+fn convert(options: &Options, out: &mut dyn Write) -> Result<i32, CliError> {
+    let (source, destination) = match options.arguments.as_slice() {
+        [_, source, destination] => (parse_source(source), parse_source(destination)),
+        _ => return usage("`convert` takes a source and a destination"),
+    };
+    let images = match (source, destination) {
+        (Source::DockerArchive(file), Source::Layout { dir, reference }) => {
+            let layout = OciLayout::create(&dir)?;
+            let images = layout.import_docker_archive(File::open(&file)?)?;
+            if let Some(reference) = reference {
+                if images.len() != 1 {
+                    return failed(format!(
+                        "the archive has {} images, so it can't be named `{}`",
+                        images.len(),
+                        reference
+                    ));
+                }
+                layout.tag(&reference, images[0].clone())?;
+            }
+            images
+        }
+        (Source::Layout { dir, reference }, Source::DockerArchive(file)) => {
+            let layout = OciLayout::open(&dir)?;
+            let images = match reference {
+                Some(reference) => vec![select_image(&layout, Some(&reference))?],
+                None => layout
+                    .index()?
+                    .manifests
+                    .into_iter()
+                    .filter(|x| x.media_type == MEDIA_TYPE_IMAGE_MANIFEST)
+                    .collect(),
+            };
+            layout.export_docker_archive(&images, File::create(&file)?)?;
+            images
+        }
+        _ => {
+            return usage("`convert` converts between docker-archive:<file> and oci:<dir>[:<ref>]")
+        }
+    };
+
+    if options.json {
+        print_json(out, &images)?;
+    } else {
+        for image in &images {
+            writeln!(out, "{}", image.digest)?;
+        }
+    }
+    Ok(0)
+}
+
+/// Runs a command line, writing its output to `out` and returning the exit code.
+fn run(args: &[String], out: &mut dyn Write) -> Result<i32, CliError> {
+    let options = parse_options(args)?;
+    if options.help {
+        writeln!(out, "{}", USAGE)?;
+        return Ok(0);
+    }
+    let command: Vec<&str> = options
+        .arguments
+        .iter()
+        .take(2)
+        .map(|x| x.as_str())
+        .collect();
+    match command.as_slice() {
+        ["config", "show", ..] => config_show(&options, out),
+        ["config", "get", ..] => config_get(&options, out),
+        ["validate", ..] => validate(&options, out),
+        ["digest", ..] => digest(&options, out),
+        ["diff-config", ..] => diff_config(&options, out),
+        ["explain", ..] => explain(&options, out),
+        ["layout", "ls", ..] => layout_ls(&options, out),
+        ["convert", ..] => convert(&options, out),
+        [] => usage("no command given"),
+        _ => usage(format!("unknown command `{}`", command.join(" "))),
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = match run(&args, &mut io::stdout().lock()) {
+        Ok(code) => code,
+        Err(CliError::Usage(message)) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            2
+        }
+        Err(CliError::Failed(message)) => {
+            eprintln!("error: {}", message);
+            1
+        }
+    };
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::{TimeZone, Utc};
+    use oci_image_spec_rs::mutate::MutableImage;
+    use oci_image_spec_rs::store::put_json;
+    use oci_image_spec_rs::v1::Manifest;
+
+    const CONFIG: &str = r#"{
+        "architecture": "amd64",
+        "os": "linux",
+        "config": {"Env": ["PATH=/bin"], "ExposedPorts": {"80/tcp": {}}},
+        "rootfs": {"type": "layers", "diff_ids": []}
+    }"#;
+
+    fn run_args(args: &[&str]) -> (Result<i32, CliError>, String) {
+        let args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+        let mut out = vec![];
+        let result = run(&args, &mut out);
+        (result, String::from_utf8(out).unwrap())
+    }
+
+    fn write_file(dir: &Path, name: &str, content: &str) -> String {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // a layout holding one image of `CONFIG`, named `app`
+    fn image_layout(dir: &Path) -> String {
+        let root = dir.join("layout");
+        let layout = OciLayout::create(&root).unwrap();
+        let config = parse_image_config(&mut CONFIG.as_bytes()).unwrap();
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config: put_json(
+                layout.blobs(),
+                "application/vnd.oci.image.config.v1+json",
+                &config,
+            )
+            .unwrap(),
+            layers: vec![],
+            artifact_type: None,
+            subject: None,
+            annotations: None,
+        };
+        let descriptor = put_json(layout.blobs(), MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        layout.tag("app", descriptor).unwrap();
+        root.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn parses_options_and_sources() {
+        let args: Vec<String> = ["--json", "digest", "--algorithm=sha512", "-"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(
+            parse_options(&args).unwrap(),
+            Options {
+                json: true,
+                algorithm: Some("sha512".to_string()),
+                arguments: vec!["digest".to_string(), "-".to_string()],
+                ..Options::default()
+            }
+        );
+
+        assert_eq!(
+            parse_source("oci:dir:v1"),
+            Source::Layout {
+                dir: "dir".to_string(),
+                reference: Some("v1".to_string())
+            }
+        );
+        assert_eq!(
+            parse_source("docker-archive:a.tar"),
+            Source::DockerArchive("a.tar".to_string())
+        );
+        assert_eq!(
+            parse_source("config.json"),
+            Source::File("config.json".to_string())
+        );
+    }
+
+    #[test]
+    fn shows_and_gets_config_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(dir.path(), "config.json", CONFIG);
+
+        let (code, out) = run_args(&["config", "show", &path]);
+        assert_eq!(code, Ok(0));
+        assert!(out.contains("Platform:     linux/amd64"));
+        assert!(out.contains("ExposedPorts: 80/tcp"));
+
+        let (code, out) = run_args(&["config", "get", "config.Env[0]", &path]);
+        assert_eq!(code, Ok(0));
+        assert_eq!(out, "PATH=/bin\n");
+
+        let (_, out) = run_args(&["--json", "config", "get", ".architecture", &path]);
+        assert_eq!(out, "\"amd64\"\n");
+    }
+
+    #[test]
+    fn reads_images_from_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = image_layout(dir.path());
+
+        let (code, out) = run_args(&["config", "get", "os", &format!("oci:{}:app", layout)]);
+        assert_eq!(code, Ok(0));
+        assert_eq!(out, "linux\n");
+
+        let (code, out) = run_args(&["layout", "ls", &layout]);
+        assert_eq!(code, Ok(0));
+        assert!(out.lines().nth(1).unwrap().starts_with("app "));
+
+        let (code, out) = run_args(&["validate", &layout]);
+        assert_eq!((code, out.as_str()), (Ok(0), "valid layout\n"));
+    }
+
+    #[test]
+    fn converts_between_layouts_and_docker_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = image_layout(dir.path());
+        let archive = dir.path().join("image.tar");
+        let archive = archive.to_str().unwrap();
+        let copy = dir.path().join("copy");
+        let copy = copy.to_str().unwrap();
+
+        let (code, exported) = run_args(&[
+            "convert",
+            &format!("oci:{}", layout),
+            &format!("docker-archive:{}", archive),
+        ]);
+        assert_eq!(code, Ok(0));
+        let (code, imported) = run_args(&[
+            "convert",
+            &format!("docker-archive:{}", archive),
+            &format!("oci:{}", copy),
+        ]);
+        assert_eq!(code, Ok(0));
+        assert_eq!(imported, exported);
+
+        // `app` isn't a `repository:tag` name, so the copy's only image is untagged
+        let (_, out) = run_args(&["config", "get", "architecture", &format!("oci:{}", copy)]);
+        assert_eq!(out, "amd64\n");
+    }
+
+    #[test]
+    fn explains_why_images_differ() {
+        let dir = tempfile::tempdir().unwrap();
+        let left = image_layout(dir.path());
+        let other = tempfile::tempdir().unwrap();
+        let right = image_layout(other.path());
+        let layout = OciLayout::open(&right).unwrap();
+        let digest = layout.resolve("app").unwrap().unwrap().digest;
+        let mut image = MutableImage::new(layout.blobs(), &digest).unwrap();
+        image.set_created(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap());
+        layout.tag("app", image.commit().unwrap()).unwrap();
+
+        let left = format!("oci:{}:app", left);
+        let (code, out) = run_args(&["explain", &left, &left]);
+        assert_eq!(code, Ok(0));
+        assert!(out.ends_with("are identical\n"));
+
+        let (code, out) = run_args(&["explain", &left, &format!("oci:{}:app", right)]);
+        assert_eq!(code, Ok(0));
+        assert!(out.contains(
+            "1. timestamps differ\n   config.created: `null` vs `\"2020-01-01T00:00:00Z\"`\n"
+        ));
+        assert!(matches!(
+            run_args(&["explain", &left, "config.json"]).0,
+            Err(CliError::Usage(_))
+        ));
+    }
+
+    #[test]
+    fn digests_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_file(dir.path(), "data", "hello");
+
+        let (code, out) = run_args(&["digest", &path]);
+        assert_eq!(code, Ok(0));
+        assert_eq!(
+            out,
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824\n"
+        );
+    }
+
+    #[test]
+    fn diffs_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = write_file(dir.path(), "old.json", CONFIG);
+        let new = write_file(dir.path(), "new.json", &CONFIG.replace("amd64", "arm64"));
+
+        let (code, out) = run_args(&["--json", "diff-config", &old, &new]);
+        assert_eq!(code, Ok(0));
+        assert!(out.contains("arm64"));
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn reports_invalid_documents() {
+            let dir = tempfile::tempdir().unwrap();
+            let path = write_file(
+                dir.path(),
+                "manifest.json",
+                r#"{"schemaVersion": 2, "mediaType": "application/vnd.oci.image.manifest.v1+json"}"#,
+            );
+
+            let (code, out) = run_args(&["validate", &path]);
+            assert_eq!(code, Ok(1));
+            assert!(out.starts_with("invalid manifest:\n"));
+
+            let (code, out) = run_args(&["--json", "validate", "--kind", "config", &path]);
+            assert_eq!(code, Ok(1));
+            let value: Value = serde_json::from_str(&out).unwrap();
+            assert_eq!(value["kind"], "config");
+            assert_eq!(value["valid"], false);
+        }
+
+        #[test]
+        fn rejects_bad_command_lines() {
+            assert!(matches!(run_args(&[]).0, Err(CliError::Usage(_))));
+            assert!(matches!(
+                run_args(&["frobnicate"]).0,
+                Err(CliError::Usage(_))
+            ));
+            assert!(matches!(
+                run_args(&["--verbose", "digest"]).0,
+                Err(CliError::Usage(_))
+            ));
+            assert!(matches!(
+                run_args(&["config", "show"]).0,
+                Err(CliError::Usage(_))
+            ));
+            assert!(matches!(
+                run_args(&["config", "get", "a[", "-"]).0,
+                Err(CliError::Failed(_))
+            ));
+            assert!(matches!(
+                run_args(&["config", "show", "/nonexistent/config.json"]).0,
+                Err(CliError::Failed(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,681 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Display;
+
+use crate::config::v1::env_var::EnvVar;
+use crate::config::v1::exposed_ports::ExposedPorts;
+use crate::config::v1::image_config::{Architecture, Config, History, ImageConfig, OS};
+
+use serde::Serialize;
+
+/// Typed changeset between two image configs, as produced by [`diff_image_configs`].
+///
+/// Renders as human-readable text through `Display` and as JSON through `Serialize`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ImageConfigDiff {
+    pub env: Vec<KeyedChange>,
+    pub exposed_ports: SetChange,
+    pub volumes: SetChange,
+    pub labels: Vec<KeyedChange>,
+    pub entrypoint: Option<FieldChange<Option<Vec<String>>>>,
+    pub cmd: Option<FieldChange<Option<Vec<String>>>>,
+    pub user: Option<FieldChange<Option<String>>>,
+    pub working_dir: Option<FieldChange<Option<String>>>,
+    pub platform: Option<PlatformChange>,
+    pub layers: Option<LayerChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum KeyedChange {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        from: String,
+        to: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SetChange {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FieldChange<T> {
+    pub from: T,
+    pub to: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlatformChange {
+    pub os: Option<FieldChange<OS>>,
+    pub architecture: Option<FieldChange<Architecture>>,
+    pub variant: Option<FieldChange<Option<String>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum LayerChange {
+    /// The new config keeps every old layer and stacks more on top.
+    Appended { added: Vec<Layer> },
+    /// The layer stacks diverge after the first `common` layers.
+    Replaced {
+        common: usize,
+        removed: Vec<Layer>,
+        added: Vec<Layer>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Layer {
+    pub index: usize,
+    pub diff_id: String,
+    // `created_by` of the non-empty `History` entry that produced this layer, if any
+    pub created_by: Option<String>,
+}
+
+pub fn diff_image_configs(old: &ImageConfig, new: &ImageConfig) -> ImageConfigDiff {
+    let old_config = old.config.as_ref();
+    let new_config = new.config.as_ref();
+
+    ImageConfigDiff {
+        env: diff_maps(
+            &env_map(old_config.and_then(|x| x.env.as_ref())),
+            &env_map(new_config.and_then(|x| x.env.as_ref())),
+        ),
+        exposed_ports: diff_sets(
+            &port_set(old_config.and_then(|x| x.exposed_ports.as_ref())),
+            &port_set(new_config.and_then(|x| x.exposed_ports.as_ref())),
+        ),
+        volumes: diff_sets(&volume_set(old_config), &volume_set(new_config)),
+        labels: diff_maps(
+            &label_map(old_config.and_then(|x| x.labels.as_ref())),
+            &label_map(new_config.and_then(|x| x.labels.as_ref())),
+        ),
+        entrypoint: diff_field(old_config, new_config, |x| x.entrypoint.clone()),
+        cmd: diff_field(old_config, new_config, |x| x.cmd.clone()),
+        user: diff_field(old_config, new_config, |x| x.user.clone()),
+        working_dir: diff_field(old_config, new_config, |x| x.working_dir.clone()),
+        platform: diff_platform(old, new),
+        layers: diff_layers(old, new),
+    }
+}
+
+impl ImageConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.env.is_empty()
+            && self.exposed_ports.is_empty()
+            && self.volumes.is_empty()
+            && self.labels.is_empty()
+            && self.entrypoint.is_none()
+            && self.cmd.is_none()
+            && self.user.is_none()
+            && self.working_dir.is_none()
+            && self.platform.is_none()
+            && self.layers.is_none()
+    }
+}
+
+impl SetChange {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+fn env_map(env: Option<&Vec<EnvVar>>) -> BTreeMap<String, String> {
+    env.into_iter()
+        .flatten()
+        .map(|x| (x.var_name.clone(), x.var_value.clone()))
+        .collect()
+}
+
+fn label_map(labels: Option<&HashMap<String, String>>) -> BTreeMap<String, String> {
+    labels
+        .into_iter()
+        .flatten()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+}
+
+fn port_set(exposed_ports: Option<&ExposedPorts>) -> BTreeSet<String> {
+    exposed_ports
+        .into_iter()
+        .flat_map(|x| x.port_protocol_map.iter())
+        .map(|(port, protocol)| match protocol {
+            Some(protocol) => format!("{}/{}", port, protocol),
+            None => format!("{}", port),
+        })
+        .collect()
+}
+
+fn volume_set(config: Option<&Config>) -> BTreeSet<String> {
+    config
+        .and_then(|x| x.volumes.as_ref())
+        .into_iter()
+        .flat_map(|x| x.0.iter().cloned())
+        .collect()
+}
+
+fn diff_maps(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<KeyedChange> {
+    let mut changes = Vec::new();
+    for (key, value) in old {
+        match new.get(key) {
+            None => changes.push(KeyedChange::Removed {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            Some(new_value) if new_value != value => changes.push(KeyedChange::Changed {
+                key: key.clone(),
+                from: value.clone(),
+                to: new_value.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    for (key, value) in new {
+        if !old.contains_key(key) {
+            changes.push(KeyedChange::Added {
+                key: key.clone(),
+                value: value.clone(),
+            });
+        }
+    }
+    changes
+}
+
+fn diff_sets(old: &BTreeSet<String>, new: &BTreeSet<String>) -> SetChange {
+    SetChange {
+        added: new.difference(old).cloned().collect(),
+        removed: old.difference(new).cloned().collect(),
+    }
+}
+
+fn diff_field<T: PartialEq, F: Fn(&Config) -> Option<T>>(
+    old: Option<&Config>,
+    new: Option<&Config>,
+    field: F,
+) -> Option<FieldChange<Option<T>>> {
+    let from = old.and_then(&field);
+    let to = new.and_then(&field);
+    if from == to {
+        None
+    } else {
+        Some(FieldChange { from, to })
+    }
+}
+
+fn diff_platform(old: &ImageConfig, new: &ImageConfig) -> Option<PlatformChange> {
+    let os = if old.os != new.os {
+        Some(FieldChange {
+            from: old.os.clone(),
+            to: new.os.clone(),
+        })
+    } else {
+        None
+    };
+    let architecture = if old.architecture != new.architecture {
+        Some(FieldChange {
+            from: old.architecture.clone(),
+            to: new.architecture.clone(),
+        })
+    } else {
+        None
+    };
+
+    let variant = if old.variant != new.variant {
+        Some(FieldChange {
+            from: old.variant.clone(),
+            to: new.variant.clone(),
+        })
+    } else {
+        None
+    };
+
+    if os.is_none() && architecture.is_none() && variant.is_none() {
+        None
+    } else {
+        Some(PlatformChange {
+            os,
+            architecture,
+            variant,
+        })
+    }
+}
+
+fn diff_layers(old: &ImageConfig, new: &ImageConfig) -> Option<LayerChange> {
+    let old_layers = layers_of(old);
+    let new_layers = layers_of(new);
+
+    let common = old_layers
+        .iter()
+        .zip(new_layers.iter())
+        .take_while(|(x, y)| x.diff_id == y.diff_id)
+        .count();
+    if common == old_layers.len() && common == new_layers.len() {
+        return None;
+    }
+
+    let added = new_layers[common..].to_vec();
+    if common == old_layers.len() {
+        Some(LayerChange::Appended { added })
+    } else {
+        Some(LayerChange::Replaced {
+            common,
+            removed: old_layers[common..].to_vec(),
+            added,
+        })
+    }
+}
+
+fn layers_of(config: &ImageConfig) -> Vec<Layer> {
+    // history entries marked `empty_layer` don't correspond to an entry in `diff_ids`
+    let mut layer_history = config
+        .history
+        .iter()
+        .flatten()
+        .filter(|x| x.empty_layer != Some(true));
+
+    config
+        .rootfs
+        .diff_ids
+        .iter()
+        .enumerate()
+        .map(|(index, diff_id)| Layer {
+            index,
+            diff_id: diff_id.clone(),
+            created_by: layer_history
+                .next()
+                .and_then(|x: &History| x.created_by.clone()),
+        })
+        .collect()
+}
+
+impl Display for ImageConfigDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+
+        write_keyed_changes(f, "Env", &self.env)?;
+        write_set_change(f, "ExposedPorts", &self.exposed_ports)?;
+        write_set_change(f, "Volumes", &self.volumes)?;
+        write_keyed_changes(f, "Labels", &self.labels)?;
+        write_field_change(f, "Entrypoint", &self.entrypoint)?;
+        write_field_change(f, "Cmd", &self.cmd)?;
+        write_field_change(f, "User", &self.user)?;
+        write_field_change(f, "WorkingDir", &self.working_dir)?;
+
+        if let Some(platform) = &self.platform {
+            if let Some(os) = &platform.os {
+                writeln!(f, "os: {} -> {}", os.from, os.to)?;
+            }
+            if let Some(architecture) = &platform.architecture {
+                writeln!(
+                    f,
+                    "architecture: {} -> {}",
+                    architecture.from, architecture.to
+                )?;
+            }
+            write_field_change(f, "variant", &platform.variant)?;
+        }
+
+        match &self.layers {
+            Some(LayerChange::Appended { added }) => {
+                writeln!(f, "Layers: {} appended", added.len())?;
+                write_layers(f, "+", added)?;
+            }
+            Some(LayerChange::Replaced {
+                common,
+                removed,
+                added,
+            }) => {
+                writeln!(f, "Layers: replaced after layer {}", common)?;
+                write_layers(f, "-", removed)?;
+                write_layers(f, "+", added)?;
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn write_keyed_changes(
+    f: &mut std::fmt::Formatter,
+    name: &str,
+    changes: &[KeyedChange],
+) -> std::fmt::Result {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "{}:", name)?;
+    for change in changes {
+        match change {
+            KeyedChange::Added { key, value } => writeln!(f, "  + {}={}", key, value)?,
+            KeyedChange::Removed { key, value } => writeln!(f, "  - {}={}", key, value)?,
+            KeyedChange::Changed { key, from, to } => {
+                writeln!(f, "  ~ {}: {} -> {}", key, from, to)?
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_set_change(
+    f: &mut std::fmt::Formatter,
+    name: &str,
+    change: &SetChange,
+) -> std::fmt::Result {
+    if change.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "{}:", name)?;
+    for item in &change.added {
+        writeln!(f, "  + {}", item)?;
+    }
+    for item in &change.removed {
+        writeln!(f, "  - {}", item)?;
+    }
+    Ok(())
+}
+
+fn write_field_change<T: FieldText>(
+    f: &mut std::fmt::Formatter,
+    name: &str,
+    change: &Option<FieldChange<Option<T>>>,
+) -> std::fmt::Result {
+    let text = |value: &Option<T>| match value {
+        Some(value) => value.text(),
+        None => "<unset>".to_string(),
+    };
+    match change {
+        Some(change) => writeln!(
+            f,
+            "{}: {} -> {}",
+            name,
+            text(&change.from),
+            text(&change.to)
+        ),
+        None => Ok(()),
+    }
+}
+
+// how the value of a field reads in the text rendering
+trait FieldText {
+    fn text(&self) -> String;
+}
+
+impl FieldText for String {
+    fn text(&self) -> String {
+        self.clone()
+    }
+}
+
+impl FieldText for Vec<String> {
+    fn text(&self) -> String {
+        self.join(" ")
+    }
+}
+
+fn write_layers(f: &mut std::fmt::Formatter, marker: &str, layers: &[Layer]) -> std::fmt::Result {
+    for layer in layers {
+        match &layer.created_by {
+            Some(created_by) => writeln!(
+                f,
+                "  {} [{}] {} ({})",
+                marker, layer.index, layer.diff_id, created_by
+            )?,
+            None => writeln!(f, "  {} [{}] {}", marker, layer.index, layer.diff_id)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::v1::exposed_ports::PortProtocol;
+    use crate::config::v1::image_config::{RootFS, RootFSType};
+    use crate::config::v1::volumes::Volumes;
+    use pretty_assertions::assert_eq;
+
+    fn image_config(diff_ids: &[&str], config: Config) -> ImageConfig {
+        ImageConfig {
+            architecture: Architecture::Amd64,
+            os: OS::Linux,
+            variant: None,
+            os_version: None,
+            os_features: None,
+            rootfs: RootFS {
+                _type: RootFSType::Layers,
+                diff_ids: diff_ids.iter().map(|x| x.to_string()).collect(),
+            },
+            created: None,
+            author: None,
+            config: Some(config),
+            history: None,
+        }
+    }
+
+    fn config(env: &[(&str, &str)]) -> Config {
+        Config {
+            user: None,
+            exposed_ports: None,
+            env: Some(
+                env.iter()
+                    .map(|(name, value)| EnvVar {
+                        var_name: name.to_string(),
+                        var_value: value.to_string(),
+                    })
+                    .collect(),
+            ),
+            entrypoint: None,
+            cmd: None,
+            volumes: None,
+            working_dir: None,
+            labels: None,
+            stop_signal: None,
+        }
+    }
+
+    fn history(created_by: &str, empty_layer: bool) -> History {
+        History {
+            created: None,
+            author: None,
+            created_by: Some(created_by.to_string()),
+            comment: None,
+            empty_layer: Some(empty_layer),
+        }
+    }
+
+    #[test]
+    fn identical_configs_have_empty_diff() {
+        let old = image_config(&["sha256:a"], config(&[("FOO", "bar")]));
+        let diff = diff_image_configs(&old, &old.clone());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no changes\n");
+    }
+
+    #[test]
+    fn reports_env_changes() {
+        let old = image_config(
+            &[],
+            config(&[("KEEP", "1"), ("GONE", "x"), ("PATH", "/bin")]),
+        );
+        let new = image_config(
+            &[],
+            config(&[("KEEP", "1"), ("PATH", "/usr/bin"), ("NEW", "y")]),
+        );
+
+        let diff = diff_image_configs(&old, &new);
+        assert_eq!(
+            diff.env,
+            vec![
+                KeyedChange::Removed {
+                    key: "GONE".to_string(),
+                    value: "x".to_string()
+                },
+                KeyedChange::Changed {
+                    key: "PATH".to_string(),
+                    from: "/bin".to_string(),
+                    to: "/usr/bin".to_string()
+                },
+                KeyedChange::Added {
+                    key: "NEW".to_string(),
+                    value: "y".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_ports_volumes_and_scalar_fields() {
+        let old = image_config(&[], config(&[]));
+        let mut new_config = config(&[]);
+        let mut port_protocol_map = HashMap::new();
+        port_protocol_map.insert(8080, Some(PortProtocol::TCP));
+        new_config.exposed_ports = Some(ExposedPorts { port_protocol_map });
+        new_config.volumes = Some(Volumes(vec!["/data".to_string()]));
+        new_config.user = Some("app".to_string());
+        new_config.entrypoint = Some(vec!["/app".to_string()]);
+        let mut new = image_config(&[], new_config);
+        new.architecture = Architecture::Arm64;
+
+        let diff = diff_image_configs(&old, &new);
+        assert_eq!(diff.exposed_ports.added, vec!["8080/tcp"]);
+        assert_eq!(diff.volumes.added, vec!["/data"]);
+        assert_eq!(
+            diff.user,
+            Some(FieldChange {
+                from: None,
+                to: Some("app".to_string())
+            })
+        );
+        assert_eq!(
+            diff.entrypoint,
+            Some(FieldChange {
+                from: None,
+                to: Some(vec!["/app".to_string()])
+            })
+        );
+        assert_eq!(diff.cmd, None);
+        assert_eq!(
+            diff.platform,
+            Some(PlatformChange {
+                os: None,
+                architecture: Some(FieldChange {
+                    from: Architecture::Amd64,
+                    to: Architecture::Arm64
+                }),
+                variant: None,
+            })
+        );
+    }
+
+    mod layers {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn reports_appended_layers_with_history() {
+            let old = image_config(&["sha256:a"], config(&[]));
+            let mut new = image_config(&["sha256:a", "sha256:b"], config(&[]));
+            new.history = Some(vec![
+                history("ADD rootfs", false),
+                history("ENV FOO=bar", true),
+                history("RUN make", false),
+            ]);
+
+            let diff = diff_image_configs(&old, &new);
+            assert_eq!(
+                diff.layers,
+                Some(LayerChange::Appended {
+                    added: vec![Layer {
+                        index: 1,
+                        diff_id: "sha256:b".to_string(),
+                        created_by: Some("RUN make".to_string()),
+                    }],
+                })
+            );
+        }
+
+        #[test]
+        fn reports_replaced_layers() {
+            let old = image_config(&["sha256:a", "sha256:b"], config(&[]));
+            let new = image_config(&["sha256:a", "sha256:c"], config(&[]));
+
+            let diff = diff_image_configs(&old, &new);
+            assert_eq!(
+                diff.layers,
+                Some(LayerChange::Replaced {
+                    common: 1,
+                    removed: vec![Layer {
+                        index: 1,
+                        diff_id: "sha256:b".to_string(),
+                        created_by: None,
+                    }],
+                    added: vec![Layer {
+                        index: 1,
+                        diff_id: "sha256:c".to_string(),
+                        created_by: None,
+                    }],
+                })
+            );
+        }
+    }
+
+    mod rendering {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn renders_as_text() {
+            let old = image_config(&["sha256:a"], config(&[("PATH", "/bin")]));
+            let new = image_config(&["sha256:a", "sha256:b"], config(&[("PATH", "/usr/bin")]));
+
+            assert_eq!(
+                diff_image_configs(&old, &new).to_string(),
+                "Env:\n  ~ PATH: /bin -> /usr/bin\nLayers: 1 appended\n  + [1] sha256:b\n"
+            );
+        }
+
+        #[test]
+        fn renders_fields_as_plain_text() {
+            let old = image_config(&[], config(&[]));
+            let mut new = old.clone();
+            let runtime = new.config.as_mut().unwrap();
+            runtime.entrypoint = Some(vec!["/bin/sh".to_string(), "-c".to_string()]);
+            runtime.user = Some("nobody".to_string());
+
+            assert_eq!(
+                diff_image_configs(&old, &new).to_string(),
+                "Entrypoint: <unset> -> /bin/sh -c\nUser: <unset> -> nobody\n"
+            );
+            assert_eq!(
+                diff_image_configs(&new, &old).to_string(),
+                "Entrypoint: /bin/sh -c -> <unset>\nUser: nobody -> <unset>\n"
+            );
+        }
+
+        #[test]
+        fn renders_as_json() {
+            let old = image_config(&[], config(&[]));
+            let new = image_config(&[], config(&[("FOO", "bar")]));
+
+            let serialized = serde_json::to_value(diff_image_configs(&old, &new)).unwrap();
+            assert_eq!(
+                serialized["env"],
+                serde_json::json!([{"change": "added", "key": "FOO", "value": "bar"}])
+            );
+            assert_eq!(serialized["layers"], serde_json::Value::Null);
+        }
+    }
+}
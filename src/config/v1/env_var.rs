@@ -2,7 +2,7 @@ use serde::de::{Deserializer, Error, Visitor};
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct EnvVar {
     pub var_name: String,
     pub var_value: String,
@@ -24,8 +24,7 @@ impl<'de> Visitor<'de> for EnvVarVisitor {
     type Value = EnvVar;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        // TODO: what do I put here?
-        formatter.write_str("TODO: idk what I put here")
+        formatter.write_str("a string of the form `VARNAME=VARVALUE`")
     }
 
     fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
@@ -63,7 +62,7 @@ mod tests {
         #[test]
         fn deserializes_correctly() {
             let raw = r#""VAR=VALUE""#;
-            let env_var: EnvVar = serde_json::from_str(&raw).unwrap();
+            let env_var: EnvVar = serde_json::from_str(raw).unwrap();
             assert_eq!(env_var.var_name, "VAR");
             assert_eq!(env_var.var_value, "VALUE");
         }
@@ -74,7 +73,7 @@ mod tests {
             #[test]
             fn deserializes_with_meaningful_error() {
                 let raw = r#""FOO=BAR=BAZ""#;
-                let result: Result<EnvVar, serde_json::error::Error> = serde_json::from_str(&raw);
+                let result: Result<EnvVar, serde_json::error::Error> = serde_json::from_str(raw);
                 assert!(result.is_err());
                 let err_string = result.err().unwrap().to_string();
                 assert!(err_string
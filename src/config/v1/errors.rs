@@ -1,15 +1,439 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::config::v1::json::ParseLimit;
+use crate::manifest::v1::VerificationError;
+
+use serde_json::error::Category;
+
+/// Error returned when parsing an image config, manifest or index.
+///
+/// * `Syntax` errors mean the input isn't well-formed JSON
+/// * `Schema` errors mean the JSON doesn't have the shape the spec requires, e.g. a missing
+///   field, a wrong type or an unparseable value
+/// * `Semantic` errors mean the document is well-formed but violates a rule of the spec
+/// * `LimitExceeded` errors mean the input was rejected by the configured `ParseLimits`
+/// * `Verification` errors mean the input didn't match the descriptor it was read for
 #[derive(Debug)]
 pub enum ParseError {
-    IOError(std::io::Error),
-    SerdeError(serde_json::error::Error),
+    Io(std::io::Error),
+    Syntax {
+        message: String,
+        line: usize,
+        column: usize,
+    },
+    Schema {
+        path: JsonPath,
+        message: String,
+        line: usize,
+        column: usize,
+    },
+    Semantic {
+        path: JsonPath,
+        message: String,
+    },
+    LimitExceeded {
+        limit: ParseLimit,
+        line: usize,
+        column: usize,
+    },
+    Verification(VerificationError),
 }
+
+impl ParseError {
+    pub(crate) fn semantic<S: Into<String>>(path: JsonPath, message: S) -> Self {
+        ParseError::Semantic {
+            path,
+            message: message.into(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&JsonPath> {
+        match self {
+            ParseError::Schema { path, .. } | ParseError::Semantic { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    // 1-based `(line, column)` of the offending input, when known
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::Syntax { line, column, .. }
+            | ParseError::Schema { line, column, .. }
+            | ParseError::LimitExceeded { line, column, .. } => Some((*line, *column)),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseError::Io(error) => write!(f, "failed to read input: {}", error),
+            ParseError::Syntax {
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "invalid JSON at line {} column {}: {}",
+                line, column, message
+            ),
+            ParseError::Schema {
+                path,
+                message,
+                line,
+                column,
+            } => write!(
+                f,
+                "invalid value at `{}` (line {} column {}): {}",
+                path, line, column, message
+            ),
+            ParseError::Semantic { path, message } => {
+                write!(f, "invalid document at `{}`: {}", path, message)
+            }
+            ParseError::LimitExceeded {
+                limit,
+                line,
+                column,
+            } => write!(
+                f,
+                "rejected input at line {} column {}: {}",
+                line, column, limit
+            ),
+            ParseError::Verification(error) => write!(f, "failed to verify input: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(error) => Some(error),
+            ParseError::Verification(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
 impl From<std::io::Error> for ParseError {
     fn from(error: std::io::Error) -> Self {
-        ParseError::IOError(error)
+        match VerificationError::from_io_error(&error) {
+            Some(verification_error) => ParseError::Verification(verification_error.clone()),
+            None => ParseError::Io(error),
+        }
+    }
+}
+
+impl From<VerificationError> for ParseError {
+    fn from(error: VerificationError) -> Self {
+        ParseError::Verification(error)
     }
 }
+
 impl From<serde_json::error::Error> for ParseError {
     fn from(error: serde_json::error::Error) -> Self {
-        ParseError::SerdeError(error)
+        ParseError::from_serde_error(JsonPath::default(), error)
+    }
+}
+
+impl ParseError {
+    pub(crate) fn from_serde_error(path: JsonPath, error: serde_json::error::Error) -> Self {
+        let (line, column) = (error.line(), error.column());
+        match error.classify() {
+            Category::Io => std::io::Error::from(error).into(),
+            Category::Syntax | Category::Eof => ParseError::Syntax {
+                message: strip_position(&error),
+                line,
+                column,
+            },
+            Category::Data => ParseError::Schema {
+                path,
+                message: strip_position(&error),
+                line,
+                column,
+            },
+        }
+    }
+}
+
+// `serde_json` appends " at line X column Y" to its messages, which we report separately
+fn strip_position(error: &serde_json::error::Error) -> String {
+    let message = error.to_string();
+    match message.rfind(" at line ") {
+        Some(idx) => message[..idx].to_string(),
+        None => message,
+    }
+}
+
+/// Location of a value within a JSON document, e.g. `config.ExposedPorts["http/tcp"]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JsonPath(pub Vec<JsonPathSegment>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonPathSegment {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    pub fn key(mut self, key: &str) -> Self {
+        self.0.push(JsonPathSegment::Key(key.to_string()));
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> Self {
+        self.0.push(JsonPathSegment::Index(index));
+        self
+    }
+}
+
+impl From<&serde_path_to_error::Path> for JsonPath {
+    fn from(path: &serde_path_to_error::Path) -> Self {
+        use serde_path_to_error::Segment;
+
+        JsonPath(
+            path.iter()
+                .filter_map(|segment| match segment {
+                    Segment::Seq { index } => Some(JsonPathSegment::Index(*index)),
+                    Segment::Map { key } => Some(JsonPathSegment::Key(key.clone())),
+                    Segment::Enum { variant } => Some(JsonPathSegment::Key(variant.clone())),
+                    Segment::Unknown => None,
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Display for JsonPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, ".");
+        }
+
+        let is_identifier = |x: &str| {
+            !x.is_empty()
+                && !x.starts_with(|c: char| c.is_ascii_digit())
+                && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        for (idx, segment) in self.0.iter().enumerate() {
+            match segment {
+                JsonPathSegment::Key(key) if is_identifier(key) => {
+                    if idx > 0 {
+                        write!(f, ".")?;
+                    }
+                    write!(f, "{}", key)?;
+                }
+                // as a JSON string, so that quotes and control characters are escaped
+                JsonPathSegment::Key(key) => {
+                    let quoted = serde_json::to_string(key).map_err(|_| std::fmt::Error)?;
+                    write!(f, "[{}]", quoted)?
+                }
+                JsonPathSegment::Index(index) => write!(f, "[{}]", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = JsonPathError;
+
+    /// Parses the syntax paths are displayed in, optionally prefixed with `$` or `.`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |reason| JsonPathError {
+            value: s.to_string(),
+            reason,
+        };
+        let mut rest = s.strip_prefix('$').unwrap_or(s);
+        if rest == "." {
+            return Ok(JsonPath::default());
+        }
+        rest = rest.strip_prefix('.').unwrap_or(rest);
+
+        let mut path = JsonPath::default();
+        let mut expect_key = !rest.starts_with('[');
+        while !rest.is_empty() || expect_key {
+            if expect_key {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(error("empty key"));
+                }
+                path = path.key(&rest[..end]);
+                rest = &rest[end..];
+            } else if rest.starts_with("[\"") {
+                // keys in brackets are JSON strings
+                let mut keys = serde_json::Deserializer::from_str(&rest[1..]).into_iter::<String>();
+                let key = match keys.next() {
+                    Some(Ok(key)) => key,
+                    _ => return Err(error("unterminated key")),
+                };
+                rest = rest[1 + keys.byte_offset()..]
+                    .strip_prefix(']')
+                    .ok_or_else(|| error("expected `]`"))?;
+                path = path.key(&key);
+            } else if let Some(indexed) = rest.strip_prefix('[') {
+                let end = indexed.find(']').ok_or_else(|| error("expected `]`"))?;
+                let index = indexed[..end]
+                    .parse::<usize>()
+                    .map_err(|_| error("invalid index"))?;
+                path = path.index(index);
+                rest = &indexed[end + 1..];
+            } else {
+                return Err(error("expected `.` or `[`"));
+            }
+
+            expect_key = false;
+            if let Some(key) = rest.strip_prefix('.') {
+                rest = key;
+                expect_key = true;
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl JsonPath {
+    /// Finds the value at this path within `value`.
+    pub fn lookup<'a>(&self, value: &'a serde_json::Value) -> Option<&'a serde_json::Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                JsonPathSegment::Key(key) => value.as_object()?.get(key),
+                JsonPathSegment::Index(index) => value.as_array()?.get(*index),
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPathError {
+    pub value: String,
+    pub reason: &'static str,
+}
+
+impl Display for JsonPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid JSON path `{}`: {}", self.value, self.reason)
+    }
+}
+
+impl std::error::Error for JsonPathError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::v1::json::{from_json_slice, ParseLimits};
+    use std::collections::HashMap;
+
+    fn from_json_str<T: serde::de::DeserializeOwned>(raw: &str) -> Result<T, ParseError> {
+        from_json_slice(raw.as_bytes(), &ParseLimits::default())
+    }
+
+    #[test]
+    fn displays_json_paths() {
+        let path = JsonPath::default()
+            .key("config")
+            .key("ExposedPorts")
+            .key("http/tcp");
+        assert_eq!(path.to_string(), r#"config.ExposedPorts["http/tcp"]"#);
+
+        let path = JsonPath::default().key("history").index(2).key("created");
+        assert_eq!(path.to_string(), "history[2].created");
+
+        assert_eq!(JsonPath::default().to_string(), ".");
+
+        let path = JsonPath::default().key("a\"b").key("\u{1b}[0m");
+        assert_eq!(path.to_string(), r#"["a\"b"]["\u001b[0m"]"#);
+    }
+
+    #[test]
+    fn parses_json_paths() {
+        for raw in &[
+            r#"config.ExposedPorts["http/tcp"]"#,
+            "history[2].created",
+            r#"["a\"b"][0][1]"#,
+            ".",
+        ] {
+            assert_eq!(raw.parse::<JsonPath>().unwrap().to_string(), *raw);
+        }
+        assert_eq!(
+            "$.config.Env[0]".parse::<JsonPath>().unwrap(),
+            JsonPath::default().key("config").key("Env").index(0)
+        );
+        assert_eq!(".rootfs".parse::<JsonPath>().unwrap().to_string(), "rootfs");
+        for key in &["\u{1b}[0m", "caf\u{e9}", "tab\there", "back\\slash"] {
+            let path = JsonPath::default().key("config").key("Labels").key(key);
+            assert_eq!(path.to_string().parse::<JsonPath>().unwrap(), path);
+        }
+
+        for (raw, reason) in &[
+            ("config..Env", "empty key"),
+            ("config.", "empty key"),
+            ("history[x]", "invalid index"),
+            ("history[0", "expected `]`"),
+            (r#"config["Env"#, "unterminated key"),
+            ("history[0]x", "expected `.` or `[`"),
+        ] {
+            assert_eq!(
+                raw.parse::<JsonPath>().unwrap_err().reason,
+                *reason,
+                "{}",
+                raw
+            );
+        }
+    }
+
+    #[test]
+    fn looks_up_values() {
+        let value = serde_json::json!({"config": {"Env": ["A=1", "B=2"]}});
+        let path: JsonPath = "config.Env[1]".parse().unwrap();
+        assert_eq!(path.lookup(&value), Some(&serde_json::json!("B=2")));
+        let path: JsonPath = "config.Env[2]".parse().unwrap();
+        assert_eq!(path.lookup(&value), None);
+        assert_eq!(JsonPath::default().lookup(&value), Some(&value));
+    }
+
+    #[test]
+    fn classifies_syntax_errors() {
+        let err = from_json_str::<HashMap<String, String>>("{\n  \"a\": \"b\",\n}").unwrap_err();
+        match &err {
+            ParseError::Syntax { line, column, .. } => assert_eq!((*line, *column), (3, 1)),
+            _ => panic!("Received unexpected error: {:?}", err),
+        }
+        assert!(err
+            .to_string()
+            .starts_with("invalid JSON at line 3 column 1"));
+    }
+
+    #[test]
+    fn classifies_truncated_input_as_syntax_error() {
+        let err = from_json_str::<HashMap<String, String>>(r#"{"a": "#).unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { .. }));
+    }
+
+    #[test]
+    fn classifies_schema_errors_with_path() {
+        let err =
+            from_json_str::<HashMap<String, Vec<u8>>>(r#"{"a": [1, 2, "three"]}"#).unwrap_err();
+        match &err {
+            ParseError::Schema { path, line, .. } => {
+                assert_eq!(path.to_string(), "a[2]");
+                assert_eq!(*line, 1);
+            }
+            _ => panic!("Received unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_content() {
+        let err = from_json_str::<HashMap<String, String>>("{} {}").unwrap_err();
+        assert!(matches!(err, ParseError::Syntax { .. }));
+    }
+
+    #[test]
+    fn implements_std_error() {
+        let err: Box<dyn std::error::Error> =
+            Box::new(ParseError::semantic(JsonPath::default().key("a"), "nope"));
+        assert_eq!(err.to_string(), "invalid document at `a`: nope");
     }
 }
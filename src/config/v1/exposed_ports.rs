@@ -1,11 +1,11 @@
 use std::collections::HashMap;
 use std::fmt::Display;
 
-use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::de::{DeserializeSeed, Deserializer, Error, MapAccess, Visitor};
 use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ExposedPorts {
     pub port_protocol_map: HashMap<i32, Option<PortProtocol>>,
 }
@@ -41,40 +41,49 @@ impl<'de> Visitor<'de> for ExposedPortsVisitor {
     type Value = ExposedPorts;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        // TODO: what do I put here
-        formatter.write_str("TODO: idk what I put here")
+        formatter.write_str("a map of `port/protocol` keys to empty objects")
     }
 
     fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
         let mut port_protocol_map: HashMap<i32, Option<PortProtocol>> = HashMap::new();
 
-        while let Some((port_protocol, _)) = access.next_entry::<String, HashMap<(), ()>>()? {
-            let tokens = port_protocol.split("/").collect::<Vec<&str>>();
-            if tokens.len() > 1 {
-                let port: i32 = tokens[0].parse().unwrap();
-                let protocol = tokens[1];
-                match protocol {
-                    "tcp" => {
-                        port_protocol_map.insert(port, Some(PortProtocol::TCP));
-                    }
-                    "udp" => {
-                        port_protocol_map.insert(port, Some(PortProtocol::UDP));
-                    }
-                    _ => { /*TODO: idk lol*/ }
-                }
-            } else {
-                let port: i32 = tokens[0].parse().unwrap();
-                port_protocol_map.insert(port, None);
-            }
+        while let Some(port_protocol) = access.next_key::<String>()? {
+            let (port, protocol) = access.next_value_seed(PortProtocolSeed(&port_protocol))?;
+            port_protocol_map.insert(port, protocol);
         }
 
-        Ok(ExposedPorts {
-            port_protocol_map: port_protocol_map,
-        })
+        Ok(ExposedPorts { port_protocol_map })
+    }
+}
+
+// parses the key while consuming its (empty) value, so that errors get reported at the key's path
+struct PortProtocolSeed<'a>(&'a str);
+impl<'de, 'a> DeserializeSeed<'de> for PortProtocolSeed<'a> {
+    type Value = (i32, Option<PortProtocol>);
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let tokens = self.0.split('/').collect::<Vec<&str>>();
+        let port: i32 = tokens[0]
+            .parse()
+            .map_err(|_| Error::custom(format!("invalid port `{}`", tokens[0])))?;
+        let protocol = match tokens.get(1) {
+            Some(&"tcp") => Some(PortProtocol::TCP),
+            Some(&"udp") => Some(PortProtocol::UDP),
+            Some(protocol) => {
+                return Err(Error::custom(format!(
+                    "unsupported protocol `{}`; should be `tcp` or `udp`",
+                    protocol
+                )))
+            }
+            None => None,
+        };
+
+        HashMap::<(), ()>::deserialize(deserializer)?;
+        Ok((port, protocol))
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum PortProtocol {
     TCP,
@@ -102,9 +111,7 @@ mod tests {
             port_protocol_map.insert(11111, Some(PortProtocol::TCP));
             port_protocol_map.insert(22222, Some(PortProtocol::UDP));
             port_protocol_map.insert(33333, None);
-            let exposed_ports = ExposedPorts {
-                port_protocol_map: port_protocol_map,
-            };
+            let exposed_ports = ExposedPorts { port_protocol_map };
 
             let serialized = serde_json::to_string(&exposed_ports).unwrap();
             let possible_serializations = vec![
@@ -128,13 +135,13 @@ mod tests {
                     break;
                 }
             }
-            assert_eq!(was_ever_serialized_correctly, true);
+            assert!(was_ever_serialized_correctly);
         }
 
         #[test]
         fn deserializes_correctly() {
             let raw = r#"{"11111/tcp":{},"22222/udp":{},"33333":{}}"#;
-            let exposed_ports: ExposedPorts = serde_json::from_str(&raw).unwrap();
+            let exposed_ports: ExposedPorts = serde_json::from_str(raw).unwrap();
 
             assert_map_len(&exposed_ports.port_protocol_map, 3);
             assert_map_contains(
@@ -149,5 +156,33 @@ mod tests {
             );
             assert_map_contains(&exposed_ports.port_protocol_map, 33333, None);
         }
+
+        mod with_bad_input {
+            use super::*;
+
+            #[test]
+            fn deserializes_with_meaningful_error_for_bad_port() {
+                let raw = r#"{"http/tcp":{}}"#;
+                let result: Result<ExposedPorts, serde_json::error::Error> =
+                    serde_json::from_str(raw);
+                assert!(result
+                    .err()
+                    .unwrap()
+                    .to_string()
+                    .contains("invalid port `http`"));
+            }
+
+            #[test]
+            fn deserializes_with_meaningful_error_for_bad_protocol() {
+                let raw = r#"{"8080/sctp":{}}"#;
+                let result: Result<ExposedPorts, serde_json::error::Error> =
+                    serde_json::from_str(raw);
+                assert!(result
+                    .err()
+                    .unwrap()
+                    .to_string()
+                    .contains("unsupported protocol `sctp`"));
+            }
+        }
     }
 }
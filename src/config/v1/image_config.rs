@@ -1,27 +1,36 @@
 use std::collections::HashMap;
+use std::fmt::Display;
 
 use crate::config::v1::env_var::EnvVar;
-use crate::config::v1::errors::ParseError;
+use crate::config::v1::errors::{JsonPath, ParseError};
 use crate::config::v1::exposed_ports::ExposedPorts;
+use crate::config::v1::json::{from_json_reader, from_json_slice, ParseLimits};
 use crate::config::v1::volumes::Volumes;
+use crate::manifest::v1::{Descriptor, Platform, VerifyingReader};
 
 use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ImageConfig {
     // required
     pub architecture: Architecture,
     pub os: OS,
     pub rootfs: RootFS,
     // optional
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    #[serde(rename = "os.version", skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    #[serde(rename = "os.features", skip_serializing_if = "Option::is_none")]
+    pub os_features: Option<Vec<String>>,
     pub created: Option<DateTime<Utc>>,
     pub author: Option<String>,
     pub config: Option<Config>,
     pub history: Option<Vec<History>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Architecture {
     #[serde(rename = "386")]
@@ -35,11 +44,26 @@ pub enum Architecture {
     Mipsle,
     Ppc64,
     Ppc64le,
+    Riscv64,
+    Loong64,
     S390x,
     Wasm,
+    /// Anything else, like the `unknown` of attestation manifests in indexes.
+    #[serde(untagged)]
+    Other(String),
+}
+impl Display for Architecture {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let Architecture::Other(other) = self {
+            return write!(f, "{}", other);
+        }
+        let mut to_display = format!("{:?}", self);
+        to_display.make_ascii_lowercase();
+        write!(f, "{}", to_display.trim_start_matches('_'))
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum OS {
     Aix,
@@ -55,9 +79,22 @@ pub enum OS {
     Plan9,
     Solaris,
     Windows,
+    /// Anything else, like the `unknown` of attestation manifests in indexes.
+    #[serde(untagged)]
+    Other(String),
+}
+impl Display for OS {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if let OS::Other(other) = self {
+            return write!(f, "{}", other);
+        }
+        let mut to_display = format!("{:?}", self);
+        to_display.make_ascii_lowercase();
+        write!(f, "{}", to_display)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RootFS {
     #[serde(rename = "type")]
     pub _type: RootFSType,
@@ -65,7 +102,7 @@ pub struct RootFS {
     pub diff_ids: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Config {
     // TODO: make a struct for `user` like for `ExposedPorts`?
@@ -81,7 +118,7 @@ pub struct Config {
     pub stop_signal: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct History {
     pub created: Option<DateTime<Utc>>,
     pub author: Option<String>,
@@ -90,17 +127,115 @@ pub struct History {
     pub empty_layer: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum RootFSType {
     Layers,
 }
 
+impl ImageConfig {
+    /// Checks the rules of the spec that can't be expressed by the shape of the JSON alone.
+    /// Parsing only checks the shape, so callers that want to be strict call this as well.
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if !is_valid_platform(&self.os, &self.architecture) {
+            return Err(ParseError::semantic(
+                JsonPath::default().key("architecture"),
+                format!(
+                    "`{}` is not a valid architecture for os `{}`",
+                    self.architecture, self.os
+                ),
+            ));
+        }
+
+        if let Some(history) = &self.history {
+            // images built without history are common, but a partial history is not
+            let layer_count = history
+                .iter()
+                .filter(|x| x.empty_layer != Some(true))
+                .count();
+            if !history.is_empty() && layer_count != self.rootfs.diff_ids.len() {
+                return Err(ParseError::semantic(
+                    JsonPath::default().key("history"),
+                    format!(
+                        "{} history entries create layers but `rootfs.diff_ids` lists {}",
+                        layer_count,
+                        self.rootfs.diff_ids.len()
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The platform an image with this config runs on, as recorded in an index.
+    pub fn platform(&self) -> Platform {
+        Platform {
+            architecture: self.architecture.clone(),
+            os: self.os.clone(),
+            os_version: self.os_version.clone(),
+            os_features: self.os_features.clone(),
+            variant: self.variant.clone(),
+        }
+    }
+}
+
+// valid `$GOOS/$GOARCH` combinations, which the spec defers to
+fn is_valid_platform(os: &OS, architecture: &Architecture) -> bool {
+    use Architecture::{Amd64, Arm, Arm64, Mips64, Other, Ppc64, Wasm};
+
+    match os {
+        OS::Aix => matches!(architecture, Ppc64),
+        OS::Android => matches!(architecture, Architecture::_386 | Amd64 | Arm | Arm64),
+        OS::Darwin => matches!(architecture, Architecture::_386 | Amd64 | Arm | Arm64),
+        OS::Dragonfly => matches!(architecture, Amd64),
+        OS::Freebsd => matches!(architecture, Architecture::_386 | Amd64 | Arm | Arm64),
+        OS::Illumos => matches!(architecture, Amd64),
+        OS::Js => matches!(architecture, Wasm),
+        OS::Linux => !matches!(architecture, Wasm | Other(_)),
+        OS::Netbsd => matches!(architecture, Architecture::_386 | Amd64 | Arm | Arm64),
+        OS::Openbsd => matches!(
+            architecture,
+            Architecture::_386 | Amd64 | Arm | Arm64 | Mips64
+        ),
+        OS::Plan9 => matches!(architecture, Architecture::_386 | Amd64 | Arm),
+        OS::Solaris => matches!(architecture, Amd64),
+        OS::Windows => matches!(architecture, Architecture::_386 | Amd64 | Arm | Arm64),
+        OS::Other(_) => false,
+    }
+}
+
 pub fn parse_image_config<T: std::io::Read>(source: &mut T) -> Result<ImageConfig, ParseError> {
-    let mut raw = String::new();
-    source.read_to_string(&mut raw)?;
+    parse_image_config_with_limits(source, &ParseLimits::default())
+}
 
-    let config: ImageConfig = serde_json::from_str(&raw)?;
+pub fn parse_image_config_with_limits<T: std::io::Read>(
+    source: &mut T,
+    limits: &ParseLimits,
+) -> Result<ImageConfig, ParseError> {
+    let config: ImageConfig = from_json_reader(source, limits)?;
+    Ok(config)
+}
+
+/// Parses a config blob read for `descriptor`, failing unless the content matches its digest
+/// and size.
+pub fn parse_image_config_with_descriptor<T: std::io::Read>(
+    source: &mut T,
+    descriptor: &Descriptor,
+) -> Result<ImageConfig, ParseError> {
+    let mut verifying_reader = VerifyingReader::for_descriptor(source, descriptor)?;
+    parse_image_config(&mut verifying_reader)
+}
+
+pub fn parse_image_config_from_slice(source: &[u8]) -> Result<ImageConfig, ParseError> {
+    parse_image_config_from_slice_with_limits(source, &ParseLimits::default())
+}
+
+pub fn parse_image_config_from_slice_with_limits(
+    source: &[u8],
+    limits: &ParseLimits,
+) -> Result<ImageConfig, ParseError> {
+    let config: ImageConfig = from_json_slice(source, limits)?;
     Ok(config)
 }
 
@@ -116,6 +251,9 @@ mod tests {
             let config = ImageConfig {
                 architecture: Architecture::_386,
                 os: OS::Linux,
+                variant: None,
+                os_version: None,
+                os_features: None,
                 rootfs: RootFS {
                     _type: RootFSType::Layers,
                     diff_ids: vec![],
@@ -156,6 +294,10 @@ mod tests {
   }
 }"#;
             let deserialized = parse_image_config(&mut raw.to_string().as_bytes()).unwrap();
+            assert_eq!(
+                parse_image_config_from_slice(raw.as_bytes()).unwrap(),
+                deserialized
+            );
 
             match deserialized.architecture {
                 Architecture::_386 => {}
@@ -175,9 +317,172 @@ mod tests {
             assert_eq!(deserialized.rootfs.diff_ids[0], "sha256:bogus-sha");
         }
 
+        #[test]
+        fn displays_platform_like_it_serializes() {
+            assert_eq!(Architecture::_386.to_string(), "386");
+            assert_eq!(Architecture::Ppc64le.to_string(), "ppc64le");
+            assert_eq!(OS::Linux.to_string(), "linux");
+            assert_eq!(Architecture::Riscv64.to_string(), "riscv64");
+            assert_eq!(
+                Architecture::Other("unknown".to_string()).to_string(),
+                "unknown"
+            );
+        }
+
         #[test]
         fn allows_only_valid_platform_combinations() {
-            // TODO: make this test using validator from spec repo as guidance
+            let raw = |os: &str, architecture: &str| {
+                format!(
+                    r#"{{"architecture": "{}", "os": "{}", "rootfs": {{"type": "layers", "diff_ids": []}}}}"#,
+                    architecture, os
+                )
+            };
+
+            for (os, architecture) in &[
+                ("linux", "amd64"),
+                ("linux", "s390x"),
+                ("linux", "riscv64"),
+                ("linux", "loong64"),
+                ("windows", "arm64"),
+                ("js", "wasm"),
+                ("aix", "ppc64"),
+            ] {
+                let config = parse_image_config(&mut raw(os, architecture).as_bytes()).unwrap();
+                let result = config.validate();
+                assert!(result.is_ok(), "{}/{}: {:?}", os, architecture, result);
+            }
+            for (os, architecture) in &[
+                ("linux", "wasm"),
+                ("js", "amd64"),
+                ("windows", "s390x"),
+                ("darwin", "ppc64le"),
+                ("linux", "z80"),
+                ("unknown", "unknown"),
+            ] {
+                // parsing alone doesn't check the combination
+                let config = parse_image_config(&mut raw(os, architecture).as_bytes()).unwrap();
+                let err = config.validate().unwrap_err();
+                assert_eq!(err.path().unwrap().to_string(), "architecture");
+                assert!(matches!(err, ParseError::Semantic { .. }));
+            }
+        }
+    }
+
+    mod with_bad_input {
+        use super::*;
+        use crate::manifest::v1::VerificationError;
+
+        #[test]
+        fn reports_syntax_errors_with_position() {
+            let raw = "{\n  \"architecture\": \"amd64\",\n  \"os\": linux\n}";
+            let err = parse_image_config(&mut raw.as_bytes()).unwrap_err();
+            assert!(matches!(err, ParseError::Syntax { .. }));
+            assert_eq!(err.position(), Some((3, 9)));
+        }
+
+        #[test]
+        fn reports_schema_errors_with_path() {
+            let raw = r#"{
+  "architecture": "amd64",
+  "os": "linux",
+  "rootfs": {"type": "layers", "diff_ids": []},
+  "config": {
+    "ExposedPorts": {
+      "http/tcp": {}
+    }
+  }
+}"#;
+            let err = parse_image_config(&mut raw.as_bytes()).unwrap_err();
+            assert!(matches!(err, ParseError::Schema { .. }));
+            assert_eq!(
+                err.path().unwrap().to_string(),
+                r#"config.ExposedPorts["http/tcp"]"#
+            );
+            assert_eq!(err.position().unwrap().0, 7);
+            assert!(err.to_string().contains("invalid port `http`"));
+        }
+
+        #[test]
+        fn reports_unknown_enum_values_with_path() {
+            let raw = r#"{"architecture": "amd64", "os": "linux", "rootfs": {"type": "z80", "diff_ids": []}}"#;
+            let err = parse_image_config(&mut raw.as_bytes()).unwrap_err();
+            assert_eq!(err.path().unwrap().to_string(), "rootfs.type");
+            assert!(err.to_string().contains("unknown variant `z80`"));
+        }
+
+        #[test]
+        fn rejects_configs_exceeding_limits() {
+            let labels = (0..100)
+                .map(|x| format!(r#""label.{}": "value""#, x))
+                .collect::<Vec<String>>()
+                .join(",");
+            let raw = format!(
+                r#"{{"architecture": "amd64", "os": "linux", "rootfs": {{"type": "layers", "diff_ids": []}}, "config": {{"Labels": {{{}}}}}}}"#,
+                labels
+            );
+            let limits = ParseLimits {
+                max_collection_len: 50,
+                ..ParseLimits::default()
+            };
+
+            let err = parse_image_config_with_limits(&mut raw.as_bytes(), &limits).unwrap_err();
+            assert!(matches!(err, ParseError::LimitExceeded { .. }));
+            let err =
+                parse_image_config_from_slice_with_limits(raw.as_bytes(), &limits).unwrap_err();
+            assert!(matches!(err, ParseError::LimitExceeded { .. }));
+            assert!(parse_image_config_from_slice(raw.as_bytes()).is_ok());
+        }
+
+        #[test]
+        fn rejects_content_not_matching_descriptor() {
+            let raw = r#"{"architecture": "amd64", "os": "linux", "rootfs": {"type": "layers", "diff_ids": []}}"#;
+            let mut descriptor = Descriptor {
+                media_type: crate::manifest::v1::MEDIA_TYPE_IMAGE_CONFIG.to_string(),
+                digest: crate::manifest::v1::Digest::sha256(raw.as_bytes()),
+                size: raw.len() as u64,
+                urls: None,
+                artifact_type: None,
+                annotations: None,
+                platform: None,
+            };
+            assert!(parse_image_config_with_descriptor(&mut raw.as_bytes(), &descriptor).is_ok());
+
+            let tampered = raw.replace("amd64", "arm64");
+            let err = parse_image_config_with_descriptor(&mut tampered.as_bytes(), &descriptor)
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                ParseError::Verification(VerificationError::DigestMismatch { .. })
+            ));
+
+            descriptor.size = 10;
+            let err =
+                parse_image_config_with_descriptor(&mut raw.as_bytes(), &descriptor).unwrap_err();
+            assert!(matches!(
+                err,
+                ParseError::Verification(VerificationError::SizeExceeded { expected: 10 })
+            ));
+        }
+
+        #[test]
+        fn reports_mismatched_history_as_semantic_error() {
+            let raw = r#"{
+  "architecture": "amd64",
+  "os": "linux",
+  "rootfs": {"type": "layers", "diff_ids": ["sha256:a"]},
+  "history": [
+    {"created_by": "ADD rootfs"},
+    {"created_by": "RUN make"},
+    {"created_by": "ENV FOO=bar", "empty_layer": true}
+  ]
+}"#;
+            let config = parse_image_config(&mut raw.as_bytes()).unwrap();
+            let err = config.validate().unwrap_err();
+            assert!(matches!(err, ParseError::Semantic { .. }));
+            assert_eq!(
+                err.to_string(),
+                "invalid document at `history`: 2 history entries create layers but `rootfs.diff_ids` lists 1"
+            );
         }
     }
 
@@ -197,6 +502,9 @@ mod tests {
             let config = ImageConfig {
                 architecture: Architecture::_386,
                 os: OS::Linux,
+                variant: None,
+                os_version: None,
+                os_features: None,
                 rootfs: RootFS {
                     _type: RootFSType::Layers,
                     diff_ids: vec!["sha256:some-sha".to_string()],
@@ -205,9 +513,7 @@ mod tests {
                 author: Some("Some One <someone@some.where>".to_string()),
                 config: Some(Config {
                     user: Some(String::from("user")),
-                    exposed_ports: Some(ExposedPorts {
-                        port_protocol_map: port_protocol_map,
-                    }),
+                    exposed_ports: Some(ExposedPorts { port_protocol_map }),
                     env: Some(vec![EnvVar {
                         var_name: "FOO".to_string(),
                         var_value: "BAR".to_string(),
@@ -0,0 +1,320 @@
+use std::fmt::Display;
+use std::io::{BufReader, Read};
+
+use crate::config::v1::errors::{JsonPath, ParseError};
+
+use serde::de::DeserializeOwned;
+
+/// Bounds applied while parsing documents from untrusted sources.
+///
+/// `max_collection_len` applies to every JSON array and object, which covers `Labels`, `Env`,
+/// `history`, `diff_ids` and so on. `serde_json` refuses to nest deeper than 128 levels
+/// regardless of `max_depth`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseLimits {
+    pub max_bytes: u64,
+    pub max_depth: usize,
+    pub max_collection_len: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            // same limit `containerd` applies to manifests
+            max_bytes: 4 * 1024 * 1024,
+            max_depth: 64,
+            max_collection_len: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseLimit {
+    Bytes(u64),
+    Depth(usize),
+    CollectionLen(usize),
+}
+
+impl Display for ParseLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ParseLimit::Bytes(max) => write!(f, "input is larger than {} bytes", max),
+            ParseLimit::Depth(max) => write!(f, "input is nested deeper than {} levels", max),
+            ParseLimit::CollectionLen(max) => {
+                write!(f, "input has a collection with more than {} entries", max)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LimitExceeded {
+    limit: ParseLimit,
+    line: usize,
+    column: usize,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.limit)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl From<LimitExceeded> for ParseError {
+    fn from(error: LimitExceeded) -> Self {
+        ParseError::LimitExceeded {
+            limit: error.limit,
+            line: error.line,
+            column: error.column,
+        }
+    }
+}
+
+/// Tracks just enough JSON structure (strings, nesting and separators) to enforce
+/// [`ParseLimits`] on a byte stream without buffering it.
+struct Scanner {
+    limits: ParseLimits,
+    bytes: u64,
+    line: usize,
+    column: usize,
+    in_string: bool,
+    escaped: bool,
+    // number of `,` seen in each open array or object
+    separators: Vec<usize>,
+}
+
+impl Scanner {
+    fn new(limits: &ParseLimits) -> Self {
+        Scanner {
+            limits: limits.clone(),
+            bytes: 0,
+            line: 1,
+            column: 0,
+            in_string: false,
+            escaped: false,
+            separators: Vec::new(),
+        }
+    }
+
+    fn scan(&mut self, buf: &[u8]) -> Result<(), LimitExceeded> {
+        for &byte in buf {
+            self.bytes += 1;
+            if byte == b'\n' {
+                self.line += 1;
+                self.column = 0;
+            } else {
+                self.column += 1;
+            }
+            if self.bytes > self.limits.max_bytes {
+                return Err(self.exceeded(ParseLimit::Bytes(self.limits.max_bytes)));
+            }
+
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => {
+                    if self.separators.len() >= self.limits.max_depth {
+                        return Err(self.exceeded(ParseLimit::Depth(self.limits.max_depth)));
+                    }
+                    self.separators.push(0);
+                }
+                b'}' | b']' => {
+                    self.separators.pop();
+                }
+                b',' => {
+                    if let Some(separators) = self.separators.last_mut() {
+                        *separators += 1;
+                        if *separators >= self.limits.max_collection_len {
+                            let max = self.limits.max_collection_len;
+                            return Err(self.exceeded(ParseLimit::CollectionLen(max)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn exceeded(&self, limit: ParseLimit) -> LimitExceeded {
+        LimitExceeded {
+            limit,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+struct LimitedReader<R: Read> {
+    inner: R,
+    scanner: Scanner,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.scanner
+            .scan(&buf[..read])
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(read)
+    }
+}
+
+/// Deserializes a document as it's read from `source`, failing as soon as a limit is exceeded.
+pub(crate) fn from_json_reader<T: DeserializeOwned, R: Read>(
+    source: R,
+    limits: &ParseLimits,
+) -> Result<T, ParseError> {
+    let reader = LimitedReader {
+        inner: BufReader::new(source),
+        scanner: Scanner::new(limits),
+    };
+    deserialize(serde_json::Deserializer::from_reader(reader)).map_err(|error| match error {
+        ParseError::Io(error) => match error.get_ref().and_then(|x| x.downcast_ref()) {
+            Some(LimitExceeded {
+                limit,
+                line,
+                column,
+            }) => ParseError::LimitExceeded {
+                limit: *limit,
+                line: *line,
+                column: *column,
+            },
+            None => error.into(),
+        },
+        error => error,
+    })
+}
+
+pub(crate) fn from_json_slice<T: DeserializeOwned>(
+    source: &[u8],
+    limits: &ParseLimits,
+) -> Result<T, ParseError> {
+    Scanner::new(limits).scan(source)?;
+    deserialize(serde_json::Deserializer::from_slice(source))
+}
+
+// keeps track of the JSON path to whichever value fails
+fn deserialize<'de, T, R>(mut deserializer: serde_json::Deserializer<R>) -> Result<T, ParseError>
+where
+    T: DeserializeOwned,
+    R: serde_json::de::Read<'de>,
+{
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|error| {
+        let path = JsonPath::from(error.path());
+        ParseError::from_serde_error(path, error.into_inner())
+    })?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn limits(max_bytes: u64, max_depth: usize, max_collection_len: usize) -> ParseLimits {
+        ParseLimits {
+            max_bytes,
+            max_depth,
+            max_collection_len,
+        }
+    }
+
+    // yields one byte at a time to make sure limits are enforced across reads
+    struct Trickle<'a>(&'a [u8]);
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    fn parse_both(raw: &str, limits: &ParseLimits) -> Vec<Result<serde_json::Value, ParseError>> {
+        vec![
+            from_json_reader(Trickle(raw.as_bytes()), limits),
+            from_json_slice(raw.as_bytes(), limits),
+        ]
+    }
+
+    #[test]
+    fn parses_within_limits() {
+        let raw = r#"{"a": [1, 2, 3], "b": {"c": "d"}}"#;
+        for result in parse_both(raw, &limits(raw.len() as u64, 2, 3)) {
+            assert!(result.is_ok(), "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn enforces_byte_limit() {
+        let raw = r#"{"a": "bcdefghijklmnopqrstuvwxyz"}"#;
+        for result in parse_both(raw, &limits(10, 64, 64)) {
+            match result.unwrap_err() {
+                ParseError::LimitExceeded { limit, column, .. } => {
+                    assert_eq!(limit, ParseLimit::Bytes(10));
+                    assert_eq!(column, 11);
+                }
+                err => panic!("Received unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn enforces_depth_limit() {
+        let raw = r#"{"a": {"b": [["c"]]}}"#;
+        for result in parse_both(raw, &limits(1024, 3, 64)) {
+            match result.unwrap_err() {
+                ParseError::LimitExceeded { limit, .. } => assert_eq!(limit, ParseLimit::Depth(3)),
+                err => panic!("Received unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn enforces_collection_limit() {
+        let raw = "{\n\"a\": [1, 2], \"b\": [1, 2, 3]}";
+        for result in parse_both(raw, &limits(1024, 64, 2)) {
+            match result.unwrap_err() {
+                ParseError::LimitExceeded { limit, line, .. } => {
+                    assert_eq!(limit, ParseLimit::CollectionLen(2));
+                    assert_eq!(line, 2);
+                }
+                err => panic!("Received unexpected error: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn ignores_structural_characters_in_strings() {
+        let raw = r#"{"a": "[[[{{{,,,\"]]]"}"#;
+        for result in parse_both(raw, &limits(1024, 1, 1)) {
+            assert!(result.is_ok(), "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn still_reports_paths_for_schema_errors() {
+        let raw = r#"{"a": [1, 2, "three"]}"#;
+        let err = from_json_reader::<HashMap<String, Vec<u8>>, _>(
+            raw.as_bytes(),
+            &ParseLimits::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.path().unwrap().to_string(), "a[2]");
+    }
+}
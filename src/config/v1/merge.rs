@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+
+use crate::config::v1::env_var::EnvVar;
+use crate::config::v1::exposed_ports::ExposedPorts;
+use crate::config::v1::image_config::{Config, ImageConfig, RootFS};
+use crate::config::v1::volumes::Volumes;
+
+impl ImageConfig {
+    /// Derives a new config from `base` the same way a Dockerfile `FROM` does, treating `self` as
+    /// the child. See [`merge_image_configs`] for the rules applied.
+    pub fn derive_from(&self, base: &ImageConfig) -> ImageConfig {
+        merge_image_configs(base, self)
+    }
+
+    /// The part of `self` that a build on top of `base` added. See [`strip_base_config`].
+    pub fn strip_base(&self, base: &ImageConfig) -> ImageConfig {
+        strip_base_config(base, self)
+    }
+}
+
+/// Merges a `child` config on top of a `base` config:
+///
+/// * `Env` entries are overridden by variable name, keeping the base's ordering
+/// * `ExposedPorts`, `Volumes` and `Labels` are unioned, with the child winning on conflicts
+/// * setting `Entrypoint` in the child resets any inherited `Cmd`
+/// * `User`, `WorkingDir` and `StopSignal` are inherited unless the child sets them
+/// * `rootfs.diff_ids` and `history` are concatenated, base first
+/// * platform fields come from the child, `created` and `author` fall back to the base
+pub fn merge_image_configs(base: &ImageConfig, child: &ImageConfig) -> ImageConfig {
+    let mut diff_ids = base.rootfs.diff_ids.clone();
+    diff_ids.extend(child.rootfs.diff_ids.iter().cloned());
+
+    ImageConfig {
+        architecture: child.architecture.clone(),
+        os: child.os.clone(),
+        variant: child.variant.clone(),
+        os_version: child.os_version.clone(),
+        os_features: child.os_features.clone(),
+        rootfs: RootFS {
+            _type: child.rootfs._type.clone(),
+            diff_ids,
+        },
+        created: child.created.or(base.created),
+        author: child.author.clone().or_else(|| base.author.clone()),
+        config: merge_option(&base.config, &child.config, merge_config),
+        history: merge_option(&base.history, &child.history, |base, child| {
+            base.iter().chain(child.iter()).cloned().collect()
+        }),
+    }
+}
+
+/// Undoes [`merge_image_configs`] for an `image` built on `base`, so that the result can be
+/// derived from another base:
+///
+/// * the first `rootfs.diff_ids` and `history` entries, as many as `base` has, are dropped
+/// * `Env` entries, `ExposedPorts`, `Volumes` and `Labels` that are the same as in the base are
+///   dropped
+/// * `User`, `WorkingDir`, `StopSignal`, `Entrypoint` and `Cmd` that are the same as in the base
+///   are unset, except for an `Entrypoint` that reset the base's `Cmd`
+///
+/// It doesn't check that `image` actually starts with the layers and history of `base`.
+pub fn strip_base_config(base: &ImageConfig, image: &ImageConfig) -> ImageConfig {
+    ImageConfig {
+        rootfs: RootFS {
+            _type: image.rootfs._type.clone(),
+            diff_ids: image
+                .rootfs
+                .diff_ids
+                .iter()
+                .skip(base.rootfs.diff_ids.len())
+                .cloned()
+                .collect(),
+        },
+        config: match (&base.config, &image.config) {
+            (Some(base), Some(image)) => Some(strip_config(base, image)),
+            (_, config) => config.clone(),
+        },
+        history: image.history.as_ref().map(|history| {
+            let count = base.history.as_ref().map_or(0, |x| x.len());
+            history.iter().skip(count).cloned().collect()
+        }),
+        ..image.clone()
+    }
+}
+
+// `None` for a value `image` inherited
+fn own<T: Clone + PartialEq>(base: &Option<T>, image: &Option<T>) -> Option<T> {
+    if base == image {
+        None
+    } else {
+        image.clone()
+    }
+}
+
+fn strip_config(base: &Config, image: &Config) -> Config {
+    // setting `ENTRYPOINT` again is the only way the base's `CMD` can have gone away
+    let reset_cmd = image.cmd.is_none() && base.cmd.is_some();
+    let entrypoint = if reset_cmd {
+        image.entrypoint.clone()
+    } else {
+        own(&base.entrypoint, &image.entrypoint)
+    };
+
+    Config {
+        user: own(&base.user, &image.user),
+        exposed_ports: image.exposed_ports.as_ref().map(|ports| {
+            let inherited = base.exposed_ports.as_ref().map(|x| &x.port_protocol_map);
+            ExposedPorts {
+                port_protocol_map: ports
+                    .port_protocol_map
+                    .iter()
+                    .filter(|(port, protocol)| {
+                        inherited.and_then(|x| x.get(port)) != Some(protocol)
+                    })
+                    .map(|(port, protocol)| (*port, protocol.clone()))
+                    .collect(),
+            }
+        }),
+        env: image.env.as_ref().map(|env| {
+            let inherited = base.env.as_deref().unwrap_or_default();
+            env.iter()
+                .filter(|x| !inherited.contains(x))
+                .cloned()
+                .collect()
+        }),
+        entrypoint,
+        cmd: own(&base.cmd, &image.cmd),
+        volumes: image.volumes.as_ref().map(|volumes| {
+            let inherited = base
+                .volumes
+                .as_ref()
+                .map(|x| x.0.as_slice())
+                .unwrap_or_default();
+            Volumes(
+                volumes
+                    .0
+                    .iter()
+                    .filter(|x| !inherited.contains(x))
+                    .cloned()
+                    .collect(),
+            )
+        }),
+        working_dir: own(&base.working_dir, &image.working_dir),
+        labels: image.labels.as_ref().map(|labels| {
+            labels
+                .iter()
+                .filter(|(k, v)| base.labels.as_ref().and_then(|x| x.get(*k)) != Some(*v))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect()
+        }),
+        stop_signal: own(&base.stop_signal, &image.stop_signal),
+    }
+}
+
+fn merge_config(base: &Config, child: &Config) -> Config {
+    // a child `ENTRYPOINT` invalidates whatever `CMD` the base was built around
+    let cmd = if child.entrypoint.is_some() {
+        child.cmd.clone()
+    } else {
+        child.cmd.clone().or_else(|| base.cmd.clone())
+    };
+
+    Config {
+        user: child.user.clone().or_else(|| base.user.clone()),
+        exposed_ports: merge_option(
+            &base.exposed_ports,
+            &child.exposed_ports,
+            merge_exposed_ports,
+        ),
+        env: merge_option(&base.env, &child.env, |base, child| merge_env(base, child)),
+        entrypoint: child.entrypoint.clone().or_else(|| base.entrypoint.clone()),
+        cmd,
+        volumes: merge_option(&base.volumes, &child.volumes, merge_volumes),
+        working_dir: child
+            .working_dir
+            .clone()
+            .or_else(|| base.working_dir.clone()),
+        labels: merge_option(&base.labels, &child.labels, merge_labels),
+        stop_signal: child
+            .stop_signal
+            .clone()
+            .or_else(|| base.stop_signal.clone()),
+    }
+}
+
+fn merge_option<T: Clone, F: Fn(&T, &T) -> T>(
+    base: &Option<T>,
+    child: &Option<T>,
+    merge: F,
+) -> Option<T> {
+    match (base, child) {
+        (Some(base), Some(child)) => Some(merge(base, child)),
+        (Some(base), None) => Some(base.clone()),
+        (None, Some(child)) => Some(child.clone()),
+        (None, None) => None,
+    }
+}
+
+fn merge_env(base: &[EnvVar], child: &[EnvVar]) -> Vec<EnvVar> {
+    let mut merged = base.to_vec();
+    for env_var in child {
+        match merged.iter_mut().find(|x| x.var_name == env_var.var_name) {
+            Some(existing) => existing.var_value = env_var.var_value.clone(),
+            None => merged.push(env_var.clone()),
+        }
+    }
+    merged
+}
+
+fn merge_exposed_ports(base: &ExposedPorts, child: &ExposedPorts) -> ExposedPorts {
+    let mut port_protocol_map = base.port_protocol_map.clone();
+    for (port, protocol) in &child.port_protocol_map {
+        port_protocol_map.insert(*port, protocol.clone());
+    }
+    ExposedPorts { port_protocol_map }
+}
+
+fn merge_volumes(base: &Volumes, child: &Volumes) -> Volumes {
+    let mut volumes = base.0.clone();
+    for volume in &child.0 {
+        if !volumes.contains(volume) {
+            volumes.push(volume.clone());
+        }
+    }
+    Volumes(volumes)
+}
+
+fn merge_labels(
+    base: &HashMap<String, String>,
+    child: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    let mut labels = base.clone();
+    labels.extend(child.iter().map(|(k, v)| (k.clone(), v.clone())));
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::v1::exposed_ports::PortProtocol;
+    use crate::config::v1::image_config::{Architecture, History, RootFSType, OS};
+    use crate::test_helpers::assertions::*;
+
+    fn env_var(var_name: &str, var_value: &str) -> EnvVar {
+        EnvVar {
+            var_name: var_name.to_string(),
+            var_value: var_value.to_string(),
+        }
+    }
+
+    fn history(created_by: &str) -> History {
+        History {
+            created: None,
+            author: None,
+            created_by: Some(created_by.to_string()),
+            comment: None,
+            empty_layer: None,
+        }
+    }
+
+    fn image_config(diff_ids: &[&str], config: Option<Config>) -> ImageConfig {
+        ImageConfig {
+            architecture: Architecture::Amd64,
+            os: OS::Linux,
+            variant: None,
+            os_version: None,
+            os_features: None,
+            rootfs: RootFS {
+                _type: RootFSType::Layers,
+                diff_ids: diff_ids.iter().map(|x| x.to_string()).collect(),
+            },
+            created: None,
+            author: None,
+            config,
+            history: None,
+        }
+    }
+
+    fn empty_config() -> Config {
+        Config {
+            user: None,
+            exposed_ports: None,
+            env: None,
+            entrypoint: None,
+            cmd: None,
+            volumes: None,
+            working_dir: None,
+            labels: None,
+            stop_signal: None,
+        }
+    }
+
+    #[test]
+    fn derive_from_is_merge_with_self_as_child() {
+        let base = image_config(&["sha256:base"], None);
+        let child = image_config(&["sha256:child"], None);
+        assert_eq!(child.derive_from(&base), merge_image_configs(&base, &child));
+    }
+
+    #[test]
+    fn concatenates_layers_and_history() {
+        let mut base = image_config(&["sha256:a", "sha256:b"], None);
+        base.history = Some(vec![history("ADD a"), history("ADD b")]);
+        let mut child = image_config(&["sha256:c"], None);
+        child.history = Some(vec![history("ADD c")]);
+
+        let merged = child.derive_from(&base);
+        assert_eq!(
+            merged.rootfs.diff_ids,
+            vec!["sha256:a", "sha256:b", "sha256:c"]
+        );
+        assert_eq!(
+            merged.history.unwrap(),
+            vec![history("ADD a"), history("ADD b"), history("ADD c")]
+        );
+    }
+
+    #[test]
+    fn inherits_base_config_when_child_has_none() {
+        let mut config = empty_config();
+        config.user = Some("nobody".to_string());
+        let base = image_config(&[], Some(config.clone()));
+        let child = image_config(&[], None);
+
+        assert_eq!(child.derive_from(&base).config, Some(config));
+    }
+
+    #[test]
+    fn strips_what_the_base_contributed() {
+        let mut base_config = empty_config();
+        base_config.env = Some(vec![env_var("PATH", "/bin"), env_var("LANG", "C")]);
+        base_config.user = Some("nobody".to_string());
+        base_config.cmd = Some(vec!["sh".to_string()]);
+        base_config.volumes = Some(Volumes(vec!["/data".to_string()]));
+        let mut base = image_config(&["sha256:a"], Some(base_config));
+        base.history = Some(vec![history("ADD a")]);
+        let mut child_config = empty_config();
+        child_config.env = Some(vec![env_var("PATH", "/app/bin"), env_var("APP", "1")]);
+        child_config.entrypoint = Some(vec!["/app".to_string()]);
+        let mut child = image_config(&["sha256:b"], Some(child_config.clone()));
+        child.history = Some(vec![history("ADD b")]);
+
+        let image = child.derive_from(&base);
+        let stripped = image.strip_base(&base);
+        assert_eq!(stripped.rootfs.diff_ids, vec!["sha256:b"]);
+        assert_eq!(stripped.history, Some(vec![history("ADD b")]));
+        child_config.volumes = Some(Volumes(vec![]));
+        assert_eq!(stripped.config, Some(child_config));
+        assert_eq!(stripped.derive_from(&base), image);
+    }
+
+    #[test]
+    fn strips_values_that_match_the_base() {
+        let mut base_config = empty_config();
+        base_config.entrypoint = Some(vec!["/entrypoint.sh".to_string()]);
+        base_config.working_dir = Some("/".to_string());
+        let base = image_config(&[], Some(base_config.clone()));
+        base_config.working_dir = Some("/app".to_string());
+        let image = image_config(&[], Some(base_config));
+
+        let config = strip_base_config(&base, &image).config.unwrap();
+        assert_eq!(config.entrypoint, None);
+        assert_eq!(config.working_dir, Some("/app".to_string()));
+    }
+
+    mod env {
+        use super::*;
+
+        #[test]
+        fn overrides_by_key_preserving_order() {
+            let mut base_config = empty_config();
+            base_config.env = Some(vec![env_var("PATH", "/bin"), env_var("LANG", "C")]);
+            let mut child_config = empty_config();
+            child_config.env = Some(vec![env_var("FOO", "bar"), env_var("PATH", "/usr/bin")]);
+
+            let merged = image_config(&[], Some(child_config))
+                .derive_from(&image_config(&[], Some(base_config)));
+            assert_eq!(
+                merged.config.unwrap().env.unwrap(),
+                vec![
+                    env_var("PATH", "/usr/bin"),
+                    env_var("LANG", "C"),
+                    env_var("FOO", "bar"),
+                ]
+            );
+        }
+    }
+
+    mod unioned_fields {
+        use super::*;
+
+        #[test]
+        fn unions_exposed_ports() {
+            let mut base_ports = HashMap::new();
+            base_ports.insert(80, Some(PortProtocol::TCP));
+            let mut child_ports = HashMap::new();
+            child_ports.insert(53, Some(PortProtocol::UDP));
+            let mut base_config = empty_config();
+            base_config.exposed_ports = Some(ExposedPorts {
+                port_protocol_map: base_ports,
+            });
+            let mut child_config = empty_config();
+            child_config.exposed_ports = Some(ExposedPorts {
+                port_protocol_map: child_ports,
+            });
+
+            let merged = image_config(&[], Some(child_config))
+                .derive_from(&image_config(&[], Some(base_config)));
+            let exposed_ports = merged.config.unwrap().exposed_ports.unwrap();
+            assert_map_len(&exposed_ports.port_protocol_map, 2);
+            assert_map_contains(
+                &exposed_ports.port_protocol_map,
+                80,
+                Some(PortProtocol::TCP),
+            );
+            assert_map_contains(
+                &exposed_ports.port_protocol_map,
+                53,
+                Some(PortProtocol::UDP),
+            );
+        }
+
+        #[test]
+        fn unions_volumes_without_duplicates() {
+            let mut base_config = empty_config();
+            base_config.volumes = Some(Volumes(vec!["/data".to_string(), "/logs".to_string()]));
+            let mut child_config = empty_config();
+            child_config.volumes = Some(Volumes(vec!["/logs".to_string(), "/cache".to_string()]));
+
+            let merged = image_config(&[], Some(child_config))
+                .derive_from(&image_config(&[], Some(base_config)));
+            assert_eq!(
+                merged.config.unwrap().volumes.unwrap(),
+                Volumes(vec![
+                    "/data".to_string(),
+                    "/logs".to_string(),
+                    "/cache".to_string(),
+                ])
+            );
+        }
+
+        #[test]
+        fn unions_labels_with_child_winning() {
+            let mut base_labels = HashMap::new();
+            base_labels.insert("maintainer".to_string(), "base".to_string());
+            base_labels.insert("base.only".to_string(), "yes".to_string());
+            let mut child_labels = HashMap::new();
+            child_labels.insert("maintainer".to_string(), "child".to_string());
+            let mut base_config = empty_config();
+            base_config.labels = Some(base_labels);
+            let mut child_config = empty_config();
+            child_config.labels = Some(child_labels);
+
+            let merged = image_config(&[], Some(child_config))
+                .derive_from(&image_config(&[], Some(base_config)));
+            let labels = merged.config.unwrap().labels.unwrap();
+            assert_map_len(&labels, 2);
+            assert_map_contains(&labels, "maintainer".to_string(), "child".to_string());
+            assert_map_contains(&labels, "base.only".to_string(), "yes".to_string());
+        }
+    }
+
+    mod entrypoint_and_cmd {
+        use super::*;
+
+        fn base() -> ImageConfig {
+            let mut config = empty_config();
+            config.entrypoint = Some(vec!["/docker-entrypoint.sh".to_string()]);
+            config.cmd = Some(vec!["nginx".to_string()]);
+            image_config(&[], Some(config))
+        }
+
+        #[test]
+        fn inherits_cmd_when_entrypoint_is_unset() {
+            let merged = image_config(&[], Some(empty_config())).derive_from(&base());
+            let config = merged.config.unwrap();
+            assert_eq!(
+                config.entrypoint,
+                Some(vec!["/docker-entrypoint.sh".to_string()])
+            );
+            assert_eq!(config.cmd, Some(vec!["nginx".to_string()]));
+        }
+
+        #[test]
+        fn resets_inherited_cmd_when_entrypoint_is_set() {
+            let mut child_config = empty_config();
+            child_config.entrypoint = Some(vec!["/app".to_string()]);
+
+            let merged = image_config(&[], Some(child_config)).derive_from(&base());
+            let config = merged.config.unwrap();
+            assert_eq!(config.entrypoint, Some(vec!["/app".to_string()]));
+            assert_eq!(config.cmd, None);
+        }
+
+        #[test]
+        fn keeps_child_cmd_when_entrypoint_is_set() {
+            let mut child_config = empty_config();
+            child_config.entrypoint = Some(vec!["/app".to_string()]);
+            child_config.cmd = Some(vec!["--help".to_string()]);
+
+            let merged = image_config(&[], Some(child_config)).derive_from(&base());
+            assert_eq!(merged.config.unwrap().cmd, Some(vec!["--help".to_string()]));
+        }
+    }
+}
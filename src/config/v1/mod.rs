@@ -1,14 +1,31 @@
+mod diff;
+pub use diff::{
+    diff_image_configs, FieldChange, ImageConfigDiff, KeyedChange, Layer, LayerChange,
+    PlatformChange, SetChange,
+};
+
 mod env_var;
 pub use env_var::EnvVar;
 
 mod errors;
-pub use errors::ParseError;
+pub use errors::{JsonPath, JsonPathError, JsonPathSegment, ParseError};
 
 mod exposed_ports;
 pub use exposed_ports::{ExposedPorts, PortProtocol};
 
 mod image_config;
-pub use image_config::{Architecture, Config, History, ImageConfig, RootFS, RootFSType, OS};
+pub use image_config::{
+    parse_image_config, parse_image_config_from_slice, parse_image_config_from_slice_with_limits,
+    parse_image_config_with_descriptor, parse_image_config_with_limits, Architecture, Config,
+    History, ImageConfig, RootFS, RootFSType, OS,
+};
+
+mod json;
+pub(crate) use json::from_json_reader;
+pub use json::{ParseLimit, ParseLimits};
+
+mod merge;
+pub use merge::{merge_image_configs, strip_base_config};
 
 mod volumes;
 pub use volumes::Volumes;
@@ -3,7 +3,7 @@ use serde::ser::{SerializeMap, Serializer};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Volumes(pub Vec<String>);
 
 impl Serialize for Volumes {
@@ -30,8 +30,7 @@ impl<'de> Visitor<'de> for VolumesVisitor {
     type Value = Volumes;
 
     fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-        // TODO: what do I put here
-        formatter.write_str("TODO: idk what I put here")
+        formatter.write_str("a map of volume paths to empty objects")
     }
 
     fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
@@ -69,11 +68,11 @@ mod tests {
         #[test]
         fn deserializes_correctly() {
             let raw = r#"{"/var/job-result-data":{},"/var/log/my-app-logs":{}}"#;
-            let volumes: Volumes = serde_json::from_str(&raw).unwrap();
+            let volumes: Volumes = serde_json::from_str(raw).unwrap();
             assert_eq!(volumes.0.len(), 2);
             assert_consists_of(
                 volumes.0,
-                &vec![
+                &[
                     "/var/job-result-data".to_string(),
                     "/var/log/my-app-logs".to_string(),
                 ],
@@ -0,0 +1,446 @@
+use std::fmt::{self, Display};
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::store::{BlobStore, BlobWriter, StoreError};
+use crate::v1::{
+    Descriptor, Digest, Digester, ParseError, VerifyingReader, MEDIA_TYPE_IMAGE_LAYER,
+    MEDIA_TYPE_IMAGE_LAYER_GZIP, MEDIA_TYPE_IMAGE_LAYER_ZSTD,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// How a layer's tar stream is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    Uncompressed,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The compression a layer media type declares, or `None` if it isn't a tar layer. The
+    /// non-distributable and Docker layer media types are understood as well.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        if media_type.ends_with(".tar") {
+            Some(Compression::Uncompressed)
+        } else if media_type.ends_with(".tar+gzip") || media_type.ends_with(".tar.gzip") {
+            Some(Compression::Gzip)
+        } else if media_type.ends_with(".tar+zstd") || media_type.ends_with(".tar.zstd") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Works out the compression from the first bytes of a layer; anything that isn't gzip or
+    /// zstd is taken to be a plain tar.
+    pub fn sniff(magic: &[u8]) -> Self {
+        if magic.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::Uncompressed
+        }
+    }
+
+    /// The OCI layer media type for this compression.
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Compression::Uncompressed => MEDIA_TYPE_IMAGE_LAYER,
+            Compression::Gzip => MEDIA_TYPE_IMAGE_LAYER_GZIP,
+            Compression::Zstd => MEDIA_TYPE_IMAGE_LAYER_ZSTD,
+        }
+    }
+
+    /// Decompresses what's read from `reader`.
+    pub fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Uncompressed => Box::new(reader),
+            // tools like pigz write more than one gzip member
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+        })
+    }
+
+    /// Compresses what's written into `writer`; call [`Encoder::finish`] once done.
+    pub fn encoder<W: Write>(self, writer: W) -> io::Result<Encoder<W>> {
+        Ok(Encoder(match self {
+            Compression::Uncompressed => Inner::Uncompressed(writer),
+            // the header's mtime stays 0, so the same tar always compresses the same way
+            Compression::Gzip => Inner::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            Compression::Zstd => Inner::Zstd(zstd::stream::write::Encoder::new(writer, 0)?),
+        }))
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Compression::Uncompressed => write!(f, "uncompressed"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// A compressing writer made by [`Compression::encoder`].
+pub struct Encoder<W: Write>(Inner<W>);
+
+enum Inner<W: Write> {
+    Uncompressed(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes out the end of the compressed stream, returning the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self.0 {
+            Inner::Uncompressed(writer) => Ok(writer),
+            Inner::Gzip(encoder) => encoder.finish(),
+            Inner::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Inner::Uncompressed(writer) => writer.write(buf),
+            Inner::Gzip(encoder) => encoder.write(buf),
+            Inner::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Inner::Uncompressed(writer) => writer.flush(),
+            Inner::Gzip(encoder) => encoder.flush(),
+            Inner::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl<W: Write> fmt::Debug for Encoder<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let compression = match self.0 {
+            Inner::Uncompressed(_) => Compression::Uncompressed,
+            Inner::Gzip(_) => Compression::Gzip,
+            Inner::Zstd(_) => Compression::Zstd,
+        };
+        f.debug_tuple("Encoder").field(&compression).finish()
+    }
+}
+
+/// Both digests of a layer: `digest` and `size` of the blob as stored, which go in its
+/// descriptor, and `diff_id` of the uncompressed tar, which goes in `rootfs.diff_ids`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub compression: Compression,
+    pub digest: Digest,
+    pub size: u64,
+    pub diff_id: Digest,
+}
+
+impl Layer {
+    pub fn descriptor(&self) -> Descriptor {
+        Descriptor {
+            media_type: self.compression.media_type().to_string(),
+            digest: self.digest.clone(),
+            size: self.size,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        }
+    }
+}
+
+// counts and hashes whatever is read through it
+pub(super) struct Hashing<R> {
+    inner: R,
+    pub(super) digester: Digester,
+    pub(super) size: u64,
+}
+
+impl<R> Hashing<R> {
+    pub(super) fn new(inner: R) -> Self {
+        Hashing {
+            inner,
+            digester: Digester::sha256(),
+            size: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.digester.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+// the compression a media type declares, falling back to the magic bytes for anything else
+fn detect<R: BufRead>(reader: &mut R, media_type: Option<&str>) -> io::Result<Compression> {
+    match media_type.and_then(Compression::from_media_type) {
+        Some(compression) => Ok(compression),
+        None => Ok(Compression::sniff(reader.fill_buf()?)),
+    }
+}
+
+/// Hashes a layer blob and the tar inside it in one pass. The compression comes from
+/// `media_type` if it's a layer media type and from the blob's magic bytes otherwise.
+pub fn digest_layer<R: Read>(reader: R, media_type: Option<&str>) -> io::Result<Layer> {
+    let mut blob = Hashing::new(reader);
+    let mut diff_id = Digester::sha256();
+    let compression = {
+        let mut buffered = BufReader::new(&mut blob);
+        let compression = detect(&mut buffered, media_type)?;
+        io::copy(&mut compression.decoder(&mut buffered)?, &mut diff_id)?;
+        // whatever follows the compressed stream is still part of the blob
+        io::copy(&mut buffered, &mut io::sink())?;
+        compression
+    };
+    Ok(Layer {
+        compression,
+        digest: blob.digester.finalize(),
+        size: blob.size,
+        diff_id: diff_id.finalize(),
+    })
+}
+
+/// Compresses the tar read from `tar` into the store, hashing it on the way.
+pub fn write_layer<S: BlobStore + ?Sized, R: Read>(
+    store: &S,
+    mut tar: R,
+    compression: Compression,
+) -> Result<Layer, StoreError> {
+    let mut writer = LayerWriter::new(store, compression)?;
+    io::copy(&mut tar, &mut writer)?;
+    writer.finish()
+}
+
+/// Compresses a tar written into it into the store, for layers that are built rather than
+/// copied. Nothing is stored until [`LayerWriter::finish`] is called.
+pub struct LayerWriter<'a> {
+    compression: Compression,
+    encoder: Encoder<Box<dyn BlobWriter + 'a>>,
+    diff_id: Digester,
+}
+
+impl<'a> LayerWriter<'a> {
+    pub fn new<S: BlobStore + ?Sized>(
+        store: &'a S,
+        compression: Compression,
+    ) -> Result<Self, StoreError> {
+        Ok(LayerWriter {
+            compression,
+            encoder: compression.encoder(store.writer()?)?,
+            diff_id: Digester::sha256(),
+        })
+    }
+
+    pub fn finish(self) -> Result<Layer, StoreError> {
+        let (digest, size) = self.encoder.finish()?.commit()?;
+        Ok(Layer {
+            compression: self.compression,
+            digest,
+            size,
+            diff_id: self.diff_id.finalize(),
+        })
+    }
+}
+
+impl<'a> Write for LayerWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.encoder.write(buf)?;
+        self.diff_id.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encoder.flush()
+    }
+}
+
+impl<'a> fmt::Debug for LayerWriter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("LayerWriter")
+            .field("compression", &self.compression)
+            .finish()
+    }
+}
+
+/// Reads the uncompressed tar of a stored layer, verifying the blob against `descriptor` as
+/// it's read.
+pub fn open_layer<'a, S: BlobStore + ?Sized>(
+    store: &'a S,
+    descriptor: &Descriptor,
+) -> Result<Box<dyn Read + 'a>, StoreError> {
+    let reader = VerifyingReader::for_descriptor(store.reader(&descriptor.digest)?, descriptor)
+        .map_err(ParseError::from)?;
+    let mut reader = BufReader::new(reader);
+    let compression = detect(&mut reader, Some(&descriptor.media_type))?;
+    Ok(compression.decoder(reader)?)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::store::MemoryBlobStore;
+    use crate::v1::Digest;
+
+    pub(crate) fn tar_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    // names go straight into the headers, so that they needn't be valid paths or UTF-8
+    pub(crate) fn tar_of_raw(files: &[(&[u8], &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name);
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    pub(crate) fn tar_data() -> Vec<u8> {
+        tar_of(&[("hello.txt", b"hello")])
+    }
+
+    #[test]
+    fn reads_compression_from_media_types() {
+        assert_eq!(
+            Compression::from_media_type(MEDIA_TYPE_IMAGE_LAYER_ZSTD),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            Compression::from_media_type(
+                "application/vnd.oci.image.layer.nondistributable.v1.tar+gzip"
+            ),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_media_type("application/vnd.docker.image.rootfs.diff.tar.gzip"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_media_type(MEDIA_TYPE_IMAGE_LAYER),
+            Some(Compression::Uncompressed)
+        );
+        assert_eq!(
+            Compression::from_media_type("application/vnd.oci.image.config.v1+json"),
+            None
+        );
+    }
+
+    #[test]
+    fn sniffs_magic_bytes() {
+        assert_eq!(Compression::sniff(&[0x1f, 0x8b, 0x08]), Compression::Gzip);
+        assert_eq!(
+            Compression::sniff(&[0x28, 0xb5, 0x2f, 0xfd, 0x00]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::sniff(b"hello"), Compression::Uncompressed);
+        assert_eq!(Compression::sniff(&[]), Compression::Uncompressed);
+    }
+
+    #[test]
+    fn writes_and_reads_layers() {
+        let tar = tar_data();
+        for compression in [
+            Compression::Uncompressed,
+            Compression::Gzip,
+            Compression::Zstd,
+        ] {
+            let store = MemoryBlobStore::new();
+            let layer = write_layer(&store, tar.as_slice(), compression).unwrap();
+            let blob = store.get(&layer.digest).unwrap();
+            assert_eq!(layer.compression, compression);
+            assert_eq!(layer.diff_id, Digest::sha256(&tar));
+            assert_eq!(layer.digest, Digest::sha256(&blob));
+            assert_eq!(layer.size, blob.len() as u64);
+            assert_eq!(Compression::sniff(&blob), compression);
+
+            let mut read = vec![];
+            open_layer(&store, &layer.descriptor())
+                .unwrap()
+                .read_to_end(&mut read)
+                .unwrap();
+            assert_eq!(read, tar);
+
+            // sniffing and the media type agree
+            assert_eq!(digest_layer(blob.as_slice(), None).unwrap(), layer);
+            assert_eq!(
+                digest_layer(blob.as_slice(), Some(compression.media_type())).unwrap(),
+                layer
+            );
+        }
+    }
+
+    #[test]
+    fn sniffs_layers_with_foreign_media_types() {
+        let store = MemoryBlobStore::new();
+        let layer = write_layer(&store, tar_data().as_slice(), Compression::Zstd).unwrap();
+        let mut descriptor = layer.descriptor();
+        descriptor.media_type = "application/octet-stream".to_string();
+
+        let mut read = vec![];
+        open_layer(&store, &descriptor)
+            .unwrap()
+            .read_to_end(&mut read)
+            .unwrap();
+        assert_eq!(read, tar_data());
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn fails_on_corrupt_compression() {
+            let mut blob = vec![0x1f, 0x8b];
+            blob.extend_from_slice(b"not really gzip");
+            assert!(digest_layer(blob.as_slice(), None).is_err());
+            assert!(digest_layer(&b"plain"[..], Some(MEDIA_TYPE_IMAGE_LAYER_ZSTD)).is_err());
+        }
+
+        #[test]
+        fn verifies_layers_as_they_are_read() {
+            let store = MemoryBlobStore::new();
+            let layer = write_layer(&store, tar_data().as_slice(), Compression::Gzip).unwrap();
+            let mut descriptor = layer.descriptor();
+            descriptor.digest = Digest::sha256(b"something else");
+            assert!(matches!(
+                open_layer(&store, &descriptor),
+                Err(StoreError::NotFound(_))
+            ));
+
+            let mut descriptor = layer.descriptor();
+            descriptor.size -= 1;
+            let mut read = vec![];
+            let result = open_layer(&store, &descriptor)
+                .unwrap()
+                .read_to_end(&mut read);
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,813 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Display};
+use std::io::{self, Read};
+
+use chrono::DateTime;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::layer::compression::Hashing;
+use crate::layer::unpack::entry_path;
+use crate::layer::{open_layer, Compression};
+use crate::store::{read_manifest, BlobStore, StoreError};
+use crate::v1::{Descriptor, Digest, Digester, JsonPath, JsonPathSegment};
+
+/// Why two images differ, from the most fundamental reason to the most superficial.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    /// Files were added, removed or changed, so the images really are different.
+    LayerContent,
+    /// The same entries were written in another order.
+    EntryOrder,
+    /// Entries have different mtimes, owners, modes, names or PAX records.
+    EntryHeader,
+    /// The same tar was compressed with another algorithm or other settings.
+    Compression,
+    /// `created` times, including the same time written with another precision.
+    Timestamp,
+    /// Any other field of the config or manifest.
+    Field,
+    /// Annotations of the manifest or of its descriptors, such as a build ID.
+    Annotation,
+    /// The same JSON with its keys in another order or other whitespace.
+    Serialization,
+}
+
+impl Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cause::LayerContent => write!(f, "files in the layers differ"),
+            Cause::EntryOrder => write!(f, "tar entries are in a different order"),
+            Cause::EntryHeader => write!(f, "tar entry headers differ"),
+            Cause::Compression => write!(f, "layers are compressed differently"),
+            Cause::Timestamp => write!(f, "timestamps differ"),
+            Cause::Field => write!(f, "config or manifest fields differ"),
+            Cause::Annotation => write!(f, "annotations differ"),
+            Cause::Serialization => write!(f, "the same JSON is serialized differently"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Difference {
+    pub cause: Cause,
+    /// Where the difference is, such as `layer 0` or `config.created`.
+    pub location: String,
+    pub detail: String,
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.detail)
+    }
+}
+
+/// Why the digests of two images differ, as produced by [`explain_difference`].
+///
+/// Renders as a ranked list through `Display` and as JSON through `Serialize`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Explanation {
+    pub left: Digest,
+    pub right: Digest,
+    /// Ordered by cause, most fundamental first.
+    pub differences: Vec<Difference>,
+}
+
+impl Explanation {
+    pub fn is_identical(&self) -> bool {
+        self.left == self.right
+    }
+
+    /// The causes found, most fundamental first.
+    pub fn causes(&self) -> Vec<Cause> {
+        let causes: BTreeSet<Cause> = self.differences.iter().map(|x| x.cause).collect();
+        causes.into_iter().collect()
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_identical() {
+            return writeln!(f, "`{}` and `{}` are identical", self.left, self.right);
+        }
+        writeln!(f, "`{}` and `{}` differ:", self.left, self.right)?;
+        for (rank, cause) in self.causes().into_iter().enumerate() {
+            writeln!(f, "{}. {}", rank + 1, cause)?;
+            for difference in self.differences.iter().filter(|x| x.cause == cause) {
+                writeln!(f, "   {}", difference)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares two image manifests down to the bytes of their layers to explain why their digests
+/// differ.
+///
+/// The manifests and configs are compared as JSON, field by field. Layers that differ are
+/// decompressed and their tar entries matched by path, telling changed files apart from
+/// reordered entries, header fields like mtime, owner names and xattrs, and tars that are the
+/// same but compressed differently.
+pub fn explain_difference<L: BlobStore + ?Sized, R: BlobStore + ?Sized>(
+    left_store: &L,
+    left: &Digest,
+    right_store: &R,
+    right: &Digest,
+) -> Result<Explanation, StoreError> {
+    let mut differences = vec![];
+    if left != right {
+        compare_json(
+            "manifest",
+            &left_store.get(left)?,
+            &right_store.get(right)?,
+            manifest_cause,
+            &mut differences,
+        )?;
+
+        let left_manifest = read_manifest(left_store, left)?;
+        let right_manifest = read_manifest(right_store, right)?;
+        let (left_config, right_config) = (&left_manifest.config, &right_manifest.config);
+        if left_config.digest != right_config.digest {
+            compare_json(
+                "config",
+                &left_store.get(&left_config.digest)?,
+                &right_store.get(&right_config.digest)?,
+                config_cause,
+                &mut differences,
+            )?;
+        }
+
+        let (left_layers, right_layers) = (&left_manifest.layers, &right_manifest.layers);
+        if left_layers.len() != right_layers.len() {
+            differences.push(Difference {
+                cause: Cause::LayerContent,
+                location: "manifest".to_string(),
+                detail: format!("{} layers vs {}", left_layers.len(), right_layers.len()),
+            });
+        }
+        for (index, (left, right)) in left_layers.iter().zip(right_layers).enumerate() {
+            let layers = LayerPair {
+                location: format!("layer {}", index),
+                left: (left_store, left),
+                right: (right_store, right),
+            };
+            layers.compare(&mut differences)?;
+        }
+    }
+    // stable, so differences of the same cause stay in document order
+    differences.sort_by_key(|x| x.cause);
+    Ok(Explanation {
+        left: left.clone(),
+        right: right.clone(),
+        differences,
+    })
+}
+
+fn segments(path: &JsonPath) -> Vec<&str> {
+    path.0
+        .iter()
+        .map(|x| match x {
+            JsonPathSegment::Key(key) => key.as_str(),
+            JsonPathSegment::Index(_) => "[]",
+        })
+        .collect()
+}
+
+fn manifest_cause(path: &JsonPath) -> Option<Cause> {
+    let segments = segments(path);
+    if segments.contains(&"annotations") {
+        return Some(Cause::Annotation);
+    }
+    match segments.as_slice() {
+        // compared through the config and layers themselves
+        ["config", "digest" | "size"]
+        | ["layers", "[]"]
+        | ["layers", "[]", "digest" | "size" | "mediaType"] => None,
+        _ => Some(Cause::Field),
+    }
+}
+
+fn config_cause(path: &JsonPath) -> Option<Cause> {
+    match segments(path).as_slice() {
+        ["created"] | ["history", "[]", "created"] => Some(Cause::Timestamp),
+        // follows from the layers
+        ["rootfs", "diff_ids", ..] => None,
+        _ => Some(Cause::Field),
+    }
+}
+
+fn compare_json(
+    document: &str,
+    left: &[u8],
+    right: &[u8],
+    cause_of: fn(&JsonPath) -> Option<Cause>,
+    differences: &mut Vec<Difference>,
+) -> Result<(), StoreError> {
+    let left_value: Value = serde_json::from_slice(left)?;
+    let right_value: Value = serde_json::from_slice(right)?;
+    if left_value == right_value {
+        if left != right {
+            differences.push(Difference {
+                cause: Cause::Serialization,
+                location: document.to_string(),
+                detail: "same values, but different key order or whitespace".to_string(),
+            });
+        }
+        return Ok(());
+    }
+
+    let mut changes = vec![];
+    walk(
+        JsonPath::default(),
+        Some(&left_value),
+        Some(&right_value),
+        &mut changes,
+    );
+    for (path, left, right) in changes {
+        let cause = match cause_of(&path) {
+            Some(cause) => cause,
+            None => continue,
+        };
+        let detail = match (cause, left, right) {
+            (Cause::Timestamp, Some(Value::String(left)), Some(Value::String(right))) => {
+                describe_timestamps(left, right)
+            }
+            _ => format!("{} vs {}", describe_value(left), describe_value(right)),
+        };
+        differences.push(Difference {
+            cause,
+            location: format!("{}.{}", document, path),
+            detail,
+        });
+    }
+    Ok(())
+}
+
+// the leaves that differ, with `None` where a document doesn't have the value; objects only
+// one document has are broken down too, so that each of their keys is reported
+fn walk<'a>(
+    path: JsonPath,
+    left: Option<&'a Value>,
+    right: Option<&'a Value>,
+    changes: &mut Vec<(JsonPath, Option<&'a Value>, Option<&'a Value>)>,
+) {
+    let objects = match (left, right) {
+        (Some(Value::Object(left)), Some(Value::Object(right))) => Some((Some(left), Some(right))),
+        (Some(Value::Object(left)), None) => Some((Some(left), None)),
+        (None, Some(Value::Object(right))) => Some((None, Some(right))),
+        _ => None,
+    };
+    if let Some((left, right)) = objects {
+        let keys: BTreeSet<&String> = left
+            .into_iter()
+            .chain(right)
+            .flat_map(|x| x.keys())
+            .collect();
+        for key in keys {
+            let (left, right) = (
+                left.and_then(|x| x.get(key)),
+                right.and_then(|x| x.get(key)),
+            );
+            walk(path.clone().key(key), left, right, changes);
+        }
+        return;
+    }
+    match (left, right) {
+        (Some(Value::Array(left)), Some(Value::Array(right))) => {
+            for index in 0..left.len().max(right.len()) {
+                let path = path.clone().index(index);
+                walk(path, left.get(index), right.get(index), changes);
+            }
+        }
+        (left, right) if left != right => changes.push((path, left, right)),
+        _ => {}
+    }
+}
+
+fn describe_value(value: Option<&Value>) -> String {
+    match value {
+        Some(value) => format!("`{}`", value),
+        None => "missing".to_string(),
+    }
+}
+
+fn describe_timestamps(left: &str, right: &str) -> String {
+    let times = (
+        DateTime::parse_from_rfc3339(left),
+        DateTime::parse_from_rfc3339(right),
+    );
+    let how = match times {
+        (Ok(a), Ok(b)) if a == b => " (the same time, written differently)",
+        (Ok(a), Ok(b)) if a.timestamp() == b.timestamp() => {
+            " (the same second with a different sub-second precision)"
+        }
+        _ => "",
+    };
+    format!("`{}` vs `{}`{}", left, right, how)
+}
+
+struct LayerPair<'a, L: ?Sized, R: ?Sized> {
+    location: String,
+    left: (&'a L, &'a Descriptor),
+    right: (&'a R, &'a Descriptor),
+}
+
+impl<L: BlobStore + ?Sized, R: BlobStore + ?Sized> LayerPair<'_, L, R> {
+    fn difference(&self, cause: Cause, detail: String) -> Difference {
+        Difference {
+            cause,
+            location: self.location.clone(),
+            detail,
+        }
+    }
+
+    fn compare(&self, differences: &mut Vec<Difference>) -> Result<(), StoreError> {
+        let ((left_store, left), (right_store, right)) = (self.left, self.right);
+        if left.digest == right.digest {
+            return Ok(());
+        }
+        let compressions = (
+            Compression::from_media_type(&left.media_type),
+            Compression::from_media_type(&right.media_type),
+        );
+        let (left_compression, right_compression) = match compressions {
+            (Some(left), Some(right)) => (left, right),
+            _ => {
+                differences.push(self.difference(
+                    Cause::LayerContent,
+                    format!(
+                        "`{}` vs `{}`, which aren't both tars",
+                        left.digest, right.digest
+                    ),
+                ));
+                return Ok(());
+            }
+        };
+        if left_compression != right_compression {
+            differences.push(self.difference(
+                Cause::Compression,
+                format!("{} vs {}", left_compression, right_compression),
+            ));
+        }
+
+        let (left_entries, left_diff_id) = read_entries(open_layer(left_store, left)?)?;
+        let (right_entries, right_diff_id) = read_entries(open_layer(right_store, right)?)?;
+        if left_diff_id == right_diff_id {
+            if left_compression == right_compression {
+                let detail = compression_settings(
+                    &mut left_store.reader(&left.digest)?,
+                    &mut right_store.reader(&right.digest)?,
+                    left_compression,
+                )?;
+                differences.push(self.difference(Cause::Compression, detail));
+            }
+            return Ok(());
+        }
+
+        let count = differences.len();
+        self.compare_entries(&left_entries, &right_entries, differences);
+        if differences.len() == count {
+            differences.push(self.difference(
+                Cause::EntryHeader,
+                "the same entries are encoded differently (header format or padding)".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn compare_entries(
+        &self,
+        left: &[TarEntry],
+        right: &[TarEntry],
+        differences: &mut Vec<Difference>,
+    ) {
+        // a later entry for the same path replaces an earlier one
+        let left_paths: HashMap<&str, &TarEntry> =
+            left.iter().map(|x| (x.path.as_str(), x)).collect();
+        let right_paths: HashMap<&str, &TarEntry> =
+            right.iter().map(|x| (x.path.as_str(), x)).collect();
+        let paths: BTreeSet<&str> = left_paths
+            .keys()
+            .chain(right_paths.keys())
+            .copied()
+            .collect();
+
+        // per header field, how many entries differ and the first one that does
+        let mut headers: BTreeMap<&str, (usize, String)> = BTreeMap::new();
+        for path in paths {
+            let (left, right) = match (left_paths.get(path), right_paths.get(path)) {
+                (Some(left), Some(right)) => (left, right),
+                (Some(_), None) => {
+                    let detail = format!("`{}` is only in the left image", path);
+                    differences.push(self.difference(Cause::LayerContent, detail));
+                    continue;
+                }
+                (None, _) => {
+                    let detail = format!("`{}` is only in the right image", path);
+                    differences.push(self.difference(Cause::LayerContent, detail));
+                    continue;
+                }
+            };
+            if left.content() != right.content() {
+                let detail = format!("`{}` has different content or type", path);
+                differences.push(self.difference(Cause::LayerContent, detail));
+            }
+            for (field, value) in HEADER_FIELDS {
+                let (left, right) = (value(left), value(right));
+                if left != right {
+                    let example = format!("`{}`: {} vs {}", path, left, right);
+                    headers.entry(field).or_insert((0, example)).0 += 1;
+                }
+            }
+        }
+        for (field, (count, example)) in headers {
+            let detail = format!("{} differs for {} entries, e.g. {}", field, count, example);
+            differences.push(self.difference(Cause::EntryHeader, detail));
+        }
+
+        // only the order of entries both have
+        let order = |entries: &[TarEntry], other: &HashMap<&str, &TarEntry>| -> Vec<String> {
+            entries
+                .iter()
+                .filter(|x| other.contains_key(x.path.as_str()))
+                .map(|x| x.path.clone())
+                .collect()
+        };
+        let left_order = order(left, &right_paths);
+        let right_order = order(right, &left_paths);
+        if let Some((position, (left, right))) = left_order
+            .iter()
+            .zip(&right_order)
+            .enumerate()
+            .find(|(_, (left, right))| left != right)
+        {
+            let detail = format!("first at entry {}: `{}` vs `{}`", position, left, right);
+            differences.push(self.difference(Cause::EntryOrder, detail));
+        }
+    }
+}
+
+struct TarEntry {
+    path: String,
+    name: String,
+    kind: u8,
+    link: Option<String>,
+    data: Digest,
+    mtime: Option<u64>,
+    uid: Option<u64>,
+    gid: Option<u64>,
+    mode: Option<u32>,
+    uname: Option<String>,
+    gname: Option<String>,
+    xattrs: BTreeMap<String, String>,
+    pax: BTreeMap<String, String>,
+}
+
+impl TarEntry {
+    fn content(&self) -> (u8, &Option<String>, &Digest) {
+        (self.kind, &self.link, &self.data)
+    }
+}
+
+fn describe<T: fmt::Debug>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => format!("{:?}", value),
+        None => "none".to_string(),
+    }
+}
+
+type HeaderField = (&'static str, fn(&TarEntry) -> String);
+
+const HEADER_FIELDS: [HeaderField; 9] = [
+    ("name", |x| format!("{:?}", x.name)),
+    ("mtime", |x| describe(&x.mtime)),
+    ("uid", |x| describe(&x.uid)),
+    ("gid", |x| describe(&x.gid)),
+    ("mode", |x| match x.mode {
+        Some(mode) => format!("{:o}", mode),
+        None => "none".to_string(),
+    }),
+    ("uname", |x| describe(&x.uname)),
+    ("gname", |x| describe(&x.gname)),
+    ("xattrs", |x| format!("{:?}", x.xattrs)),
+    ("PAX records", |x| format!("{:?}", x.pax)),
+];
+
+// the entries of a tar and its digest
+fn read_entries<Rd: Read>(tar: Rd) -> Result<(Vec<TarEntry>, Digest), StoreError> {
+    let mut tar = Hashing::new(tar);
+    let mut entries = vec![];
+    let mut archive = tar::Archive::new(&mut tar);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let path = entry_path(&entry.path()?)?.to_string_lossy().to_string();
+        let mut xattrs = BTreeMap::new();
+        let mut pax = BTreeMap::new();
+        if let Some(extensions) = entry.pax_extensions()? {
+            for extension in extensions {
+                let extension = extension?;
+                let key = String::from_utf8_lossy(extension.key_bytes()).to_string();
+                let value = String::from_utf8_lossy(extension.value_bytes()).to_string();
+                match key.strip_prefix("SCHILY.xattr.") {
+                    Some(xattr) => xattrs.insert(xattr.to_string(), value),
+                    None => pax.insert(key, value),
+                };
+            }
+        }
+        let header = entry.header();
+        let kind = header.entry_type().as_byte();
+        let link = entry.link_name()?.map(|x| x.to_string_lossy().to_string());
+        let (mtime, uid, gid, mode) = (
+            header.mtime().ok(),
+            header.uid().ok(),
+            header.gid().ok(),
+            header.mode().ok(),
+        );
+        let uname = header.username().ok().flatten().map(|x| x.to_string());
+        let gname = header.groupname().ok().flatten().map(|x| x.to_string());
+        let mut digester = Digester::sha256();
+        io::copy(&mut entry, &mut digester)?;
+        entries.push(TarEntry {
+            path,
+            name,
+            kind,
+            link,
+            data: digester.finalize(),
+            mtime,
+            uid,
+            gid,
+            mode,
+            uname,
+            gname,
+            xattrs,
+            pax,
+        });
+    }
+    io::copy(&mut tar, &mut io::sink())?;
+    Ok((entries, tar.digester.finalize()))
+}
+
+// what in the headers of two compressed blobs of the same tar tells them apart
+fn compression_settings(
+    left: &mut dyn Read,
+    right: &mut dyn Read,
+    compression: Compression,
+) -> io::Result<String> {
+    let (mut left_header, mut right_header) = ([0; 10], [0; 10]);
+    let left_read = read_up_to(left, &mut left_header)?;
+    let right_read = read_up_to(right, &mut right_header)?;
+    let (left_header, right_header) = (&left_header[..left_read], &right_header[..right_read]);
+
+    let mut settings = vec![];
+    match compression {
+        Compression::Gzip if left_read == 10 && right_read == 10 => {
+            let mtime = |x: &[u8]| u32::from_le_bytes([x[4], x[5], x[6], x[7]]);
+            let fields: [(&str, u32, u32); 4] = [
+                (
+                    "flags (name, comment, extra)",
+                    left_header[3].into(),
+                    right_header[3].into(),
+                ),
+                ("mtime", mtime(left_header), mtime(right_header)),
+                (
+                    "XFL (level hint)",
+                    left_header[8].into(),
+                    right_header[8].into(),
+                ),
+                ("OS", left_header[9].into(), right_header[9].into()),
+            ];
+            for (field, left, right) in fields {
+                if left != right {
+                    settings.push(format!("gzip header {} {} vs {}", field, left, right));
+                }
+            }
+        }
+        Compression::Zstd
+            if left_read > 4 && right_read > 4 && left_header[4] != right_header[4] =>
+        {
+            settings.push(format!(
+                "zstd frame header (content size, checksum, window) {:#010b} vs {:#010b}",
+                left_header[4], right_header[4]
+            ));
+        }
+        _ => {}
+    }
+    if settings.is_empty() {
+        return Ok("the same tar compressed with other settings (level or encoder)".to_string());
+    }
+    Ok(format!(
+        "the same tar with a different {}",
+        settings.join(", ")
+    ))
+}
+
+fn read_up_to(reader: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{normalize_image, write_layer, Ownership};
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::MemoryBlobStore;
+    use chrono::{TimeZone, Utc};
+    use std::io::Write;
+
+    fn entry(path: &str, data: &[u8], mtime: u64) -> (tar::Header, String, Vec<u8>) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(mtime);
+        (header, path.to_string(), data.to_vec())
+    }
+
+    fn tar(entries: Vec<(tar::Header, String, Vec<u8>)>) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (mut header, path, data) in entries {
+            builder
+                .append_data(&mut header, path, data.as_slice())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn image_with_layer(
+        store: &MemoryBlobStore,
+        layer: Descriptor,
+        diff_id: &Digest,
+        created: &str,
+    ) -> Digest {
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![diff_id.to_string()];
+        let mut config = serde_json::to_value(&config).unwrap();
+        config["created"] = Value::String(created.to_string());
+        put_image(store, &config, vec![layer]).digest
+    }
+
+    fn put_tar(store: &MemoryBlobStore, tar: &[u8], created: &str) -> Digest {
+        let layer = write_layer(store, tar, Compression::Gzip).unwrap();
+        image_with_layer(store, layer.descriptor(), &layer.diff_id, created)
+    }
+
+    #[test]
+    fn identical_images_have_no_differences() {
+        let store = MemoryBlobStore::new();
+        let image = put_tar(
+            &store,
+            &tar(vec![entry("a", b"a", 0)]),
+            "2020-01-01T00:00:00Z",
+        );
+        let explanation = explain_difference(&store, &image, &store, &image).unwrap();
+        assert!(explanation.is_identical());
+        assert!(explanation.differences.is_empty());
+    }
+
+    #[test]
+    fn ranks_the_causes() {
+        let store = MemoryBlobStore::new();
+        let left = put_tar(
+            &store,
+            &tar(vec![
+                entry("a", b"a", 1),
+                entry("b", b"b", 1),
+                entry("c", b"c", 1),
+            ]),
+            "2020-01-01T00:00:00Z",
+        );
+        let right = put_tar(
+            &store,
+            &tar(vec![
+                entry("b", b"b", 2),
+                entry("a", b"a", 2),
+                entry("d", b"d", 1),
+            ]),
+            "2020-01-01T00:00:00.5Z",
+        );
+
+        let explanation = explain_difference(&store, &left, &store, &right).unwrap();
+        assert_eq!(
+            explanation.causes(),
+            vec![
+                Cause::LayerContent,
+                Cause::EntryOrder,
+                Cause::EntryHeader,
+                Cause::Timestamp
+            ]
+        );
+        let details: Vec<String> = explanation
+            .differences
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        assert_eq!(
+            details,
+            vec![
+                "layer 0: `c` is only in the left image",
+                "layer 0: `d` is only in the right image",
+                "layer 0: first at entry 0: `a` vs `b`",
+                "layer 0: mtime differs for 2 entries, e.g. `a`: 1 vs 2",
+                "config.created: `2020-01-01T00:00:00Z` vs `2020-01-01T00:00:00.5Z` \
+                 (the same second with a different sub-second precision)",
+            ]
+        );
+        let text = explanation.to_string();
+        assert!(text.contains("1. files in the layers differ\n"));
+        assert!(text.contains("4. timestamps differ\n"));
+    }
+
+    #[test]
+    fn explains_compression_settings() {
+        let store = MemoryBlobStore::new();
+        let tar = tar(vec![entry("a", b"a", 0)]);
+        let layer = write_layer(&store, tar.as_slice(), Compression::Gzip).unwrap();
+        let left = image_with_layer(
+            &store,
+            layer.descriptor(),
+            &layer.diff_id,
+            "2020-01-01T00:00:00Z",
+        );
+
+        // as `gzip` on Unix would write it
+        let mut encoder = flate2::GzBuilder::new()
+            .mtime(1_600_000_000)
+            .operating_system(3)
+            .write(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&tar).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        let descriptor = Descriptor {
+            digest: store.put(&gzipped).unwrap(),
+            size: gzipped.len() as u64,
+            ..layer.descriptor()
+        };
+        let right = image_with_layer(&store, descriptor, &layer.diff_id, "2020-01-01T00:00:00Z");
+
+        let explanation = explain_difference(&store, &left, &store, &right).unwrap();
+        assert_eq!(explanation.causes(), vec![Cause::Compression]);
+        assert_eq!(
+            explanation.differences[0].detail,
+            "the same tar with a different gzip header mtime 0 vs 1600000000, \
+             gzip header XFL (level hint) 0 vs 2, gzip header OS 255 vs 3"
+        );
+    }
+
+    #[test]
+    fn explains_annotations_and_key_order() {
+        let store = MemoryBlobStore::new();
+        let image = put_tar(
+            &store,
+            &tar(vec![entry("a", b"a", 0)]),
+            "2020-01-01T00:00:00Z",
+        );
+        let mut manifest: Value = serde_json::from_slice(&store.get(&image).unwrap()).unwrap();
+        manifest["annotations"] = serde_json::json!({"org.example.build": "2"});
+        let annotated = store.put(&serde_json::to_vec(&manifest).unwrap()).unwrap();
+        let pretty = store
+            .put(&serde_json::to_vec_pretty(&manifest).unwrap())
+            .unwrap();
+
+        let explanation = explain_difference(&store, &image, &store, &annotated).unwrap();
+        assert_eq!(explanation.causes(), vec![Cause::Annotation]);
+        assert_eq!(
+            explanation.differences[0].to_string(),
+            "manifest.annotations[\"org.example.build\"]: missing vs `\"2\"`"
+        );
+
+        let explanation = explain_difference(&store, &annotated, &store, &pretty).unwrap();
+        assert_eq!(explanation.causes(), vec![Cause::Serialization]);
+    }
+
+    #[test]
+    fn normalized_images_only_differ_by_what_normalizing_kept() {
+        let store = MemoryBlobStore::new();
+        let epoch = Utc.timestamp_opt(0, 0).unwrap();
+        let left = put_tar(
+            &store,
+            &tar(vec![entry("a", b"a", 1)]),
+            "2020-01-01T00:00:00Z",
+        );
+        let right = put_tar(
+            &store,
+            &tar(vec![entry("a", b"a", 2)]),
+            "2021-01-01T00:00:00Z",
+        );
+        let left =
+            normalize_image(&store, &left, epoch, Ownership::Keep, Compression::Gzip).unwrap();
+        let right =
+            normalize_image(&store, &right, epoch, Ownership::Keep, Compression::Gzip).unwrap();
+
+        let explanation = explain_difference(&store, &left.digest, &store, &right.digest).unwrap();
+        assert!(explanation.is_identical());
+    }
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+use std::io::Read;
+use std::time::Duration;
+
+use crate::store::{BlobStore, StoreError};
+use crate::v1::{Descriptor, ParseError, VerifyingReader};
+
+/// The layer media type Docker uses for Windows base layers, which are only downloadable from
+/// their URLs.
+pub const MEDIA_TYPE_DOCKER_FOREIGN_LAYER: &str =
+    "application/vnd.docker.image.rootfs.foreign.diff.tar.gzip";
+
+/// Whether a layer is non-distributable: its content is usually left out of registries and
+/// layouts and downloaded from its `urls` instead.
+pub fn is_foreign_layer(descriptor: &Descriptor) -> bool {
+    descriptor
+        .media_type
+        .starts_with("application/vnd.oci.image.layer.nondistributable.")
+        || descriptor.media_type == MEDIA_TYPE_DOCKER_FOREIGN_LAYER
+}
+
+/// Downloads the content of foreign layers from one of their URLs.
+pub trait LayerFetcher: Send + Sync {
+    fn fetch(&self, url: &str) -> Result<Box<dyn Read + Send + '_>, StoreError>;
+}
+
+/// Fetches foreign layers over HTTP(S), optionally only from URLs with an allowed prefix.
+#[derive(Debug, Clone)]
+pub struct HttpFetcher {
+    agent: ureq::Agent,
+    allowed_prefixes: Vec<String>,
+}
+
+impl Default for HttpFetcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpFetcher {
+    pub fn new() -> Self {
+        HttpFetcher {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .redirects(5)
+                .build(),
+            allowed_prefixes: vec![],
+        }
+    }
+
+    /// Only fetches from URLs starting with one of `prefixes`, such as
+    /// `https://mcr.microsoft.com/`.
+    pub fn with_allowed_prefixes(mut self, prefixes: &[&str]) -> Self {
+        self.allowed_prefixes = prefixes.iter().map(|x| x.to_string()).collect();
+        self
+    }
+
+    fn allows(&self, url: &str) -> bool {
+        (url.starts_with("https://") || url.starts_with("http://"))
+            && (self.allowed_prefixes.is_empty()
+                || self.allowed_prefixes.iter().any(|x| url.starts_with(x)))
+    }
+}
+
+impl LayerFetcher for HttpFetcher {
+    fn fetch(&self, url: &str) -> Result<Box<dyn Read + Send + '_>, StoreError> {
+        if !self.allows(url) {
+            return Err(StoreError::Invalid(format!(
+                "fetching layers from `{}` isn't allowed",
+                url
+            )));
+        }
+        match self.agent.get(url).call() {
+            Ok(response) => Ok(Box::new(response.into_reader())),
+            Err(error) => Err(StoreError::Invalid(format!(
+                "failed to fetch `{}`: {}",
+                url, error
+            ))),
+        }
+    }
+}
+
+/// What copying or unpacking an image does with foreign layers.
+pub enum ForeignLayerPolicy {
+    /// Downloads them from their URLs with the fetcher, unless the content is already at hand.
+    Fetch(Box<dyn LayerFetcher>),
+    /// Leaves them out and reports them. Unpacking still uses content that's already in the
+    /// store, but copying never copies it.
+    Skip,
+    /// Fails on images that have any.
+    Reject,
+}
+
+impl fmt::Debug for ForeignLayerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ForeignLayerPolicy::Fetch(_) => write!(f, "Fetch(..)"),
+            ForeignLayerPolicy::Skip => write!(f, "Skip"),
+            ForeignLayerPolicy::Reject => write!(f, "Reject"),
+        }
+    }
+}
+
+pub(crate) fn rejected(layer: &Descriptor) -> StoreError {
+    StoreError::Invalid(format!(
+        "layer `{}` is non-distributable (`{}`)",
+        layer.digest, layer.media_type
+    ))
+}
+
+/// Downloads a foreign layer into the store from the first of its URLs that serves the right
+/// content.
+pub fn fetch_layer<S: BlobStore + ?Sized>(
+    store: &S,
+    fetcher: &dyn LayerFetcher,
+    layer: &Descriptor,
+) -> Result<(), StoreError> {
+    let urls = layer.urls.as_deref().unwrap_or_default();
+    let mut last_error = None;
+    for url in urls {
+        match fetch_from(store, fetcher, layer, url) {
+            Ok(()) => return Ok(()),
+            Err(error) => {
+                log::warn!("couldn't fetch layer `{}`: {}", layer.digest, error);
+                last_error = Some(error);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        StoreError::Invalid(format!("layer `{}` has no URLs to fetch", layer.digest))
+    }))
+}
+
+fn fetch_from<S: BlobStore + ?Sized>(
+    store: &S,
+    fetcher: &dyn LayerFetcher,
+    layer: &Descriptor,
+    url: &str,
+) -> Result<(), StoreError> {
+    let mut reader =
+        VerifyingReader::for_descriptor(fetcher.fetch(url)?, layer).map_err(ParseError::from)?;
+    let mut writer = store.writer()?;
+    std::io::copy(&mut reader, &mut writer)?;
+    writer.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::store::MemoryBlobStore;
+    use crate::v1::{Digest, MEDIA_TYPE_IMAGE_LAYER_GZIP, MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// Serves fixed content for URLs, remembering which ones were fetched.
+    #[derive(Default)]
+    pub(crate) struct StaticFetcher {
+        pub(crate) content: HashMap<String, Vec<u8>>,
+        pub(crate) fetched: Mutex<Vec<String>>,
+    }
+
+    impl LayerFetcher for StaticFetcher {
+        fn fetch(&self, url: &str) -> Result<Box<dyn Read + Send + '_>, StoreError> {
+            self.fetched.lock().unwrap().push(url.to_string());
+            match self.content.get(url) {
+                Some(data) => Ok(Box::new(data.as_slice())),
+                None => Err(StoreError::Invalid(format!("`{}` not found", url))),
+            }
+        }
+    }
+
+    pub(crate) fn foreign_layer(data: &[u8], urls: &[&str]) -> Descriptor {
+        Descriptor {
+            media_type: MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE.to_string(),
+            digest: Digest::sha256(data),
+            size: data.len() as u64,
+            urls: Some(urls.iter().map(|x| x.to_string()).collect()),
+            annotations: None,
+            platform: None,
+            artifact_type: None,
+        }
+    }
+
+    #[test]
+    fn recognizes_foreign_layers() {
+        let mut layer = foreign_layer(b"layer", &[]);
+        assert!(is_foreign_layer(&layer));
+        layer.media_type = MEDIA_TYPE_DOCKER_FOREIGN_LAYER.to_string();
+        assert!(is_foreign_layer(&layer));
+        layer.media_type = MEDIA_TYPE_IMAGE_LAYER_GZIP.to_string();
+        assert!(!is_foreign_layer(&layer));
+    }
+
+    #[test]
+    fn fetches_from_the_first_url_that_works() {
+        let store = MemoryBlobStore::new();
+        let layer = foreign_layer(b"layer", &["https://a/layer", "https://b/layer"]);
+        let mut fetcher = StaticFetcher::default();
+        fetcher
+            .content
+            .insert("https://b/layer".to_string(), b"layer".to_vec());
+
+        fetch_layer(&store, &fetcher, &layer).unwrap();
+        assert_eq!(store.get(&layer.digest).unwrap(), b"layer");
+        assert_eq!(
+            *fetcher.fetched.lock().unwrap(),
+            vec!["https://a/layer", "https://b/layer"]
+        );
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_content_that_doesnt_match() {
+            let store = MemoryBlobStore::new();
+            let layer = foreign_layer(b"layer", &["https://a/layer"]);
+            let mut fetcher = StaticFetcher::default();
+            fetcher
+                .content
+                .insert("https://a/layer".to_string(), b"other".to_vec());
+
+            assert!(fetch_layer(&store, &fetcher, &layer).is_err());
+            assert!(!store.contains(&layer.digest).unwrap());
+        }
+
+        #[test]
+        fn fails_without_urls() {
+            let store = MemoryBlobStore::new();
+            let layer = foreign_layer(b"layer", &[]);
+            assert!(matches!(
+                fetch_layer(&store, &StaticFetcher::default(), &layer),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+
+        #[test]
+        fn only_fetches_allowed_urls() {
+            let fetcher = HttpFetcher::new().with_allowed_prefixes(&["https://mcr.microsoft.com/"]);
+            assert!(fetcher.allows("https://mcr.microsoft.com/v2/windows/blobs/x"));
+            assert!(!fetcher.allows("https://example.com/layer"));
+            assert!(!HttpFetcher::new().allows("file:///etc/passwd"));
+            assert!(matches!(
+                HttpFetcher::new().fetch("file:///etc/passwd"),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Reading and writing the tar layers images are made of.
+
+pub(crate) mod compression;
+pub use compression::{
+    digest_layer, open_layer, write_layer, Compression, Encoder, Layer, LayerWriter,
+};
+
+mod explain;
+pub use explain::{explain_difference, Cause, Difference, Explanation};
+
+pub(crate) mod foreign;
+pub use foreign::{
+    fetch_layer, is_foreign_layer, ForeignLayerPolicy, HttpFetcher, LayerFetcher,
+    MEDIA_TYPE_DOCKER_FOREIGN_LAYER,
+};
+
+mod normalize;
+pub use normalize::{
+    normalize_image, normalize_tar, source_date_epoch, Ownership, SOURCE_DATE_EPOCH,
+};
+
+mod squash;
+pub use squash::{flatten_image, squash_image};
+
+mod transcode;
+pub use transcode::{transcode_image, verify_diff_ids};
+
+mod unpack;
+pub use unpack::{unpack_image, OPAQUE_WHITEOUT, WHITEOUT_PREFIX};
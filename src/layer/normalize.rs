@@ -0,0 +1,412 @@
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use serde::Serialize;
+
+use crate::layer::compression::Hashing;
+use crate::layer::transcode::{check_diff_id, check_layer_count};
+use crate::layer::unpack::entry_path;
+use crate::layer::{is_foreign_layer, open_layer, Compression, LayerWriter};
+use crate::store::{put_json, resolve_image_config, BlobStore, StoreError};
+use crate::v1::{Descriptor, Digest, ANNOTATION_CREATED, MEDIA_TYPE_IMAGE_MANIFEST};
+
+/// The environment variable reproducible builds take their timestamp from, as seconds since
+/// the Unix epoch.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+// extended attributes carry file capabilities and such, so they're the PAX records kept
+const XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// Who [`normalize_tar`] makes entries owned by. Owner names are dropped either way.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Ownership {
+    /// Entries keep their numeric owners, which are part of the image.
+    #[default]
+    Keep,
+    /// Every entry is owned by `uid` and `gid`, such as 0 and 0 for root.
+    Set { uid: u64, gid: u64 },
+}
+
+/// The time in `SOURCE_DATE_EPOCH`, or `None` if it isn't set or isn't a number of seconds.
+pub fn source_date_epoch() -> Option<DateTime<Utc>> {
+    let value = std::env::var(SOURCE_DATE_EPOCH).ok()?;
+    match value.trim().parse() {
+        Ok(seconds) => Utc.timestamp_opt(seconds, 0).single(),
+        Err(_) => {
+            log::warn!("ignoring `{}={}`", SOURCE_DATE_EPOCH, value);
+            None
+        }
+    }
+}
+
+/// Rewrites an image so that building the same content always gives the same digests, storing
+/// the new config and manifest and returning the manifest's descriptor.
+///
+/// Every layer goes through [`normalize_tar`] with `ownership` and is recompressed with
+/// `compression`, the `created` times of the config, its history and the manifest's
+/// annotation are set to `epoch`, and the config and manifest are stored with their keys
+/// sorted. Foreign layers and layers that aren't tars are left as they are.
+pub fn normalize_image<S: BlobStore + ?Sized>(
+    store: &S,
+    manifest_digest: &Digest,
+    epoch: DateTime<Utc>,
+    ownership: Ownership,
+    compression: Compression,
+) -> Result<Descriptor, StoreError> {
+    let (mut manifest, mut config) = resolve_image_config(store, manifest_digest)?;
+    check_layer_count(manifest_digest, &manifest.layers, &config.rootfs.diff_ids)?;
+
+    let diff_ids = config.rootfs.diff_ids.iter_mut();
+    for (layer, diff_id) in manifest.layers.iter_mut().zip(diff_ids) {
+        if is_foreign_layer(layer) || Compression::from_media_type(&layer.media_type).is_none() {
+            continue;
+        }
+        let mut tar = Hashing::new(open_layer(store, layer)?);
+        let writer = LayerWriter::new(store, compression)?;
+        let writer = normalize_tar(&mut tar, writer, epoch, ownership)?;
+        io::copy(&mut tar, &mut io::sink())?;
+        check_diff_id(&layer.digest, &tar.digester.finalize(), diff_id)?;
+
+        let written = writer.finish()?;
+        *diff_id = written.diff_id.to_string();
+        *layer = Descriptor {
+            media_type: compression.media_type().to_string(),
+            digest: written.digest,
+            size: written.size,
+            ..layer.clone()
+        };
+    }
+
+    config.created = Some(epoch);
+    for entry in config.history.iter_mut().flatten() {
+        entry.created = Some(epoch);
+    }
+    if let Some(created) = manifest
+        .annotations
+        .as_mut()
+        .and_then(|x| x.get_mut(ANNOTATION_CREATED))
+    {
+        *created = epoch.to_rfc3339_opts(SecondsFormat::Secs, true);
+    }
+    manifest.config = put_sorted_json(store, &manifest.config.media_type, &config)?;
+    put_sorted_json(store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)
+}
+
+// labels and annotations are hash maps, which serialize in a different order every run
+fn put_sorted_json<S: BlobStore + ?Sized, T: Serialize>(
+    store: &S,
+    media_type: &str,
+    value: &T,
+) -> Result<Descriptor, StoreError> {
+    put_json(store, media_type, &serde_json::to_value(value)?)
+}
+
+struct Entry {
+    path: PathBuf,
+    header: tar::Header,
+    link: Option<PathBuf>,
+    xattrs: Vec<(String, Vec<u8>)>,
+    data: Vec<u8>,
+}
+
+/// Copies a tar stream to `out` in a form that only depends on the files in it: entries are
+/// sorted by path, with hard links last so that their targets come first; every mtime is
+/// `epoch`; owner names and PAX records other than extended attributes are dropped; numeric
+/// owners are set according to `ownership`; and paths lose any leading `./`. Modes are kept, as
+/// they're part of the image.
+///
+/// The whole layer is held in memory while it's sorted.
+pub fn normalize_tar<R: Read, W: Write>(
+    tar: R,
+    out: W,
+    epoch: DateTime<Utc>,
+    ownership: Ownership,
+) -> Result<W, StoreError> {
+    let mtime = epoch.timestamp().max(0) as u64;
+    let mut entries = vec![];
+    let mut archive = tar::Archive::new(tar);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let kind = entry.header().entry_type();
+        if kind == tar::EntryType::XGlobalHeader {
+            continue;
+        }
+        let path = entry_path(&entry.path()?)?;
+        // the root directory itself
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+
+        let mut xattrs = vec![];
+        // owners too large for the header are only in PAX records
+        let (mut uid, mut gid) = (entry.header().uid()?, entry.header().gid()?);
+        if let Some(extensions) = entry.pax_extensions()? {
+            for extension in extensions {
+                let extension = extension?;
+                match extension.key() {
+                    Ok(key) if key.starts_with(XATTR_PREFIX) => {
+                        xattrs.push((key.to_string(), extension.value_bytes().to_vec()))
+                    }
+                    Ok("uid") => uid = pax_id(extension.value())?,
+                    Ok("gid") => gid = pax_id(extension.value())?,
+                    _ => {}
+                }
+            }
+        }
+        xattrs.sort();
+        if let Ownership::Set { uid: u, gid: g } = ownership {
+            uid = u;
+            gid = g;
+        }
+
+        let link = match entry.link_name()? {
+            Some(target) if kind.is_hard_link() => Some(entry_path(&target)?),
+            Some(target) => Some(target.into_owned()),
+            None => None,
+        };
+
+        let original = entry.header();
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_mode(original.mode()? & 0o7777);
+        header.set_uid(uid);
+        header.set_gid(gid);
+        header.set_mtime(mtime);
+        if kind.is_character_special() || kind.is_block_special() {
+            header.set_device_major(original.device_major()?.unwrap_or(0))?;
+            header.set_device_minor(original.device_minor()?.unwrap_or(0))?;
+        }
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+        header.set_size(data.len() as u64);
+
+        entries.push(Entry {
+            path,
+            header,
+            link,
+            xattrs,
+            data,
+        });
+    }
+    // stable, so of two entries for the same path the last one still wins
+    entries.sort_by(|a, b| {
+        let a_key = (a.header.entry_type().is_hard_link(), &a.path);
+        a_key.cmp(&(b.header.entry_type().is_hard_link(), &b.path))
+    });
+
+    let mut builder = tar::Builder::new(out);
+    for mut entry in entries {
+        builder.append_pax_extensions(
+            entry
+                .xattrs
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice())),
+        )?;
+        match &entry.link {
+            Some(target) => builder.append_link(&mut entry.header, &entry.path, target)?,
+            None => builder.append_data(&mut entry.header, &entry.path, entry.data.as_slice())?,
+        }
+    }
+    Ok(builder.into_inner()?)
+}
+
+fn pax_id(value: Result<&str, std::str::Utf8Error>) -> io::Result<u64> {
+    value
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid PAX uid or gid"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::verify_diff_ids;
+    use crate::layer::write_layer;
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::{read_manifest, MemoryBlobStore};
+    use crate::v1::History;
+    use std::collections::HashMap;
+
+    // a tar of `files` as a particular build might have produced it
+    fn built_tar(files: &[(&str, &[u8])], mtime: u64, owner: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, data) in files {
+            builder
+                .append_pax_extensions([
+                    ("mtime", format!("{}.5", mtime).as_bytes()),
+                    ("SCHILY.xattr.user.origin", b"source"),
+                ])
+                .unwrap();
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o100644);
+            header.set_mtime(mtime);
+            header.set_uid(1000);
+            header.set_gid(1000);
+            header.set_username(owner).unwrap();
+            builder
+                .append_data(&mut header, format!("./{}", path), *data)
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn built_image(store: &MemoryBlobStore, tar: &[u8], created: DateTime<Utc>) -> Digest {
+        let layer = write_layer(store, tar, Compression::Gzip).unwrap();
+        let mut config = image_config();
+        config.created = Some(created);
+        config.rootfs.diff_ids = vec![layer.diff_id.to_string()];
+        config.history = Some(vec![History {
+            created: Some(created),
+            author: None,
+            created_by: Some("COPY . .".to_string()),
+            comment: None,
+            empty_layer: None,
+        }]);
+        let image = put_image(store, &config, vec![layer.descriptor()]);
+        let mut manifest = read_manifest(store, &image.digest).unwrap();
+        let annotations = manifest.annotations.get_or_insert_with(HashMap::new);
+        for i in 0..8 {
+            annotations.insert(format!("com.example.{}", i), i.to_string());
+        }
+        annotations.insert(ANNOTATION_CREATED.to_string(), created.to_rfc3339());
+        put_json(store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)
+            .unwrap()
+            .digest
+    }
+
+    #[test]
+    fn gives_two_builds_the_same_digests() {
+        let store = MemoryBlobStore::new();
+        let epoch = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let first = built_image(
+            &store,
+            &built_tar(&[("a.txt", b"a"), ("b.txt", b"b")], 1_700_000_000, "alice"),
+            Utc.timestamp_opt(1_700_000_000, 0).unwrap(),
+        );
+        let second = built_image(
+            &store,
+            &built_tar(&[("b.txt", b"b"), ("a.txt", b"a")], 1_700_000_100, "bob"),
+            Utc.timestamp_opt(1_700_000_100, 0).unwrap(),
+        );
+        assert_ne!(first, second);
+
+        let first =
+            normalize_image(&store, &first, epoch, Ownership::Keep, Compression::Zstd).unwrap();
+        let second =
+            normalize_image(&store, &second, epoch, Ownership::Keep, Compression::Zstd).unwrap();
+        assert_eq!(first, second);
+        verify_diff_ids(&store, &first.digest).unwrap();
+        // normalizing is idempotent
+        assert_eq!(
+            normalize_image(
+                &store,
+                &first.digest,
+                epoch,
+                Ownership::Keep,
+                Compression::Zstd
+            )
+            .unwrap(),
+            first
+        );
+
+        let (manifest, config) = resolve_image_config(&store, &first.digest).unwrap();
+        assert_eq!(config.created, Some(epoch));
+        assert_eq!(config.history.unwrap()[0].created, Some(epoch));
+        assert_eq!(
+            manifest.annotations.unwrap()[ANNOTATION_CREATED],
+            "2020-09-13T12:26:40Z"
+        );
+    }
+
+    #[test]
+    fn normalizes_tar_headers() {
+        let epoch = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let tar = built_tar(&[("b.txt", b"b"), ("a.txt", b"a")], 1_700_000_000, "alice");
+        let normalized = normalize_tar(tar.as_slice(), Vec::new(), epoch, Ownership::Keep).unwrap();
+
+        let mut archive = tar::Archive::new(normalized.as_slice());
+        let mut paths = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!(header.mtime().unwrap(), 1_600_000_000);
+            assert_eq!(header.username().unwrap(), Some(""));
+            assert_eq!(header.mode().unwrap(), 0o644);
+            let pax: Vec<(String, Vec<u8>)> = entry
+                .pax_extensions()
+                .unwrap()
+                .unwrap()
+                .map(|x| x.unwrap())
+                .map(|x| (x.key().unwrap().to_string(), x.value_bytes().to_vec()))
+                .collect();
+            assert_eq!(
+                pax,
+                vec![("SCHILY.xattr.user.origin".to_string(), b"source".to_vec())]
+            );
+            paths.push(entry.path().unwrap().to_string_lossy().to_string());
+        }
+        assert_eq!(paths, vec!["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn rewrites_owners() {
+        let mut builder = tar::Builder::new(Vec::new());
+        // too large for the header, so only in PAX records
+        builder
+            .append_pax_extensions([("uid", &b"3000000"[..]), ("gid", &b"3000001"[..])])
+            .unwrap();
+        let mut header = tar::Header::new_ustar();
+        header.set_size(1);
+        header.set_mode(0o644);
+        header.set_username("build").unwrap();
+        builder
+            .append_data(&mut header, "a.txt", &b"a"[..])
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+        let owners = |ownership| {
+            let epoch = Utc.timestamp_opt(0, 0).unwrap();
+            let normalized = normalize_tar(tar.as_slice(), Vec::new(), epoch, ownership).unwrap();
+            let mut archive = tar::Archive::new(normalized.as_slice());
+            let entry = archive.entries().unwrap().next().unwrap().unwrap();
+            let header = entry.header();
+            assert_eq!(header.username().unwrap(), Some(""));
+            (header.uid().unwrap(), header.gid().unwrap())
+        };
+
+        assert_eq!(owners(Ownership::Keep), (3_000_000, 3_000_001));
+        assert_eq!(owners(Ownership::Set { uid: 0, gid: 0 }), (0, 0));
+    }
+
+    #[test]
+    fn puts_hard_links_after_their_targets() {
+        let header = |kind: tar::EntryType, size: u64| {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(size);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header
+        };
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut link = header(tar::EntryType::Link, 0);
+        builder.append_link(&mut link, "a-link", "z.txt").unwrap();
+        let mut header = header(tar::EntryType::Regular, 1);
+        builder
+            .append_data(&mut header, "z.txt", &b"z"[..])
+            .unwrap();
+        let tar = builder.into_inner().unwrap();
+
+        let epoch = Utc.timestamp_opt(0, 0).unwrap();
+        let normalized = normalize_tar(tar.as_slice(), Vec::new(), epoch, Ownership::Keep).unwrap();
+        let mut archive = tar::Archive::new(normalized.as_slice());
+        let paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|x| x.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(paths, vec!["z.txt", "a-link"]);
+    }
+}
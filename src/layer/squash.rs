@@ -0,0 +1,492 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::iter;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::layer::compression::Hashing;
+use crate::layer::foreign::rejected;
+use crate::layer::transcode::{check_diff_id, check_layer_count};
+use crate::layer::unpack::{entry_path, whited_out};
+use crate::layer::{
+    is_foreign_layer, open_layer, Compression, Layer, LayerWriter, OPAQUE_WHITEOUT, WHITEOUT_PREFIX,
+};
+use crate::store::{put_json, read_manifest, resolve_image_config, BlobStore, StoreError};
+use crate::v1::{Descriptor, Digest, History, MEDIA_TYPE_IMAGE_MANIFEST};
+
+/// Merges the layers in `layers`, indexes into the image's layers, into a single layer with
+/// `compression`, storing the new config and manifest and returning the manifest's
+/// descriptor.
+///
+/// Whiteouts are applied while merging. When the range starts above the first layer, the
+/// merged layer keeps the whiteouts that hide files of the layers below it. The history
+/// entries of the merged layers collapse into one, and `empty_layer` entries among them are
+/// kept ahead of it so that the history still lines up with the layers.
+pub fn squash_image<S: BlobStore + ?Sized>(
+    store: &S,
+    manifest_digest: &Digest,
+    layers: Range<usize>,
+    compression: Compression,
+) -> Result<Descriptor, StoreError> {
+    let (mut manifest, mut config) = resolve_image_config(store, manifest_digest)?;
+    // the history has to line up with the layers for its entries to be merged
+    config.validate()?;
+    check_layer_count(manifest_digest, &manifest.layers, &config.rootfs.diff_ids)?;
+    if layers.is_empty() || layers.end > manifest.layers.len() {
+        return Err(StoreError::Invalid(format!(
+            "can't squash layers {}..{} of an image with {}",
+            layers.start,
+            layers.end,
+            manifest.layers.len()
+        )));
+    }
+    if let Some(layer) = manifest.layers[layers.clone()]
+        .iter()
+        .find(|x| is_foreign_layer(x))
+    {
+        return Err(rejected(layer));
+    }
+
+    let squashed = merge_layers(
+        store,
+        &manifest.layers[layers.clone()],
+        &config.rootfs.diff_ids[layers.clone()],
+        layers.start == 0,
+        compression,
+    )?;
+    // an empty history is as good as none
+    if let Some(history) = config.history.as_mut().filter(|x| !x.is_empty()) {
+        squash_history(history, &layers, manifest.layers.len())?;
+    }
+    config
+        .rootfs
+        .diff_ids
+        .splice(layers.clone(), iter::once(squashed.diff_id.to_string()));
+    manifest
+        .layers
+        .splice(layers, iter::once(squashed.descriptor()));
+    manifest.config = put_json(store, &manifest.config.media_type, &config)?;
+    put_json(store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)
+}
+
+/// Squashes every layer of an image into one.
+pub fn flatten_image<S: BlobStore + ?Sized>(
+    store: &S,
+    manifest_digest: &Digest,
+    compression: Compression,
+) -> Result<Descriptor, StoreError> {
+    let count = read_manifest(store, manifest_digest)?.layers.len();
+    squash_image(store, manifest_digest, 0..count, compression)
+}
+
+// what ends up at a path of the merged layer
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Node {
+    // the `index`th entry of the `layer`th layer being merged
+    Entry { layer: usize, index: usize },
+    Whiteout,
+}
+
+#[derive(Default)]
+struct Merge {
+    nodes: BTreeMap<PathBuf, Node>,
+    // directories whose contents in lower layers are hidden
+    opaque: BTreeSet<PathBuf>,
+}
+
+impl Merge {
+    // drops what's below `dir`, other than what `keep` holds on to
+    fn clear_below(&mut self, dir: &Path, keep: impl Fn(&Node) -> bool) {
+        // paths order by component, so everything below `dir` comes right after it
+        let below: Vec<PathBuf> = self
+            .nodes
+            .range(dir.to_path_buf()..)
+            .skip_while(|(path, _)| *path == dir)
+            .take_while(|(path, _)| path.starts_with(dir))
+            .filter(|(_, node)| !keep(node))
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in below {
+            self.nodes.remove(&path);
+        }
+        self.opaque.retain(|x| x == dir || !x.starts_with(dir));
+    }
+
+    fn add(&mut self, layer: usize, index: usize, path: PathBuf, is_dir: bool) {
+        if !is_dir {
+            // a file replaces whatever directory was there
+            self.clear_below(&path, |_| false);
+            self.opaque.remove(&path);
+        } else if self.nodes.get(&path) == Some(&Node::Whiteout) {
+            // a directory that was deleted and made again doesn't have the old contents
+            self.opaque.insert(path.clone());
+        }
+        self.nodes.insert(path, Node::Entry { layer, index });
+    }
+
+    fn white_out(&mut self, path: PathBuf) {
+        self.clear_below(&path, |_| false);
+        self.opaque.remove(&path);
+        self.nodes.insert(path, Node::Whiteout);
+    }
+
+    fn make_opaque(&mut self, layer: usize, dir: PathBuf) {
+        // it only hides what lower layers put there
+        self.clear_below(
+            &dir,
+            |node| matches!(node, Node::Entry { layer: x, .. } if *x == layer),
+        );
+        self.opaque.insert(dir);
+    }
+}
+
+fn merge_layers<S: BlobStore + ?Sized>(
+    store: &S,
+    layers: &[Descriptor],
+    diff_ids: &[String],
+    from_base: bool,
+    compression: Compression,
+) -> Result<Layer, StoreError> {
+    let mut merge = Merge::default();
+    for (layer, (descriptor, diff_id)) in layers.iter().zip(diff_ids).enumerate() {
+        let mut tar = Hashing::new(open_layer(store, descriptor)?);
+        let mut archive = tar::Archive::new(&mut tar);
+        for (index, entry) in archive.entries()?.enumerate() {
+            let entry = entry?;
+            let path = entry_path(&entry.path()?)?;
+            let name = match path.file_name() {
+                Some(name) => name,
+                // the root of the layer
+                None => continue,
+            };
+            let parent = path.parent().unwrap_or_else(|| Path::new(""));
+            if name == OsStr::new(OPAQUE_WHITEOUT) {
+                merge.make_opaque(layer, parent.to_path_buf());
+            } else if let Some(hidden) = whited_out(name) {
+                merge.white_out(parent.join(hidden));
+            } else {
+                merge.add(layer, index, path, entry.header().entry_type().is_dir());
+            }
+        }
+        io::copy(&mut tar, &mut io::sink())?;
+        check_diff_id(&descriptor.digest, &tar.digester.finalize(), diff_id)?;
+    }
+    // nothing is left to hide beneath the first layer
+    if from_base {
+        merge.nodes.retain(|_, node| *node != Node::Whiteout);
+        merge.opaque.clear();
+    }
+
+    let mut builder = tar::Builder::new(LayerWriter::new(store, compression)?);
+    for dir in &merge.opaque {
+        append_marker(&mut builder, &dir.join(OPAQUE_WHITEOUT))?;
+    }
+    for (path, node) in &merge.nodes {
+        if let (Node::Whiteout, Some(name)) = (node, path.file_name()) {
+            let mut marker = OsString::from(WHITEOUT_PREFIX);
+            marker.push(name);
+            append_marker(&mut builder, &path.with_file_name(marker))?;
+        }
+    }
+    for (layer, descriptor) in layers.iter().enumerate() {
+        let mut archive = tar::Archive::new(open_layer(store, descriptor)?);
+        for (index, entry) in archive.entries()?.enumerate() {
+            let mut entry = entry?;
+            let path = entry_path(&entry.path()?)?;
+            if merge.nodes.get(&path) != Some(&Node::Entry { layer, index }) {
+                continue;
+            }
+            let mut header = entry.header().clone();
+            match entry.link_name()?.map(|x| x.into_owned()) {
+                Some(target) => builder.append_link(&mut header, &path, target)?,
+                None => builder.append_data(&mut header, &path, &mut entry)?,
+            }
+        }
+    }
+    builder.into_inner()?.finish()
+}
+
+fn append_marker<W: io::Write>(builder: &mut tar::Builder<W>, path: &Path) -> io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(0);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, path, io::empty())
+}
+
+fn squash_history(
+    history: &mut Vec<History>,
+    layers: &Range<usize>,
+    layer_count: usize,
+) -> Result<(), StoreError> {
+    // where the entry of each layer is; validating the config already checked they line up,
+    // but the indexes below mustn't go out of bounds
+    let positions: Vec<usize> = history
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.empty_layer != Some(true))
+        .map(|(i, _)| i)
+        .collect();
+    if positions.len() != layer_count {
+        return Err(StoreError::Invalid(format!(
+            "the history has entries for {} layers but the image has {}",
+            positions.len(),
+            layer_count
+        )));
+    }
+
+    let first = positions[layers.start];
+    let last = positions[layers.end - 1];
+    let merged: Vec<History> = history.drain(first..=last).collect();
+    let (empty, squashed): (Vec<History>, Vec<History>) = merged
+        .into_iter()
+        .partition(|x| x.empty_layer == Some(true));
+    let steps: Vec<&str> = squashed
+        .iter()
+        .filter_map(|x| x.created_by.as_deref())
+        .collect();
+    let mut comment = format!("squashed {} layers", squashed.len());
+    if !steps.is_empty() {
+        comment = format!("{}: {}", comment, steps.join("; "));
+    }
+    let entry = History {
+        created: squashed.last().and_then(|x| x.created),
+        author: None,
+        created_by: None,
+        comment: Some(comment),
+        empty_layer: None,
+    };
+    history.splice(first..first, empty.into_iter().chain(iter::once(entry)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::compression::tests::{tar_of, tar_of_raw};
+    use crate::layer::{unpack_image, verify_diff_ids, write_layer, ForeignLayerPolicy};
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::MemoryBlobStore;
+    use crate::v1::MEDIA_TYPE_IMAGE_CONFIG;
+    use std::io::Read;
+
+    fn step(created_by: &str, empty_layer: bool) -> History {
+        History {
+            created: None,
+            author: None,
+            created_by: Some(created_by.to_string()),
+            comment: None,
+            empty_layer: if empty_layer { Some(true) } else { None },
+        }
+    }
+
+    fn image_with_layers(store: &MemoryBlobStore, layers: &[Vec<u8>]) -> Digest {
+        let mut config = image_config();
+        let mut history = vec![];
+        let mut descriptors = vec![];
+        for (i, tar) in layers.iter().enumerate() {
+            let layer = write_layer(store, tar.as_slice(), Compression::Gzip).unwrap();
+            config.rootfs.diff_ids.push(layer.diff_id.to_string());
+            descriptors.push(layer.descriptor());
+            history.push(step(&format!("RUN step {}", i), false));
+            history.push(step(&format!("ENV STEP={}", i), true));
+        }
+        config.history = Some(history);
+        put_image(store, &config, descriptors).digest
+    }
+
+    fn layers() -> Vec<Vec<u8>> {
+        vec![
+            tar_of(&[("a.txt", b"a"), ("dir/b.txt", b"b"), ("dir/c.txt", b"c")]),
+            tar_of(&[
+                (".wh.a.txt", b""),
+                ("dir/.wh..wh..opq", b""),
+                ("dir/d.txt", b"d"),
+                ("e.txt", b"e"),
+            ]),
+            tar_of(&[("e.txt", b"e2"), (".wh.dir", b"")]),
+        ]
+    }
+
+    fn entries(store: &MemoryBlobStore, layer: &Descriptor) -> Vec<String> {
+        let mut archive = tar::Archive::new(open_layer(store, layer).unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|x| x.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn unpacked(store: &MemoryBlobStore, image: &Digest) -> BTreeMap<String, String> {
+        let dir = tempfile::tempdir().unwrap();
+        unpack_image(store, image, dir.path(), &ForeignLayerPolicy::Reject).unwrap();
+        let mut files = BTreeMap::new();
+        let mut pending = vec![dir.path().to_path_buf()];
+        while let Some(path) = pending.pop() {
+            for entry in std::fs::read_dir(&path).unwrap() {
+                let path = entry.unwrap().path();
+                let name = path.strip_prefix(dir.path()).unwrap();
+                let name = name.to_string_lossy().to_string();
+                if path.is_dir() {
+                    files.insert(name, String::new());
+                    pending.push(path);
+                } else {
+                    let mut content = String::new();
+                    std::fs::File::open(&path)
+                        .unwrap()
+                        .read_to_string(&mut content)
+                        .unwrap();
+                    files.insert(name, content);
+                }
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn flattens_images() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(&store, &layers());
+
+        let flattened = flatten_image(&store, &image, Compression::Zstd).unwrap();
+        let (manifest, config) = resolve_image_config(&store, &flattened.digest).unwrap();
+        assert_eq!(manifest.layers.len(), 1);
+        assert_eq!(entries(&store, &manifest.layers[0]), vec!["e.txt"]);
+        verify_diff_ids(&store, &flattened.digest).unwrap();
+        assert_eq!(
+            unpacked(&store, &flattened.digest),
+            unpacked(&store, &image)
+        );
+
+        let history = config.history.unwrap();
+        let created_by: Vec<Option<&str>> =
+            history.iter().map(|x| x.created_by.as_deref()).collect();
+        assert_eq!(
+            created_by,
+            vec![
+                Some("ENV STEP=0"),
+                Some("ENV STEP=1"),
+                None,
+                Some("ENV STEP=2")
+            ]
+        );
+        assert_eq!(
+            history[2].comment.as_deref(),
+            Some("squashed 3 layers: RUN step 0; RUN step 1; RUN step 2")
+        );
+    }
+
+    #[test]
+    fn keeps_whiteouts_of_lower_layers() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(&store, &layers());
+
+        let squashed = squash_image(&store, &image, 1..3, Compression::Gzip).unwrap();
+        let (manifest, config) = resolve_image_config(&store, &squashed.digest).unwrap();
+        assert_eq!(manifest.layers.len(), 2);
+        assert_eq!(config.rootfs.diff_ids.len(), 2);
+        assert_eq!(
+            entries(&store, &manifest.layers[1]),
+            vec![".wh.a.txt", ".wh.dir", "e.txt"]
+        );
+        assert_eq!(unpacked(&store, &squashed.digest), unpacked(&store, &image));
+
+        let history = config.history.unwrap();
+        assert_eq!(
+            history.iter().filter(|x| x.empty_layer.is_none()).count(),
+            2
+        );
+        assert_eq!(history.len(), 5);
+    }
+
+    #[test]
+    fn keeps_what_an_opaque_directory_adds() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(
+            &store,
+            &[
+                tar_of(&[("base.txt", b"base")]),
+                tar_of(&[("dir/old.txt", b"old")]),
+                tar_of(&[("dir/new.txt", b"new"), ("dir/.wh..wh..opq", b"")]),
+            ],
+        );
+
+        let squashed = squash_image(&store, &image, 1..3, Compression::Gzip).unwrap();
+        let manifest = read_manifest(&store, &squashed.digest).unwrap();
+        assert_eq!(
+            entries(&store, &manifest.layers[1]),
+            vec!["dir/.wh..wh..opq", "dir/new.txt"]
+        );
+        assert_eq!(unpacked(&store, &squashed.digest), unpacked(&store, &image));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_names_that_arent_utf8() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(
+            &store,
+            &[
+                tar_of(&[("base.txt", b"base")]),
+                tar_of_raw(&[(b"caf\xe9", b"latin-1"), (b"na\xefve", b"latin-1")]),
+                tar_of_raw(&[(b".wh.caf\xe9", b"")]),
+            ],
+        );
+
+        let squashed = squash_image(&store, &image, 1..3, Compression::Gzip).unwrap();
+        let manifest = read_manifest(&store, &squashed.digest).unwrap();
+        let mut archive = tar::Archive::new(open_layer(&store, &manifest.layers[1]).unwrap());
+        let names: Vec<Vec<u8>> = archive
+            .entries()
+            .unwrap()
+            .map(|x| x.unwrap().path_bytes().into_owned())
+            .collect();
+        assert_eq!(names, vec![b".wh.caf\xe9".to_vec(), b"na\xefve".to_vec()]);
+    }
+
+    #[test]
+    fn squashes_images_with_an_empty_history() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(&store, &layers());
+        let (mut manifest, mut config) = resolve_image_config(&store, &image).unwrap();
+        config.history = Some(vec![]);
+        manifest.config = put_json(&store, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap();
+        let image = put_json(&store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+
+        let flattened = flatten_image(&store, &image.digest, Compression::Gzip).unwrap();
+        let (manifest, config) = resolve_image_config(&store, &flattened.digest).unwrap();
+        assert_eq!(manifest.layers.len(), 1);
+        assert_eq!(config.history, Some(vec![]));
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_bad_ranges() {
+            let store = MemoryBlobStore::new();
+            let image = image_with_layers(&store, &layers());
+            for range in [0..0, 2..4] {
+                assert!(matches!(
+                    squash_image(&store, &image, range, Compression::Gzip),
+                    Err(StoreError::Invalid(_))
+                ));
+            }
+        }
+
+        #[test]
+        fn rejects_history_that_doesnt_match_the_layers() {
+            let store = MemoryBlobStore::new();
+            let image = image_with_layers(&store, &layers());
+            let (mut manifest, mut config) = resolve_image_config(&store, &image).unwrap();
+            config.history.as_mut().unwrap().remove(0);
+            manifest.config = put_json(&store, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap();
+            let image = put_json(&store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+
+            assert!(matches!(
+                flatten_image(&store, &image.digest, Compression::Gzip),
+                Err(StoreError::Parse(_))
+            ));
+        }
+    }
+}
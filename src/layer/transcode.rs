@@ -0,0 +1,363 @@
+use crate::layer::{
+    digest_layer, is_foreign_layer, open_layer, write_layer, Compression,
+    MEDIA_TYPE_DOCKER_FOREIGN_LAYER,
+};
+use crate::store::{put_json, read_index, resolve_image_config, BlobStore, StoreError};
+use crate::v1::{
+    is_image_index, is_image_manifest, Descriptor, Digest, MEDIA_TYPE_DOCKER_CONFIG,
+    MEDIA_TYPE_DOCKER_MANIFEST, MEDIA_TYPE_DOCKER_MANIFEST_LIST, MEDIA_TYPE_IMAGE_CONFIG,
+    MEDIA_TYPE_IMAGE_INDEX, MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE_GZIP,
+    MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+/// Recompresses every layer of an image with `compression`, storing the rewritten manifest and
+/// returning its descriptor. For an index, every image in it is transcoded and a new index is
+/// stored; platforms and annotations carry over.
+///
+/// `rootfs.diff_ids` hash the uncompressed layers, so the `ImageConfig` is left as it is and
+/// every new layer is checked against it once written. Layers that aren't tars and
+/// non-distributable layers are kept unchanged, and images already compressed that way come
+/// back with their original descriptor. Referrers still point at the original manifests.
+///
+/// Docker manifests and manifest lists can't reference zstd layers, so they're always
+/// converted to their OCI counterparts. Anything that isn't an image manifest or an index is
+/// rejected with [`StoreError::Invalid`].
+pub fn transcode_image<S: BlobStore + ?Sized>(
+    store: &S,
+    descriptor: &Descriptor,
+    compression: Compression,
+) -> Result<Descriptor, StoreError> {
+    let rewritten = if is_image_manifest(&descriptor.media_type) {
+        transcode_manifest(store, &descriptor.digest, compression)?
+    } else if is_image_index(&descriptor.media_type) {
+        transcode_index(store, &descriptor.digest, compression)?
+    } else {
+        return Err(StoreError::Invalid(format!(
+            "`{}` is a `{}` rather than an image manifest or index",
+            descriptor.digest, descriptor.media_type
+        )));
+    };
+    Ok(match rewritten {
+        Some(new) => Descriptor {
+            media_type: new.media_type,
+            digest: new.digest,
+            size: new.size,
+            ..descriptor.clone()
+        },
+        None => descriptor.clone(),
+    })
+}
+
+/// Checks that every layer of an image manifest decompresses to the diff ID its
+/// `ImageConfig` lists for it. Foreign layers that aren't in the store are passed over.
+pub fn verify_diff_ids<S: BlobStore + ?Sized>(
+    store: &S,
+    manifest_digest: &Digest,
+) -> Result<(), StoreError> {
+    let (manifest, config) = resolve_image_config(store, manifest_digest)?;
+    let diff_ids = &config.rootfs.diff_ids;
+    check_layer_count(manifest_digest, &manifest.layers, diff_ids)?;
+    for (layer, expected) in manifest.layers.iter().zip(diff_ids) {
+        if is_foreign_layer(layer) && !store.contains(&layer.digest)? {
+            continue;
+        }
+        let actual = digest_layer(store.reader(&layer.digest)?, Some(&layer.media_type))?;
+        check_diff_id(&layer.digest, &actual.diff_id, expected)?;
+    }
+    Ok(())
+}
+
+pub(super) fn check_layer_count(
+    manifest: &Digest,
+    layers: &[Descriptor],
+    diff_ids: &[String],
+) -> Result<(), StoreError> {
+    if layers.len() != diff_ids.len() {
+        return Err(StoreError::Invalid(format!(
+            "manifest `{}` has {} layers but its config lists {} diff IDs",
+            manifest,
+            layers.len(),
+            diff_ids.len()
+        )));
+    }
+    Ok(())
+}
+
+pub(super) fn check_diff_id(
+    layer: &Digest,
+    actual: &Digest,
+    expected: &str,
+) -> Result<(), StoreError> {
+    // diff_ids are plain strings in `ImageConfig`
+    if actual.to_string() != expected {
+        return Err(StoreError::Invalid(format!(
+            "layer `{}` decompresses to `{}` but its diff ID is `{}`",
+            layer, actual, expected
+        )));
+    }
+    Ok(())
+}
+
+fn is_transcodable(layer: &Descriptor) -> bool {
+    // their URLs point at the blob as it is
+    !is_foreign_layer(layer) && Compression::from_media_type(&layer.media_type).is_some()
+}
+
+// the digest and size of the rewritten manifest, or `None` if nothing changed
+fn transcode_manifest<S: BlobStore + ?Sized>(
+    store: &S,
+    digest: &Digest,
+    compression: Compression,
+) -> Result<Option<Descriptor>, StoreError> {
+    let (mut manifest, config) = resolve_image_config(store, digest)?;
+    let diff_ids = &config.rootfs.diff_ids;
+    check_layer_count(digest, &manifest.layers, diff_ids)?;
+
+    let mut changed = manifest.media_type.as_deref() == Some(MEDIA_TYPE_DOCKER_MANIFEST);
+    if changed {
+        to_oci(&mut manifest.config, &mut manifest.layers);
+        manifest.media_type = Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string());
+    }
+    for (layer, diff_id) in manifest.layers.iter_mut().zip(diff_ids) {
+        if !is_transcodable(layer)
+            || Compression::from_media_type(&layer.media_type) == Some(compression)
+        {
+            continue;
+        }
+        let written = write_layer(store, open_layer(store, layer)?, compression)?;
+        check_diff_id(&layer.digest, &written.diff_id, diff_id)?;
+        layer.media_type = compression.media_type().to_string();
+        layer.digest = written.digest;
+        layer.size = written.size;
+        changed = true;
+    }
+    if !changed {
+        return Ok(None);
+    }
+
+    let descriptor = put_json(store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)?;
+    verify_diff_ids(store, &descriptor.digest)?;
+    Ok(Some(descriptor))
+}
+
+// switches the media types of a Docker manifest's config and layers to OCI's, which describe
+// the same content
+fn to_oci(config: &mut Descriptor, layers: &mut [Descriptor]) {
+    if config.media_type == MEDIA_TYPE_DOCKER_CONFIG {
+        config.media_type = MEDIA_TYPE_IMAGE_CONFIG.to_string();
+    }
+    for layer in layers {
+        if layer.media_type == MEDIA_TYPE_DOCKER_FOREIGN_LAYER {
+            layer.media_type = MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE_GZIP.to_string();
+        } else if let Some(compression) = Compression::from_media_type(&layer.media_type) {
+            layer.media_type = compression.media_type().to_string();
+        }
+    }
+}
+
+fn transcode_index<S: BlobStore + ?Sized>(
+    store: &S,
+    digest: &Digest,
+    compression: Compression,
+) -> Result<Option<Descriptor>, StoreError> {
+    let mut index = read_index(store, digest)?;
+    let mut changed = index.media_type.as_deref() == Some(MEDIA_TYPE_DOCKER_MANIFEST_LIST);
+    if changed {
+        index.media_type = Some(MEDIA_TYPE_IMAGE_INDEX.to_string());
+    }
+    for entry in index.manifests.iter_mut() {
+        let transcoded = transcode_image(store, entry, compression)?;
+        changed |= transcoded.digest != entry.digest;
+        *entry = transcoded;
+    }
+    if !changed {
+        return Ok(None);
+    }
+    Ok(Some(put_json(store, MEDIA_TYPE_IMAGE_INDEX, &index)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::compression::tests::tar_data;
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::{assemble_index, read_manifest, MemoryBlobStore};
+    use crate::v1::{Architecture, MEDIA_TYPE_DOCKER_LAYER_GZIP, MEDIA_TYPE_IMAGE_LAYER_ZSTD};
+    use std::io::Read;
+
+    fn image_with_layers(
+        store: &MemoryBlobStore,
+        architecture: Architecture,
+        compressions: &[Compression],
+    ) -> Descriptor {
+        let mut config = image_config();
+        config.architecture = architecture;
+        let mut layers = vec![];
+        for compression in compressions {
+            let layer = write_layer(store, tar_data().as_slice(), *compression).unwrap();
+            config.rootfs.diff_ids.push(layer.diff_id.to_string());
+            layers.push(layer.descriptor());
+        }
+        put_image(store, &config, layers)
+    }
+
+    #[test]
+    fn transcodes_image_layers() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(
+            &store,
+            Architecture::Amd64,
+            &[Compression::Gzip, Compression::Uncompressed],
+        );
+        let original = read_manifest(&store, &image.digest).unwrap();
+
+        let transcoded = transcode_image(&store, &image, Compression::Zstd).unwrap();
+        assert_ne!(transcoded.digest, image.digest);
+        let manifest = read_manifest(&store, &transcoded.digest).unwrap();
+        assert_eq!(manifest.config, original.config);
+        for layer in &manifest.layers {
+            assert_eq!(layer.media_type, MEDIA_TYPE_IMAGE_LAYER_ZSTD);
+            let mut tar = vec![];
+            open_layer(&store, layer)
+                .unwrap()
+                .read_to_end(&mut tar)
+                .unwrap();
+            assert_eq!(tar, tar_data());
+        }
+        verify_diff_ids(&store, &transcoded.digest).unwrap();
+
+        // and back again
+        let back = transcode_image(&store, &transcoded, Compression::Gzip).unwrap();
+        verify_diff_ids(&store, &back.digest).unwrap();
+    }
+
+    #[test]
+    fn keeps_images_already_compressed_that_way() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(&store, Architecture::Amd64, &[Compression::Zstd]);
+        assert_eq!(
+            transcode_image(&store, &image, Compression::Zstd).unwrap(),
+            image
+        );
+    }
+
+    #[test]
+    fn transcodes_every_platform_of_an_index() {
+        let store = MemoryBlobStore::new();
+        let amd64 = image_with_layers(&store, Architecture::Amd64, &[Compression::Gzip]);
+        let arm64 = image_with_layers(&store, Architecture::Arm64, &[Compression::Gzip]);
+        let (index, original) = assemble_index(&store, &[amd64, arm64]).unwrap();
+
+        let transcoded = transcode_image(&store, &index, Compression::Zstd).unwrap();
+        assert_eq!(transcoded.media_type, MEDIA_TYPE_IMAGE_INDEX);
+        let manifests = read_index(&store, &transcoded.digest).unwrap().manifests;
+        assert_eq!(manifests.len(), 2);
+        for (entry, original) in manifests.iter().zip(&original.manifests) {
+            assert_eq!(entry.platform, original.platform);
+            assert_ne!(entry.digest, original.digest);
+            let manifest = read_manifest(&store, &entry.digest).unwrap();
+            assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_IMAGE_LAYER_ZSTD);
+        }
+    }
+
+    // the same image as Docker would describe it, with a Docker manifest list around it
+    fn docker_image(store: &MemoryBlobStore, image: &Descriptor) -> (Descriptor, Descriptor) {
+        let mut manifest = read_manifest(store, &image.digest).unwrap();
+        manifest.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string());
+        manifest.config.media_type = MEDIA_TYPE_DOCKER_CONFIG.to_string();
+        for layer in &mut manifest.layers {
+            layer.media_type = MEDIA_TYPE_DOCKER_LAYER_GZIP.to_string();
+        }
+        let image = put_json(store, MEDIA_TYPE_DOCKER_MANIFEST, &manifest).unwrap();
+        let (_, mut list) = assemble_index(store, std::slice::from_ref(&image)).unwrap();
+        list.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST_LIST.to_string());
+        let list = put_json(store, MEDIA_TYPE_DOCKER_MANIFEST_LIST, &list).unwrap();
+        (image, list)
+    }
+
+    #[test]
+    fn converts_docker_manifests_to_oci() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(&store, Architecture::Amd64, &[Compression::Gzip]);
+        let (docker, _) = docker_image(&store, &image);
+
+        // even when the layers are already compressed that way
+        for compression in &[Compression::Gzip, Compression::Zstd] {
+            let transcoded = transcode_image(&store, &docker, *compression).unwrap();
+            assert_eq!(transcoded.media_type, MEDIA_TYPE_IMAGE_MANIFEST);
+            let manifest = read_manifest(&store, &transcoded.digest).unwrap();
+            assert_eq!(
+                manifest.media_type.as_deref(),
+                Some(MEDIA_TYPE_IMAGE_MANIFEST)
+            );
+            assert_eq!(manifest.config.media_type, MEDIA_TYPE_IMAGE_CONFIG);
+            assert_eq!(manifest.layers[0].media_type, compression.media_type());
+            verify_diff_ids(&store, &transcoded.digest).unwrap();
+        }
+    }
+
+    #[test]
+    fn converts_docker_manifest_lists_to_oci() {
+        let store = MemoryBlobStore::new();
+        let image = image_with_layers(&store, Architecture::Amd64, &[Compression::Gzip]);
+        let (_, list) = docker_image(&store, &image);
+
+        let transcoded = transcode_image(&store, &list, Compression::Zstd).unwrap();
+        assert_eq!(transcoded.media_type, MEDIA_TYPE_IMAGE_INDEX);
+        let index = read_index(&store, &transcoded.digest).unwrap();
+        assert_eq!(index.media_type.as_deref(), Some(MEDIA_TYPE_IMAGE_INDEX));
+        assert_eq!(index.manifests[0].media_type, MEDIA_TYPE_IMAGE_MANIFEST);
+        let manifest = read_manifest(&store, &index.manifests[0].digest).unwrap();
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_IMAGE_LAYER_ZSTD);
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_what_isnt_an_image() {
+            let store = MemoryBlobStore::new();
+            let image = image_with_layers(&store, Architecture::Amd64, &[Compression::Gzip]);
+            let layer = read_manifest(&store, &image.digest).unwrap().layers[0].clone();
+
+            assert!(matches!(
+                transcode_image(&store, &layer, Compression::Zstd),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+
+        #[test]
+        fn rejects_layers_that_dont_match_their_diff_ids() {
+            let store = MemoryBlobStore::new();
+            let image = image_with_layers(&store, Architecture::Amd64, &[Compression::Gzip]);
+            let mut manifest = read_manifest(&store, &image.digest).unwrap();
+            let mut config = image_config();
+            config.rootfs.diff_ids = vec![Digest::sha256(b"other").to_string()];
+            manifest.config = put_json(&store, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap();
+            let image = put_json(&store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+
+            assert!(matches!(
+                transcode_image(&store, &image, Compression::Zstd),
+                Err(StoreError::Invalid(_))
+            ));
+            assert!(matches!(
+                verify_diff_ids(&store, &image.digest),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+
+        #[test]
+        fn rejects_configs_with_missing_diff_ids() {
+            let store = MemoryBlobStore::new();
+            let image = image_with_layers(&store, Architecture::Amd64, &[Compression::Gzip]);
+            let mut manifest = read_manifest(&store, &image.digest).unwrap();
+            manifest.config = put_json(&store, MEDIA_TYPE_IMAGE_CONFIG, &image_config()).unwrap();
+            let image = put_json(&store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+
+            assert!(matches!(
+                transcode_image(&store, &image, Compression::Zstd),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use crate::layer::compression::Hashing;
+use crate::layer::foreign::{fetch_layer, rejected};
+use crate::layer::transcode::{check_diff_id, check_layer_count};
+use crate::layer::{is_foreign_layer, open_layer, ForeignLayerPolicy};
+use crate::store::{resolve_image_config, BlobStore, StoreError};
+use crate::v1::{Descriptor, Digest};
+
+/// Marks a file of a lower layer as deleted, as `.wh.<name>`.
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+/// Hides everything lower layers put in the directory it's in.
+pub const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// Extracts the layers of an image into `dir` one after the other, applying their whiteouts,
+/// and returns the foreign layers that the policy skipped. Each layer is checked against its
+/// diff ID as it's extracted; skipped layers don't shift which diff ID the others are checked
+/// against.
+pub fn unpack_image<S: BlobStore + ?Sized, P: AsRef<Path>>(
+    store: &S,
+    manifest_digest: &Digest,
+    dir: P,
+    policy: &ForeignLayerPolicy,
+) -> Result<Vec<Descriptor>, StoreError> {
+    let dir = dir.as_ref();
+    let (manifest, config) = resolve_image_config(store, manifest_digest)?;
+    let diff_ids = &config.rootfs.diff_ids;
+    check_layer_count(manifest_digest, &manifest.layers, diff_ids)?;
+
+    fs::create_dir_all(dir)?;
+    let mut skipped = vec![];
+    for (layer, expected) in manifest.layers.iter().zip(diff_ids) {
+        if is_foreign_layer(layer) {
+            match policy {
+                ForeignLayerPolicy::Reject => return Err(rejected(layer)),
+                _ if store.contains(&layer.digest)? => {}
+                ForeignLayerPolicy::Fetch(fetcher) => fetch_layer(store, fetcher.as_ref(), layer)?,
+                ForeignLayerPolicy::Skip => {
+                    skipped.push(layer.clone());
+                    continue;
+                }
+            }
+        }
+
+        let mut tar = Hashing::new(open_layer(store, layer)?);
+        apply_layer(&mut tar, dir)?;
+        // tar readers stop at the end-of-archive marker
+        io::copy(&mut tar, &mut io::sink())?;
+        check_diff_id(&layer.digest, &tar.digester.finalize(), expected)?;
+    }
+    Ok(skipped)
+}
+
+// a path inside the layer, without any `./`
+pub(super) fn entry_path(path: &Path) -> Result<PathBuf, StoreError> {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(x) => clean.push(x),
+            Component::CurDir => {}
+            _ => {
+                return Err(StoreError::Invalid(format!(
+                    "`{}` is outside of the layer",
+                    path.display()
+                )))
+            }
+        }
+    }
+    Ok(clean)
+}
+
+// what a whiteout named `name` hides, if it is one; names are matched as bytes, as they
+// needn't be UTF-8
+#[cfg(unix)]
+pub(super) fn whited_out(name: &OsStr) -> Option<&OsStr> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes()
+        .strip_prefix(WHITEOUT_PREFIX.as_bytes())
+        .map(OsStr::from_bytes)
+}
+
+// elsewhere the tar crate only reads paths that are UTF-8
+#[cfg(not(unix))]
+pub(super) fn whited_out(name: &OsStr) -> Option<&OsStr> {
+    name.to_str()?.strip_prefix(WHITEOUT_PREFIX).map(OsStr::new)
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+// whiteouts may only remove what's under `dir`, however earlier layers placed symlinks
+fn inside(dir: &Path, path: &Path) -> io::Result<bool> {
+    match path.parent().map(|x| x.canonicalize()) {
+        Some(Ok(parent)) => Ok(parent.starts_with(dir.canonicalize()?)),
+        // nothing to remove if the parent doesn't exist
+        _ => Ok(false),
+    }
+}
+
+fn apply_layer<R: Read>(tar: R, dir: &Path) -> Result<(), StoreError> {
+    let mut archive = tar::Archive::new(tar);
+    // what this layer added, which opaque whiteouts leave in place
+    let mut added: HashSet<PathBuf> = HashSet::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry_path(&entry.path()?)?;
+        let name = match path.file_name() {
+            Some(name) => name,
+            // the root of the layer
+            None => continue,
+        };
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+
+        if name == OsStr::new(OPAQUE_WHITEOUT) {
+            let target = dir.join(parent);
+            if !inside(dir, &target.join(OPAQUE_WHITEOUT))? {
+                continue;
+            }
+            for child in fs::read_dir(&target)? {
+                let child = child?;
+                if !added.contains(&parent.join(child.file_name())) {
+                    remove(&child.path())?;
+                }
+            }
+        } else if let Some(hidden) = whited_out(name) {
+            let target = dir.join(parent).join(hidden);
+            if inside(dir, &target)? {
+                remove(&target)?;
+            }
+        } else {
+            let target = dir.join(&path);
+            // whatever a lower layer had here is replaced, unless both are directories
+            let replacing_directory = entry.header().entry_type().is_dir()
+                && fs::symlink_metadata(&target).is_ok_and(|x| x.is_dir());
+            if !replacing_directory && inside(dir, &target)? {
+                remove(&target)?;
+            }
+            entry.unpack_in(dir)?;
+            added.insert(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::compression::tests::{tar_of, tar_of_raw};
+    use crate::layer::foreign::tests::{foreign_layer, StaticFetcher};
+    use crate::layer::{write_layer, Compression};
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::MemoryBlobStore;
+
+    // an image whose second layer is foreign and, unless `stored`, not in the store
+    fn image_with_foreign_layer(store: &MemoryBlobStore, stored: bool) -> (Digest, Vec<u8>) {
+        let first = write_layer(
+            store,
+            tar_of(&[("a.txt", b"a"), ("dir/b.txt", b"b"), ("dir/c.txt", b"c")]).as_slice(),
+            Compression::Gzip,
+        )
+        .unwrap();
+        let foreign_tar = tar_of(&[("windows.txt", b"base")]);
+        let foreign = foreign_layer(&foreign_tar, &["https://example.com/base"]);
+        if stored {
+            store.put(&foreign_tar).unwrap();
+        }
+        let last = write_layer(
+            store,
+            tar_of(&[
+                (".wh.a.txt", b""),
+                ("dir/d.txt", b"d"),
+                ("dir/.wh..wh..opq", b""),
+            ])
+            .as_slice(),
+            Compression::Zstd,
+        )
+        .unwrap();
+
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![
+            first.diff_id.to_string(),
+            foreign.digest.to_string(),
+            last.diff_id.to_string(),
+        ];
+        let layers = vec![first.descriptor(), foreign, last.descriptor()];
+        (put_image(store, &config, layers).digest, foreign_tar)
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files = vec![];
+        for entry in fs::read_dir(dir).unwrap() {
+            let entry = entry.unwrap();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() {
+                files.extend(files_in(&entry.path(), &name));
+            } else {
+                files.push(name);
+            }
+        }
+        files.sort();
+        files
+    }
+
+    fn files_in(dir: &Path, prefix: &str) -> Vec<String> {
+        files(dir)
+            .into_iter()
+            .map(|x| format!("{}/{}", prefix, x))
+            .collect()
+    }
+
+    #[test]
+    fn applies_whiteouts_and_skips_foreign_layers() {
+        let store = MemoryBlobStore::new();
+        let (image, _) = image_with_foreign_layer(&store, false);
+        let dir = tempfile::tempdir().unwrap();
+
+        let skipped = unpack_image(&store, &image, dir.path(), &ForeignLayerPolicy::Skip).unwrap();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(files(dir.path()), vec!["dir/d.txt"]);
+    }
+
+    #[test]
+    fn unpacks_foreign_layers_already_in_the_store() {
+        let store = MemoryBlobStore::new();
+        let (image, _) = image_with_foreign_layer(&store, true);
+        let dir = tempfile::tempdir().unwrap();
+
+        let skipped = unpack_image(&store, &image, dir.path(), &ForeignLayerPolicy::Skip).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(files(dir.path()), vec!["dir/d.txt", "windows.txt"]);
+    }
+
+    #[test]
+    fn fetches_foreign_layers() {
+        let store = MemoryBlobStore::new();
+        let (image, foreign_tar) = image_with_foreign_layer(&store, false);
+        let mut fetcher = StaticFetcher::default();
+        fetcher
+            .content
+            .insert("https://example.com/base".to_string(), foreign_tar.clone());
+        let dir = tempfile::tempdir().unwrap();
+
+        let policy = ForeignLayerPolicy::Fetch(Box::new(fetcher));
+        assert!(unpack_image(&store, &image, dir.path(), &policy)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("windows.txt")).unwrap(),
+            "base"
+        );
+        assert!(store.contains(&Digest::sha256(&foreign_tar)).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unpacks_names_that_arent_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let dir = tempfile::tempdir().unwrap();
+        let first = tar_of_raw(&[(b"caf\xe9", b"latin-1"), (b"na\xefve", b"latin-1")]);
+        let second = tar_of_raw(&[(b".wh.caf\xe9", b"")]);
+        apply_layer(first.as_slice(), dir.path()).unwrap();
+        assert!(dir.path().join(OsStr::from_bytes(b"caf\xe9")).exists());
+
+        apply_layer(second.as_slice(), dir.path()).unwrap();
+        assert!(!dir.path().join(OsStr::from_bytes(b"caf\xe9")).exists());
+        assert_eq!(
+            fs::read(dir.path().join(OsStr::from_bytes(b"na\xefve"))).unwrap(),
+            b"latin-1"
+        );
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_foreign_layers() {
+            let store = MemoryBlobStore::new();
+            let (image, _) = image_with_foreign_layer(&store, true);
+            let dir = tempfile::tempdir().unwrap();
+
+            assert!(matches!(
+                unpack_image(&store, &image, dir.path(), &ForeignLayerPolicy::Reject),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+
+        #[test]
+        fn keeps_whiteouts_inside_the_directory() {
+            let dir = tempfile::tempdir().unwrap();
+            let root = dir.path().join("root");
+            fs::create_dir(&root).unwrap();
+            fs::write(dir.path().join("outside.txt"), "x").unwrap();
+
+            // the tar crate won't write such a path, so it goes straight into the header
+            let tar = tar_of_raw(&[(b"../.wh.outside.txt", b"")]);
+
+            assert!(apply_layer(tar.as_slice(), &root).is_err());
+            assert!(dir.path().join("outside.txt").exists());
+        }
+    }
+}
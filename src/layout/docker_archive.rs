@@ -0,0 +1,345 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::layer::Compression;
+use crate::layout::{ref_name, OciLayout};
+use crate::store::{put_json, read_manifest, BlobStore, StoreError};
+use crate::v1::{
+    parse_image_config, Descriptor, Digest, Manifest, MEDIA_TYPE_IMAGE_CONFIG,
+    MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The index of a `docker save` archive.
+pub const DOCKER_ARCHIVE_MANIFEST: &str = "manifest.json";
+
+static IMPORT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ArchiveImage {
+    config: String,
+    #[serde(default)]
+    repo_tags: Option<Vec<String>>,
+    layers: Vec<String>,
+}
+
+// removes the unpacked archive however the import ends
+struct Staging(PathBuf);
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+impl OciLayout {
+    /// Imports the images of an archive written by `docker save`, naming each after its
+    /// `RepoTags`, such as `ubuntu:latest`. Returns the descriptors of their manifests.
+    pub fn import_docker_archive<R: Read>(
+        &self,
+        archive: R,
+    ) -> Result<Vec<Descriptor>, StoreError> {
+        // archives aren't ordered, so `manifest.json` may well come after the layers
+        let staging = Staging(self.root().join(format!(
+            ".import-{}-{}",
+            std::process::id(),
+            IMPORT_COUNTER.fetch_add(1, Ordering::SeqCst)
+        )));
+        fs::create_dir_all(&staging.0)?;
+        tar::Archive::new(archive).unpack(&staging.0)?;
+
+        let images: Vec<ArchiveImage> = match File::open(staging.0.join(DOCKER_ARCHIVE_MANIFEST)) {
+            Ok(file) => serde_json::from_reader(file)?,
+            Err(_) => {
+                return Err(StoreError::Invalid(format!(
+                    "not a docker archive: missing `{}`",
+                    DOCKER_ARCHIVE_MANIFEST
+                )))
+            }
+        };
+
+        let mut descriptors = Vec::with_capacity(images.len());
+        for image in images {
+            let config_data = fs::read(staged(&staging.0, &image.config)?)?;
+            parse_image_config(&mut config_data.as_slice())?;
+            let config = Descriptor {
+                media_type: MEDIA_TYPE_IMAGE_CONFIG.to_string(),
+                digest: self.put(&config_data)?,
+                size: config_data.len() as u64,
+                urls: None,
+                artifact_type: None,
+                annotations: None,
+                platform: None,
+            };
+
+            let mut layers = Vec::with_capacity(image.layers.len());
+            for layer in &image.layers {
+                let mut file = File::open(staged(&staging.0, layer)?)?;
+                let mut magic = [0; 4];
+                let read = file.read(&mut magic)?;
+                let mut writer = self.writer()?;
+                writer.write_all(&magic[..read])?;
+                std::io::copy(&mut file, &mut writer)?;
+                let (digest, size) = writer.commit()?;
+                layers.push(Descriptor {
+                    media_type: Compression::sniff(&magic[..read]).media_type().to_string(),
+                    digest,
+                    size,
+                    urls: None,
+                    artifact_type: None,
+                    annotations: None,
+                    platform: None,
+                });
+            }
+
+            let manifest = Manifest {
+                schema_version: 2,
+                media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+                config,
+                layers,
+                artifact_type: None,
+                subject: None,
+                annotations: None,
+            };
+            let descriptor = put_json(self, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)?;
+            match image.repo_tags.as_deref() {
+                Some(tags) if !tags.is_empty() => {
+                    for tag in tags {
+                        self.tag(tag, descriptor.clone())?;
+                    }
+                }
+                // untagged images still need an entry to be kept
+                _ => self.update_index(|index| {
+                    if !index
+                        .manifests
+                        .iter()
+                        .any(|x| x.digest == descriptor.digest)
+                    {
+                        index.manifests.push(descriptor.clone());
+                    }
+                    Ok(())
+                })?,
+            }
+            descriptors.push(descriptor);
+        }
+        Ok(descriptors)
+    }
+
+    /// Writes image manifests of the layout as an archive `docker load` accepts. Names of
+    /// the form `<repository>:<tag>` in their `org.opencontainers.image.ref.name` become the
+    /// images' `RepoTags`.
+    pub fn export_docker_archive<W: Write>(
+        &self,
+        images: &[Descriptor],
+        writer: W,
+    ) -> Result<(), StoreError> {
+        let mut builder = tar::Builder::new(writer);
+        let mut written: Vec<Digest> = Vec::new();
+        let mut archive_images = Vec::with_capacity(images.len());
+
+        for descriptor in images {
+            if descriptor.media_type != MEDIA_TYPE_IMAGE_MANIFEST {
+                return Err(StoreError::Invalid(format!(
+                    "`{}` is a `{}` rather than an image manifest",
+                    descriptor.digest, descriptor.media_type
+                )));
+            }
+            let manifest = read_manifest(self, &descriptor.digest)?;
+            let mut add_blob = |blob: &Descriptor| -> Result<String, StoreError> {
+                let path = format!(
+                    "blobs/{}/{}",
+                    blob.digest.algorithm(),
+                    blob.digest.encoded()
+                );
+                if !written.contains(&blob.digest) {
+                    let mut header = tar::Header::new_gnu();
+                    header.set_size(blob.size);
+                    header.set_mode(0o644);
+                    header.set_mtime(0);
+                    header.set_cksum();
+                    builder.append_data(&mut header, &path, self.reader(&blob.digest)?)?;
+                    written.push(blob.digest.clone());
+                }
+                Ok(path)
+            };
+
+            let config = add_blob(&manifest.config)?;
+            let layers = manifest
+                .layers
+                .iter()
+                .map(&mut add_blob)
+                .collect::<Result<Vec<_>, _>>()?;
+            let repo_tags: Vec<String> = ref_name(descriptor)
+                .filter(|x| x.rfind(':').is_some_and(|idx| !x[idx..].contains('/')))
+                .map(String::from)
+                .into_iter()
+                .collect();
+            archive_images.push(ArchiveImage {
+                config,
+                repo_tags: Some(repo_tags),
+                layers,
+            });
+        }
+
+        let data = serde_json::to_vec(&archive_images)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+        builder.append_data(&mut header, DOCKER_ARCHIVE_MANIFEST, data.as_slice())?;
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+// resolves a path named by `manifest.json`, which must stay within the archive, symlinks
+// included
+fn staged(staging: &Path, name: &str) -> Result<PathBuf, StoreError> {
+    let escapes = Path::new(name)
+        .components()
+        .any(|x| !matches!(x, Component::Normal(_) | Component::CurDir));
+    let path = staging.join(name);
+    if escapes || !path.canonicalize()?.starts_with(staging.canonicalize()?) {
+        return Err(StoreError::Invalid(format!(
+            "`{}` is outside of the archive",
+            name
+        )));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::image_config;
+    use crate::v1::{ANNOTATION_REF_NAME, MEDIA_TYPE_IMAGE_LAYER};
+
+    fn append(builder: &mut tar::Builder<Vec<u8>>, path: &str, data: &[u8]) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+
+    fn layer_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        append(&mut builder, "etc/motd", b"hello");
+        builder.into_inner().unwrap()
+    }
+
+    // an archive as `docker save` writes it, with the manifest last
+    fn docker_archive(repo_tags: &[&str], config_path: &str) -> (Vec<u8>, Vec<u8>) {
+        let layer = layer_tar();
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![Digest::sha256(&layer).to_string()];
+        let config = serde_json::to_vec(&config).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        append(&mut builder, "abc123/layer.tar", &layer);
+        append(&mut builder, "config.json", &config);
+        let manifest = serde_json::json!([{
+            "Config": config_path,
+            "RepoTags": repo_tags,
+            "Layers": ["abc123/layer.tar"],
+        }]);
+        append(
+            &mut builder,
+            DOCKER_ARCHIVE_MANIFEST,
+            &serde_json::to_vec(&manifest).unwrap(),
+        );
+        (builder.into_inner().unwrap(), config)
+    }
+
+    #[test]
+    fn imports_docker_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (archive, config) = docker_archive(&["app:v1", "app:latest"], "config.json");
+
+        let images = layout.import_docker_archive(archive.as_slice()).unwrap();
+        assert_eq!(images.len(), 1);
+        let manifest = read_manifest(&layout, &images[0].digest).unwrap();
+        assert_eq!(manifest.config.digest, Digest::sha256(&config));
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_IMAGE_LAYER);
+        assert_eq!(manifest.layers[0].digest, Digest::sha256(&layer_tar()));
+
+        let mut tags = layout.tags().unwrap();
+        tags.sort();
+        assert_eq!(tags, vec!["app:latest", "app:v1"]);
+        assert!(layout.fsck().unwrap().is_ok());
+        // nothing of the unpacked archive is left behind
+        assert_eq!(layout.list().unwrap().len(), 3);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn round_trips_through_docker_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path().join("a")).unwrap();
+        let (archive, _) = docker_archive(&["app:v1"], "config.json");
+        layout.import_docker_archive(archive.as_slice()).unwrap();
+
+        let mut exported = Vec::new();
+        let image = layout.resolve("app:v1").unwrap().unwrap();
+        layout
+            .export_docker_archive(std::slice::from_ref(&image), &mut exported)
+            .unwrap();
+
+        let other = OciLayout::create(dir.path().join("b")).unwrap();
+        let images = other.import_docker_archive(exported.as_slice()).unwrap();
+        assert_eq!(images[0].digest, image.digest);
+        let reimported = other.resolve("app:v1").unwrap().unwrap();
+        assert_eq!(
+            reimported.annotations.unwrap()[ANNOTATION_REF_NAME],
+            "app:v1"
+        );
+    }
+
+    #[test]
+    fn keeps_untagged_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (archive, _) = docker_archive(&[], "config.json");
+        let images = layout.import_docker_archive(archive.as_slice()).unwrap();
+        assert_eq!(layout.index().unwrap().manifests, images);
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_paths_outside_the_archive() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path().join("layout")).unwrap();
+            fs::write(dir.path().join("secret.json"), b"{}").unwrap();
+            let (archive, _) = docker_archive(&["app:v1"], "../../secret.json");
+
+            let err = layout
+                .import_docker_archive(archive.as_slice())
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "`../../secret.json` is outside of the archive"
+            );
+        }
+
+        #[test]
+        fn rejects_other_archives() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            let err = layout
+                .import_docker_archive(layer_tar().as_slice())
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "not a docker archive: missing `manifest.json`"
+            );
+        }
+    }
+}
@@ -0,0 +1,745 @@
+use std::collections::BTreeSet;
+use std::fmt::Display;
+use std::io::Read;
+
+use crate::config::v1::from_json_reader;
+use crate::layer::{digest_layer, is_foreign_layer, Compression, MEDIA_TYPE_DOCKER_FOREIGN_LAYER};
+use crate::layout::OciLayout;
+use crate::store::{BlobStore, StoreError};
+use crate::v1::{
+    is_image_index, is_image_manifest, parse_image_config, parse_image_index, parse_image_manifest,
+    Descriptor, Digest, Digester, ImageConfig, ParseLimits, MEDIA_TYPE_DOCKER_CONFIG,
+    MEDIA_TYPE_DOCKER_LAYER_GZIP, MEDIA_TYPE_IMAGE_CONFIG, MEDIA_TYPE_IMAGE_LAYER,
+    MEDIA_TYPE_IMAGE_LAYER_GZIP, MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE,
+    MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE_GZIP, MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE_ZSTD,
+    MEDIA_TYPE_IMAGE_LAYER_ZSTD,
+};
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FsckReport {
+    pub blobs_checked: usize,
+    pub issues: Vec<FsckIssue>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckIssue {
+    /// The blob's content doesn't hash to its file name.
+    CorruptBlob {
+        digest: Digest,
+        actual: Digest,
+    },
+    MissingBlob {
+        digest: Digest,
+    },
+    /// A blob under `blobs/<algorithm>/` that can't be checked, as the algorithm isn't one
+    /// we can compute.
+    UnsupportedAlgorithm {
+        digest: Digest,
+    },
+    SizeMismatch {
+        digest: Digest,
+        expected: u64,
+        actual: u64,
+    },
+    /// A descriptor's media type disagrees with the `mediaType` of the document it points to,
+    /// or isn't one that can appear where the descriptor is.
+    MediaTypeMismatch {
+        digest: Digest,
+        expected: String,
+        actual: String,
+    },
+    /// A manifest, index or config that doesn't parse.
+    Invalid {
+        digest: Digest,
+        message: String,
+    },
+    LayerCountMismatch {
+        manifest: Digest,
+        layers: usize,
+        diff_ids: usize,
+    },
+    DiffIdMismatch {
+        manifest: Digest,
+        layer: Digest,
+        expected: Digest,
+        actual: Digest,
+    },
+}
+
+impl Display for FsckIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FsckIssue::CorruptBlob { digest, actual } => {
+                write!(f, "blob `{}` hashes to `{}`", digest, actual)
+            }
+            FsckIssue::MissingBlob { digest } => write!(f, "blob `{}` is missing", digest),
+            FsckIssue::UnsupportedAlgorithm { digest } => write!(
+                f,
+                "blob `{}` can't be checked: unsupported digest algorithm `{}`",
+                digest,
+                digest.algorithm()
+            ),
+            FsckIssue::SizeMismatch {
+                digest,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "blob `{}` is {} bytes but is referenced as {}",
+                digest, actual, expected
+            ),
+            FsckIssue::MediaTypeMismatch {
+                digest,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "blob `{}` is referenced as `{}` but is `{}`",
+                digest, expected, actual
+            ),
+            FsckIssue::Invalid { digest, message } => {
+                write!(f, "blob `{}` is invalid: {}", digest, message)
+            }
+            FsckIssue::LayerCountMismatch {
+                manifest,
+                layers,
+                diff_ids,
+            } => write!(
+                f,
+                "manifest `{}` has {} layers but its config has {} diff_ids",
+                manifest, layers, diff_ids
+            ),
+            FsckIssue::DiffIdMismatch {
+                manifest,
+                layer,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "layer `{}` of manifest `{}` has diff_id `{}` but its config expects `{}`",
+                layer, manifest, actual, expected
+            ),
+        }
+    }
+}
+
+impl OciLayout {
+    /// Checks every blob against its digest, then everything reachable from `index.json`:
+    /// descriptors have to point at blobs of the right size and media type, manifests and
+    /// configs have to parse and layers have to decompress to the config's `rootfs.diff_ids`.
+    ///
+    /// Problems with the content are collected in the report; only failing to read the
+    /// layout is an error.
+    pub fn fsck(&self) -> Result<FsckReport, StoreError> {
+        let mut checker = Checker {
+            layout: self,
+            report: FsckReport::default(),
+            corrupt: BTreeSet::new(),
+            checked: BTreeSet::new(),
+        };
+        for digest in self.list()? {
+            checker.check_blob(&digest)?;
+        }
+        for descriptor in self.index()?.manifests {
+            checker.check_descriptor(&descriptor)?;
+        }
+        Ok(checker.report)
+    }
+}
+
+struct Checker<'a> {
+    layout: &'a OciLayout,
+    report: FsckReport,
+    corrupt: BTreeSet<Digest>,
+    // descriptors already followed
+    checked: BTreeSet<Digest>,
+}
+
+impl<'a> Checker<'a> {
+    fn check_blob(&mut self, digest: &Digest) -> Result<(), StoreError> {
+        self.report.blobs_checked += 1;
+        // `list` returns whatever is named like a digest, whether or not we can compute it
+        let mut digester = match Digester::new(digest.algorithm()) {
+            Ok(digester) => digester,
+            Err(_) => {
+                self.corrupt.insert(digest.clone());
+                self.report.issues.push(FsckIssue::UnsupportedAlgorithm {
+                    digest: digest.clone(),
+                });
+                return Ok(());
+            }
+        };
+        std::io::copy(&mut self.layout.reader(digest)?, &mut digester)?;
+        let actual = digester.finalize();
+        if &actual != digest {
+            self.corrupt.insert(digest.clone());
+            self.report.issues.push(FsckIssue::CorruptBlob {
+                digest: digest.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    // whether the blob is there, intact and of the right size
+    fn check_exists(&mut self, descriptor: &Descriptor) -> Result<bool, StoreError> {
+        let digest = &descriptor.digest;
+        let size = match self.layout.stat(digest)? {
+            Some(size) => size,
+            None => {
+                self.report.issues.push(FsckIssue::MissingBlob {
+                    digest: digest.clone(),
+                });
+                return Ok(false);
+            }
+        };
+        if size != descriptor.size {
+            self.report.issues.push(FsckIssue::SizeMismatch {
+                digest: digest.clone(),
+                expected: descriptor.size,
+                actual: size,
+            });
+            return Ok(false);
+        }
+        Ok(!self.corrupt.contains(digest))
+    }
+
+    fn check_descriptor(&mut self, descriptor: &Descriptor) -> Result<(), StoreError> {
+        if !self.checked.insert(descriptor.digest.clone()) {
+            return Ok(());
+        }
+        if !self.check_exists(descriptor)? {
+            return Ok(());
+        }
+        let index = is_image_index(&descriptor.media_type);
+        if !index && !is_image_manifest(&descriptor.media_type) {
+            return Ok(());
+        }
+        if !self.check_media_type(descriptor)? {
+            return Ok(());
+        }
+        if index {
+            self.check_index(descriptor)
+        } else {
+            self.check_manifest(descriptor)
+        }
+    }
+
+    // documents don't have to declare their `mediaType`, but it has to match if they do
+    fn check_media_type(&mut self, descriptor: &Descriptor) -> Result<bool, StoreError> {
+        let reader = self.layout.reader(&descriptor.digest)?;
+        let declared = match from_json_reader::<Declared, _>(reader, &ParseLimits::default()) {
+            Ok(Declared {
+                media_type: Some(declared),
+            }) => declared,
+            // parsing it properly will say what's wrong with it
+            _ => return Ok(true),
+        };
+        if declared == descriptor.media_type {
+            return Ok(true);
+        }
+        self.report.issues.push(FsckIssue::MediaTypeMismatch {
+            digest: descriptor.digest.clone(),
+            expected: descriptor.media_type.clone(),
+            actual: declared,
+        });
+        Ok(false)
+    }
+
+    fn check_index(&mut self, descriptor: &Descriptor) -> Result<(), StoreError> {
+        let digest = &descriptor.digest;
+        let index = parse_image_index(&mut self.layout.reader(digest)?);
+        let index = match index.and_then(|x| x.validate().map(|_| x)) {
+            Ok(index) => index,
+            Err(error) => {
+                self.invalid(digest, error);
+                return Ok(());
+            }
+        };
+        for manifest in &index.manifests {
+            self.check_descriptor(manifest)?;
+        }
+        Ok(())
+    }
+
+    fn check_manifest(&mut self, descriptor: &Descriptor) -> Result<(), StoreError> {
+        let digest = &descriptor.digest;
+        let manifest = parse_image_manifest(&mut self.layout.reader(digest)?);
+        let manifest = match manifest.and_then(|x| x.validate().map(|_| x)) {
+            Ok(manifest) => manifest,
+            Err(error) => {
+                self.invalid(digest, error);
+                return Ok(());
+            }
+        };
+
+        let mut present = Vec::with_capacity(manifest.layers.len());
+        for layer in &manifest.layers {
+            // non-distributable layers are usually left out when copying images
+            present.push(
+                if is_foreign_layer(layer) && !self.layout.contains(&layer.digest)? {
+                    false
+                } else {
+                    self.check_exists(layer)?
+                },
+            );
+        }
+        // anything else is an artifact, whose layers can be anything
+        let config_type = manifest.config.media_type.as_str();
+        if config_type != MEDIA_TYPE_IMAGE_CONFIG && config_type != MEDIA_TYPE_DOCKER_CONFIG {
+            self.check_exists(&manifest.config)?;
+            return Ok(());
+        }
+        let config = match self.check_config(&manifest.config)? {
+            Some(config) => config,
+            None => return Ok(()),
+        };
+
+        let diff_ids = &config.rootfs.diff_ids;
+        if diff_ids.len() != manifest.layers.len() {
+            self.report.issues.push(FsckIssue::LayerCountMismatch {
+                manifest: digest.clone(),
+                layers: manifest.layers.len(),
+                diff_ids: diff_ids.len(),
+            });
+            return Ok(());
+        }
+        // checked layer by layer, so that missing ones don't throw the others off
+        for ((layer, expected), present) in manifest.layers.iter().zip(diff_ids).zip(present) {
+            let reader = self.layout.reader(&layer.digest);
+            let actual = match (present, reader) {
+                (true, Ok(reader)) => diff_id(reader, &layer.media_type)?,
+                _ => None,
+            };
+            let actual = match actual {
+                Some(actual) => actual,
+                None => continue,
+            };
+            // diff_ids are plain strings in `ImageConfig`
+            if actual.to_string() != *expected {
+                self.report.issues.push(FsckIssue::DiffIdMismatch {
+                    manifest: digest.clone(),
+                    layer: layer.digest.clone(),
+                    expected: expected.parse().unwrap_or_else(|_| actual.clone()),
+                    actual,
+                });
+            }
+        }
+        for layer in &manifest.layers {
+            if !is_image_layer(&layer.media_type) {
+                self.report.issues.push(FsckIssue::MediaTypeMismatch {
+                    digest: layer.digest.clone(),
+                    expected: MEDIA_TYPE_IMAGE_LAYER.to_string(),
+                    actual: layer.media_type.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_config(&mut self, descriptor: &Descriptor) -> Result<Option<ImageConfig>, StoreError> {
+        if !self.check_exists(descriptor)? {
+            return Ok(None);
+        }
+        let config = parse_image_config(&mut self.layout.reader(&descriptor.digest)?);
+        match config.and_then(|x| x.validate().map(|_| x)) {
+            Ok(config) => Ok(Some(config)),
+            Err(error) => {
+                self.invalid(&descriptor.digest, error);
+                Ok(None)
+            }
+        }
+    }
+
+    fn invalid<E: Display>(&mut self, digest: &Digest, error: E) {
+        self.report.issues.push(FsckIssue::Invalid {
+            digest: digest.clone(),
+            message: error.to_string(),
+        });
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Declared {
+    media_type: Option<String>,
+}
+
+fn is_image_layer(media_type: &str) -> bool {
+    media_type == MEDIA_TYPE_IMAGE_LAYER
+        || media_type == MEDIA_TYPE_IMAGE_LAYER_GZIP
+        || media_type == MEDIA_TYPE_IMAGE_LAYER_ZSTD
+        || media_type == MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE
+        || media_type == MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE_GZIP
+        || media_type == MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE_ZSTD
+        || media_type == MEDIA_TYPE_DOCKER_LAYER_GZIP
+        || media_type == MEDIA_TYPE_DOCKER_FOREIGN_LAYER
+}
+
+// hashes the uncompressed layer; `None` for compressions we don't know about
+fn diff_id<R: Read>(reader: R, media_type: &str) -> Result<Option<Digest>, StoreError> {
+    if Compression::from_media_type(media_type).is_none() {
+        return Ok(None);
+    }
+    Ok(Some(digest_layer(reader, Some(media_type))?.diff_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::{put_json, read_manifest};
+    use crate::v1::{
+        Index, Manifest, MEDIA_TYPE_DOCKER_MANIFEST, MEDIA_TYPE_DOCKER_MANIFEST_LIST,
+        MEDIA_TYPE_IMAGE_INDEX, MEDIA_TYPE_IMAGE_MANIFEST,
+    };
+    use std::io::Write;
+
+    fn put_layer(layout: &OciLayout, media_type: &str, data: &[u8]) -> Descriptor {
+        let compressed = match media_type {
+            MEDIA_TYPE_IMAGE_LAYER_GZIP => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            MEDIA_TYPE_IMAGE_LAYER_ZSTD => zstd::encode_all(data, 0).unwrap(),
+            _ => data.to_vec(),
+        };
+        Descriptor {
+            media_type: media_type.to_string(),
+            digest: layout.put(&compressed).unwrap(),
+            size: compressed.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        }
+    }
+
+    // tags a manifest with one layer of each compression
+    fn tag_image(layout: &OciLayout) -> (Descriptor, Manifest) {
+        let layers = vec![
+            put_layer(layout, MEDIA_TYPE_IMAGE_LAYER, b"plain"),
+            put_layer(layout, MEDIA_TYPE_IMAGE_LAYER_GZIP, b"gzip"),
+            put_layer(layout, MEDIA_TYPE_IMAGE_LAYER_ZSTD, b"zstd"),
+        ];
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![
+            Digest::sha256(b"plain").to_string(),
+            Digest::sha256(b"gzip").to_string(),
+            Digest::sha256(b"zstd").to_string(),
+        ];
+        let descriptor = put_image(layout, &config, layers);
+        layout.tag("latest", descriptor.clone()).unwrap();
+        let manifest = read_manifest(layout, &descriptor.digest).unwrap();
+        (descriptor, manifest)
+    }
+
+    #[test]
+    fn passes_intact_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (manifest, _) = tag_image(&layout);
+        let index = Index {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            manifests: vec![manifest],
+            media_type: Some(MEDIA_TYPE_IMAGE_INDEX.to_string()),
+            annotations: None,
+        };
+        let index = put_json(&layout, MEDIA_TYPE_IMAGE_INDEX, &index).unwrap();
+        layout.tag("index", index).unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(report.issues, vec![]);
+        assert_eq!(report.blobs_checked, 6);
+        assert!(report.is_ok());
+    }
+
+    #[test]
+    fn detects_corrupt_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (_, manifest) = tag_image(&layout);
+        let layer = &manifest.layers[0];
+        std::fs::write(layout.blobs().blob_path(&layer.digest), b"PLAIN").unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::CorruptBlob {
+                digest: layer.digest.clone(),
+                actual: Digest::sha256(b"PLAIN"),
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_blobs_of_unsupported_algorithms() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        tag_image(&layout);
+        let encoded = "a".repeat(64);
+        let blake3 = dir.path().join("blobs").join("blake3");
+        std::fs::create_dir_all(&blake3).unwrap();
+        std::fs::write(blake3.join(&encoded), b"stray").unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::UnsupportedAlgorithm {
+                digest: Digest::new("blake3", &encoded).unwrap(),
+            }]
+        );
+        assert_eq!(report.blobs_checked, 6);
+    }
+
+    #[test]
+    fn detects_missing_and_truncated_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (_, manifest) = tag_image(&layout);
+        layout.delete(&manifest.layers[1].digest).unwrap();
+
+        let mut descriptor = manifest.layers[0].clone();
+        descriptor.size += 1;
+        descriptor.media_type = "application/octet-stream".to_string();
+        layout.tag("other", descriptor.clone()).unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![
+                FsckIssue::MissingBlob {
+                    digest: manifest.layers[1].digest.clone(),
+                },
+                FsckIssue::SizeMismatch {
+                    digest: descriptor.digest,
+                    expected: 6,
+                    actual: 5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_mismatched_media_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (mut descriptor, _) = tag_image(&layout);
+        descriptor.media_type = MEDIA_TYPE_IMAGE_INDEX.to_string();
+        layout.tag("latest", descriptor.clone()).unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::MediaTypeMismatch {
+                digest: descriptor.digest,
+                expected: MEDIA_TYPE_IMAGE_INDEX.to_string(),
+                actual: MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+            }]
+        );
+
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![Digest::sha256(b"layer").to_string()];
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: None,
+            config: put_json(&layout, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap(),
+            layers: vec![put_layer(&layout, "application/octet-stream", b"layer")],
+            annotations: None,
+        };
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        layout.tag("latest", descriptor).unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::MediaTypeMismatch {
+                digest: manifest.layers[0].digest.clone(),
+                expected: MEDIA_TYPE_IMAGE_LAYER.to_string(),
+                actual: "application/octet-stream".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn detects_invalid_configs() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let config = Descriptor {
+            media_type: MEDIA_TYPE_IMAGE_CONFIG.to_string(),
+            digest: layout.put(b"{}").unwrap(),
+            size: 2,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        };
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: None,
+            config: config.clone(),
+            layers: vec![],
+            annotations: None,
+        };
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        layout.tag("latest", descriptor).unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(report.issues.len(), 1);
+        match &report.issues[0] {
+            FsckIssue::Invalid { digest, message } => {
+                assert_eq!(digest, &config.digest);
+                assert!(message.contains("architecture"), "{}", message);
+            }
+            issue => panic!("Received unexpected issue: {:?}", issue),
+        }
+    }
+
+    #[test]
+    fn detects_mismatched_diff_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let layers = vec![
+            put_layer(&layout, MEDIA_TYPE_IMAGE_LAYER_GZIP, b"one"),
+            put_layer(&layout, MEDIA_TYPE_IMAGE_LAYER_ZSTD, b"two"),
+        ];
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![
+            Digest::sha256(b"one").to_string(),
+            Digest::sha256(b"three").to_string(),
+        ];
+        let mut manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: None,
+            config: put_json(&layout, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap(),
+            layers,
+            annotations: None,
+        };
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        layout.tag("latest", descriptor.clone()).unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::DiffIdMismatch {
+                manifest: descriptor.digest,
+                layer: manifest.layers[1].digest.clone(),
+                expected: Digest::sha256(b"three"),
+                actual: Digest::sha256(b"two"),
+            }]
+        );
+
+        manifest.layers.pop();
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        layout.tag("latest", descriptor.clone()).unwrap();
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::LayerCountMismatch {
+                manifest: descriptor.digest,
+                layers: 1,
+                diff_ids: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn checks_docker_manifests_and_lists() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let mut layer = put_layer(&layout, MEDIA_TYPE_IMAGE_LAYER_GZIP, b"one");
+        layer.media_type = MEDIA_TYPE_DOCKER_LAYER_GZIP.to_string();
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![Digest::sha256(b"two").to_string()];
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string()),
+            config: put_json(&layout, MEDIA_TYPE_DOCKER_CONFIG, &config).unwrap(),
+            layers: vec![layer.clone()],
+            annotations: None,
+        };
+        let image = put_json(&layout, MEDIA_TYPE_DOCKER_MANIFEST, &manifest).unwrap();
+        let list = Index {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_DOCKER_MANIFEST_LIST.to_string()),
+            artifact_type: None,
+            subject: None,
+            manifests: vec![image.clone()],
+            annotations: None,
+        };
+        let list = put_json(&layout, MEDIA_TYPE_DOCKER_MANIFEST_LIST, &list).unwrap();
+        layout.tag("latest", list).unwrap();
+
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::DiffIdMismatch {
+                manifest: image.digest,
+                layer: layer.digest,
+                expected: Digest::sha256(b"two"),
+                actual: Digest::sha256(b"one"),
+            }]
+        );
+    }
+
+    #[test]
+    fn passes_over_missing_foreign_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let mut foreign = put_layer(&layout, MEDIA_TYPE_IMAGE_LAYER_NONDISTRIBUTABLE, b"base");
+        foreign.urls = Some(vec!["https://example.com/base".to_string()]);
+        layout.delete(&foreign.digest).unwrap();
+        let layers = vec![
+            foreign,
+            put_layer(&layout, MEDIA_TYPE_IMAGE_LAYER_GZIP, b"two"),
+        ];
+        let mut config = image_config();
+        config.rootfs.diff_ids = vec![
+            Digest::sha256(b"base").to_string(),
+            Digest::sha256(b"three").to_string(),
+        ];
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: None,
+            config: put_json(&layout, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap(),
+            layers,
+            annotations: None,
+        };
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        layout.tag("latest", descriptor.clone()).unwrap();
+
+        // the layer after the missing one is still checked against its own diff_id
+        let report = layout.fsck().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![FsckIssue::DiffIdMismatch {
+                manifest: descriptor.digest,
+                layer: manifest.layers[1].digest.clone(),
+                expected: Digest::sha256(b"three"),
+                actual: Digest::sha256(b"two"),
+            }]
+        );
+    }
+}
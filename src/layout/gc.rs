@@ -0,0 +1,436 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::config::v1::from_json_reader;
+use crate::layout::OciLayout;
+use crate::store::{BlobStore, StoreError};
+use crate::v1::{is_image_index, is_image_manifest, Descriptor, Digest, ParseLimits};
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcOptions {
+    /// Only report what would be removed.
+    pub dry_run: bool,
+    /// Blobs to keep, along with everything they reference, even if `index.json` doesn't.
+    pub retain: Vec<Digest>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcReport {
+    /// Number of blobs reachable from `index.json` or the retained roots.
+    pub reachable: usize,
+    /// Blobs nothing references, which were removed unless running dry.
+    pub unreachable: Vec<Digest>,
+    pub reclaimed_bytes: u64,
+    /// Blobs that are referenced but aren't in the layout.
+    pub missing: Vec<Digest>,
+}
+
+// the parts of a manifest or index that point at other blobs
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+    config: Option<Descriptor>,
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+    subject: Option<Descriptor>,
+}
+
+// just enough to tell a manifest or index apart from other blobs
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Probe {
+    schema_version: Option<IgnoredAny>,
+}
+
+impl Node {
+    fn children(self) -> impl Iterator<Item = Descriptor> {
+        self.manifests
+            .into_iter()
+            .chain(self.config)
+            .chain(self.layers)
+            .chain(self.subject)
+    }
+}
+
+impl OciLayout {
+    /// Removes every blob that can't be reached from `index.json` or `options.retain`.
+    ///
+    /// Marking follows indexes to manifests and manifests to their config and layers. A manifest
+    /// whose `subject` is reachable, such as a signature or an SBOM, is kept along with what it
+    /// references. Blobs written but not yet referenced by `index.json` count as unreachable, so
+    /// nothing should be writing to the layout while it's being collected.
+    ///
+    /// A manifest or index that can't be read fails the collection before anything is removed,
+    /// rather than leaving what it references unmarked.
+    pub fn gc(&self, options: &GcOptions) -> Result<GcReport, StoreError> {
+        let mut marker = Marker {
+            layout: self,
+            marked: BTreeSet::new(),
+            missing: BTreeSet::new(),
+        };
+        for descriptor in self.index()?.manifests {
+            marker.mark(&descriptor.digest, Some(&descriptor.media_type))?;
+        }
+        for digest in &options.retain {
+            marker.mark(digest, None)?;
+        }
+
+        // referrers point at what they annotate rather than the other way around, so they're
+        // found among the unmarked blobs, repeatedly since referrers can have referrers too
+        let mut subjects = BTreeMap::new();
+        for digest in self.list()? {
+            if !marker.marked.contains(&digest) {
+                if let Some(node) = marker.node(&digest, None)? {
+                    if let Some(subject) = node.subject {
+                        subjects.insert(digest, subject.digest);
+                    }
+                }
+            }
+        }
+        loop {
+            let referrers: Vec<Digest> = subjects
+                .iter()
+                .filter(|(_, subject)| marker.marked.contains(subject))
+                .map(|(referrer, _)| referrer.clone())
+                .collect();
+            if referrers.is_empty() {
+                break;
+            }
+            for referrer in referrers {
+                subjects.remove(&referrer);
+                marker.mark(&referrer, None)?;
+            }
+        }
+
+        let mut report = GcReport {
+            reachable: marker.marked.len() - marker.missing.len(),
+            missing: marker.missing.into_iter().collect(),
+            ..GcReport::default()
+        };
+        for digest in self.list()? {
+            if marker.marked.contains(&digest) {
+                continue;
+            }
+            report.reclaimed_bytes += self.stat(&digest)?.unwrap_or(0);
+            if !options.dry_run {
+                self.delete(&digest)?;
+            }
+            report.unreachable.push(digest);
+        }
+        Ok(report)
+    }
+}
+
+struct Marker<'a> {
+    layout: &'a OciLayout,
+    marked: BTreeSet<Digest>,
+    missing: BTreeSet<Digest>,
+}
+
+impl<'a> Marker<'a> {
+    // `media_type` is `None` for roots that weren't reached through a descriptor
+    fn mark(&mut self, digest: &Digest, media_type: Option<&str>) -> Result<(), StoreError> {
+        let mut pending = vec![(digest.clone(), media_type.map(String::from))];
+        while let Some((digest, media_type)) = pending.pop() {
+            if !self.marked.insert(digest.clone()) {
+                continue;
+            }
+            if !self.layout.contains(&digest)? {
+                self.missing.insert(digest);
+                continue;
+            }
+            let may_have_children = match &media_type {
+                Some(media_type) => is_image_manifest(media_type) || is_image_index(media_type),
+                None => true,
+            };
+            if !may_have_children {
+                continue;
+            }
+            if let Some(node) = self.node(&digest, media_type.as_deref())? {
+                for child in node.children() {
+                    pending.push((child.digest, Some(child.media_type)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Blobs of an unknown media type are only followed if they're JSON objects with a
+    // `schemaVersion`; layers and configs don't reference other blobs. Failing to parse what is
+    // a manifest or index is an error, as skipping it would sweep everything it references.
+    fn node(&self, digest: &Digest, media_type: Option<&str>) -> Result<Option<Node>, StoreError> {
+        if media_type.is_none() {
+            let unlimited = ParseLimits {
+                max_bytes: u64::MAX,
+                max_depth: usize::MAX,
+                max_collection_len: usize::MAX,
+            };
+            match from_json_reader::<Probe, _>(self.layout.reader(digest)?, &unlimited) {
+                Ok(Probe {
+                    schema_version: Some(_),
+                }) => {}
+                _ => return Ok(None),
+            }
+        }
+        match from_json_reader(self.layout.reader(digest)?, &ParseLimits::default()) {
+            Ok(node) => Ok(Some(node)),
+            Err(error) => Err(StoreError::Invalid(format!(
+                "can't follow the references of `{}`: {}",
+                digest, error
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::{put_json, read_manifest};
+    use crate::v1::{
+        Index, Manifest, MEDIA_TYPE_DOCKER_CONFIG, MEDIA_TYPE_DOCKER_MANIFEST,
+        MEDIA_TYPE_DOCKER_MANIFEST_LIST, MEDIA_TYPE_IMAGE_CONFIG, MEDIA_TYPE_IMAGE_LAYER,
+        MEDIA_TYPE_IMAGE_MANIFEST,
+    };
+
+    fn image_with_layer(layout: &OciLayout, layer: &[u8]) -> (Descriptor, Vec<Digest>) {
+        let layer = Descriptor {
+            media_type: MEDIA_TYPE_IMAGE_LAYER.to_string(),
+            digest: layout.put(layer).unwrap(),
+            size: layer.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        };
+        let descriptor = put_image(layout, &image_config(), vec![layer.clone()]);
+        let config = read_manifest(layout, &descriptor.digest).unwrap().config;
+        let digests = vec![descriptor.digest.clone(), config.digest, layer.digest];
+        (descriptor, digests)
+    }
+
+    fn config_json(digests: &[Digest]) -> serde_json::Value {
+        serde_json::json!({
+            "mediaType": MEDIA_TYPE_IMAGE_CONFIG,
+            "digest": digests[1].to_string(),
+            "size": 1
+        })
+    }
+
+    fn sorted(mut digests: Vec<Digest>) -> Vec<Digest> {
+        digests.sort();
+        digests.dedup();
+        digests
+    }
+
+    #[test]
+    fn removes_blobs_of_untagged_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (kept, kept_digests) = image_with_layer(&layout, b"kept layer");
+        let (removed, removed_digests) = image_with_layer(&layout, b"removed layer");
+        layout.tag("kept", kept).unwrap();
+        layout.tag("removed", removed).unwrap();
+        layout.untag("removed").unwrap();
+
+        let report = layout.gc(&GcOptions::default()).unwrap();
+        // the config is shared by both images
+        assert_eq!(
+            report.unreachable,
+            sorted(vec![removed_digests[0].clone(), removed_digests[2].clone()])
+        );
+        assert_eq!(report.reachable, 3);
+        assert!(report.reclaimed_bytes > 0);
+        assert!(report.missing.is_empty());
+        assert_eq!(layout.list().unwrap(), sorted(kept_digests));
+    }
+
+    #[test]
+    fn only_reports_on_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (_, digests) = image_with_layer(&layout, b"layer");
+
+        let options = GcOptions {
+            dry_run: true,
+            ..GcOptions::default()
+        };
+        let report = layout.gc(&options).unwrap();
+        assert_eq!(report.unreachable, sorted(digests.clone()));
+        assert_eq!(layout.list().unwrap(), sorted(digests));
+    }
+
+    #[test]
+    fn keeps_retained_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (descriptor, digests) = image_with_layer(&layout, b"layer");
+        let stray = layout.put(b"stray").unwrap();
+
+        let options = GcOptions {
+            retain: vec![descriptor.digest],
+            ..GcOptions::default()
+        };
+        let report = layout.gc(&options).unwrap();
+        assert_eq!(report.unreachable, vec![stray]);
+        assert_eq!(layout.list().unwrap(), sorted(digests));
+    }
+
+    #[test]
+    fn keeps_referrers_of_reachable_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (image, image_digests) = image_with_layer(&layout, b"layer");
+        layout.tag("latest", image.clone()).unwrap();
+
+        let signature = layout.put(b"signature").unwrap();
+        let referrer = serde_json::json!({
+            "schemaVersion": 2,
+            "mediaType": MEDIA_TYPE_IMAGE_MANIFEST,
+            "config": config_json(&image_digests),
+            "layers": [{
+                "mediaType": "application/vnd.example.signature",
+                "digest": signature.to_string(),
+                "size": 9
+            }],
+            "subject": image
+        });
+        let referrer = layout.put(&serde_json::to_vec(&referrer).unwrap()).unwrap();
+        // and a referrer of the referrer
+        let nested = serde_json::json!({
+            "schemaVersion": 2,
+            "config": config_json(&image_digests),
+            "layers": [],
+            "subject": {
+                "mediaType": MEDIA_TYPE_IMAGE_MANIFEST,
+                "digest": referrer.to_string(),
+                "size": 1
+            }
+        });
+        let nested = layout.put(&serde_json::to_vec(&nested).unwrap()).unwrap();
+        let orphan = serde_json::json!({
+            "schemaVersion": 2,
+            "config": config_json(&image_digests),
+            "layers": [],
+            "subject": {
+                "mediaType": MEDIA_TYPE_IMAGE_MANIFEST,
+                "digest": Digest::sha256(b"gone").to_string(),
+                "size": 1
+            }
+        });
+        let orphan = layout.put(&serde_json::to_vec(&orphan).unwrap()).unwrap();
+
+        let report = layout.gc(&GcOptions::default()).unwrap();
+        assert_eq!(report.unreachable, vec![orphan]);
+        let mut expected = image_digests;
+        expected.extend(vec![signature, referrer, nested]);
+        assert_eq!(layout.list().unwrap(), sorted(expected));
+    }
+
+    #[test]
+    fn reports_missing_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let (image, digests) = image_with_layer(&layout, b"layer");
+        layout.tag("latest", image).unwrap();
+        layout.delete(&digests[2]).unwrap();
+
+        let report = layout.gc(&GcOptions::default()).unwrap();
+        assert_eq!(report.missing, vec![digests[2].clone()]);
+        assert_eq!(report.reachable, 2);
+        assert!(report.unreachable.is_empty());
+    }
+
+    #[test]
+    fn follows_docker_manifests_and_lists() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let config = put_json(&layout, MEDIA_TYPE_DOCKER_CONFIG, &image_config()).unwrap();
+        let layer = Descriptor {
+            media_type: "application/vnd.docker.image.rootfs.diff.tar.gzip".to_string(),
+            digest: layout.put(b"layer").unwrap(),
+            size: 5,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        };
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string()),
+            config: config.clone(),
+            layers: vec![layer.clone()],
+            annotations: None,
+        };
+        let manifest = put_json(&layout, MEDIA_TYPE_DOCKER_MANIFEST, &manifest).unwrap();
+        let list = Index {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            manifests: vec![manifest.clone()],
+            media_type: Some(MEDIA_TYPE_DOCKER_MANIFEST_LIST.to_string()),
+            annotations: None,
+        };
+        let list = put_json(&layout, MEDIA_TYPE_DOCKER_MANIFEST_LIST, &list).unwrap();
+        layout.tag("latest", list.clone()).unwrap();
+
+        let report = layout.gc(&GcOptions::default()).unwrap();
+        assert!(report.unreachable.is_empty());
+        assert_eq!(
+            layout.list().unwrap(),
+            sorted(vec![
+                list.digest,
+                manifest.digest,
+                config.digest,
+                layer.digest
+            ])
+        );
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn fails_without_removing_anything_on_unreadable_manifests() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            let (image, digests) = image_with_layer(&layout, b"layer");
+            layout.tag("latest", image).unwrap();
+            let stray = layout.put(b"stray").unwrap();
+            // more layers than the default limits allow
+            let layers: Vec<serde_json::Value> = (0..10_001)
+                .map(|_| serde_json::json!({ "mediaType": MEDIA_TYPE_IMAGE_LAYER, "digest": digests[2].to_string(), "size": 5 }))
+                .collect();
+            let huge = serde_json::json!({
+                "schemaVersion": 2,
+                "config": config_json(&digests),
+                "layers": layers,
+            });
+            let huge = serde_json::to_vec(&huge).unwrap();
+            let descriptor = Descriptor {
+                media_type: MEDIA_TYPE_IMAGE_MANIFEST.to_string(),
+                digest: layout.put(&huge).unwrap(),
+                size: huge.len() as u64,
+                urls: None,
+                artifact_type: None,
+                annotations: None,
+                platform: None,
+            };
+            layout.tag("huge", descriptor.clone()).unwrap();
+
+            assert!(matches!(
+                layout.gc(&GcOptions::default()),
+                Err(StoreError::Invalid(_))
+            ));
+            let mut expected = digests;
+            expected.extend(vec![stray, descriptor.digest]);
+            assert_eq!(layout.list().unwrap(), sorted(expected));
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::store::{BlobStore, BlobWriter, FsBlobStore, StoreError};
+use crate::v1::{
+    parse_image_index_with_limits, Descriptor, Digest, Index, ParseLimits, ANNOTATION_REF_NAME,
+    MEDIA_TYPE_IMAGE_INDEX,
+};
+
+use serde::{Deserialize, Serialize};
+
+mod docker_archive;
+pub use docker_archive::DOCKER_ARCHIVE_MANIFEST;
+
+mod fsck;
+pub use fsck::{FsckIssue, FsckReport};
+
+mod gc;
+pub use gc::{GcOptions, GcReport};
+
+mod referrers;
+pub use referrers::referrers_tag;
+
+pub const LAYOUT_FILE: &str = "oci-layout";
+pub const INDEX_FILE: &str = "index.json";
+pub const BLOBS_DIR: &str = "blobs";
+pub const IMAGE_LAYOUT_VERSION: &str = "1.0.0";
+
+/// Limits [`OciLayout::index`] reads `index.json` with. Layouts are local and may well hold more
+/// images than the default limits, meant for documents pulled from elsewhere, allow.
+pub const INDEX_PARSE_LIMITS: ParseLimits = ParseLimits {
+    max_bytes: 256 * 1024 * 1024,
+    max_depth: 64,
+    max_collection_len: 1_000_000,
+};
+
+static INDEX_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutHeader {
+    pub image_layout_version: String,
+}
+
+/// An OCI image layout directory: `oci-layout`, `index.json` and content-addressed `blobs/`.
+///
+/// Blobs are accessed through its [`BlobStore`] implementation; references to them are
+/// recorded in `index.json`, optionally named through `org.opencontainers.image.ref.name`.
+#[derive(Debug, Clone)]
+pub struct OciLayout {
+    root: PathBuf,
+    blobs: FsBlobStore,
+}
+
+impl OciLayout {
+    /// Initializes a layout at `root`, keeping any existing `index.json` and blobs.
+    pub fn create<P: AsRef<Path>>(root: P) -> Result<Self, StoreError> {
+        let root = root.as_ref();
+        fs::create_dir_all(root.join(BLOBS_DIR))?;
+        let header = LayoutHeader {
+            image_layout_version: IMAGE_LAYOUT_VERSION.to_string(),
+        };
+        fs::write(root.join(LAYOUT_FILE), serde_json::to_vec(&header)?)?;
+
+        let layout = Self::at(root);
+        if !root.join(INDEX_FILE).exists() {
+            layout.write_index(&Index {
+                schema_version: 2,
+                artifact_type: None,
+                subject: None,
+                manifests: vec![],
+                media_type: Some(MEDIA_TYPE_IMAGE_INDEX.to_string()),
+                annotations: None,
+            })?;
+        }
+        Ok(layout)
+    }
+
+    /// Opens an existing layout, checking its `oci-layout` header.
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self, StoreError> {
+        let root = root.as_ref();
+        let header: LayoutHeader = match fs::read(root.join(LAYOUT_FILE)) {
+            Ok(raw) => serde_json::from_slice(&raw)?,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Err(StoreError::Invalid(format!(
+                    "`{}` is not an OCI image layout: missing `{}`",
+                    root.display(),
+                    LAYOUT_FILE
+                )))
+            }
+            Err(error) => return Err(error.into()),
+        };
+        if header.image_layout_version != IMAGE_LAYOUT_VERSION {
+            return Err(StoreError::Invalid(format!(
+                "unsupported image layout version `{}`",
+                header.image_layout_version
+            )));
+        }
+        Ok(Self::at(root))
+    }
+
+    fn at(root: &Path) -> Self {
+        OciLayout {
+            root: root.to_path_buf(),
+            // partially written blobs live next to `blobs/` to keep it free of stray files
+            blobs: FsBlobStore::with_ingest_dir(root.join(BLOBS_DIR), root),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn blobs(&self) -> &FsBlobStore {
+        &self.blobs
+    }
+
+    /// Reads `index.json` with [`INDEX_PARSE_LIMITS`].
+    pub fn index(&self) -> Result<Index, StoreError> {
+        self.index_with_limits(&INDEX_PARSE_LIMITS)
+    }
+
+    pub fn index_with_limits(&self, limits: &ParseLimits) -> Result<Index, StoreError> {
+        let mut file = fs::File::open(self.root.join(INDEX_FILE))?;
+        Ok(parse_image_index_with_limits(&mut file, limits)?)
+    }
+
+    /// Replaces `index.json`. Readers never observe it half written, but changes made since
+    /// `index` was read are lost; use [`update_index`](OciLayout::update_index) to change it.
+    pub fn write_index(&self, index: &Index) -> Result<(), StoreError> {
+        // write-then-rename so readers never observe a truncated `index.json`
+        let tmp = self.root.join(format!(
+            ".{}-{}-{}.tmp",
+            INDEX_FILE,
+            std::process::id(),
+            INDEX_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let result = fs::write(&tmp, serde_json::to_vec(index)?)
+            .and_then(|_| fs::rename(&tmp, self.root.join(INDEX_FILE)));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        Ok(result?)
+    }
+
+    /// Applies `update` to `index.json`, writing it back if it changed. Updates hold an exclusive
+    /// lock on the layout's `oci-layout` file, so concurrent ones, from this process or others,
+    /// don't overwrite each other's changes. `update` must not go through `tag`, `untag` or
+    /// `update_index` itself, which would wait for the lock forever.
+    pub fn update_index<F, T>(&self, update: F) -> Result<T, StoreError>
+    where
+        F: FnOnce(&mut Index) -> Result<T, StoreError>,
+    {
+        // released when dropped; `oci-layout` is never replaced, unlike `index.json`
+        let lock = fs::File::open(self.root.join(LAYOUT_FILE))?;
+        lock.lock()?;
+        let mut index = self.index()?;
+        let original = index.clone();
+        let result = update(&mut index)?;
+        if index != original {
+            self.write_index(&index)?;
+        }
+        Ok(result)
+    }
+
+    /// Records `descriptor` in `index.json` under `name`, replacing whatever had that name.
+    pub fn tag(&self, name: &str, descriptor: Descriptor) -> Result<(), StoreError> {
+        self.update_index(|index| {
+            set_ref_name(index, name, descriptor);
+            Ok(())
+        })
+    }
+
+    /// Removes the entry named `name` from `index.json`, returning it if there was one.
+    pub fn untag(&self, name: &str) -> Result<Option<Descriptor>, StoreError> {
+        self.update_index(|index| {
+            let position = index
+                .manifests
+                .iter()
+                .position(|x| ref_name(x) == Some(name));
+            Ok(position.map(|x| index.manifests.remove(x)))
+        })
+    }
+
+    pub fn resolve(&self, name: &str) -> Result<Option<Descriptor>, StoreError> {
+        Ok(self
+            .index()?
+            .manifests
+            .into_iter()
+            .find(|x| ref_name(x) == Some(name)))
+    }
+
+    pub fn tags(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self
+            .index()?
+            .manifests
+            .iter()
+            .filter_map(|x| ref_name(x).map(String::from))
+            .collect())
+    }
+}
+
+// what `tag` does to the index
+pub(crate) fn set_ref_name(index: &mut Index, name: &str, mut descriptor: Descriptor) {
+    index.manifests.retain(|x| ref_name(x) != Some(name));
+    descriptor
+        .annotations
+        .get_or_insert_with(HashMap::new)
+        .insert(ANNOTATION_REF_NAME.to_string(), name.to_string());
+    index.manifests.push(descriptor);
+}
+
+pub(crate) fn ref_name(descriptor: &Descriptor) -> Option<&str> {
+    descriptor
+        .annotations
+        .as_ref()
+        .and_then(|x| x.get(ANNOTATION_REF_NAME))
+        .map(|x| x.as_str())
+}
+
+impl BlobStore for OciLayout {
+    fn stat(&self, digest: &Digest) -> Result<Option<u64>, StoreError> {
+        self.blobs.stat(digest)
+    }
+
+    fn reader(&self, digest: &Digest) -> Result<Box<dyn Read + '_>, StoreError> {
+        self.blobs.reader(digest)
+    }
+
+    fn writer(&self) -> Result<Box<dyn BlobWriter + '_>, StoreError> {
+        self.blobs.writer()
+    }
+
+    fn delete(&self, digest: &Digest) -> Result<bool, StoreError> {
+        self.blobs.delete(digest)
+    }
+
+    fn list(&self) -> Result<Vec<Digest>, StoreError> {
+        self.blobs.list()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::*;
+    use crate::store::{put_json, resolve_image_config};
+    use crate::v1::{Manifest, MEDIA_TYPE_IMAGE_CONFIG, MEDIA_TYPE_IMAGE_MANIFEST};
+
+    #[test]
+    fn honours_blob_store_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_blob_store_contract(&OciLayout::create(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn creates_layout_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        layout.put(b"{}").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dir.path().join(LAYOUT_FILE)).unwrap(),
+            r#"{"imageLayoutVersion":"1.0.0"}"#
+        );
+        assert!(layout.index().unwrap().manifests.is_empty());
+        assert!(dir
+            .path()
+            .join("blobs/sha256/44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a")
+            .exists());
+    }
+
+    #[test]
+    fn refuses_to_open_non_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = OciLayout::open(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("missing `oci-layout`"));
+    }
+
+    #[test]
+    fn tags_and_resolves_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+
+        let config = put_json(&layout, MEDIA_TYPE_IMAGE_CONFIG, &image_config()).unwrap();
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config,
+            layers: vec![],
+            annotations: None,
+        };
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        layout.tag("v1", descriptor.clone()).unwrap();
+        layout.tag("latest", descriptor.clone()).unwrap();
+        layout.tag("v1", descriptor.clone()).unwrap();
+
+        let layout = OciLayout::open(dir.path()).unwrap();
+        assert_eq!(layout.tags().unwrap(), vec!["latest", "v1"]);
+        let resolved = layout.resolve("v1").unwrap().unwrap();
+        assert_eq!(resolved.digest, descriptor.digest);
+        let (_, config) = resolve_image_config(&layout, &resolved.digest).unwrap();
+        assert_eq!(config, image_config());
+
+        assert!(layout.untag("v1").unwrap().is_some());
+        assert_eq!(layout.resolve("v1").unwrap(), None);
+        assert_eq!(layout.tags().unwrap(), vec!["latest"]);
+    }
+
+    #[test]
+    fn reads_indexes_with_many_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_CONFIG, &image_config()).unwrap();
+        let mut index = layout.index().unwrap();
+        index.manifests = vec![descriptor; ParseLimits::default().max_collection_len + 1];
+        layout.write_index(&index).unwrap();
+
+        assert_eq!(layout.index().unwrap(), index);
+        assert!(layout.index_with_limits(&ParseLimits::default()).is_err());
+    }
+
+    #[test]
+    fn keeps_concurrent_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let descriptor = put_json(&layout, MEDIA_TYPE_IMAGE_CONFIG, &image_config()).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let layout = OciLayout::open(dir.path()).unwrap();
+                let descriptor = descriptor.clone();
+                std::thread::spawn(move || {
+                    for j in 0..10 {
+                        layout
+                            .tag(&format!("{}-{}", i, j), descriptor.clone())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(layout.tags().unwrap().len(), 80);
+        // nothing but the layout itself is left
+        let mut files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|x| x.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec![BLOBS_DIR, INDEX_FILE, LAYOUT_FILE]);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::layout::OciLayout;
+use crate::store::{put_json, read_index, StoreError};
+use crate::v1::{
+    Descriptor, Digest, Index, Manifest, MEDIA_TYPE_IMAGE_INDEX, MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+/// The tag schema fallback for referrers: the index of everything referring to `digest` is
+/// tagged `<algorithm>-<encoded>`, truncated to 32 and 64 characters respectively.
+pub fn referrers_tag(digest: &Digest) -> String {
+    let algorithm: String = digest.algorithm().chars().take(32).collect();
+    let encoded: String = digest.encoded().chars().take(64).collect();
+    format!("{}-{}", algorithm, encoded)
+}
+
+impl OciLayout {
+    /// Stores a manifest that refers to another one through its `subject`, such as a signature
+    /// or an SBOM, and adds it to the referrers index of the subject.
+    pub fn attach(&self, manifest: &Manifest) -> Result<Descriptor, StoreError> {
+        let subject = match &manifest.subject {
+            Some(subject) => subject,
+            None => {
+                return Err(StoreError::Invalid(
+                    "can't attach a manifest without a `subject`".to_string(),
+                ))
+            }
+        };
+        let mut descriptor = put_json(self, MEDIA_TYPE_IMAGE_MANIFEST, manifest)?;
+        // as the distribution spec has registries fill them in
+        descriptor.artifact_type = manifest
+            .artifact_type
+            .clone()
+            .or_else(|| Some(manifest.config.media_type.clone()));
+        descriptor.annotations = manifest.annotations.clone();
+
+        self.add_referrer(&subject.digest, &descriptor)?;
+        Ok(descriptor)
+    }
+
+    /// Adds `descriptor` to the referrers index of `subject`, replacing an existing entry for
+    /// the same manifest.
+    pub(crate) fn add_referrer(
+        &self,
+        subject: &Digest,
+        descriptor: &Descriptor,
+    ) -> Result<(), StoreError> {
+        let tag = referrers_tag(subject);
+        let mut index = match self.resolve(&tag)? {
+            Some(existing) => read_index(self, &existing.digest)?,
+            None => Index {
+                schema_version: 2,
+                artifact_type: None,
+                subject: None,
+                manifests: vec![],
+                media_type: Some(MEDIA_TYPE_IMAGE_INDEX.to_string()),
+                annotations: None,
+            },
+        };
+        match index
+            .manifests
+            .iter_mut()
+            .find(|x| x.digest == descriptor.digest)
+        {
+            Some(existing) => *existing = descriptor.clone(),
+            None => index.manifests.push(descriptor.clone()),
+        }
+        let index = put_json(self, MEDIA_TYPE_IMAGE_INDEX, &index)?;
+        self.tag(&tag, index)
+    }
+
+    /// Lists the manifests attached to `subject`, only keeping those of `artifact_type` if
+    /// it's given.
+    pub fn referrers(
+        &self,
+        subject: &Digest,
+        artifact_type: Option<&str>,
+    ) -> Result<Vec<Descriptor>, StoreError> {
+        let index = match self.resolve(&referrers_tag(subject))? {
+            Some(index) => read_index(self, &index.digest)?,
+            None => return Ok(vec![]),
+        };
+        Ok(index
+            .manifests
+            .into_iter()
+            .filter(|x| artifact_type.is_none() || x.artifact_type.as_deref() == artifact_type)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::GcOptions;
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::BlobStore;
+    use std::collections::HashMap;
+
+    const SBOM: &str = "application/vnd.example.sbom";
+    const SIGNATURE: &str = "application/vnd.example.signature";
+
+    fn tag_image(layout: &OciLayout) -> Descriptor {
+        let descriptor = put_image(layout, &image_config(), vec![]);
+        layout.tag("latest", descriptor.clone()).unwrap();
+        descriptor
+    }
+
+    fn referrer(layout: &OciLayout, subject: &Descriptor, artifact_type: &str) -> Manifest {
+        let mut annotations = HashMap::new();
+        annotations.insert("com.example.type".to_string(), artifact_type.to_string());
+        Manifest {
+            schema_version: 2,
+            artifact_type: Some(artifact_type.to_string()),
+            subject: Some(subject.clone()),
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config: put_json(
+                layout,
+                "application/vnd.oci.empty.v1+json",
+                &HashMap::<(), ()>::new(),
+            )
+            .unwrap(),
+            layers: vec![],
+            annotations: Some(annotations),
+        }
+    }
+
+    #[test]
+    fn lists_attached_referrers() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let image = tag_image(&layout);
+        assert!(layout.referrers(&image.digest, None).unwrap().is_empty());
+
+        let sbom = layout.attach(&referrer(&layout, &image, SBOM)).unwrap();
+        let signature = layout
+            .attach(&referrer(&layout, &image, SIGNATURE))
+            .unwrap();
+        // attaching the same manifest again doesn't list it twice
+        layout.attach(&referrer(&layout, &image, SBOM)).unwrap();
+
+        assert_eq!(sbom.artifact_type.as_deref(), Some(SBOM));
+        assert_eq!(sbom.annotations.as_ref().unwrap()["com.example.type"], SBOM);
+        assert_eq!(
+            layout.referrers(&image.digest, None).unwrap(),
+            vec![sbom.clone(), signature.clone()]
+        );
+        assert_eq!(
+            layout.referrers(&image.digest, Some(SIGNATURE)).unwrap(),
+            vec![signature]
+        );
+        assert!(layout
+            .referrers(&image.digest, Some("application/other"))
+            .unwrap()
+            .is_empty());
+
+        // referrers of referrers work the same way
+        let nested = layout.attach(&referrer(&layout, &sbom, SIGNATURE)).unwrap();
+        assert_eq!(layout.referrers(&sbom.digest, None).unwrap(), vec![nested]);
+    }
+
+    #[test]
+    fn maintains_fallback_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let image = tag_image(&layout);
+        layout.attach(&referrer(&layout, &image, SBOM)).unwrap();
+
+        let tag = format!("sha256-{}", image.digest.encoded());
+        assert_eq!(
+            layout.tags().unwrap(),
+            vec!["latest".to_string(), tag.clone()]
+        );
+        let index = read_index(&layout, &layout.resolve(&tag).unwrap().unwrap().digest).unwrap();
+        assert_eq!(index.manifests.len(), 1);
+
+        // superseded versions of the referrers index are garbage
+        layout
+            .attach(&referrer(&layout, &image, SIGNATURE))
+            .unwrap();
+        let report = layout.gc(&GcOptions::default()).unwrap();
+        assert_eq!(report.unreachable.len(), 1);
+        assert_eq!(layout.referrers(&image.digest, None).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn refuses_manifests_without_subject() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let image = tag_image(&layout);
+        let mut manifest = referrer(&layout, &image, SBOM);
+        manifest.subject = None;
+        assert!(matches!(
+            layout.attach(&manifest),
+            Err(StoreError::Invalid(_))
+        ));
+        assert_eq!(layout.list().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn truncates_long_digests_in_tags() {
+        let digest = Digest::new("sha512", &"a".repeat(128)).unwrap();
+        assert_eq!(referrers_tag(&digest), format!("sha512-{}", "a".repeat(64)));
+    }
+}
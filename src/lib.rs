@@ -1,46 +1,81 @@
 mod config;
+mod manifest;
+
+pub mod artifact;
+pub mod layer;
+pub mod layout;
+pub mod mutate;
+pub mod registry;
+pub mod signing;
+pub mod store;
 
 #[cfg(test)]
 mod test_helpers;
 
-pub use config::v1;
+pub mod v1 {
+    pub use crate::config::v1::*;
+    pub use crate::manifest::v1::*;
+}
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    const CRATE_NAME: &'static str = "oci_image_spec_rs";
+    const CRATE_NAME: &str = "oci_image_spec_rs";
 
     #[test]
     fn test_exports_visibility() {
         let env_var_type_name = std::any::type_name::<v1::EnvVar>();
-        assert!(env_var_type_name.contains(&CRATE_NAME));
+        assert!(env_var_type_name.contains(CRATE_NAME));
 
         let port_protocol_type_name = std::any::type_name::<v1::PortProtocol>();
-        assert!(port_protocol_type_name.contains(&CRATE_NAME));
+        assert!(port_protocol_type_name.contains(CRATE_NAME));
         let exposed_ports_type_name = std::any::type_name::<v1::ExposedPorts>();
-        assert!(exposed_ports_type_name.contains(&CRATE_NAME));
+        assert!(exposed_ports_type_name.contains(CRATE_NAME));
 
         let parse_error_type_name = std::any::type_name::<v1::ParseError>();
-        assert!(parse_error_type_name.contains(&CRATE_NAME));
+        assert!(parse_error_type_name.contains(CRATE_NAME));
+        let json_path_error_type_name = std::any::type_name::<v1::JsonPathError>();
+        assert!(json_path_error_type_name.contains(CRATE_NAME));
 
         let architecture_type_name = std::any::type_name::<v1::Architecture>();
-        assert!(architecture_type_name.contains(&CRATE_NAME));
+        assert!(architecture_type_name.contains(CRATE_NAME));
         let os_type_name = std::any::type_name::<v1::OS>();
-        assert!(os_type_name.contains(&CRATE_NAME));
+        assert!(os_type_name.contains(CRATE_NAME));
         let root_fs_type_name = std::any::type_name::<v1::RootFS>();
-        assert!(root_fs_type_name.contains(&CRATE_NAME));
+        assert!(root_fs_type_name.contains(CRATE_NAME));
         let config_type_name = std::any::type_name::<v1::Config>();
-        assert!(config_type_name.contains(&CRATE_NAME));
+        assert!(config_type_name.contains(CRATE_NAME));
         let history_type_name = std::any::type_name::<v1::History>();
-        assert!(history_type_name.contains(&CRATE_NAME));
+        assert!(history_type_name.contains(CRATE_NAME));
 
         let root_fs_type_type_name = std::any::type_name::<v1::RootFSType>();
-        assert!(root_fs_type_type_name.contains(&CRATE_NAME));
+        assert!(root_fs_type_type_name.contains(CRATE_NAME));
         let volumes_root_fs_type_name = std::any::type_name::<v1::Volumes>();
-        assert!(volumes_root_fs_type_name.contains(&CRATE_NAME));
+        assert!(volumes_root_fs_type_name.contains(CRATE_NAME));
 
         let image_config_type_name = std::any::type_name::<v1::ImageConfig>();
-        assert!(image_config_type_name.contains(&CRATE_NAME));
+        assert!(image_config_type_name.contains(CRATE_NAME));
+        let image_config_diff_type_name = std::any::type_name::<v1::ImageConfigDiff>();
+        assert!(image_config_diff_type_name.contains(CRATE_NAME));
+
+        let digest_type_name = std::any::type_name::<v1::Digest>();
+        assert!(digest_type_name.contains(CRATE_NAME));
+        let descriptor_type_name = std::any::type_name::<v1::Descriptor>();
+        assert!(descriptor_type_name.contains(CRATE_NAME));
+        let platform_type_name = std::any::type_name::<v1::Platform>();
+        assert!(platform_type_name.contains(CRATE_NAME));
+        let manifest_type_name = std::any::type_name::<v1::Manifest>();
+        assert!(manifest_type_name.contains(CRATE_NAME));
+        let index_type_name = std::any::type_name::<v1::Index>();
+        assert!(index_type_name.contains(CRATE_NAME));
+        let well_known_annotations_type_name =
+            std::any::type_name::<v1::WellKnownAnnotations<'static>>();
+        assert!(well_known_annotations_type_name.contains(CRATE_NAME));
+        let verifying_reader_type_name =
+            std::any::type_name::<v1::VerifyingReader<std::io::Empty>>();
+        assert!(verifying_reader_type_name.contains(CRATE_NAME));
+        let verification_error_type_name = std::any::type_name::<v1::VerificationError>();
+        assert!(verification_error_type_name.contains(CRATE_NAME));
     }
 }
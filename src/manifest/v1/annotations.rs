@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::config::v1::Config;
+use crate::manifest::v1::digest::Digest;
+use crate::manifest::v1::index::Index;
+use crate::manifest::v1::manifest::Manifest;
+
+use chrono::prelude::*;
+use url::Url;
+
+pub const ANNOTATION_CREATED: &str = "org.opencontainers.image.created";
+pub const ANNOTATION_AUTHORS: &str = "org.opencontainers.image.authors";
+pub const ANNOTATION_URL: &str = "org.opencontainers.image.url";
+pub const ANNOTATION_DOCUMENTATION: &str = "org.opencontainers.image.documentation";
+pub const ANNOTATION_SOURCE: &str = "org.opencontainers.image.source";
+pub const ANNOTATION_VERSION: &str = "org.opencontainers.image.version";
+pub const ANNOTATION_REVISION: &str = "org.opencontainers.image.revision";
+pub const ANNOTATION_VENDOR: &str = "org.opencontainers.image.vendor";
+pub const ANNOTATION_LICENSES: &str = "org.opencontainers.image.licenses";
+pub const ANNOTATION_REF_NAME: &str = "org.opencontainers.image.ref.name";
+pub const ANNOTATION_TITLE: &str = "org.opencontainers.image.title";
+pub const ANNOTATION_DESCRIPTION: &str = "org.opencontainers.image.description";
+pub const ANNOTATION_BASE_IMAGE_NAME: &str = "org.opencontainers.image.base.name";
+pub const ANNOTATION_BASE_IMAGE_DIGEST: &str = "org.opencontainers.image.base.digest";
+
+/// Typed, read-only view over the pre-defined `org.opencontainers.image.*` keys of a set of
+/// annotations or labels.
+///
+/// Free-form keys are returned as `&str`. Keys with a defined format return `Ok(None)` when
+/// absent and an [`AnnotationError`] when present but malformed.
+#[derive(Debug, Clone, Copy)]
+pub struct WellKnownAnnotations<'a> {
+    map: Option<&'a HashMap<String, String>>,
+}
+
+impl<'a> WellKnownAnnotations<'a> {
+    pub fn new(map: &'a HashMap<String, String>) -> Self {
+        WellKnownAnnotations { map: Some(map) }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.map.and_then(|x| x.get(key)).map(|x| x.as_str())
+    }
+
+    pub fn created(&self) -> Result<Option<DateTime<Utc>>, AnnotationError> {
+        self.parse_with(ANNOTATION_CREATED, |x| {
+            DateTime::parse_from_rfc3339(x)
+                .map(|x| x.with_timezone(&Utc))
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    pub fn authors(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_AUTHORS)
+    }
+
+    pub fn url(&self) -> Result<Option<Url>, AnnotationError> {
+        self.parse(ANNOTATION_URL)
+    }
+
+    pub fn documentation(&self) -> Result<Option<Url>, AnnotationError> {
+        self.parse(ANNOTATION_DOCUMENTATION)
+    }
+
+    pub fn source(&self) -> Result<Option<Url>, AnnotationError> {
+        self.parse(ANNOTATION_SOURCE)
+    }
+
+    pub fn version(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_VERSION)
+    }
+
+    pub fn revision(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_REVISION)
+    }
+
+    pub fn vendor(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_VENDOR)
+    }
+
+    // SPDX license expression, returned as-is
+    pub fn licenses(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_LICENSES)
+    }
+
+    pub fn ref_name(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_REF_NAME)
+    }
+
+    pub fn title(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_TITLE)
+    }
+
+    pub fn description(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_DESCRIPTION)
+    }
+
+    pub fn base_name(&self) -> Option<&'a str> {
+        self.get(ANNOTATION_BASE_IMAGE_NAME)
+    }
+
+    pub fn base_digest(&self) -> Result<Option<Digest>, AnnotationError> {
+        self.parse(ANNOTATION_BASE_IMAGE_DIGEST)
+    }
+
+    fn parse<T>(&self, key: &'static str) -> Result<Option<T>, AnnotationError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse_with(key, |x| x.parse().map_err(|e: T::Err| e.to_string()))
+    }
+
+    fn parse_with<T, F>(&self, key: &'static str, parse: F) -> Result<Option<T>, AnnotationError>
+    where
+        F: Fn(&str) -> Result<T, String>,
+    {
+        match self.get(key) {
+            Some(value) => parse(value).map(Some).map_err(|reason| AnnotationError {
+                key,
+                value: value.to_string(),
+                reason,
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Config {
+    pub fn well_known_labels(&self) -> WellKnownAnnotations<'_> {
+        WellKnownAnnotations {
+            map: self.labels.as_ref(),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn well_known_annotations(&self) -> WellKnownAnnotations<'_> {
+        WellKnownAnnotations {
+            map: self.annotations.as_ref(),
+        }
+    }
+}
+
+impl Index {
+    pub fn well_known_annotations(&self) -> WellKnownAnnotations<'_> {
+        WellKnownAnnotations {
+            map: self.annotations.as_ref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnotationError {
+    pub key: &'static str,
+    pub value: String,
+    pub reason: String,
+}
+
+impl Display for AnnotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid value `{}` for `{}`: {}",
+            self.value, self.key, self.reason
+        )
+    }
+}
+
+impl std::error::Error for AnnotationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotations(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_free_form_keys() {
+        let map = annotations(&[
+            (ANNOTATION_AUTHORS, "Some One <someone@some.where>"),
+            (ANNOTATION_VERSION, "1.2.3"),
+            (ANNOTATION_LICENSES, "Apache-2.0 OR MIT"),
+            (ANNOTATION_REF_NAME, "stable"),
+            (ANNOTATION_BASE_IMAGE_NAME, "docker.io/library/alpine:3.12"),
+        ]);
+        let view = WellKnownAnnotations::new(&map);
+
+        assert_eq!(view.authors(), Some("Some One <someone@some.where>"));
+        assert_eq!(view.version(), Some("1.2.3"));
+        assert_eq!(view.licenses(), Some("Apache-2.0 OR MIT"));
+        assert_eq!(view.ref_name(), Some("stable"));
+        assert_eq!(view.base_name(), Some("docker.io/library/alpine:3.12"));
+        assert_eq!(view.title(), None);
+    }
+
+    #[test]
+    fn parses_typed_keys() {
+        let map = annotations(&[
+            (ANNOTATION_CREATED, "2020-06-01T12:30:00+02:00"),
+            (ANNOTATION_SOURCE, "https://github.com/some/repo"),
+            (
+                ANNOTATION_BASE_IMAGE_DIGEST,
+                "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b",
+            ),
+        ]);
+        let view = WellKnownAnnotations::new(&map);
+
+        assert_eq!(
+            view.created().unwrap(),
+            Some(Utc.with_ymd_and_hms(2020, 6, 1, 10, 30, 0).unwrap())
+        );
+        assert_eq!(
+            view.source().unwrap().unwrap().as_str(),
+            "https://github.com/some/repo"
+        );
+        assert_eq!(view.base_digest().unwrap().unwrap().algorithm(), "sha256");
+        assert_eq!(view.url().unwrap(), None);
+    }
+
+    #[test]
+    fn reports_malformed_typed_keys() {
+        let map = annotations(&[
+            (ANNOTATION_CREATED, "yesterday"),
+            (ANNOTATION_URL, "not a url"),
+            (ANNOTATION_BASE_IMAGE_DIGEST, "sha256:abc"),
+        ]);
+        let view = WellKnownAnnotations::new(&map);
+
+        assert_eq!(view.created().unwrap_err().key, ANNOTATION_CREATED);
+        assert_eq!(view.url().unwrap_err().value, "not a url");
+        assert!(view
+            .base_digest()
+            .unwrap_err()
+            .to_string()
+            .starts_with("invalid value `sha256:abc` for `org.opencontainers.image.base.digest`"));
+    }
+
+    #[test]
+    fn reads_config_labels() {
+        let config: Config = serde_json::from_str(
+            r#"{"Labels": {"org.opencontainers.image.vendor": "Some Vendor"}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.well_known_labels().vendor(), Some("Some Vendor"));
+    }
+
+    #[test]
+    fn handles_missing_annotations() {
+        let index: Index =
+            serde_json::from_str(r#"{"schemaVersion": 2, "manifests": []}"#).unwrap();
+        let view = index.well_known_annotations();
+        assert_eq!(view.description(), None);
+        assert_eq!(view.created().unwrap(), None);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::config::v1::{Architecture, OS};
+use crate::manifest::v1::digest::Digest;
+use crate::manifest::v1::media_types::MEDIA_TYPE_EMPTY_JSON;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    // required
+    pub media_type: String,
+    pub digest: Digest,
+    pub size: u64,
+    // optional
+    /// Where the content can be downloaded from, for non-distributable layers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+}
+
+impl Descriptor {
+    /// The well-known descriptor of `{}`, used as the config of artifacts that don't need one.
+    pub fn empty() -> Self {
+        Descriptor {
+            media_type: MEDIA_TYPE_EMPTY_JSON.to_string(),
+            digest: Digest::sha256(EMPTY_JSON),
+            size: EMPTY_JSON.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        }
+    }
+}
+
+pub const EMPTY_JSON: &[u8] = b"{}";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Platform {
+    // required
+    pub architecture: Architecture,
+    pub os: OS,
+    // optional
+    #[serde(rename = "os.version", skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    #[serde(rename = "os.features", skip_serializing_if = "Option::is_none")]
+    pub os_features: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+}
+
+impl Platform {
+    /// The same platform with its variant spelled the way `containerd` compares them: `v8` for
+    /// `arm64` and `v7` for `arm` when there is none, and with a `v` in front of bare numbers.
+    pub fn normalized(&self) -> Platform {
+        let default = match self.architecture {
+            Architecture::Arm64 => "v8",
+            Architecture::Arm => "v7",
+            _ => return self.clone(),
+        };
+        let variant = match self.variant.as_deref() {
+            None | Some("") => default.to_string(),
+            Some(x) if x.starts_with(|c: char| c.is_ascii_digit()) => format!("v{}", x),
+            Some(x) => x.to_string(),
+        };
+        Platform {
+            variant: Some(variant),
+            ..self.clone()
+        }
+    }
+}
+
+/// Formats as `os/architecture[/variant]`, like `docker --platform` takes.
+impl Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}/{}", self.os, self.architecture)?;
+        if let Some(variant) = &self.variant {
+            write!(f, "/{}", variant)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "sha256:6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b";
+
+    mod json {
+        use super::*;
+        use pretty_assertions::assert_eq;
+
+        #[test]
+        fn serializes_without_unset_optional_properties() {
+            let descriptor = Descriptor {
+                media_type: "application/vnd.oci.image.manifest.v1+json".to_string(),
+                digest: DIGEST.parse().unwrap(),
+                size: 7682,
+                urls: None,
+                artifact_type: None,
+                annotations: None,
+                platform: Some(Platform {
+                    architecture: Architecture::Arm64,
+                    os: OS::Linux,
+                    os_version: None,
+                    os_features: None,
+                    variant: Some("v8".to_string()),
+                }),
+            };
+            let serialized = serde_json::to_string_pretty(&descriptor).unwrap();
+            assert_eq!(
+                serialized,
+                format!(
+                    r#"{{
+  "mediaType": "application/vnd.oci.image.manifest.v1+json",
+  "digest": "{}",
+  "size": 7682,
+  "platform": {{
+    "architecture": "arm64",
+    "os": "linux",
+    "variant": "v8"
+  }}
+}}"#,
+                    DIGEST
+                )
+            );
+        }
+
+        #[test]
+        fn deserializes_correctly() {
+            let raw = format!(
+                r#"{{
+  "mediaType": "application/vnd.oci.image.manifest.v1+json",
+  "digest": "{}",
+  "size": 7682,
+  "platform": {{
+    "architecture": "amd64",
+    "os": "windows",
+    "os.version": "10.0.14393.1066",
+    "os.features": ["win32k"]
+  }}
+}}"#,
+                DIGEST
+            );
+            let descriptor: Descriptor = serde_json::from_str(&raw).unwrap();
+            assert_eq!(descriptor.digest.to_string(), DIGEST);
+            assert_eq!(descriptor.size, 7682);
+            let platform = descriptor.platform.unwrap();
+            assert_eq!(platform.os, OS::Windows);
+            assert_eq!(platform.os_version, Some("10.0.14393.1066".to_string()));
+            assert_eq!(platform.os_features, Some(vec!["win32k".to_string()]));
+        }
+
+        #[test]
+        fn round_trips_urls() {
+            let raw = format!(
+                r#"{{"mediaType":"application/vnd.oci.image.layer.nondistributable.v1.tar+gzip","digest":"{}","size":32654,"urls":["https://mcr.microsoft.com/v2/windows/blobs/{}"]}}"#,
+                DIGEST, DIGEST
+            );
+            let descriptor: Descriptor = serde_json::from_str(&raw).unwrap();
+            assert_eq!(
+                descriptor.urls,
+                Some(vec![format!(
+                    "https://mcr.microsoft.com/v2/windows/blobs/{}",
+                    DIGEST
+                )])
+            );
+            assert_eq!(serde_json::to_string(&descriptor).unwrap(), raw);
+        }
+
+        #[test]
+        fn serializes_empty_descriptor() {
+            assert_eq!(
+                serde_json::to_string(&Descriptor::empty()).unwrap(),
+                r#"{"mediaType":"application/vnd.oci.empty.v1+json","digest":"sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a","size":2}"#
+            );
+        }
+    }
+
+    #[test]
+    fn displays_platform() {
+        let mut platform = Platform {
+            architecture: Architecture::Arm64,
+            os: OS::Linux,
+            os_version: None,
+            os_features: None,
+            variant: None,
+        };
+        assert_eq!(platform.to_string(), "linux/arm64");
+        platform.variant = Some("v8".to_string());
+        assert_eq!(platform.to_string(), "linux/arm64/v8");
+    }
+}
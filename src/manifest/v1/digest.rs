@@ -0,0 +1,297 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::de::{Deserializer, Error, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+use sha2::Digest as _;
+
+pub const ALGORITHM_SHA256: &str = "sha256";
+pub const ALGORITHM_SHA512: &str = "sha512";
+
+/// Content identifier of the form `<algorithm>:<encoded>`, e.g. `sha256:6c3c62...`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest {
+    algorithm: String,
+    encoded: String,
+}
+
+impl Digest {
+    pub fn new(algorithm: &str, encoded: &str) -> Result<Self, DigestError> {
+        validate_algorithm(algorithm)?;
+        validate_encoded(algorithm, encoded)?;
+        Ok(Digest {
+            algorithm: algorithm.to_string(),
+            encoded: encoded.to_string(),
+        })
+    }
+
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    pub fn encoded(&self) -> &str {
+        &self.encoded
+    }
+
+    /// Computes the `sha256` digest of `data`.
+    pub fn sha256(data: &[u8]) -> Self {
+        let mut digester = Digester::sha256();
+        digester.update(data);
+        digester.finalize()
+    }
+}
+
+/// Incrementally computes a [`Digest`] over data fed through `update` or `Write`.
+#[derive(Clone)]
+pub struct Digester {
+    hasher: Hasher,
+}
+
+#[derive(Clone)]
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Sha512(sha2::Sha512),
+}
+
+impl Digester {
+    pub fn new(algorithm: &str) -> Result<Self, DigestError> {
+        let hasher = match algorithm {
+            ALGORITHM_SHA256 => Hasher::Sha256(sha2::Sha256::new()),
+            ALGORITHM_SHA512 => Hasher::Sha512(sha2::Sha512::new()),
+            _ => return Err(DigestError::new(algorithm, "unsupported algorithm")),
+        };
+        Ok(Digester { hasher })
+    }
+
+    pub fn sha256() -> Self {
+        Digester {
+            hasher: Hasher::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match &mut self.hasher {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Digest {
+        let (algorithm, encoded) = match self.hasher {
+            Hasher::Sha256(hasher) => (ALGORITHM_SHA256, hex::encode(hasher.finalize())),
+            Hasher::Sha512(hasher) => (ALGORITHM_SHA512, hex::encode(hasher.finalize())),
+        };
+        Digest {
+            algorithm: algorithm.to_string(),
+            encoded,
+        }
+    }
+}
+
+impl std::io::Write for Digester {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl FromStr for Digest {
+    type Err = DigestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find(':') {
+            Some(idx) => Digest::new(&s[..idx], &s[idx + 1..]),
+            None => Err(DigestError::new(s, "missing `:` separator")),
+        }
+    }
+}
+
+impl Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.encoded)
+    }
+}
+
+// algorithm ::= algorithm-component (algorithm-separator algorithm-component)*
+fn validate_algorithm(algorithm: &str) -> Result<(), DigestError> {
+    let is_separator = |c: char| c == '+' || c == '.' || c == '_' || c == '-';
+    let is_valid_component = |x: &str| {
+        !x.is_empty()
+            && x.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    };
+
+    if algorithm.split(is_separator).all(is_valid_component) {
+        Ok(())
+    } else {
+        Err(DigestError::new(algorithm, "invalid algorithm"))
+    }
+}
+
+fn validate_encoded(algorithm: &str, encoded: &str) -> Result<(), DigestError> {
+    let is_lower_hex = |x: &str| {
+        x.chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    };
+
+    match algorithm {
+        ALGORITHM_SHA256 if encoded.len() != 64 || !is_lower_hex(encoded) => Err(DigestError::new(
+            encoded,
+            "sha256 digests must be 64 lowercase hex characters",
+        )),
+        ALGORITHM_SHA512 if encoded.len() != 128 || !is_lower_hex(encoded) => {
+            Err(DigestError::new(
+                encoded,
+                "sha512 digests must be 128 lowercase hex characters",
+            ))
+        }
+        _ if encoded.is_empty()
+            || !encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '=' || c == '_' || c == '-') =>
+        {
+            Err(DigestError::new(encoded, "invalid encoded portion"))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DigestError {
+    pub value: String,
+    pub reason: &'static str,
+}
+
+impl DigestError {
+    fn new(value: &str, reason: &'static str) -> Self {
+        DigestError {
+            value: value.to_string(),
+            reason,
+        }
+    }
+}
+
+impl Display for DigestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid digest `{}`: {}", self.value, self.reason)
+    }
+}
+
+impl std::error::Error for DigestError {}
+
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(DigestVisitor {})
+    }
+}
+struct DigestVisitor;
+impl<'de> Visitor<'de> for DigestVisitor {
+    type Value = Digest;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("a digest string of the form `<algorithm>:<encoded>`")
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse().map_err(Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_HEX: &str = "6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b";
+
+    #[test]
+    fn parses_sha256_digest() {
+        let digest: Digest = format!("sha256:{}", SHA256_HEX).parse().unwrap();
+        assert_eq!(digest.algorithm(), "sha256");
+        assert_eq!(digest.encoded(), SHA256_HEX);
+        assert_eq!(digest.to_string(), format!("sha256:{}", SHA256_HEX));
+    }
+
+    #[test]
+    fn parses_unregistered_algorithms() {
+        let digest: Digest = "multihash+base58:QmRZxt2b1FVZPNqd8hsiykDL3TdBDeTSPX9Kv46HmX4Gx8"
+            .parse()
+            .unwrap();
+        assert_eq!(digest.algorithm(), "multihash+base58");
+    }
+
+    #[test]
+    fn computes_digests() {
+        assert_eq!(
+            Digest::sha256(b"").to_string(),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let mut digester = Digester::new("sha512").unwrap();
+        digester.update(b"hello ");
+        digester.update(b"world");
+        let digest = digester.finalize();
+        assert_eq!(digest.algorithm(), "sha512");
+        assert_eq!(digest.encoded().len(), 128);
+        assert!(Digester::new("md5").is_err());
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_missing_separator() {
+            assert!(SHA256_HEX.parse::<Digest>().is_err());
+        }
+
+        #[test]
+        fn rejects_short_sha256() {
+            let err = "sha256:abc".parse::<Digest>().unwrap_err();
+            assert!(err.to_string().contains("64 lowercase hex"));
+        }
+
+        #[test]
+        fn rejects_uppercase_sha256() {
+            let raw = format!("sha256:{}", SHA256_HEX.to_ascii_uppercase());
+            assert!(raw.parse::<Digest>().is_err());
+        }
+
+        #[test]
+        fn rejects_bad_algorithm() {
+            assert!(format!("SHA256:{}", SHA256_HEX).parse::<Digest>().is_err());
+            assert!(format!("sha256+:{}", SHA256_HEX).parse::<Digest>().is_err());
+        }
+    }
+
+    mod json {
+        use super::*;
+
+        #[test]
+        fn serializes_correctly() {
+            let digest = Digest::new("sha256", SHA256_HEX).unwrap();
+            let serialized = serde_json::to_string(&digest).unwrap();
+            assert_eq!(serialized, format!(r#""sha256:{}""#, SHA256_HEX));
+        }
+
+        #[test]
+        fn deserializes_with_meaningful_error() {
+            let result: Result<Digest, serde_json::error::Error> =
+                serde_json::from_str(r#""sha256:nope""#);
+            assert!(result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("invalid digest `nope`"));
+        }
+    }
+}
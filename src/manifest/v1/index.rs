@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+use crate::config::v1::{from_json_reader, JsonPath, ParseError, ParseLimits};
+use crate::manifest::v1::descriptor::Descriptor;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    // required
+    pub schema_version: u32,
+    pub manifests: Vec<Descriptor>,
+    // optional
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    /// The manifest this one refers to, making this one a referrer of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+impl Index {
+    /// Checks the rules of the spec beyond the shape of the JSON, which parsing doesn't.
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.schema_version != 2 {
+            return Err(ParseError::semantic(
+                JsonPath::default().key("schemaVersion"),
+                format!(
+                    "unsupported schema version {}; should be 2",
+                    self.schema_version
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_image_index<T: std::io::Read>(source: &mut T) -> Result<Index, ParseError> {
+    parse_image_index_with_limits(source, &ParseLimits::default())
+}
+
+pub fn parse_image_index_with_limits<T: std::io::Read>(
+    source: &mut T,
+    limits: &ParseLimits,
+) -> Result<Index, ParseError> {
+    let index: Index = from_json_reader(source, limits)?;
+    Ok(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::v1::{Architecture, OS};
+
+    #[test]
+    fn parses_correctly() {
+        let raw = r#"{
+  "schemaVersion": 2,
+  "mediaType": "application/vnd.oci.image.index.v1+json",
+  "manifests": [
+    {
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "size": 7143,
+      "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+      "platform": {
+        "architecture": "ppc64le",
+        "os": "linux"
+      }
+    },
+    {
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "size": 7682,
+      "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+      "platform": {
+        "architecture": "amd64",
+        "os": "linux"
+      }
+    }
+  ],
+  "annotations": {
+    "com.example.key1": "value1"
+  }
+}"#;
+        let index = parse_image_index(&mut raw.as_bytes()).unwrap();
+
+        assert_eq!(index.schema_version, 2);
+        assert_eq!(index.manifests.len(), 2);
+        let platform = index.manifests[0].platform.as_ref().unwrap();
+        assert_eq!(platform.architecture, Architecture::Ppc64le);
+        assert_eq!(platform.os, OS::Linux);
+    }
+
+    #[test]
+    fn parses_attestation_and_new_architecture_entries() {
+        let raw = r#"{
+  "schemaVersion": 2,
+  "mediaType": "application/vnd.oci.image.index.v1+json",
+  "manifests": [
+    {
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "size": 7143,
+      "digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+      "platform": {
+        "architecture": "riscv64",
+        "os": "linux"
+      }
+    },
+    {
+      "mediaType": "application/vnd.oci.image.manifest.v1+json",
+      "size": 7682,
+      "digest": "sha256:5b0bcabd1ed22e9fb1310cf6c2dec7cdef19f0ad69efa1f392e94a4333501270",
+      "annotations": {
+        "vnd.docker.reference.digest": "sha256:e692418e4cbaf90ca69d05a66403747baa33ee08806650b51fab815ad7fc331f",
+        "vnd.docker.reference.type": "attestation-manifest"
+      },
+      "platform": {
+        "architecture": "unknown",
+        "os": "unknown"
+      }
+    }
+  ]
+}"#;
+        let index = parse_image_index(&mut raw.as_bytes()).unwrap();
+
+        assert_eq!(
+            index.manifests[0].platform.as_ref().unwrap().architecture,
+            Architecture::Riscv64
+        );
+        let attestation = index.manifests[1].platform.as_ref().unwrap();
+        assert_eq!(
+            attestation.architecture,
+            Architecture::Other("unknown".to_string())
+        );
+        assert_eq!(attestation.os, OS::Other("unknown".to_string()));
+        assert_eq!(attestation.to_string(), "unknown/unknown");
+        let serialized = serde_json::to_value(&index).unwrap();
+        assert_eq!(
+            serialized["manifests"][1]["platform"]["architecture"],
+            "unknown"
+        );
+    }
+}
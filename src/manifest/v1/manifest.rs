@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use crate::config::v1::{from_json_reader, JsonPath, ParseError, ParseLimits};
+use crate::manifest::v1::descriptor::Descriptor;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    // required
+    pub schema_version: u32,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+    // optional
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifact_type: Option<String>,
+    /// The manifest this one refers to, making this one a referrer of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<Descriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+impl Manifest {
+    /// Checks the rules of the spec beyond the shape of the JSON, which parsing doesn't.
+    pub fn validate(&self) -> Result<(), ParseError> {
+        if self.schema_version != 2 {
+            return Err(ParseError::semantic(
+                JsonPath::default().key("schemaVersion"),
+                format!(
+                    "unsupported schema version {}; should be 2",
+                    self.schema_version
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_image_manifest<T: std::io::Read>(source: &mut T) -> Result<Manifest, ParseError> {
+    let manifest: Manifest = from_json_reader(source, &ParseLimits::default())?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::v1::media_types::*;
+
+    #[test]
+    fn parses_correctly() {
+        let raw = r#"{
+  "schemaVersion": 2,
+  "mediaType": "application/vnd.oci.image.manifest.v1+json",
+  "config": {
+    "mediaType": "application/vnd.oci.image.config.v1+json",
+    "digest": "sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7",
+    "size": 7023
+  },
+  "layers": [
+    {
+      "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+      "digest": "sha256:9834876dcfb05cb167a5c24953eba58c4ac89b1adf57f28f2f9d09af107ee8f0",
+      "size": 32654
+    }
+  ],
+  "annotations": {
+    "com.example.key1": "value1"
+  }
+}"#;
+        let manifest = parse_image_manifest(&mut raw.as_bytes()).unwrap();
+
+        assert_eq!(manifest.schema_version, 2);
+        assert_eq!(
+            manifest.media_type,
+            Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string())
+        );
+        assert_eq!(manifest.config.media_type, MEDIA_TYPE_IMAGE_CONFIG);
+        assert_eq!(manifest.config.size, 7023);
+        assert_eq!(manifest.layers.len(), 1);
+        assert_eq!(manifest.layers[0].media_type, MEDIA_TYPE_IMAGE_LAYER_GZIP);
+        assert_eq!(
+            manifest.annotations.unwrap()["com.example.key1"],
+            "value1".to_string()
+        );
+    }
+
+    #[test]
+    fn round_trips() {
+        let raw = r#"{"schemaVersion":2,"config":{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7","size":7023},"layers":[]}"#;
+        let manifest = parse_image_manifest(&mut raw.as_bytes()).unwrap();
+        assert_eq!(serde_json::to_string(&manifest).unwrap(), raw);
+    }
+
+    #[test]
+    fn parses_subject_and_artifact_type() {
+        let raw = r#"{"schemaVersion":2,"config":{"mediaType":"application/vnd.oci.empty.v1+json","digest":"sha256:44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a","size":2},"layers":[],"artifactType":"application/vnd.example.sbom","subject":{"mediaType":"application/vnd.oci.image.manifest.v1+json","digest":"sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7","size":7023}}"#;
+        let manifest = parse_image_manifest(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            manifest.artifact_type.as_deref(),
+            Some("application/vnd.example.sbom")
+        );
+        assert_eq!(manifest.subject.as_ref().unwrap().size, 7023);
+        assert_eq!(serde_json::to_string(&manifest).unwrap(), raw);
+    }
+
+    #[test]
+    fn rejects_unsupported_schema_version() {
+        let raw = r#"{"schemaVersion":1,"config":{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7","size":7023},"layers":[]}"#;
+        let manifest = parse_image_manifest(&mut raw.as_bytes()).unwrap();
+        let err = manifest.validate().unwrap_err();
+        assert!(matches!(err, ParseError::Semantic { .. }));
+        assert_eq!(err.path().unwrap().to_string(), "schemaVersion");
+    }
+
+    #[test]
+    fn reports_bad_layer_digest_with_path() {
+        let raw = r#"{"schemaVersion":2,"config":{"mediaType":"application/vnd.oci.image.config.v1+json","digest":"sha256:b5b2b2c507a0944348e0303114d8d93aaaa081732b86451d9bce1f432a537bc7","size":7023},"layers":[{"mediaType":"application/vnd.oci.image.layer.v1.tar","digest":"sha256:oops","size":1}]}"#;
+        let err = parse_image_manifest(&mut raw.as_bytes()).unwrap_err();
+        assert_eq!(err.path().unwrap().to_string(), "layers[0].digest");
+    }
+}
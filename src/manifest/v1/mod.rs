@@ -0,0 +1,20 @@
+mod annotations;
+pub use annotations::*;
+
+mod descriptor;
+pub use descriptor::{Descriptor, Platform, EMPTY_JSON};
+
+mod digest;
+pub use digest::{Digest, DigestError, Digester, ALGORITHM_SHA256, ALGORITHM_SHA512};
+
+mod index;
+pub use index::{parse_image_index, parse_image_index_with_limits, Index};
+
+mod manifest;
+pub use manifest::{parse_image_manifest, Manifest};
+
+mod media_types;
+pub use media_types::*;
+
+mod verify;
+pub use verify::{VerificationError, VerifyingReader};
@@ -0,0 +1,206 @@
+use std::fmt::Display;
+use std::io::Read;
+
+use crate::manifest::v1::descriptor::Descriptor;
+use crate::manifest::v1::digest::{Digest, Digester};
+
+/// Wraps a reader of content that's expected to match a digest and size, such as a blob
+/// referenced by a [`Descriptor`].
+///
+/// Reading fails with an `InvalidData` error wrapping a [`VerificationError`] as soon as more
+/// than the expected size has been read, or at EOF if the size or digest don't match.
+pub struct VerifyingReader<R: Read> {
+    inner: R,
+    digester: Option<Digester>,
+    expected_digest: Digest,
+    expected_size: u64,
+    read: u64,
+    error: Option<VerificationError>,
+}
+
+impl<R: Read> VerifyingReader<R> {
+    pub fn new(
+        inner: R,
+        expected_digest: &Digest,
+        expected_size: u64,
+    ) -> Result<Self, VerificationError> {
+        let digester = Digester::new(expected_digest.algorithm()).map_err(|_| {
+            VerificationError::UnsupportedAlgorithm(expected_digest.algorithm().to_string())
+        })?;
+        Ok(VerifyingReader {
+            inner,
+            digester: Some(digester),
+            expected_digest: expected_digest.clone(),
+            expected_size,
+            read: 0,
+            error: None,
+        })
+    }
+
+    pub fn for_descriptor(inner: R, descriptor: &Descriptor) -> Result<Self, VerificationError> {
+        Self::new(inner, &descriptor.digest, descriptor.size)
+    }
+
+    /// Whether the content has been read to EOF and matched.
+    pub fn is_verified(&self) -> bool {
+        self.digester.is_none() && self.error.is_none()
+    }
+
+    fn fail(&mut self, error: VerificationError) -> std::io::Error {
+        self.error = Some(error.clone());
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(error) = &self.error {
+            let error = error.clone();
+            return Err(self.fail(error));
+        }
+
+        let read = self.inner.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            // EOF, which is only reached once: the digester is consumed on the first one
+            if let Some(digester) = self.digester.take() {
+                if self.read != self.expected_size {
+                    return Err(self.fail(VerificationError::SizeMismatch {
+                        expected: self.expected_size,
+                        actual: self.read,
+                    }));
+                }
+                let actual = digester.finalize();
+                if actual != self.expected_digest {
+                    return Err(self.fail(VerificationError::DigestMismatch {
+                        expected: self.expected_digest.clone(),
+                        actual,
+                    }));
+                }
+            }
+            return Ok(0);
+        }
+
+        self.read += read as u64;
+        if self.read > self.expected_size {
+            return Err(self.fail(VerificationError::SizeExceeded {
+                expected: self.expected_size,
+            }));
+        }
+        if let Some(digester) = &mut self.digester {
+            digester.update(&buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationError {
+    DigestMismatch { expected: Digest, actual: Digest },
+    SizeMismatch { expected: u64, actual: u64 },
+    SizeExceeded { expected: u64 },
+    UnsupportedAlgorithm(String),
+}
+
+impl Display for VerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VerificationError::DigestMismatch { expected, actual } => write!(
+                f,
+                "content hashes to `{}` instead of the expected `{}`",
+                actual, expected
+            ),
+            VerificationError::SizeMismatch { expected, actual } => write!(
+                f,
+                "content is {} bytes instead of the expected {}",
+                actual, expected
+            ),
+            VerificationError::SizeExceeded { expected } => {
+                write!(f, "content is larger than the expected {} bytes", expected)
+            }
+            VerificationError::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "can't verify `{}` digests", algorithm)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VerificationError {}
+
+impl VerificationError {
+    /// Extracts the `VerificationError` from an error returned by a [`VerifyingReader`].
+    pub fn from_io_error(error: &std::io::Error) -> Option<&VerificationError> {
+        error.get_ref().and_then(|x| x.downcast_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all<R: Read>(reader: &mut VerifyingReader<R>) -> Result<Vec<u8>, VerificationError> {
+        let mut buf = Vec::new();
+        reader
+            .read_to_end(&mut buf)
+            .map(|_| buf)
+            .map_err(|e| VerificationError::from_io_error(&e).unwrap().clone())
+    }
+
+    #[test]
+    fn passes_through_matching_content() {
+        let digest = Digest::sha256(b"hello world");
+        let mut reader = VerifyingReader::new(&b"hello world"[..], &digest, 11).unwrap();
+        assert_eq!(read_all(&mut reader).unwrap(), b"hello world");
+        assert!(reader.is_verified());
+    }
+
+    #[test]
+    fn fails_at_eof_on_digest_mismatch() {
+        let digest = Digest::sha256(b"hello world");
+        let mut reader = VerifyingReader::new(&b"hello WORLD"[..], &digest, 11).unwrap();
+        assert_eq!(
+            read_all(&mut reader).unwrap_err(),
+            VerificationError::DigestMismatch {
+                expected: digest,
+                actual: Digest::sha256(b"hello WORLD"),
+            }
+        );
+        assert!(!reader.is_verified());
+    }
+
+    #[test]
+    fn fails_at_eof_on_short_content() {
+        let digest = Digest::sha256(b"hello world");
+        let mut reader = VerifyingReader::new(&b"hello"[..], &digest, 11).unwrap();
+        assert_eq!(
+            read_all(&mut reader).unwrap_err(),
+            VerificationError::SizeMismatch {
+                expected: 11,
+                actual: 5
+            }
+        );
+    }
+
+    #[test]
+    fn fails_as_soon_as_size_is_exceeded() {
+        // would never reach EOF
+        let digest = Digest::sha256(b"");
+        let mut reader = VerifyingReader::new(std::io::repeat(0), &digest, 1024).unwrap();
+        assert_eq!(
+            read_all(&mut reader).unwrap_err(),
+            VerificationError::SizeExceeded { expected: 1024 }
+        );
+
+        // and keeps failing afterwards
+        let mut buf = [0; 8];
+        assert!(reader.read(&mut buf).is_err());
+    }
+
+    #[test]
+    fn rejects_unsupported_algorithms() {
+        let digest: Digest = "md5:d41d8cd98f00b204e9800998ecf8427e".parse().unwrap();
+        assert_eq!(
+            VerifyingReader::new(&b""[..], &digest, 0).err().unwrap(),
+            VerificationError::UnsupportedAlgorithm("md5".to_string())
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Changing stored images while keeping the manifest, `ImageConfig` and layers consistent.
+
+use std::io::Read;
+
+use chrono::{DateTime, Utc};
+
+use crate::layer::{write_layer, Compression, Layer};
+use crate::store::{check_oci_manifest, put_json, resolve_image_config, BlobStore, StoreError};
+use crate::v1::{
+    Config, Descriptor, Digest, History, ImageConfig, Manifest, MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+/// An image being changed. Layers are stored as they're appended, but the new config and
+/// manifest are only stored by [`MutableImage::commit`], which leaves the original image as it
+/// is.
+#[derive(Debug)]
+pub struct MutableImage<'a, S: BlobStore + ?Sized> {
+    store: &'a S,
+    manifest: Manifest,
+    config: ImageConfig,
+}
+
+impl<'a, S: BlobStore + ?Sized> MutableImage<'a, S> {
+    /// Starts from the image manifest at `manifest_digest`, which has to be an OCI one: the
+    /// layers and manifest stored on commit are OCI's. Transcode Docker manifests first.
+    pub fn new(store: &'a S, manifest_digest: &Digest) -> Result<Self, StoreError> {
+        let (manifest, config) = resolve_image_config(store, manifest_digest)?;
+        check_oci_manifest(manifest_digest, &manifest)?;
+        if manifest.layers.len() != config.rootfs.diff_ids.len() {
+            return Err(StoreError::Invalid(format!(
+                "manifest `{}` has {} layers but its config lists {} diff IDs",
+                manifest_digest,
+                manifest.layers.len(),
+                config.rootfs.diff_ids.len()
+            )));
+        }
+        Ok(MutableImage {
+            store,
+            manifest,
+            config,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn config(&self) -> &ImageConfig {
+        &self.config
+    }
+
+    /// Compresses and stores the tar stream as a new top layer, adding its diff ID and
+    /// `history` entry to the config.
+    ///
+    /// The entry is left out of images that have layers but no history, since a partial
+    /// history isn't valid.
+    pub fn append_layer<R: Read>(
+        &mut self,
+        tar: R,
+        compression: Compression,
+        history: History,
+    ) -> Result<Layer, StoreError> {
+        let layer = write_layer(self.store, tar, compression)?;
+        let has_history = self.config.history.as_ref().is_some_and(|x| !x.is_empty());
+        if has_history || self.config.rootfs.diff_ids.is_empty() {
+            self.config
+                .history
+                .get_or_insert_with(Vec::new)
+                .push(History {
+                    empty_layer: None,
+                    ..history
+                });
+        }
+        self.config.rootfs.diff_ids.push(layer.diff_id.to_string());
+        self.manifest.layers.push(layer.descriptor());
+        Ok(layer)
+    }
+
+    /// Changes the runtime config, starting from an empty one if the image doesn't have any.
+    /// Use [`MutableImage::update_image_config`] for fields outside of it, like `author`.
+    pub fn update_config<F: FnOnce(&mut Config)>(&mut self, update: F) -> &mut Self {
+        update(self.config.config.get_or_insert_with(empty_config));
+        self
+    }
+
+    /// Changes any part of the `ImageConfig`. [`MutableImage::commit`] rejects the result if
+    /// `rootfs.diff_ids` no longer matches the manifest's layers.
+    pub fn update_image_config<F: FnOnce(&mut ImageConfig)>(&mut self, update: F) -> &mut Self {
+        update(&mut self.config);
+        self
+    }
+
+    pub fn set_created(&mut self, created: DateTime<Utc>) -> &mut Self {
+        self.config.created = Some(created);
+        self
+    }
+
+    pub fn annotate(&mut self, key: &str, value: &str) -> &mut Self {
+        self.manifest
+            .annotations
+            .get_or_insert_with(Default::default)
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Validates and stores the new config and manifest, returning the manifest's descriptor.
+    pub fn commit(mut self) -> Result<Descriptor, StoreError> {
+        self.config.validate()?;
+        if self.manifest.layers.len() != self.config.rootfs.diff_ids.len() {
+            return Err(StoreError::Invalid(format!(
+                "manifest has {} layers but the config lists {} diff IDs",
+                self.manifest.layers.len(),
+                self.config.rootfs.diff_ids.len()
+            )));
+        }
+        self.manifest.config =
+            put_json(self.store, &self.manifest.config.media_type, &self.config)?;
+        self.manifest.validate()?;
+        put_json(self.store, MEDIA_TYPE_IMAGE_MANIFEST, &self.manifest)
+    }
+}
+
+fn empty_config() -> Config {
+    Config {
+        user: None,
+        exposed_ports: None,
+        env: None,
+        entrypoint: None,
+        cmd: None,
+        volumes: None,
+        working_dir: None,
+        labels: None,
+        stop_signal: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::compression::tests::tar_of;
+    use crate::layer::verify_diff_ids;
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::{read_manifest, MemoryBlobStore};
+    use crate::v1::{EnvVar, ANNOTATION_TITLE, MEDIA_TYPE_DOCKER_MANIFEST};
+    use chrono::TimeZone;
+
+    fn image_with_layers(
+        store: &MemoryBlobStore,
+        history: Option<Vec<History>>,
+        layers: usize,
+    ) -> Digest {
+        let mut config = image_config();
+        config.history = history;
+        let mut descriptors = vec![];
+        for i in 0..layers {
+            let tar = tar_of(&[(&format!("{}.txt", i), b"x")]);
+            let layer = write_layer(store, tar.as_slice(), Compression::Gzip).unwrap();
+            config.rootfs.diff_ids.push(layer.diff_id.to_string());
+            descriptors.push(layer.descriptor());
+        }
+        put_image(store, &config, descriptors).digest
+    }
+
+    fn step(created_by: &str) -> History {
+        History {
+            created: None,
+            author: None,
+            created_by: Some(created_by.to_string()),
+            comment: None,
+            empty_layer: None,
+        }
+    }
+
+    #[test]
+    fn appends_layers() {
+        let store = MemoryBlobStore::new();
+        let original = image_with_layers(&store, Some(vec![step("ADD 0.txt")]), 1);
+
+        let mut image = MutableImage::new(&store, &original).unwrap();
+        let tar = tar_of(&[("app", b"app")]);
+        let layer = image
+            .append_layer(tar.as_slice(), Compression::Zstd, step("COPY app"))
+            .unwrap();
+        let descriptor = image.commit().unwrap();
+
+        verify_diff_ids(&store, &descriptor.digest).unwrap();
+        let (manifest, config) = resolve_image_config(&store, &descriptor.digest).unwrap();
+        assert_eq!(manifest.layers.last(), Some(&layer.descriptor()));
+        assert_eq!(config.history.unwrap()[1], step("COPY app"));
+        // the original is untouched
+        assert_eq!(read_manifest(&store, &original).unwrap().layers.len(), 1);
+    }
+
+    #[test]
+    fn leaves_missing_history_alone() {
+        let store = MemoryBlobStore::new();
+        let original = image_with_layers(&store, None, 1);
+
+        let mut image = MutableImage::new(&store, &original).unwrap();
+        let tar = tar_of(&[("app", b"app")]);
+        image
+            .append_layer(tar.as_slice(), Compression::Gzip, step("COPY app"))
+            .unwrap();
+        let descriptor = image.commit().unwrap();
+        let (_, config) = resolve_image_config(&store, &descriptor.digest).unwrap();
+        assert_eq!(config.rootfs.diff_ids.len(), 2);
+        assert_eq!(config.history, None);
+    }
+
+    #[test]
+    fn changes_the_config_and_annotations() {
+        let store = MemoryBlobStore::new();
+        let original = image_with_layers(&store, None, 0);
+        let created = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+
+        let mut image = MutableImage::new(&store, &original).unwrap();
+        image
+            .update_config(|config| {
+                config.env = Some(vec![EnvVar {
+                    var_name: "APP".to_string(),
+                    var_value: "1".to_string(),
+                }]);
+                config.user = Some("nobody".to_string());
+            })
+            .update_image_config(|config| config.author = Some("builder".to_string()))
+            .set_created(created)
+            .annotate(ANNOTATION_TITLE, "app");
+        let descriptor = image.commit().unwrap();
+
+        let (manifest, config) = resolve_image_config(&store, &descriptor.digest).unwrap();
+        assert_eq!(config.created, Some(created));
+        assert_eq!(config.author.as_deref(), Some("builder"));
+        assert_eq!(config.config.unwrap().user.as_deref(), Some("nobody"));
+        assert_eq!(manifest.annotations.unwrap()[ANNOTATION_TITLE], "app");
+        assert_ne!(descriptor.digest, original);
+        assert_eq!(
+            descriptor.digest,
+            Digest::sha256(&store.get(&descriptor.digest).unwrap())
+        );
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn wont_commit_an_invalid_config() {
+            let store = MemoryBlobStore::new();
+            let original = image_with_layers(&store, None, 0);
+            let mut image = MutableImage::new(&store, &original).unwrap();
+            image.config.history = Some(vec![step("RUN true"), step("RUN false")]);
+
+            assert!(matches!(image.commit(), Err(StoreError::Parse(_))));
+        }
+
+        #[test]
+        fn rejects_docker_manifests() {
+            let store = MemoryBlobStore::new();
+            let original = image_with_layers(&store, None, 1);
+            let mut manifest = read_manifest(&store, &original).unwrap();
+            manifest.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string());
+            let docker = put_json(&store, MEDIA_TYPE_DOCKER_MANIFEST, &manifest).unwrap();
+
+            assert!(matches!(
+                MutableImage::new(&store, &docker.digest),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+
+        #[test]
+        fn wont_commit_diff_ids_without_layers() {
+            let store = MemoryBlobStore::new();
+            let original = image_with_layers(&store, None, 1);
+            let mut image = MutableImage::new(&store, &original).unwrap();
+            image.update_image_config(|config| config.rootfs.diff_ids.clear());
+
+            assert!(matches!(image.commit(), Err(StoreError::Invalid(_))));
+        }
+    }
+}
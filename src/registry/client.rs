@@ -0,0 +1,1130 @@
+use std::io::Read;
+
+use crate::config::v1::from_json_reader;
+use crate::layout::referrers_tag;
+use crate::registry::{ErrorResponse, Method, RegistryError, Request, Response, Transport};
+use crate::v1::{
+    is_image_index, is_image_manifest, parse_image_config_with_descriptor, parse_image_index,
+    parse_image_manifest, Descriptor, Digest, Digester, ImageConfig, Index, Manifest, ParseLimits,
+    VerificationError, VerifyingReader, MEDIA_TYPE_DOCKER_MANIFEST,
+    MEDIA_TYPE_DOCKER_MANIFEST_LIST, MEDIA_TYPE_IMAGE_INDEX, MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+use serde::Deserialize;
+use url::Url;
+
+pub const DEFAULT_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+pub(crate) const DIGEST_HEADER: &str = "Docker-Content-Digest";
+const OCTET_STREAM: &str = "application/octet-stream";
+
+#[derive(Debug, Deserialize)]
+struct TagList {
+    #[serde(default)]
+    tags: Option<Vec<String>>,
+}
+
+/// A client for the OCI distribution API of a single registry.
+///
+/// Everything pulled is checked against the digest it was requested by, or the one the registry
+/// reports for it when requested by tag.
+#[derive(Debug)]
+pub struct RegistryClient<T: Transport> {
+    base: Url,
+    transport: T,
+}
+
+impl<T: Transport> RegistryClient<T> {
+    /// `base` is the registry's root, such as `https://registry.example.com`, or
+    /// `https://proxy.example.com/registry` for one served under a path.
+    pub fn new(base: &str, transport: T) -> Result<Self, RegistryError> {
+        let base = Url::parse(base).map_err(|error| {
+            RegistryError::Transport(format!("invalid registry URL `{}`: {}", base, error))
+        })?;
+        Ok(RegistryClient { base, transport })
+    }
+
+    /// Checks that the registry implements the distribution API.
+    pub fn ping(&self) -> Result<(), RegistryError> {
+        self.send(Method::Get, self.url("/v2/"), vec![], vec![], &[200])?;
+        Ok(())
+    }
+
+    pub fn head_manifest(
+        &self,
+        repository: &str,
+        reference: &str,
+    ) -> Result<Option<Descriptor>, RegistryError> {
+        let url = self.url(&format!("/v2/{}/manifests/{}", repository, reference));
+        let response = self.send(Method::Head, url, accept_manifests(), vec![], &[200, 404])?;
+        if response.status == 404 {
+            return Ok(None);
+        }
+        let digest = match response.header(DIGEST_HEADER) {
+            Some(digest) => parse_digest(digest)?,
+            None => reference.parse().map_err(|_| {
+                RegistryError::InvalidResponse(format!("missing `{}` header", DIGEST_HEADER))
+            })?,
+        };
+        Ok(Some(Descriptor {
+            media_type: response.header("Content-Type").unwrap_or("").to_string(),
+            digest,
+            size: content_length(&response)?,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        }))
+    }
+
+    /// Fetches a manifest or index as it's stored, verified against `reference` if it's a
+    /// digest.
+    pub fn get_manifest(
+        &self,
+        repository: &str,
+        reference: &str,
+    ) -> Result<(Descriptor, Vec<u8>), RegistryError> {
+        let url = self.url(&format!("/v2/{}/manifests/{}", repository, reference));
+        let response = self.send(Method::Get, url, accept_manifests(), vec![], &[200])?;
+        let expected = match reference.parse::<Digest>() {
+            Ok(digest) => Some(digest),
+            Err(_) => match response.header(DIGEST_HEADER) {
+                Some(digest) => Some(parse_digest(digest)?),
+                None => None,
+            },
+        };
+        let media_type = response.header("Content-Type").map(String::from);
+
+        let max_bytes = ParseLimits::default().max_bytes;
+        let mut data = Vec::new();
+        response.body.take(max_bytes + 1).read_to_end(&mut data)?;
+        if data.len() as u64 > max_bytes {
+            return Err(RegistryError::InvalidResponse(format!(
+                "manifest is larger than {} bytes",
+                max_bytes
+            )));
+        }
+
+        let digest = match expected {
+            Some(expected) => {
+                let mut digester = Digester::new(expected.algorithm()).map_err(|_| {
+                    VerificationError::UnsupportedAlgorithm(expected.algorithm().to_string())
+                })?;
+                digester.update(&data);
+                let actual = digester.finalize();
+                if actual != expected {
+                    return Err(VerificationError::DigestMismatch { expected, actual }.into());
+                }
+                actual
+            }
+            None => Digest::sha256(&data),
+        };
+        let descriptor = Descriptor {
+            media_type: media_type.unwrap_or_else(|| declared_media_type(&data)),
+            digest,
+            size: data.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        };
+        Ok((descriptor, data))
+    }
+
+    pub fn pull_manifest(
+        &self,
+        repository: &str,
+        reference: &str,
+    ) -> Result<(Descriptor, Manifest), RegistryError> {
+        let (descriptor, data) = self.get_manifest(repository, reference)?;
+        if is_image_index(&descriptor.media_type) {
+            return Err(RegistryError::InvalidResponse(format!(
+                "`{}` is an index, `{}`, rather than an image manifest",
+                reference, descriptor.media_type
+            )));
+        }
+        Ok((descriptor, parse_image_manifest(&mut data.as_slice())?))
+    }
+
+    pub fn pull_index(
+        &self,
+        repository: &str,
+        reference: &str,
+    ) -> Result<(Descriptor, Index), RegistryError> {
+        let (descriptor, data) = self.get_manifest(repository, reference)?;
+        if is_image_manifest(&descriptor.media_type) {
+            return Err(RegistryError::InvalidResponse(format!(
+                "`{}` is an image manifest, `{}`, rather than an index",
+                reference, descriptor.media_type
+            )));
+        }
+        Ok((descriptor, parse_image_index(&mut data.as_slice())?))
+    }
+
+    /// Pulls an image manifest and the `ImageConfig` it references.
+    pub fn pull_image(
+        &self,
+        repository: &str,
+        reference: &str,
+    ) -> Result<(Descriptor, Manifest, ImageConfig), RegistryError> {
+        let (descriptor, manifest) = self.pull_manifest(repository, reference)?;
+        let config = self.pull_image_config(repository, &manifest)?;
+        Ok((descriptor, manifest, config))
+    }
+
+    pub fn pull_image_config(
+        &self,
+        repository: &str,
+        manifest: &Manifest,
+    ) -> Result<ImageConfig, RegistryError> {
+        let mut reader = self
+            .blob_response(repository, &manifest.config.digest)?
+            .body;
+        Ok(parse_image_config_with_descriptor(
+            &mut reader,
+            &manifest.config,
+        )?)
+    }
+
+    /// Uploads a manifest or index under `reference`, which is either a tag or its digest.
+    pub fn put_manifest(
+        &self,
+        repository: &str,
+        reference: &str,
+        media_type: &str,
+        data: &[u8],
+    ) -> Result<Descriptor, RegistryError> {
+        let url = self.url(&format!("/v2/{}/manifests/{}", repository, reference));
+        let headers = vec![("Content-Type".to_string(), media_type.to_string())];
+        self.send(Method::Put, url, headers, data.to_vec(), &[201])?;
+        Ok(Descriptor {
+            media_type: media_type.to_string(),
+            digest: Digest::sha256(data),
+            size: data.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        })
+    }
+
+    pub fn push_manifest(
+        &self,
+        repository: &str,
+        reference: &str,
+        manifest: &Manifest,
+    ) -> Result<Descriptor, RegistryError> {
+        let media_type = manifest
+            .media_type
+            .as_deref()
+            .unwrap_or(MEDIA_TYPE_IMAGE_MANIFEST);
+        self.put_manifest(
+            repository,
+            reference,
+            media_type,
+            &serde_json::to_vec(manifest)?,
+        )
+    }
+
+    pub fn push_index(
+        &self,
+        repository: &str,
+        reference: &str,
+        index: &Index,
+    ) -> Result<Descriptor, RegistryError> {
+        let media_type = index
+            .media_type
+            .as_deref()
+            .unwrap_or(MEDIA_TYPE_IMAGE_INDEX);
+        self.put_manifest(
+            repository,
+            reference,
+            media_type,
+            &serde_json::to_vec(index)?,
+        )
+    }
+
+    pub fn delete_manifest(&self, repository: &str, digest: &Digest) -> Result<(), RegistryError> {
+        let url = self.url(&format!("/v2/{}/manifests/{}", repository, digest));
+        self.send(Method::Delete, url, vec![], vec![], &[202])?;
+        Ok(())
+    }
+
+    /// Removes a tag, leaving the manifest it pointed to. Not every registry supports it.
+    pub fn delete_tag(&self, repository: &str, tag: &str) -> Result<(), RegistryError> {
+        let url = self.url(&format!("/v2/{}/manifests/{}", repository, tag));
+        self.send(Method::Delete, url, vec![], vec![], &[202])?;
+        Ok(())
+    }
+
+    /// Returns the size of the blob, or `None` if the repository doesn't have it.
+    pub fn head_blob(
+        &self,
+        repository: &str,
+        digest: &Digest,
+    ) -> Result<Option<u64>, RegistryError> {
+        let url = self.url(&format!("/v2/{}/blobs/{}", repository, digest));
+        let response = self.send(Method::Head, url, vec![], vec![], &[200, 404])?;
+        match response.status {
+            404 => Ok(None),
+            _ => Ok(Some(content_length(&response)?)),
+        }
+    }
+
+    /// Streams a blob, failing once it's read to the end if it doesn't match `descriptor`.
+    pub fn get_blob(
+        &self,
+        repository: &str,
+        descriptor: &Descriptor,
+    ) -> Result<VerifyingReader<Box<dyn Read + Send>>, RegistryError> {
+        let response = self.blob_response(repository, &descriptor.digest)?;
+        Ok(VerifyingReader::for_descriptor(response.body, descriptor)?)
+    }
+
+    fn blob_response(&self, repository: &str, digest: &Digest) -> Result<Response, RegistryError> {
+        let url = self.url(&format!("/v2/{}/blobs/{}", repository, digest));
+        self.send(Method::Get, url, vec![], vec![], &[200])
+    }
+
+    /// Uploads a blob in a single request.
+    pub fn push_blob(&self, repository: &str, data: &[u8]) -> Result<Digest, RegistryError> {
+        let digest = Digest::sha256(data);
+        let mut url = self.start_upload(repository)?;
+        url.query_pairs_mut()
+            .append_pair("digest", &digest.to_string());
+        let headers = vec![("Content-Type".to_string(), OCTET_STREAM.to_string())];
+        self.send(Method::Put, url, headers, data.to_vec(), &[201])?;
+        Ok(digest)
+    }
+
+    /// Uploads a blob from `reader` in chunks of at most `chunk_size` bytes, so that it never has
+    /// to be held in memory.
+    pub fn push_blob_chunked<R: Read>(
+        &self,
+        repository: &str,
+        mut reader: R,
+        chunk_size: usize,
+    ) -> Result<(Digest, u64), RegistryError> {
+        let mut url = self.start_upload(repository)?;
+        let mut digester = Digester::sha256();
+        let mut buf = vec![0; chunk_size.max(1)];
+        let mut offset = 0u64;
+        loop {
+            let read = read_full(&mut reader, &mut buf)?;
+            if read == 0 {
+                break;
+            }
+            digester.update(&buf[..read]);
+            let headers = vec![
+                ("Content-Type".to_string(), OCTET_STREAM.to_string()),
+                (
+                    "Content-Range".to_string(),
+                    format!("{}-{}", offset, offset + read as u64 - 1),
+                ),
+            ];
+            let response = self.send(Method::Patch, url, headers, buf[..read].to_vec(), &[202])?;
+            url = self.location(&response)?;
+            offset += read as u64;
+            if read < buf.len() {
+                break;
+            }
+        }
+
+        let digest = digester.finalize();
+        url.query_pairs_mut()
+            .append_pair("digest", &digest.to_string());
+        self.send(Method::Put, url, vec![], vec![], &[201])?;
+        Ok((digest, offset))
+    }
+
+    /// Asks the registry to make a blob of repository `from` available in `repository` without
+    /// uploading it again. Returns whether it did; registries are free not to.
+    pub fn mount_blob(
+        &self,
+        repository: &str,
+        from: &str,
+        digest: &Digest,
+    ) -> Result<bool, RegistryError> {
+        let mut url = self.url(&format!("/v2/{}/blobs/uploads/", repository));
+        url.query_pairs_mut()
+            .append_pair("mount", &digest.to_string())
+            .append_pair("from", from);
+        let response = self.send(Method::Post, url, vec![], vec![], &[201, 202])?;
+        // a 202 opened an upload session instead, which is left to expire
+        Ok(response.status == 201)
+    }
+
+    fn start_upload(&self, repository: &str) -> Result<Url, RegistryError> {
+        let url = self.url(&format!("/v2/{}/blobs/uploads/", repository));
+        let response = self.send(Method::Post, url, vec![], vec![], &[202])?;
+        self.location(&response)
+    }
+
+    /// Lists every tag of `repository`, following `Link` headers if the registry paginates.
+    pub fn list_tags(&self, repository: &str) -> Result<Vec<String>, RegistryError> {
+        self.list_tags_paginated(repository, None)
+    }
+
+    /// Lists tags asking for pages of at most `page_size` tags.
+    pub fn list_tags_paginated(
+        &self,
+        repository: &str,
+        page_size: Option<usize>,
+    ) -> Result<Vec<String>, RegistryError> {
+        let mut url = self.url(&format!("/v2/{}/tags/list", repository));
+        if let Some(page_size) = page_size {
+            url.query_pairs_mut()
+                .append_pair("n", &page_size.to_string());
+        }
+
+        let mut tags = Vec::new();
+        loop {
+            let response = self.send(Method::Get, url, vec![], vec![], &[200])?;
+            let next = response.header("Link").and_then(next_link);
+            // unpaginated lists can have any number of tags, but not be of any size
+            let limits = ParseLimits {
+                max_collection_len: usize::MAX,
+                ..ParseLimits::default()
+            };
+            let page: TagList = from_json_reader(response.body, &limits).map_err(|error| {
+                RegistryError::InvalidResponse(format!("invalid tag list: {}", error))
+            })?;
+            tags.extend(page.tags.unwrap_or_default());
+            match next {
+                Some(next) => url = self.join(&next)?,
+                None => return Ok(tags),
+            }
+        }
+    }
+
+    /// Lists the manifests whose `subject` is `digest`, falling back to the `<alg>-<encoded>`
+    /// tag for registries without the referrers API.
+    pub fn referrers(
+        &self,
+        repository: &str,
+        digest: &Digest,
+        artifact_type: Option<&str>,
+    ) -> Result<Vec<Descriptor>, RegistryError> {
+        let mut url = self.url(&format!("/v2/{}/referrers/{}", repository, digest));
+        if let Some(artifact_type) = artifact_type {
+            url.query_pairs_mut()
+                .append_pair("artifactType", artifact_type);
+        }
+        let response = self.send(Method::Get, url, vec![], vec![], &[200, 404])?;
+        let index = if response.status == 200 {
+            let mut body = response.body;
+            parse_image_index(&mut body)?
+        } else {
+            match self.pull_index(repository, &referrers_tag(digest)) {
+                Ok((_, index)) => index,
+                Err(RegistryError::Status { status: 404, .. }) => return Ok(vec![]),
+                Err(error) => return Err(error),
+            }
+        };
+        // registries don't have to apply the filter
+        Ok(index
+            .manifests
+            .into_iter()
+            .filter(|x| artifact_type.is_none() || x.artifact_type.as_deref() == artifact_type)
+            .collect())
+    }
+
+    // registries can be served under a path, as behind a proxy
+    fn url(&self, path: &str) -> Url {
+        let mut url = self.base.clone();
+        url.set_path(&format!(
+            "{}{}",
+            self.base.path().trim_end_matches('/'),
+            path
+        ));
+        url
+    }
+
+    // `Location` and `Link` may be relative to the registry
+    fn join(&self, location: &str) -> Result<Url, RegistryError> {
+        self.base.join(location).map_err(|error| {
+            RegistryError::InvalidResponse(format!("invalid location `{}`: {}", location, error))
+        })
+    }
+
+    fn location(&self, response: &Response) -> Result<Url, RegistryError> {
+        match response.header("Location") {
+            Some(location) => self.join(location),
+            None => Err(RegistryError::InvalidResponse(
+                "missing `Location` header".to_string(),
+            )),
+        }
+    }
+
+    fn send(
+        &self,
+        method: Method,
+        url: Url,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+        expected: &[u16],
+    ) -> Result<Response, RegistryError> {
+        let display_url = url.to_string();
+        // `Location` and `Link` can point anywhere, and credentials are only for the registry
+        let credentials = url.origin() == self.base.origin();
+        let mut request = Request::new(method, url);
+        request.headers = headers;
+        request.body = body;
+        request.credentials = credentials;
+        let response = self.transport.send(request)?;
+        if expected.contains(&response.status) {
+            return Ok(response);
+        }
+
+        let status = response.status;
+        let errors =
+            match from_json_reader::<ErrorResponse, _>(response.body, &ParseLimits::default()) {
+                Ok(body) => body.errors,
+                Err(_) => vec![],
+            };
+        Err(RegistryError::Status {
+            method,
+            url: display_url,
+            status,
+            errors,
+        })
+    }
+}
+
+// most registries serve Docker's types unless asked for them, if they can convert at all
+fn accept_manifests() -> Vec<(String, String)> {
+    vec![(
+        "Accept".to_string(),
+        [
+            MEDIA_TYPE_IMAGE_MANIFEST,
+            MEDIA_TYPE_IMAGE_INDEX,
+            MEDIA_TYPE_DOCKER_MANIFEST,
+            MEDIA_TYPE_DOCKER_MANIFEST_LIST,
+        ]
+        .join(", "),
+    )]
+}
+
+fn parse_digest(value: &str) -> Result<Digest, RegistryError> {
+    value.parse().map_err(|_| {
+        RegistryError::InvalidResponse(format!("invalid `{}` header `{}`", DIGEST_HEADER, value))
+    })
+}
+
+fn content_length(response: &Response) -> Result<u64, RegistryError> {
+    response
+        .header("Content-Length")
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| {
+            RegistryError::InvalidResponse("missing `Content-Length` header".to_string())
+        })
+}
+
+// used when the registry doesn't say what it's serving
+pub(crate) fn declared_media_type(data: &[u8]) -> String {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Declared {
+        media_type: Option<String>,
+    }
+    serde_json::from_slice::<Declared>(data)
+        .ok()
+        .and_then(|x| x.media_type)
+        .unwrap_or_default()
+}
+
+// extracts the target of `rel="next"` from a `Link` header
+fn next_link(header: &str) -> Option<String> {
+    header.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let target = parts.next()?.trim();
+        let is_next = parts.any(|x| {
+            let x = x.trim().replace(' ', "");
+            x == "rel=\"next\"" || x == "rel=next"
+        });
+        if is_next && target.starts_with('<') && target.ends_with('>') {
+            Some(target[1..target.len() - 1].to_string())
+        } else {
+            None
+        }
+    })
+}
+
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::foreign::tests::foreign_layer;
+    use crate::layer::ForeignLayerPolicy;
+    use crate::registry::MockRegistry;
+    use crate::store::tests::image_config;
+    use crate::store::{copy_image, put_json, BlobStore, MemoryBlobStore};
+    use crate::v1::{
+        EMPTY_JSON, MEDIA_TYPE_DOCKER_CONFIG, MEDIA_TYPE_IMAGE_CONFIG, MEDIA_TYPE_IMAGE_LAYER_GZIP,
+    };
+
+    use std::io::Cursor;
+
+    const REGISTRY: &str = "http://registry.test";
+
+    fn client(registry: &MockRegistry) -> RegistryClient<&MockRegistry> {
+        RegistryClient::new(REGISTRY, registry).unwrap()
+    }
+
+    fn blob_descriptor(media_type: &str, data: &[u8]) -> Descriptor {
+        Descriptor {
+            media_type: media_type.to_string(),
+            digest: Digest::sha256(data),
+            size: data.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        }
+    }
+
+    // pushes an image with one layer, tagged `latest`
+    fn push_image(
+        client: &RegistryClient<&MockRegistry>,
+        repository: &str,
+    ) -> (Descriptor, Manifest) {
+        let config = serde_json::to_vec(&image_config()).unwrap();
+        client.push_blob(repository, &config).unwrap();
+        client.push_blob(repository, b"layer").unwrap();
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config: blob_descriptor(MEDIA_TYPE_IMAGE_CONFIG, &config),
+            layers: vec![blob_descriptor(MEDIA_TYPE_IMAGE_LAYER_GZIP, b"layer")],
+            artifact_type: None,
+            subject: None,
+            annotations: None,
+        };
+        let descriptor = client
+            .push_manifest(repository, "latest", &manifest)
+            .unwrap();
+        (descriptor, manifest)
+    }
+
+    fn referrer(subject: &Descriptor, artifact_type: &str) -> Manifest {
+        Manifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config: Descriptor::empty(),
+            layers: vec![],
+            artifact_type: Some(artifact_type.to_string()),
+            subject: Some(subject.clone()),
+            annotations: None,
+        }
+    }
+
+    #[test]
+    fn pushes_and_pulls_images() {
+        let registry = MockRegistry::new();
+        let client = client(&registry);
+        client.ping().unwrap();
+        let (descriptor, manifest) = push_image(&client, "library/app");
+
+        let (pulled, pulled_manifest, config) = client.pull_image("library/app", "latest").unwrap();
+        assert_eq!(pulled, descriptor);
+        assert_eq!(pulled_manifest, manifest);
+        assert_eq!(config, image_config());
+
+        let by_digest = client
+            .head_manifest("library/app", &descriptor.digest.to_string())
+            .unwrap();
+        assert_eq!(by_digest, Some(descriptor.clone()));
+        assert_eq!(
+            client.head_manifest("library/app", "missing").unwrap(),
+            None
+        );
+
+        let mut layer = Vec::new();
+        client
+            .get_blob("library/app", &manifest.layers[0])
+            .unwrap()
+            .read_to_end(&mut layer)
+            .unwrap();
+        assert_eq!(layer, b"layer");
+        assert_eq!(
+            client
+                .head_blob("library/app", &manifest.layers[0].digest)
+                .unwrap(),
+            Some(5)
+        );
+
+        client
+            .delete_manifest("library/app", &descriptor.digest)
+            .unwrap();
+        assert_eq!(client.head_manifest("library/app", "latest").unwrap(), None);
+    }
+
+    #[test]
+    fn copies_images_without_their_foreign_layers() {
+        let source = MemoryBlobStore::new();
+        let config = put_json(&source, MEDIA_TYPE_IMAGE_CONFIG, &image_config()).unwrap();
+        source.put(b"layer").unwrap();
+        let foreign = foreign_layer(b"foreign", &["https://example.com/foreign"]);
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config,
+            layers: vec![
+                blob_descriptor(MEDIA_TYPE_IMAGE_LAYER_GZIP, b"layer"),
+                foreign,
+            ],
+            artifact_type: None,
+            subject: None,
+            annotations: None,
+        };
+        let image = put_json(&source, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+        let staging = MemoryBlobStore::new();
+        copy_image(&source, &staging, &image, &ForeignLayerPolicy::Skip).unwrap();
+
+        let registry = MockRegistry::new();
+        let client = client(&registry);
+        for digest in staging.list().unwrap() {
+            if digest != image.digest {
+                client
+                    .push_blob("app", &staging.get(&digest).unwrap())
+                    .unwrap();
+            }
+        }
+        let pushed = client.push_manifest("app", "latest", &manifest).unwrap();
+        assert_eq!(pushed.digest, image.digest);
+        assert_eq!(client.pull_manifest("app", "latest").unwrap().1, manifest);
+    }
+
+    // serves the registry under `/mirror`, as a proxy would
+    struct Prefixed {
+        registry: MockRegistry,
+        paths: std::sync::Mutex<Vec<String>>,
+    }
+
+    impl Transport for Prefixed {
+        fn send(&self, mut request: Request) -> Result<Response, RegistryError> {
+            let path = request.url.path().to_string();
+            self.paths.lock().unwrap().push(path.clone());
+            match path.strip_prefix("/mirror") {
+                Some(path) => request.url.set_path(path),
+                None => return Ok(Response::new(404)),
+            }
+            let mut response = self.registry.send(request)?;
+            for (name, value) in &mut response.headers {
+                if name == "Location" {
+                    *value = format!("/mirror{}", value);
+                }
+            }
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn talks_to_registries_under_a_path() {
+        let transport = Prefixed {
+            registry: MockRegistry::new(),
+            paths: Default::default(),
+        };
+        let client = RegistryClient::new("http://proxy.test/mirror/", &transport).unwrap();
+        client.ping().unwrap();
+        client
+            .push_blob_chunked("app", &b"layer content"[..], 4)
+            .unwrap();
+        client.push_blob("app", b"layer").unwrap();
+        assert!(client
+            .head_blob("app", &Digest::sha256(b"layer"))
+            .unwrap()
+            .is_some());
+
+        let paths = transport.paths.lock().unwrap();
+        assert_eq!(paths[0], "/mirror/v2/");
+        assert!(paths.iter().all(|x| x.starts_with("/mirror/v2/")));
+    }
+
+    #[test]
+    fn pulls_docker_manifests_and_lists() {
+        let registry = MockRegistry::new();
+        let client = client(&registry);
+        let (_, mut manifest) = push_image(&client, "app");
+        manifest.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string());
+        manifest.config.media_type = MEDIA_TYPE_DOCKER_CONFIG.to_string();
+        let pushed = client.push_manifest("app", "docker", &manifest).unwrap();
+        let list = Index {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            manifests: vec![pushed.clone()],
+            media_type: Some(MEDIA_TYPE_DOCKER_MANIFEST_LIST.to_string()),
+            annotations: None,
+        };
+        client.push_index("app", "list", &list).unwrap();
+
+        let (descriptor, pulled, config) = client.pull_image("app", "docker").unwrap();
+        assert_eq!(descriptor, pushed);
+        assert_eq!(pulled, manifest);
+        assert_eq!(config, image_config());
+        let (descriptor, pulled) = client.pull_index("app", "list").unwrap();
+        assert_eq!(descriptor.media_type, MEDIA_TYPE_DOCKER_MANIFEST_LIST);
+        assert_eq!(pulled, list);
+        assert!(matches!(
+            client.pull_manifest("app", "list"),
+            Err(RegistryError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            client.pull_index("app", "docker"),
+            Err(RegistryError::InvalidResponse(_))
+        ));
+        assert!(accept_manifests()[0]
+            .1
+            .contains(MEDIA_TYPE_DOCKER_MANIFEST_LIST));
+    }
+
+    #[test]
+    fn uploads_blobs_in_chunks() {
+        let registry = MockRegistry::new();
+        let client = client(&registry);
+        let data: Vec<u8> = (0..10u8).collect();
+
+        let (digest, size) = client
+            .push_blob_chunked("app", Cursor::new(&data), 4)
+            .unwrap();
+        assert_eq!(digest, Digest::sha256(&data));
+        assert_eq!(size, 10);
+        assert_eq!(client.head_blob("app", &digest).unwrap(), Some(10));
+
+        let patches = registry
+            .requests()
+            .iter()
+            .filter(|x| x.starts_with("PATCH"))
+            .count();
+        assert_eq!(patches, 3);
+
+        // a size that's a multiple of the chunk size doesn't need an empty chunk
+        let (digest, _) = client
+            .push_blob_chunked("app", Cursor::new(&data[..8]), 4)
+            .unwrap();
+        assert_eq!(digest, Digest::sha256(&data[..8]));
+    }
+
+    #[test]
+    fn mounts_blobs_across_repositories() {
+        let registry = MockRegistry::new();
+        let client = client(&registry);
+        let digest = client.push_blob("a", b"shared").unwrap();
+
+        assert!(client.mount_blob("b", "a", &digest).unwrap());
+        assert_eq!(client.head_blob("b", &digest).unwrap(), Some(6));
+        // unknown blobs open an upload session instead
+        let missing = Digest::sha256(b"missing");
+        assert!(!client.mount_blob("b", "a", &missing).unwrap());
+    }
+
+    #[test]
+    fn lists_tags_across_pages() {
+        let registry = MockRegistry::new();
+        let client = client(&registry);
+        let (descriptor, manifest) = push_image(&client, "app");
+        for tag in &["v1", "v2", "v3", "v4"] {
+            client.push_manifest("app", tag, &manifest).unwrap();
+        }
+
+        let tags = client.list_tags_paginated("app", Some(2)).unwrap();
+        assert_eq!(tags, vec!["latest", "v1", "v2", "v3", "v4"]);
+        let pages = registry
+            .requests()
+            .iter()
+            .filter(|x| x.contains("/tags/list"))
+            .count();
+        assert_eq!(pages, 3);
+        assert_eq!(client.list_tags("app").unwrap(), tags);
+
+        let (pulled, _) = client.pull_manifest("app", "v3").unwrap();
+        assert_eq!(pulled.digest, descriptor.digest);
+    }
+
+    #[test]
+    fn lists_referrers() {
+        let registry = MockRegistry::new();
+        let client = client(&registry);
+        let (subject, _) = push_image(&client, "app");
+        client.push_blob("app", EMPTY_JSON).unwrap();
+        // referrers are usually pushed by digest alone
+        let push = |manifest: &Manifest| {
+            let digest = Digest::sha256(&serde_json::to_vec(manifest).unwrap());
+            client
+                .push_manifest("app", &digest.to_string(), manifest)
+                .unwrap()
+                .digest
+        };
+        let signature = push(&referrer(&subject, "application/vnd.example.signature"));
+        push(&referrer(&subject, "application/vnd.example.sbom"));
+
+        let referrers = client.referrers("app", &subject.digest, None).unwrap();
+        assert_eq!(referrers.len(), 2);
+        let signatures = client
+            .referrers(
+                "app",
+                &subject.digest,
+                Some("application/vnd.example.signature"),
+            )
+            .unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0].digest, signature);
+        assert_eq!(
+            signatures[0].artifact_type.as_deref(),
+            Some("application/vnd.example.signature")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_referrers_tag() {
+        let registry = MockRegistry::without_referrers_api();
+        let client = client(&registry);
+        let (subject, _) = push_image(&client, "app");
+        assert_eq!(
+            client.referrers("app", &subject.digest, None).unwrap(),
+            vec![]
+        );
+
+        client.push_blob("app", EMPTY_JSON).unwrap();
+        let signature = referrer(&subject, "application/vnd.example.signature");
+        let mut descriptor = client
+            .push_manifest("app", "signature", &signature)
+            .unwrap();
+        descriptor.artifact_type = signature.artifact_type.clone();
+        let index = Index {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_IMAGE_INDEX.to_string()),
+            manifests: vec![descriptor.clone()],
+            artifact_type: None,
+            subject: None,
+            annotations: None,
+        };
+        client
+            .push_index("app", &referrers_tag(&subject.digest), &index)
+            .unwrap();
+
+        assert_eq!(
+            client.referrers("app", &subject.digest, None).unwrap(),
+            vec![descriptor]
+        );
+        assert_eq!(
+            client
+                .referrers("app", &subject.digest, Some("application/vnd.example.sbom"))
+                .unwrap(),
+            vec![]
+        );
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        // serves whatever it's given for every GET, as a compromised registry or mirror might
+        struct Tampering {
+            registry: MockRegistry,
+            replacement: Vec<u8>,
+        }
+
+        impl Transport for Tampering {
+            fn send(&self, request: Request) -> Result<Response, RegistryError> {
+                let method = request.method;
+                let mut response = self.registry.send(request)?;
+                if method == Method::Get && response.status == 200 {
+                    response.body = Box::new(Cursor::new(self.replacement.clone()));
+                }
+                Ok(response)
+            }
+        }
+
+        // points `Location` and `Link` at another host, whose requests it forwards to the
+        // registry, recording which requests were allowed credentials
+        struct Elsewhere {
+            registry: MockRegistry,
+            sent: std::sync::Mutex<Vec<(String, bool)>>,
+        }
+
+        impl Transport for Elsewhere {
+            fn send(&self, mut request: Request) -> Result<Response, RegistryError> {
+                let host = request.url.host_str().unwrap_or_default().to_string();
+                self.sent.lock().unwrap().push((host, request.credentials));
+                request.url.set_host(Some("registry.test")).unwrap();
+                let mut response = self.registry.send(request)?;
+                for (name, value) in &mut response.headers {
+                    if name == "Location" || name == "Link" {
+                        *value = value.replacen("/v2/", "http://elsewhere.test/v2/", 1);
+                    }
+                }
+                Ok(response)
+            }
+        }
+
+        #[test]
+        fn only_sends_credentials_to_the_registry() {
+            let transport = Elsewhere {
+                registry: MockRegistry::new(),
+                sent: Default::default(),
+            };
+            let client = RegistryClient::new(REGISTRY, &transport).unwrap();
+            client
+                .push_blob_chunked("app", &b"layer content"[..], 4)
+                .unwrap();
+            for tag in &["a", "b", "c"] {
+                client
+                    .put_manifest(
+                        "app",
+                        tag,
+                        MEDIA_TYPE_IMAGE_INDEX,
+                        br#"{"schemaVersion":2,"manifests":[]}"#,
+                    )
+                    .unwrap();
+            }
+            assert_eq!(
+                client.list_tags_paginated("app", Some(1)).unwrap(),
+                vec!["a", "b", "c"]
+            );
+
+            let sent = transport.sent.lock().unwrap();
+            assert!(sent.iter().any(|(host, _)| host == "elsewhere.test"));
+            for (host, credentials) in sent.iter() {
+                assert_eq!(*credentials, host == "registry.test", "{}", host);
+            }
+        }
+
+        // answers every request with an endless stream of JSON
+        struct Endless;
+
+        impl Transport for Endless {
+            fn send(&self, _: Request) -> Result<Response, RegistryError> {
+                let mut response = Response::new(200);
+                let body = std::io::repeat(b' ');
+                response.body = Box::new(Read::chain(&b"{\"tags\": ["[..], body));
+                Ok(response)
+            }
+        }
+
+        #[test]
+        fn bounds_what_it_reads_of_responses() {
+            let client = RegistryClient::new(REGISTRY, Endless).unwrap();
+            assert!(matches!(
+                client.list_tags("app"),
+                Err(RegistryError::InvalidResponse(_))
+            ));
+            // and of error responses
+            assert!(matches!(
+                client.push_blob("app", b"layer"),
+                Err(RegistryError::Status { status: 200, .. })
+            ));
+        }
+
+        #[test]
+        fn reports_registry_errors() {
+            let registry = MockRegistry::new();
+            let client = client(&registry);
+            let err = client.pull_manifest("app", "latest").unwrap_err();
+            match &err {
+                RegistryError::Status { status, errors, .. } => {
+                    assert_eq!(*status, 404);
+                    assert_eq!(errors[0].code, "NAME_UNKNOWN");
+                }
+                _ => panic!("unexpected error: {}", err),
+            }
+            assert_eq!(
+                err.to_string(),
+                "GET http://registry.test/v2/app/manifests/latest responded with 404; \
+                 NAME_UNKNOWN: repository not known to registry"
+            );
+        }
+
+        #[test]
+        fn rejects_manifests_with_missing_blobs() {
+            let registry = MockRegistry::new();
+            let client = client(&registry);
+            let (_, mut manifest) = push_image(&client, "app");
+            manifest.layers[0] = blob_descriptor(MEDIA_TYPE_IMAGE_LAYER_GZIP, b"missing");
+            let err = client
+                .push_manifest("app", "latest", &manifest)
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                RegistryError::Status { status: 400, ref errors, .. }
+                    if errors[0].code == "MANIFEST_BLOB_UNKNOWN"
+            ));
+        }
+
+        #[test]
+        fn rejects_tampered_content() {
+            // a well-formed config, so that only verification can catch it
+            let mut config = image_config();
+            config.architecture = crate::v1::Architecture::Arm64;
+            let tampering = Tampering {
+                registry: MockRegistry::new(),
+                replacement: serde_json::to_vec(&config).unwrap(),
+            };
+            let (descriptor, manifest) = {
+                let client = client(&tampering.registry);
+                push_image(&client, "app")
+            };
+            let client = RegistryClient::new(REGISTRY, &tampering).unwrap();
+
+            let err = client
+                .pull_manifest("app", &descriptor.digest.to_string())
+                .unwrap_err();
+            assert!(matches!(
+                err,
+                RegistryError::Verification(VerificationError::DigestMismatch { .. })
+            ));
+            // the digest the registry reports is checked too
+            assert!(matches!(
+                client.pull_manifest("app", "latest"),
+                Err(RegistryError::Verification(_))
+            ));
+            assert!(matches!(
+                client.pull_image_config("app", &manifest),
+                Err(RegistryError::Verification(_))
+            ));
+
+            let mut layer = Vec::new();
+            let err = client
+                .get_blob("app", &manifest.layers[0])
+                .unwrap()
+                .read_to_end(&mut layer)
+                .unwrap_err();
+            assert!(VerificationError::from_io_error(&err).is_some());
+        }
+
+        #[test]
+        fn rejects_uploads_with_wrong_digest() {
+            let registry = MockRegistry::new();
+            let client = client(&registry);
+            let url = client.start_upload("app").unwrap();
+            let mut url = client.join(url.as_str()).unwrap();
+            url.query_pairs_mut()
+                .append_pair("digest", &Digest::sha256(b"other").to_string());
+            let err = client
+                .send(Method::Put, url, vec![], b"data".to_vec(), &[201])
+                .unwrap_err();
+            assert!(matches!(err, RegistryError::Status { status: 400, .. }));
+        }
+    }
+
+    #[test]
+    fn parses_link_headers() {
+        assert_eq!(
+            next_link("</v2/app/tags/list?n=2&last=b>; rel=\"next\""),
+            Some("/v2/app/tags/list?n=2&last=b".to_string())
+        );
+        assert_eq!(next_link("<https://x/y>; rel=prev"), None);
+    }
+}
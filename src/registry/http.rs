@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use crate::registry::{Method, RegistryError, Request, Response, Transport};
+
+/// Talks to a registry over HTTP(S).
+#[derive(Debug, Clone)]
+pub struct HttpTransport {
+    agent: ureq::Agent,
+    authorization: Option<String>,
+}
+
+impl Default for HttpTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpTransport {
+    pub fn new() -> Self {
+        HttpTransport {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                // most registries redirect blob downloads to their storage backend
+                .redirects(5)
+                .redirect_auth_headers(ureq::RedirectAuthHeaders::Never)
+                .build(),
+            authorization: None,
+        }
+    }
+
+    /// Sends `authorization` as the `Authorization` header of requests that allow
+    /// [`credentials`](Request::credentials), such as `Basic <credentials>` or `Bearer <token>`.
+    /// It's never carried over to redirects.
+    pub fn with_authorization(mut self, authorization: &str) -> Self {
+        self.authorization = Some(authorization.to_string());
+        self
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, request: Request) -> Result<Response, RegistryError> {
+        let mut http_request = self
+            .agent
+            .request_url(request.method.as_str(), &request.url);
+        match &self.authorization {
+            Some(authorization) if request.credentials => {
+                http_request = http_request.set("Authorization", authorization);
+            }
+            _ => {}
+        }
+        for (name, value) in &request.headers {
+            http_request = http_request.set(name, value);
+        }
+
+        let result = match request.method {
+            Method::Get | Method::Head | Method::Delete if request.body.is_empty() => {
+                http_request.call()
+            }
+            _ => http_request.send_bytes(&request.body),
+        };
+        let http_response = match result {
+            Ok(response) => response,
+            // error statuses are for the client to interpret
+            Err(ureq::Error::Status(_, response)) => response,
+            Err(error) => return Err(RegistryError::Transport(error.to_string())),
+        };
+
+        let headers = http_response
+            .headers_names()
+            .into_iter()
+            .flat_map(|name| {
+                http_response
+                    .all(&name)
+                    .into_iter()
+                    .map(|value| (name.clone(), value.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Ok(Response {
+            status: http_response.status(),
+            headers,
+            body: Box::new(http_response.into_reader()),
+        })
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use crate::layer::is_foreign_layer;
+use crate::registry::client::DIGEST_HEADER;
+use crate::registry::routes::*;
+use crate::registry::{Method, RegistryError, Request, Response, Transport};
+use crate::v1::{is_image_index, parse_image_index, parse_image_manifest, Descriptor, Digest};
+
+use serde::Deserialize;
+
+/// An in-memory registry that handles requests in-process, for testing code built on
+/// [`RegistryClient`](crate::registry::RegistryClient) without a network.
+#[derive(Debug)]
+pub struct MockRegistry {
+    referrers_api: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    repositories: BTreeMap<String, Repository>,
+    uploads: HashMap<String, Upload>,
+    next_upload: usize,
+    requests: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Repository {
+    blobs: BTreeMap<Digest, Vec<u8>>,
+    // media type and content
+    manifests: BTreeMap<Digest, (String, Vec<u8>)>,
+    tags: BTreeMap<String, Digest>,
+}
+
+#[derive(Debug)]
+struct Upload {
+    repository: String,
+    data: Vec<u8>,
+}
+
+// the parts of a manifest that make it a referrer
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Referrer {
+    artifact_type: Option<String>,
+    config: Option<Descriptor>,
+    subject: Option<Descriptor>,
+    annotations: Option<HashMap<String, String>>,
+}
+
+impl Default for MockRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockRegistry {
+    pub fn new() -> Self {
+        MockRegistry {
+            referrers_api: true,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Behaves like registries that predate the referrers API, answering 404 to it.
+    pub fn without_referrers_api() -> Self {
+        MockRegistry {
+            referrers_api: false,
+            ..Self::new()
+        }
+    }
+
+    /// Every request handled so far, as `METHOD path?query`.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    fn handle(&self, request: Request) -> Response {
+        let mut state = self.state.lock().unwrap();
+        let path = request.url.path().to_string();
+        state.requests.push(match request.url.query() {
+            Some(query) => format!("{} {}?{}", request.method, path, query),
+            None => format!("{} {}", request.method, path),
+        });
+
+        match route(&path) {
+            Route::Base => Response::new(200),
+            Route::Manifest { name, reference } => state.manifest(&request, &name, reference),
+            Route::Blob { name, digest } => state.blob(&request, &name, digest),
+            Route::Upload { name, id } => state.upload(&request, &name, id),
+            Route::Tags { name } => state.tags(&request, &name),
+            Route::Referrers { name, digest } if self.referrers_api => {
+                state.referrers(&request, &name, digest)
+            }
+            _ => not_found(),
+        }
+    }
+}
+
+impl Transport for MockRegistry {
+    fn send(&self, request: Request) -> Result<Response, RegistryError> {
+        Ok(self.handle(request))
+    }
+}
+
+impl State {
+    fn manifest(&mut self, request: &Request, name: &str, reference: &str) -> Response {
+        if request.method == Method::Put {
+            return self.put_manifest(request, name, reference);
+        }
+
+        let repository = match self.repositories.get_mut(name) {
+            Some(repository) => repository,
+            None => return name_unknown(),
+        };
+        let digest = match reference.parse::<Digest>() {
+            Ok(digest) => digest,
+            Err(_) => match repository.tags.get(reference) {
+                Some(digest) => digest.clone(),
+                None => return manifest_unknown(),
+            },
+        };
+        let (media_type, data) = match repository.manifests.get(&digest) {
+            Some(manifest) => manifest.clone(),
+            None => return manifest_unknown(),
+        };
+
+        match request.method {
+            Method::Get | Method::Head => {
+                let mut response = content(request.method, media_type.as_str(), data);
+                response
+                    .headers
+                    .push((DIGEST_HEADER.to_string(), digest.to_string()));
+                response
+            }
+            Method::Delete => {
+                if reference.parse::<Digest>().is_ok() {
+                    repository.manifests.remove(&digest);
+                    repository.tags.retain(|_, x| *x != digest);
+                } else {
+                    repository.tags.remove(reference);
+                }
+                Response::new(202)
+            }
+            _ => unsupported(),
+        }
+    }
+
+    fn put_manifest(&mut self, request: &Request, name: &str, reference: &str) -> Response {
+        let media_type = request.header("Content-Type").unwrap_or("").to_string();
+        let data = request.body.clone();
+        let digest = Digest::sha256(&data);
+        if let Ok(expected) = reference.parse::<Digest>() {
+            if expected != digest {
+                return digest_invalid("manifest doesn't match its digest");
+            }
+        }
+
+        let repository = self.repositories.entry(name.to_string()).or_default();
+        let (references, subject) = if is_image_index(&media_type) {
+            match parse_image_index(&mut data.as_slice()) {
+                Ok(index) => (index.manifests, index.subject),
+                Err(e) => return manifest_invalid(&e.to_string()),
+            }
+        } else {
+            match parse_image_manifest(&mut data.as_slice()) {
+                Ok(manifest) => {
+                    let mut blobs = manifest.layers;
+                    blobs.push(manifest.config);
+                    (blobs, manifest.subject)
+                }
+                Err(e) => return manifest_invalid(&e.to_string()),
+            }
+        };
+        for descriptor in references {
+            let distributable = !is_foreign_layer(&descriptor) && descriptor.urls.is_none();
+            if distributable
+                && !repository.blobs.contains_key(&descriptor.digest)
+                && !repository.manifests.contains_key(&descriptor.digest)
+            {
+                return error(
+                    400,
+                    "MANIFEST_BLOB_UNKNOWN",
+                    &format!("`{}` isn't in the repository", descriptor.digest),
+                );
+            }
+        }
+
+        repository
+            .manifests
+            .insert(digest.clone(), (media_type, data));
+        if reference.parse::<Digest>().is_err() {
+            repository
+                .tags
+                .insert(reference.to_string(), digest.clone());
+        }
+        manifest_created(name, &digest, subject.map(|x| x.digest).as_ref())
+    }
+
+    fn blob(&mut self, request: &Request, name: &str, digest: &str) -> Response {
+        let digest = match digest.parse::<Digest>() {
+            Ok(digest) => digest,
+            Err(_) => return digest_invalid("invalid digest"),
+        };
+        let repository = match self.repositories.get_mut(name) {
+            Some(repository) => repository,
+            None => return name_unknown(),
+        };
+        let data = match repository.blobs.get(&digest) {
+            Some(data) => data.clone(),
+            None => return blob_unknown(),
+        };
+        match request.method {
+            Method::Get | Method::Head => {
+                let mut response = content(request.method, "application/octet-stream", data);
+                response
+                    .headers
+                    .push((DIGEST_HEADER.to_string(), digest.to_string()));
+                response
+            }
+            Method::Delete => {
+                repository.blobs.remove(&digest);
+                Response::new(202)
+            }
+            _ => unsupported(),
+        }
+    }
+
+    fn upload(&mut self, request: &Request, name: &str, id: &str) -> Response {
+        let query = query(request);
+        match (request.method, id) {
+            (Method::Post, "") => {
+                if let (Some(mount), Some(from)) = (query.get("mount"), query.get("from")) {
+                    if let Some(response) = self.mount(name, mount, from) {
+                        return response;
+                    }
+                }
+                let mut upload = Upload {
+                    repository: name.to_string(),
+                    data: vec![],
+                };
+                if let Some(digest) = query.get("digest") {
+                    upload.data = request.body.clone();
+                    return self.finish_upload(upload, digest);
+                }
+
+                self.next_upload += 1;
+                let id = format!("upload-{}", self.next_upload);
+                self.uploads.insert(id.clone(), upload);
+                upload_response(name, &id, 0)
+            }
+            (Method::Patch, id) => {
+                let upload = match self.uploads.get_mut(id) {
+                    Some(upload) if upload.repository == name => upload,
+                    _ => return upload_unknown(),
+                };
+                if !chunk_in_order(request, upload.data.len() as u64) {
+                    return error(416, "BLOB_UPLOAD_INVALID", "chunk out of order");
+                }
+                upload.data.extend_from_slice(&request.body);
+                upload_response(name, id, upload.data.len() as u64)
+            }
+            (Method::Put, id) => {
+                let mut upload = match self.uploads.remove(id) {
+                    Some(upload) if upload.repository == name => upload,
+                    _ => return upload_unknown(),
+                };
+                upload.data.extend_from_slice(&request.body);
+                match query.get("digest") {
+                    Some(digest) => self.finish_upload(upload, digest),
+                    None => digest_invalid("missing digest"),
+                }
+            }
+            _ => unsupported(),
+        }
+    }
+
+    fn mount(&mut self, name: &str, digest: &str, from: &str) -> Option<Response> {
+        let digest = digest.parse::<Digest>().ok()?;
+        let data = self.repositories.get(from)?.blobs.get(&digest)?.clone();
+        self.repositories
+            .entry(name.to_string())
+            .or_default()
+            .blobs
+            .insert(digest.clone(), data);
+        Some(blob_created(name, &digest))
+    }
+
+    fn finish_upload(&mut self, upload: Upload, digest: &str) -> Response {
+        let actual = Digest::sha256(&upload.data);
+        if digest.parse() != Ok(actual.clone()) {
+            return digest_invalid("upload doesn't match its digest");
+        }
+        self.repositories
+            .entry(upload.repository.clone())
+            .or_default()
+            .blobs
+            .insert(actual.clone(), upload.data);
+        blob_created(&upload.repository, &actual)
+    }
+
+    fn tags(&self, request: &Request, name: &str) -> Response {
+        let repository = match self.repositories.get(name) {
+            Some(repository) => repository,
+            None => return name_unknown(),
+        };
+        let tags: Vec<String> = repository.tags.keys().cloned().collect();
+        tags_page(request, name, &tags)
+    }
+
+    fn referrers(&self, request: &Request, name: &str, digest: &str) -> Response {
+        let digest = match digest.parse::<Digest>() {
+            Ok(digest) => digest,
+            Err(_) => return digest_invalid("invalid digest"),
+        };
+        let mut manifests = vec![];
+        for (referrer_digest, (media_type, data)) in self
+            .repositories
+            .get(name)
+            .map(|x| &x.manifests)
+            .into_iter()
+            .flatten()
+        {
+            let referrer: Referrer = match serde_json::from_slice(data) {
+                Ok(referrer) => referrer,
+                Err(_) => continue,
+            };
+            if referrer.subject.map(|x| x.digest) != Some(digest.clone()) {
+                continue;
+            }
+            let Referrer {
+                artifact_type: referrer_type,
+                config,
+                annotations,
+                ..
+            } = referrer;
+            let referrer_type = referrer_type.or_else(|| config.map(|x| x.media_type));
+            manifests.push(Descriptor {
+                media_type: media_type.clone(),
+                digest: referrer_digest.clone(),
+                size: data.len() as u64,
+                urls: None,
+                artifact_type: referrer_type,
+                annotations,
+                platform: None,
+            });
+        }
+
+        referrers_index(request, manifests)
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Display;
+use std::io::Read;
+
+use crate::v1::{ParseError, VerificationError};
+
+use serde::{Deserialize, Serialize};
+
+mod client;
+pub use client::{RegistryClient, DEFAULT_CHUNK_SIZE};
+
+mod http;
+pub use http::HttpTransport;
+
+mod mock;
+pub use mock::MockRegistry;
+
+mod reference;
+pub use reference::{Reference, ReferenceError, DEFAULT_TAG, DOCKER_HUB_DOMAIN};
+
+mod routes;
+
+mod server;
+pub use server::{LayoutRegistry, RegistryServer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Patch,
+    Delete,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Patch => "PATCH",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: Method,
+    pub url: url::Url,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Whether the transport may send its credentials along. [`RegistryClient`] only allows it
+    /// for URLs with the scheme, host and port of the registry, so that a `Location` or `Link`
+    /// pointing elsewhere doesn't get them.
+    pub credentials: bool,
+}
+
+impl Request {
+    pub fn new(method: Method, url: url::Url) -> Self {
+        Request {
+            method,
+            url,
+            headers: vec![],
+            body: vec![],
+            credentials: true,
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Box<dyn Read + Send>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body: Box::new(std::io::empty()),
+        }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+}
+
+impl std::fmt::Debug for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+// header names are case-insensitive
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Sends requests to a registry. Responses with error statuses are still responses; only
+/// failing to get one at all is an error.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: Request) -> Result<Response, RegistryError>;
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    fn send(&self, request: Request) -> Result<Response, RegistryError> {
+        (**self).send(request)
+    }
+}
+
+/// An entry of the `errors` a registry responds with, as defined by the distribution spec.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ErrorResponse {
+    pub errors: Vec<ErrorInfo>,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Transport(String),
+    /// The registry responded with an unexpected status.
+    Status {
+        method: Method,
+        url: String,
+        status: u16,
+        errors: Vec<ErrorInfo>,
+    },
+    InvalidResponse(String),
+    Io(std::io::Error),
+    Parse(ParseError),
+    Serialize(serde_json::Error),
+    Verification(VerificationError),
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RegistryError::Transport(message) => write!(f, "{}", message),
+            RegistryError::Status {
+                method,
+                url,
+                status,
+                errors,
+            } => {
+                write!(f, "{} {} responded with {}", method, url, status)?;
+                for error in errors {
+                    write!(f, "; {}: {}", error.code, error.message)?;
+                }
+                Ok(())
+            }
+            RegistryError::InvalidResponse(message) => {
+                write!(f, "invalid response from registry: {}", message)
+            }
+            RegistryError::Io(error) => write!(f, "{}", error),
+            RegistryError::Parse(error) => write!(f, "{}", error),
+            RegistryError::Serialize(error) => write!(f, "failed to serialize: {}", error),
+            RegistryError::Verification(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RegistryError::Io(error) => Some(error),
+            RegistryError::Parse(error) => Some(error),
+            RegistryError::Serialize(error) => Some(error),
+            RegistryError::Verification(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RegistryError {
+    fn from(error: std::io::Error) -> Self {
+        match VerificationError::from_io_error(&error) {
+            Some(verification_error) => RegistryError::Verification(verification_error.clone()),
+            None => RegistryError::Io(error),
+        }
+    }
+}
+
+impl From<ParseError> for RegistryError {
+    fn from(error: ParseError) -> Self {
+        match error {
+            ParseError::Verification(error) => RegistryError::Verification(error),
+            error => RegistryError::Parse(error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for RegistryError {
+    fn from(error: serde_json::Error) -> Self {
+        RegistryError::Serialize(error)
+    }
+}
+
+impl From<VerificationError> for RegistryError {
+    fn from(error: VerificationError) -> Self {
+        RegistryError::Verification(error)
+    }
+}
@@ -0,0 +1,492 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::v1::Digest;
+
+use serde::de::{Deserializer, Error, Visitor};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+pub const DOCKER_HUB_DOMAIN: &str = "docker.io";
+pub const DEFAULT_TAG: &str = "latest";
+
+const DOCKER_HUB_LEGACY_DOMAIN: &str = "index.docker.io";
+// where Docker Hub actually serves the distribution API
+const DOCKER_HUB_REGISTRY: &str = "registry-1.docker.io";
+const OFFICIAL_NAMESPACE: &str = "library";
+const MAX_NAME_LENGTH: usize = 255;
+const MAX_TAG_LENGTH: usize = 128;
+
+/// A fully qualified image reference, `<domain>/<path>[:<tag>][@<digest>]`.
+///
+/// Parsing follows the grammar of the distribution project and Docker's normalization: a
+/// reference without a domain is on Docker Hub, where single-component paths are official
+/// images under `library/`, and a reference with neither tag nor digest is tagged `latest`. So
+/// `ubuntu` is `docker.io/library/ubuntu:latest`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reference {
+    domain: String,
+    path: String,
+    tag: Option<String>,
+    digest: Option<Digest>,
+}
+
+impl Reference {
+    /// The registry the repository is on, possibly with a port, such as `docker.io` or
+    /// `localhost:5000`.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// The repository within the registry, such as `library/ubuntu`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub fn digest(&self) -> Option<&Digest> {
+        self.digest.as_ref()
+    }
+
+    /// The host to send distribution API requests to, which for Docker Hub isn't its domain.
+    pub fn registry(&self) -> &str {
+        match self.domain.as_str() {
+            DOCKER_HUB_DOMAIN => DOCKER_HUB_REGISTRY,
+            domain => domain,
+        }
+    }
+
+    /// What to ask the registry's manifests endpoint for: the digest if there is one, since
+    /// it's what the tag is only a name for.
+    pub fn manifest_reference(&self) -> String {
+        match (&self.digest, &self.tag) {
+            (Some(digest), _) => digest.to_string(),
+            (None, Some(tag)) => tag.clone(),
+            (None, None) => DEFAULT_TAG.to_string(),
+        }
+    }
+
+    pub fn with_tag(&self, tag: &str) -> Result<Self, ReferenceError> {
+        validate_tag(tag)?;
+        Ok(Reference {
+            tag: Some(tag.to_string()),
+            digest: None,
+            ..self.clone()
+        })
+    }
+
+    pub fn with_digest(&self, digest: Digest) -> Self {
+        Reference {
+            digest: Some(digest),
+            ..self.clone()
+        }
+    }
+
+    /// The short form Docker displays, leaving out the Docker Hub domain and `library/`:
+    /// `ubuntu:latest` rather than `docker.io/library/ubuntu:latest`.
+    pub fn familiar(&self) -> String {
+        let name = if self.domain == DOCKER_HUB_DOMAIN {
+            match self.path.strip_prefix("library/") {
+                Some(image) if !image.contains('/') => image.to_string(),
+                _ => self.path.clone(),
+            }
+        } else {
+            format!("{}/{}", self.domain, self.path)
+        };
+        with_tag_and_digest(name, self.tag.as_deref(), self.digest.as_ref())
+    }
+}
+
+fn with_tag_and_digest(mut name: String, tag: Option<&str>, digest: Option<&Digest>) -> String {
+    if let Some(tag) = tag {
+        name.push(':');
+        name.push_str(tag);
+    }
+    if let Some(digest) = digest {
+        name.push('@');
+        name.push_str(&digest.to_string());
+    }
+    name
+}
+
+impl FromStr for Reference {
+    type Err = ReferenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(ReferenceError::new(s, "empty reference"));
+        }
+        let (name, digest) = match s.find('@') {
+            Some(idx) => {
+                let digest = s[idx + 1..]
+                    .parse::<Digest>()
+                    .map_err(|_| ReferenceError::new(&s[idx + 1..], "invalid digest"))?;
+                (&s[..idx], Some(digest))
+            }
+            None => (s, None),
+        };
+        // a colon before the last slash separates a port
+        let (name, tag) = match name.rfind(':') {
+            Some(idx) if !name[idx..].contains('/') => (&name[..idx], Some(&name[idx + 1..])),
+            _ => (name, None),
+        };
+
+        let (domain, path) = split_domain(name);
+        validate_domain(domain)?;
+        let path = match domain {
+            DOCKER_HUB_DOMAIN if !path.contains('/') => format!("{}/{}", OFFICIAL_NAMESPACE, path),
+            _ => path.to_string(),
+        };
+        validate_path(&path)?;
+        if domain.len() + 1 + path.len() > MAX_NAME_LENGTH {
+            return Err(ReferenceError::new(
+                name,
+                "name is longer than 255 characters",
+            ));
+        }
+        if let Some(tag) = tag {
+            validate_tag(tag)?;
+        }
+
+        Ok(Reference {
+            domain: domain.to_string(),
+            path,
+            tag: match (tag, &digest) {
+                (None, None) => Some(DEFAULT_TAG.to_string()),
+                (tag, _) => tag.map(String::from),
+            },
+            digest,
+        })
+    }
+}
+
+impl Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = format!("{}/{}", self.domain, self.path);
+        let reference = with_tag_and_digest(name, self.tag.as_deref(), self.digest.as_ref());
+        write!(f, "{}", reference)
+    }
+}
+
+// the first component is a domain if it looks like a host rather than a path, as in Docker
+fn split_domain(name: &str) -> (&str, &str) {
+    match name.find('/') {
+        Some(idx) => {
+            let first = &name[..idx];
+            let is_domain = first.contains('.')
+                || first.contains(':')
+                || first == "localhost"
+                || first.chars().any(|c| c.is_ascii_uppercase());
+            match first {
+                DOCKER_HUB_LEGACY_DOMAIN => (DOCKER_HUB_DOMAIN, &name[idx + 1..]),
+                _ if is_domain => (first, &name[idx + 1..]),
+                _ => (DOCKER_HUB_DOMAIN, name),
+            }
+        }
+        None => (DOCKER_HUB_DOMAIN, name),
+    }
+}
+
+// domain ::= host (':' port-number)?
+// host ::= domain-name | IPv4address | '[' IPv6address ']'
+fn validate_domain(domain: &str) -> Result<(), ReferenceError> {
+    let (host, port) = if domain.starts_with('[') {
+        match domain.find(']') {
+            Some(idx) => {
+                let ipv6 = &domain[1..idx];
+                if ipv6.is_empty() || !ipv6.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+                    return Err(ReferenceError::new(domain, "invalid IPv6 address"));
+                }
+                match &domain[idx + 1..] {
+                    "" => (None, None),
+                    rest => match rest.strip_prefix(':') {
+                        Some(port) => (None, Some(port)),
+                        None => return Err(ReferenceError::new(domain, "invalid domain")),
+                    },
+                }
+            }
+            None => return Err(ReferenceError::new(domain, "invalid IPv6 address")),
+        }
+    } else {
+        match domain.find(':') {
+            Some(idx) => (Some(&domain[..idx]), Some(&domain[idx + 1..])),
+            None => (Some(domain), None),
+        }
+    };
+
+    // domain-component ::= [a-zA-Z0-9] | [a-zA-Z0-9][a-zA-Z0-9-]*[a-zA-Z0-9]
+    let is_valid_component = |x: &str| {
+        !x.is_empty()
+            && !x.starts_with('-')
+            && !x.ends_with('-')
+            && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if let Some(host) = host {
+        if !host.split('.').all(is_valid_component) {
+            return Err(ReferenceError::new(domain, "invalid domain"));
+        }
+    }
+    if let Some(port) = port {
+        if port.is_empty() || !port.chars().all(|c| c.is_ascii_digit()) {
+            return Err(ReferenceError::new(domain, "invalid port"));
+        }
+    }
+    Ok(())
+}
+
+// path-component ::= alpha-numeric (separator alpha-numeric)*
+// separator ::= [_.] | '__' | [-]*
+fn validate_path(path: &str) -> Result<(), ReferenceError> {
+    if path.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(ReferenceError::new(
+            path,
+            "repository name must be lowercase",
+        ));
+    }
+    let is_alphanumeric = |x: &str| {
+        !x.is_empty()
+            && x.chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+    };
+    let is_separator = |x: &str| {
+        x == "." || x == "_" || x == "__" || (!x.is_empty() && x.chars().all(|c| c == '-'))
+    };
+    let is_valid_component = |component: &str| {
+        let is_alphanumeric_char = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit();
+        // alternating runs of alpha-numerics and separators, starting and ending with the former
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for (idx, c) in component.char_indices().skip(1) {
+            let previous = component[..idx].chars().last().unwrap();
+            if is_alphanumeric_char(c) != is_alphanumeric_char(previous) {
+                runs.push(&component[start..idx]);
+                start = idx;
+            }
+        }
+        runs.push(&component[start..]);
+        runs.len() % 2 == 1
+            && runs.iter().enumerate().all(|(idx, run)| {
+                if idx % 2 == 0 {
+                    is_alphanumeric(run)
+                } else {
+                    is_separator(run)
+                }
+            })
+    };
+
+    if path.split('/').all(is_valid_component) {
+        Ok(())
+    } else {
+        Err(ReferenceError::new(path, "invalid repository name"))
+    }
+}
+
+// tag ::= [\w][\w.-]{0,127}
+fn validate_tag(tag: &str) -> Result<(), ReferenceError> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let valid = match tag.chars().next() {
+        Some(first) => {
+            is_word(first)
+                && tag.len() <= MAX_TAG_LENGTH
+                && tag.chars().all(|c| is_word(c) || c == '.' || c == '-')
+        }
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(ReferenceError::new(tag, "invalid tag"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReferenceError {
+    pub value: String,
+    pub reason: &'static str,
+}
+
+impl ReferenceError {
+    fn new(value: &str, reason: &'static str) -> Self {
+        ReferenceError {
+            value: value.to_string(),
+            reason,
+        }
+    }
+}
+
+impl Display for ReferenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid reference `{}`: {}", self.value, self.reason)
+    }
+}
+
+impl std::error::Error for ReferenceError {}
+
+impl Serialize for Reference {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Reference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(ReferenceVisitor {})
+    }
+}
+struct ReferenceVisitor;
+impl<'de> Visitor<'de> for ReferenceVisitor {
+    type Value = Reference;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str("an image reference such as `docker.io/library/ubuntu:latest`")
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse().map_err(Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_HEX: &str = "6c3c624b58dbbcd3c0dd82b4c53f04194d1247c6eebdaab7c610cf7d66709b3b";
+
+    fn parse(s: &str) -> Reference {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn normalizes_docker_hub_references() {
+        let reference = parse("ubuntu");
+        assert_eq!(reference.domain(), "docker.io");
+        assert_eq!(reference.path(), "library/ubuntu");
+        assert_eq!(reference.tag(), Some("latest"));
+        assert_eq!(reference.digest(), None);
+        assert_eq!(reference.to_string(), "docker.io/library/ubuntu:latest");
+        assert_eq!(reference.registry(), "registry-1.docker.io");
+
+        assert_eq!(
+            parse("bitnami/redis:7").to_string(),
+            "docker.io/bitnami/redis:7"
+        );
+        assert_eq!(
+            parse("index.docker.io/ubuntu").to_string(),
+            "docker.io/library/ubuntu:latest"
+        );
+        assert_eq!(
+            parse("docker.io/library/ubuntu:22.04").to_string(),
+            "docker.io/library/ubuntu:22.04"
+        );
+    }
+
+    #[test]
+    fn parses_domains_ports_and_digests() {
+        let raw = format!("localhost:5000/team/app:v1.2@sha256:{}", SHA256_HEX);
+        let reference = parse(&raw);
+        assert_eq!(reference.domain(), "localhost:5000");
+        assert_eq!(reference.path(), "team/app");
+        assert_eq!(reference.tag(), Some("v1.2"));
+        assert_eq!(reference.digest().unwrap().encoded(), SHA256_HEX);
+        assert_eq!(
+            reference.manifest_reference(),
+            format!("sha256:{}", SHA256_HEX)
+        );
+        assert_eq!(reference.to_string(), raw);
+
+        // a digest alone doesn't get the default tag
+        let reference = parse(&format!("ghcr.io/org/app@sha256:{}", SHA256_HEX));
+        assert_eq!(reference.tag(), None);
+        assert_eq!(reference.registry(), "ghcr.io");
+
+        assert_eq!(parse("localhost/app").domain(), "localhost");
+        assert_eq!(parse("[::1]:5000/app").domain(), "[::1]:5000");
+        assert_eq!(parse("192.168.1.1/app").domain(), "192.168.1.1");
+        assert_eq!(parse("Registry/app").domain(), "Registry");
+        assert_eq!(parse("a-b.c-d/e--f/g_h:1").path(), "e--f/g_h");
+    }
+
+    #[test]
+    fn displays_familiar_form() {
+        assert_eq!(parse("ubuntu").familiar(), "ubuntu:latest");
+        assert_eq!(
+            parse("docker.io/bitnami/redis:7").familiar(),
+            "bitnami/redis:7"
+        );
+        assert_eq!(parse("library/a/b").familiar(), "library/a/b:latest");
+        assert_eq!(parse("quay.io/org/app:v1").familiar(), "quay.io/org/app:v1");
+    }
+
+    #[test]
+    fn replaces_tags_and_digests() {
+        let reference = parse("ubuntu").with_tag("24.04").unwrap();
+        assert_eq!(reference.manifest_reference(), "24.04");
+        let digest = Digest::new("sha256", SHA256_HEX).unwrap();
+        let pinned = reference.with_digest(digest.clone());
+        assert_eq!(pinned.tag(), Some("24.04"));
+        assert_eq!(pinned.manifest_reference(), digest.to_string());
+        assert_eq!(pinned.with_tag("25.04").unwrap().digest(), None);
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        fn reason(s: &str) -> &'static str {
+            s.parse::<Reference>().unwrap_err().reason
+        }
+
+        #[test]
+        fn rejects_uppercase_repositories() {
+            assert_eq!(reason("Ubuntu"), "repository name must be lowercase");
+            assert_eq!(
+                reason("ghcr.io/Org/app"),
+                "repository name must be lowercase"
+            );
+        }
+
+        #[test]
+        fn rejects_invalid_names() {
+            assert_eq!(reason(""), "empty reference");
+            assert_eq!(reason("a..b"), "invalid repository name");
+            assert_eq!(reason("a/-b"), "invalid repository name");
+            assert_eq!(reason("ghcr.io/app/"), "invalid repository name");
+            assert_eq!(reason("a___b"), "invalid repository name");
+            assert_eq!(reason("-host.io/app"), "invalid domain");
+            assert_eq!(reason("host.io:port/app"), "invalid port");
+            assert_eq!(reason("[::1/app"), "invalid IPv6 address");
+            let long = format!("ghcr.io/{}", "a".repeat(250));
+            assert_eq!(reason(&long), "name is longer than 255 characters");
+        }
+
+        #[test]
+        fn rejects_invalid_tags_and_digests() {
+            assert_eq!(reason("app:.v1"), "invalid tag");
+            assert_eq!(reason("app:"), "invalid tag");
+            assert_eq!(reason(&format!("app:{}", "v".repeat(129))), "invalid tag");
+            let err = "app@sha256:abc".parse::<Reference>().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "invalid reference `sha256:abc`: invalid digest"
+            );
+        }
+    }
+
+    mod json {
+        use super::*;
+
+        #[test]
+        fn round_trips_as_string() {
+            let reference = parse("ubuntu");
+            let serialized = serde_json::to_string(&reference).unwrap();
+            assert_eq!(serialized, r#""docker.io/library/ubuntu:latest""#);
+            assert_eq!(
+                serde_json::from_str::<Reference>(&serialized).unwrap(),
+                reference
+            );
+            assert!(serde_json::from_str::<Reference>(r#""Ubuntu""#).is_err());
+        }
+    }
+}
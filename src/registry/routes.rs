@@ -0,0 +1,299 @@
+//! Routing and responses shared by the registries this crate implements.
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use crate::registry::client::DIGEST_HEADER;
+use crate::registry::{ErrorInfo, ErrorResponse, Method, Request, Response};
+use crate::v1::{Descriptor, Digest, Index, MEDIA_TYPE_IMAGE_INDEX};
+
+use serde::Serialize;
+
+/// The distribution API endpoint a request is for.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Route<'a> {
+    Base,
+    Manifest { name: String, reference: &'a str },
+    Blob { name: String, digest: &'a str },
+    // `id` is empty when starting an upload
+    Upload { name: String, id: &'a str },
+    Tags { name: String },
+    Referrers { name: String, digest: &'a str },
+    Unknown,
+}
+
+// repository names may contain slashes, so endpoints are matched from the end of the path
+pub(crate) fn route(path: &str) -> Route<'_> {
+    if path == "/v2/" || path == "/v2" {
+        return Route::Base;
+    }
+    let segments: Vec<&str> = match path.strip_prefix("/v2/") {
+        Some(rest) => rest.split('/').collect(),
+        None => return Route::Unknown,
+    };
+    let count = segments.len();
+    let name = |end: usize| segments[..end].join("/");
+
+    if count >= 4 && segments[count - 3] == "blobs" && segments[count - 2] == "uploads" {
+        Route::Upload {
+            name: name(count - 3),
+            id: segments[count - 1],
+        }
+    } else if count >= 3 && segments[count - 2] == "manifests" {
+        Route::Manifest {
+            name: name(count - 2),
+            reference: segments[count - 1],
+        }
+    } else if count >= 3 && segments[count - 2] == "blobs" {
+        Route::Blob {
+            name: name(count - 2),
+            digest: segments[count - 1],
+        }
+    } else if count >= 3 && segments[count - 2] == "tags" && segments[count - 1] == "list" {
+        Route::Tags {
+            name: name(count - 2),
+        }
+    } else if count >= 3 && segments[count - 2] == "referrers" {
+        Route::Referrers {
+            name: name(count - 2),
+            digest: segments[count - 1],
+        }
+    } else {
+        Route::Unknown
+    }
+}
+
+pub(crate) fn query(request: &Request) -> HashMap<String, String> {
+    request.url.query_pairs().into_owned().collect()
+}
+
+pub(crate) fn content(method: Method, media_type: &str, data: Vec<u8>) -> Response {
+    let mut response = Response::new(200);
+    response.headers = vec![
+        ("Content-Type".to_string(), media_type.to_string()),
+        ("Content-Length".to_string(), data.len().to_string()),
+    ];
+    if method != Method::Head {
+        response.body = Box::new(Cursor::new(data));
+    }
+    response
+}
+
+pub(crate) fn json<T: Serialize>(mut response: Response, value: &T) -> Response {
+    let data = serde_json::to_vec(value).unwrap();
+    response
+        .headers
+        .push(("Content-Length".to_string(), data.len().to_string()));
+    response.body = Box::new(Cursor::new(data));
+    if response.header("Content-Type").is_none() {
+        response
+            .headers
+            .push(("Content-Type".to_string(), "application/json".to_string()));
+    }
+    response
+}
+
+pub(crate) fn error(status: u16, code: &str, message: &str) -> Response {
+    let body = ErrorResponse {
+        errors: vec![ErrorInfo {
+            code: code.to_string(),
+            message: message.to_string(),
+        }],
+    };
+    json(Response::new(status), &body)
+}
+
+pub(crate) fn not_found() -> Response {
+    error(404, "NOT_FOUND", "unknown endpoint")
+}
+
+pub(crate) fn name_unknown() -> Response {
+    error(404, "NAME_UNKNOWN", "repository not known to registry")
+}
+
+pub(crate) fn manifest_unknown() -> Response {
+    error(404, "MANIFEST_UNKNOWN", "manifest unknown")
+}
+
+pub(crate) fn blob_unknown() -> Response {
+    error(404, "BLOB_UNKNOWN", "blob unknown to registry")
+}
+
+pub(crate) fn digest_invalid(message: &str) -> Response {
+    error(400, "DIGEST_INVALID", message)
+}
+
+pub(crate) fn manifest_invalid(message: &str) -> Response {
+    error(400, "MANIFEST_INVALID", message)
+}
+
+pub(crate) fn upload_unknown() -> Response {
+    error(404, "BLOB_UPLOAD_UNKNOWN", "upload unknown")
+}
+
+pub(crate) fn unsupported() -> Response {
+    error(405, "UNSUPPORTED", "method not allowed")
+}
+
+pub(crate) fn upload_response(name: &str, id: &str, received: u64) -> Response {
+    let mut response = Response::new(202);
+    response.headers = vec![
+        (
+            "Location".to_string(),
+            format!("/v2/{}/blobs/uploads/{}", name, id),
+        ),
+        (
+            "Range".to_string(),
+            format!("0-{}", received.saturating_sub(1)),
+        ),
+        ("Docker-Upload-UUID".to_string(), id.to_string()),
+    ];
+    response
+}
+
+pub(crate) fn blob_created(name: &str, digest: &Digest) -> Response {
+    let mut response = Response::new(201);
+    response.headers = vec![
+        (
+            "Location".to_string(),
+            format!("/v2/{}/blobs/{}", name, digest),
+        ),
+        (DIGEST_HEADER.to_string(), digest.to_string()),
+    ];
+    response
+}
+
+pub(crate) fn manifest_created(name: &str, digest: &Digest, subject: Option<&Digest>) -> Response {
+    let mut response = Response::new(201);
+    response.headers = vec![
+        (
+            "Location".to_string(),
+            format!("/v2/{}/manifests/{}", name, digest),
+        ),
+        (DIGEST_HEADER.to_string(), digest.to_string()),
+    ];
+    // tells clients they don't have to maintain the referrers tag themselves
+    if let Some(subject) = subject {
+        response
+            .headers
+            .push(("OCI-Subject".to_string(), subject.to_string()));
+    }
+    response
+}
+
+/// Checks where the `Content-Range` of a chunk starts, if it has one, against how much of the
+/// upload has been received.
+pub(crate) fn chunk_in_order(request: &Request, received: u64) -> bool {
+    let start = request
+        .header("Content-Range")
+        .and_then(|x| x.split('-').next())
+        .and_then(|x| x.trim().parse::<u64>().ok());
+    start.is_none() || start == Some(received)
+}
+
+#[derive(Serialize)]
+struct TagList<'a> {
+    name: &'a str,
+    tags: Vec<&'a str>,
+}
+
+/// Responds with a page of `tags`, which must be sorted, honouring the `n` and `last` query
+/// parameters and linking to the next page if there is one.
+pub(crate) fn tags_page(request: &Request, name: &str, tags: &[String]) -> Response {
+    let query = query(request);
+    let last = query.get("last");
+    let page_size = query.get("n").and_then(|x| x.parse::<usize>().ok());
+
+    let mut tags: Vec<&str> = tags
+        .iter()
+        .map(|x| x.as_str())
+        .filter(|x| last.is_none_or(|last| *x > last.as_str()))
+        .collect();
+    let mut response = Response::new(200);
+    if let Some(page_size) = page_size {
+        if tags.len() > page_size {
+            tags.truncate(page_size);
+            response.headers.push((
+                "Link".to_string(),
+                format!(
+                    "</v2/{}/tags/list?n={}&last={}>; rel=\"next\"",
+                    name,
+                    page_size,
+                    tags.last().copied().unwrap_or_default()
+                ),
+            ));
+        }
+    }
+    json(response, &TagList { name, tags })
+}
+
+/// Responds with the index of `referrers`, keeping only those of the `artifactType` query
+/// parameter if there is one.
+pub(crate) fn referrers_index(request: &Request, referrers: Vec<Descriptor>) -> Response {
+    let query = query(request);
+    let artifact_type = query.get("artifactType");
+
+    let mut response = Response::new(200);
+    response.headers.push((
+        "Content-Type".to_string(),
+        MEDIA_TYPE_IMAGE_INDEX.to_string(),
+    ));
+    if artifact_type.is_some() {
+        response.headers.push((
+            "OCI-Filters-Applied".to_string(),
+            "artifactType".to_string(),
+        ));
+    }
+    let index = Index {
+        schema_version: 2,
+        artifact_type: None,
+        subject: None,
+        manifests: referrers
+            .into_iter()
+            .filter(|x| artifact_type.is_none() || x.artifact_type.as_ref() == artifact_type)
+            .collect(),
+        media_type: Some(MEDIA_TYPE_IMAGE_INDEX.to_string()),
+        annotations: None,
+    };
+    json(response, &index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_requests() {
+        assert_eq!(route("/v2/"), Route::Base);
+        assert_eq!(
+            route("/v2/library/app/manifests/latest"),
+            Route::Manifest {
+                name: "library/app".to_string(),
+                reference: "latest"
+            }
+        );
+        assert_eq!(
+            route("/v2/app/blobs/uploads/"),
+            Route::Upload {
+                name: "app".to_string(),
+                id: ""
+            }
+        );
+        // `blobs` and `manifests` may be part of a repository name
+        assert_eq!(
+            route("/v2/blobs/manifests/blobs/abc"),
+            Route::Blob {
+                name: "blobs/manifests".to_string(),
+                digest: "abc"
+            }
+        );
+        assert_eq!(
+            route("/v2/a/b/tags/list"),
+            Route::Tags {
+                name: "a/b".to_string()
+            }
+        );
+        assert_eq!(route("/v2/app/tags"), Route::Unknown);
+        assert_eq!(route("/other"), Route::Unknown);
+    }
+}
@@ -0,0 +1,1085 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use crate::layer::is_foreign_layer;
+use crate::layout::{ref_name, set_ref_name, OciLayout, BLOBS_DIR, LAYOUT_FILE};
+use crate::registry::client::{declared_media_type, DIGEST_HEADER};
+use crate::registry::routes::*;
+use crate::registry::{Method, RegistryError, Request, Response, Transport};
+use crate::store::{BlobStore, StoreError};
+use crate::v1::{
+    is_image_index, is_image_manifest, parse_image_config_with_descriptor, parse_image_index,
+    parse_image_manifest, Descriptor, Digest, VerificationError, VerifyingReader,
+    MEDIA_TYPE_DOCKER_CONFIG, MEDIA_TYPE_IMAGE_CONFIG, MEDIA_TYPE_IMAGE_INDEX,
+    MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+// bodies other than blobs are manifests, which registries commonly limit to 4 MiB
+const MAX_BODY_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A registry serving a directory of OCI image layouts, one per repository: repository
+/// `library/app` is the layout at `<root>/library/app`, created when it's first pushed to.
+///
+/// Manifests are validated when pushed, including the `ImageConfig` of image manifests, and
+/// kept in `index.json`: tagged through `org.opencontainers.image.ref.name`, untagged if pushed
+/// by digest. Referrers are tracked through the referrers tag schema, so the layouts work with
+/// [`OciLayout::referrers`] too.
+///
+/// Requests are either handled in-process through its [`Transport`] implementation or over HTTP
+/// with [`serve`](LayoutRegistry::serve).
+#[derive(Debug)]
+pub struct LayoutRegistry {
+    root: PathBuf,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    uploads: HashMap<String, Upload>,
+    next_upload: usize,
+}
+
+#[derive(Debug)]
+struct Upload {
+    repository: String,
+    path: PathBuf,
+    received: u64,
+}
+
+impl LayoutRegistry {
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Self, StoreError> {
+        fs::create_dir_all(root.as_ref())?;
+        Ok(LayoutRegistry {
+            root: root.as_ref().to_path_buf(),
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The layout of `repository`, if anything has been pushed to it.
+    pub fn layout(&self, repository: &str) -> Result<Option<OciLayout>, StoreError> {
+        if !valid_name(repository) {
+            return Ok(None);
+        }
+        let path = self.root.join(repository);
+        if !path.join(LAYOUT_FILE).exists() {
+            return Ok(None);
+        }
+        Ok(Some(OciLayout::open(path)?))
+    }
+
+    /// Listens on `address`, such as `127.0.0.1:5000` or `127.0.0.1:0` for any free port, and
+    /// handles requests on background threads until the returned server is dropped.
+    pub fn serve(self, address: &str) -> Result<RegistryServer, RegistryError> {
+        let server = tiny_http::Server::http(address).map_err(std::io::Error::other)?;
+        let address = server.server_addr().to_ip().ok_or_else(|| {
+            RegistryError::Transport(format!("`{}` is not an IP address", address))
+        })?;
+
+        let server = Arc::new(server);
+        let registry = Arc::new(self);
+        let listener = Arc::clone(&server);
+        let thread = thread::spawn(move || {
+            for request in listener.incoming_requests() {
+                let registry = Arc::clone(&registry);
+                thread::spawn(move || registry.respond(request));
+            }
+        });
+        Ok(RegistryServer {
+            address,
+            server,
+            thread: Some(thread),
+        })
+    }
+
+    fn respond(&self, mut http_request: tiny_http::Request) {
+        let method = match http_request.method() {
+            tiny_http::Method::Get => Some(Method::Get),
+            tiny_http::Method::Head => Some(Method::Head),
+            tiny_http::Method::Post => Some(Method::Post),
+            tiny_http::Method::Put => Some(Method::Put),
+            tiny_http::Method::Patch => Some(Method::Patch),
+            tiny_http::Method::Delete => Some(Method::Delete),
+            _ => None,
+        };
+        // only the path and query matter
+        let url = url::Url::parse("http://localhost").and_then(|x| x.join(http_request.url()));
+        let response = match (method, url) {
+            (Some(method), Ok(url)) => {
+                let mut request = Request::new(method, url);
+                request.headers = http_request
+                    .headers()
+                    .iter()
+                    .map(|x| (x.field.to_string(), x.value.to_string()))
+                    .collect();
+                let path = request.url.path().to_string();
+                match route(&path) {
+                    // blobs go straight to disk
+                    Route::Upload { name, id } if valid_name(&name) => self
+                        .upload(&request, &name, id, http_request.as_reader())
+                        .unwrap_or_else(|e| error(500, "UNKNOWN", &e.to_string())),
+                    _ => {
+                        let mut body = http_request.as_reader().take(MAX_BODY_SIZE + 1);
+                        match body.read_to_end(&mut request.body) {
+                            Ok(size) if size as u64 > MAX_BODY_SIZE => {
+                                error(413, "SIZE_INVALID", "request body too large")
+                            }
+                            Ok(_) => self.handle(&request),
+                            Err(_) => return,
+                        }
+                    }
+                }
+            }
+            (None, _) => unsupported(),
+            (_, Err(_)) => not_found(),
+        };
+
+        let length = response
+            .header("Content-Length")
+            .and_then(|x| x.parse::<usize>().ok());
+        let headers = response
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()).ok()
+            })
+            .collect();
+        let http_response = tiny_http::Response::new(
+            tiny_http::StatusCode(response.status),
+            headers,
+            response.body,
+            length,
+            None,
+        )
+        // otherwise large blobs are sent chunked, without the `Content-Length` HEAD relies on
+        .with_chunked_threshold(usize::MAX);
+        // the client going away isn't the registry's problem
+        let _ = http_request.respond(http_response);
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let path = request.url.path().to_string();
+        let result = match route(&path) {
+            Route::Base => Ok(Response::new(200)),
+            Route::Unknown => Ok(not_found()),
+            Route::Manifest { name, .. }
+            | Route::Blob { name, .. }
+            | Route::Upload { name, .. }
+            | Route::Tags { name }
+            | Route::Referrers { name, .. }
+                if !valid_name(&name) =>
+            {
+                Ok(error(400, "NAME_INVALID", "invalid repository name"))
+            }
+            Route::Manifest { name, reference } => match request.method {
+                Method::Put => self.put_manifest(request, &name, reference),
+                _ => self.manifest(request, &name, reference),
+            },
+            Route::Blob { name, digest } => self.blob(request, &name, digest),
+            Route::Upload { name, id } => {
+                self.upload(request, &name, id, &mut request.body.as_slice())
+            }
+            Route::Tags { name } => self.tags(request, &name),
+            Route::Referrers { name, digest } => self.referrers(request, &name, digest),
+        };
+        result.unwrap_or_else(|e| error(500, "UNKNOWN", &e.to_string()))
+    }
+
+    fn manifest(
+        &self,
+        request: &Request,
+        name: &str,
+        reference: &str,
+    ) -> Result<Response, StoreError> {
+        let layout = match self.layout(name)? {
+            Some(layout) => layout,
+            None => return Ok(name_unknown()),
+        };
+        let (digest, media_type) = match reference.parse::<Digest>() {
+            Ok(digest) => (digest, None),
+            Err(_) => match layout.resolve(reference)? {
+                Some(descriptor) => (descriptor.digest, Some(descriptor.media_type)),
+                None => return Ok(manifest_unknown()),
+            },
+        };
+        let data = match layout.stat(&digest)? {
+            Some(_) => layout.get(&digest)?,
+            None => return Ok(manifest_unknown()),
+        };
+        let media_type = match media_type {
+            Some(media_type) => media_type,
+            None => match manifest_media_type(&data) {
+                Some(media_type) => media_type,
+                // a blob that isn't a manifest
+                None => return Ok(manifest_unknown()),
+            },
+        };
+
+        match request.method {
+            Method::Get | Method::Head => {
+                let mut response = content(request.method, &media_type, data);
+                response
+                    .headers
+                    .push((DIGEST_HEADER.to_string(), digest.to_string()));
+                Ok(response)
+            }
+            Method::Delete => {
+                let _state = self.state.lock().unwrap();
+                if reference.parse::<Digest>().is_err() {
+                    layout.untag(reference)?;
+                } else {
+                    layout.update_index(|index| {
+                        index.manifests.retain(|x| x.digest != digest);
+                        Ok(())
+                    })?;
+                    layout.delete(&digest)?;
+                }
+                Ok(Response::new(202))
+            }
+            _ => Ok(unsupported()),
+        }
+    }
+
+    fn put_manifest(
+        &self,
+        request: &Request,
+        name: &str,
+        reference: &str,
+    ) -> Result<Response, StoreError> {
+        let data = &request.body;
+        let digest = Digest::sha256(data);
+        let tag = match reference.parse::<Digest>() {
+            Ok(expected) if expected != digest => {
+                return Ok(digest_invalid("manifest doesn't match its digest"))
+            }
+            Ok(_) => None,
+            Err(_) => Some(reference),
+        };
+
+        let media_type = request
+            .header("Content-Type")
+            .unwrap_or(MEDIA_TYPE_IMAGE_MANIFEST);
+        let (references, subject, artifact_type) = match media_type {
+            media_type if is_image_index(media_type) => {
+                match parse_image_index(&mut data.as_slice()).and_then(|x| x.validate().map(|_| x))
+                {
+                    Ok(index) => (index.manifests, index.subject, index.artifact_type),
+                    Err(e) => return Ok(manifest_invalid(&e.to_string())),
+                }
+            }
+            media_type if is_image_manifest(media_type) => {
+                match parse_image_manifest(&mut data.as_slice())
+                    .and_then(|x| x.validate().map(|_| x))
+                {
+                    Ok(manifest) => {
+                        let artifact_type = manifest
+                            .artifact_type
+                            .clone()
+                            .or_else(|| Some(manifest.config.media_type.clone()));
+                        let mut blobs = manifest.layers;
+                        blobs.insert(0, manifest.config);
+                        (blobs, manifest.subject, artifact_type)
+                    }
+                    Err(e) => return Ok(manifest_invalid(&e.to_string())),
+                }
+            }
+            _ => {
+                return Ok(manifest_invalid(&format!(
+                    "unsupported media type `{}`",
+                    media_type
+                )))
+            }
+        };
+
+        let layout = OciLayout::create(self.root.join(name))?;
+        for descriptor in &references {
+            match layout.stat(&descriptor.digest)? {
+                Some(size) if size == descriptor.size => {}
+                Some(size) => {
+                    return Ok(manifest_invalid(&format!(
+                        "`{}` is {} bytes rather than {}",
+                        descriptor.digest, size, descriptor.size
+                    )))
+                }
+                // non-distributable layers are usually left out when copying images
+                None if is_foreign_layer(descriptor) => continue,
+                None => {
+                    return Ok(error(
+                        400,
+                        "MANIFEST_BLOB_UNKNOWN",
+                        &format!("`{}` isn't in the repository", descriptor.digest),
+                    ))
+                }
+            }
+            if descriptor.media_type == MEDIA_TYPE_IMAGE_CONFIG
+                || descriptor.media_type == MEDIA_TYPE_DOCKER_CONFIG
+            {
+                let mut reader = layout.reader(&descriptor.digest)?;
+                let config = parse_image_config_with_descriptor(&mut reader, descriptor);
+                if let Err(e) = config.and_then(|x| x.validate()) {
+                    return Ok(manifest_invalid(&format!("invalid image config: {}", e)));
+                }
+            }
+        }
+
+        let _state = self.state.lock().unwrap();
+        layout.put(data)?;
+        let mut descriptor = Descriptor {
+            media_type: media_type.to_string(),
+            digest: digest.clone(),
+            size: data.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        };
+        record(&layout, tag, descriptor.clone())?;
+        if let Some(subject) = &subject {
+            descriptor.artifact_type = artifact_type;
+            layout.add_referrer(&subject.digest, &descriptor)?;
+        }
+        Ok(manifest_created(
+            name,
+            &digest,
+            subject.as_ref().map(|x| &x.digest),
+        ))
+    }
+
+    fn blob(&self, request: &Request, name: &str, digest: &str) -> Result<Response, StoreError> {
+        let digest = match digest.parse::<Digest>() {
+            Ok(digest) => digest,
+            Err(_) => return Ok(digest_invalid("invalid digest")),
+        };
+        let layout = match self.layout(name)? {
+            Some(layout) => layout,
+            None => return Ok(name_unknown()),
+        };
+        let size = match layout.stat(&digest)? {
+            Some(size) => size,
+            None => return Ok(blob_unknown()),
+        };
+
+        match request.method {
+            Method::Get | Method::Head => {
+                let mut response = Response::new(200);
+                response.headers = vec![
+                    (
+                        "Content-Type".to_string(),
+                        "application/octet-stream".to_string(),
+                    ),
+                    ("Content-Length".to_string(), size.to_string()),
+                    (DIGEST_HEADER.to_string(), digest.to_string()),
+                ];
+                if request.method == Method::Get {
+                    response.body = Box::new(File::open(layout.blobs().blob_path(&digest))?);
+                }
+                Ok(response)
+            }
+            Method::Delete => {
+                layout.delete(&digest)?;
+                Ok(Response::new(202))
+            }
+            _ => Ok(unsupported()),
+        }
+    }
+
+    // `body` is read straight into the upload's file, so blobs are never held in memory
+    fn upload(
+        &self,
+        request: &Request,
+        name: &str,
+        id: &str,
+        body: &mut dyn Read,
+    ) -> Result<Response, StoreError> {
+        let query = query(request);
+        match (request.method, id) {
+            (Method::Post, "") => {
+                if let (Some(digest), Some(from)) = (query.get("mount"), query.get("from")) {
+                    if let Some(response) = self.mount(name, digest, from)? {
+                        return Ok(response);
+                    }
+                }
+                let layout = OciLayout::create(self.root.join(name))?;
+                let (id, upload) = self.start_upload(&layout, name)?;
+                if let Some(digest) = query.get("digest") {
+                    let result = self.finish_session(name, &upload.path, digest, body);
+                    let _ = fs::remove_file(&upload.path);
+                    return result;
+                }
+                self.state
+                    .lock()
+                    .unwrap()
+                    .uploads
+                    .insert(id.clone(), upload);
+                Ok(upload_response(name, &id, 0))
+            }
+            (Method::Patch, id) => {
+                // out of the sessions while the chunk comes in, so the lock isn't held meanwhile
+                let mut upload = match self.take_upload(name, id) {
+                    Some(upload) => upload,
+                    None => return Ok(upload_unknown()),
+                };
+                if !chunk_in_order(request, upload.received) {
+                    self.state
+                        .lock()
+                        .unwrap()
+                        .uploads
+                        .insert(id.to_string(), upload);
+                    return Ok(error(416, "BLOB_UPLOAD_INVALID", "chunk out of order"));
+                }
+                match append(&upload.path, body) {
+                    Ok(received) => upload.received += received,
+                    // what made it to the file is unknown, so the session can't go on
+                    Err(e) => {
+                        let _ = fs::remove_file(&upload.path);
+                        return Err(e.into());
+                    }
+                }
+                let received = upload.received;
+                self.state
+                    .lock()
+                    .unwrap()
+                    .uploads
+                    .insert(id.to_string(), upload);
+                Ok(upload_response(name, id, received))
+            }
+            (Method::Put, id) => {
+                let upload = match self.take_upload(name, id) {
+                    Some(upload) => upload,
+                    None => return Ok(upload_unknown()),
+                };
+                let result = match query.get("digest") {
+                    Some(digest) => self.finish_session(name, &upload.path, digest, body),
+                    None => Ok(digest_invalid("missing digest")),
+                };
+                // the session is over whether or not it succeeded
+                let _ = fs::remove_file(&upload.path);
+                result
+            }
+            _ => Ok(unsupported()),
+        }
+    }
+
+    fn start_upload(&self, layout: &OciLayout, name: &str) -> Result<(String, Upload), StoreError> {
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.next_upload += 1;
+            format!("{}-{}", std::process::id(), state.next_upload)
+        };
+        let path = layout.root().join(format!(".upload-{}", id));
+        File::create(&path)?;
+        let upload = Upload {
+            repository: name.to_string(),
+            path,
+            received: 0,
+        };
+        Ok((id, upload))
+    }
+
+    // removes the session, unless it belongs to another repository
+    fn take_upload(&self, name: &str, id: &str) -> Option<Upload> {
+        let mut state = self.state.lock().unwrap();
+        match state.uploads.get(id) {
+            Some(upload) if upload.repository == name => state.uploads.remove(id),
+            _ => None,
+        }
+    }
+
+    // appends the last of the blob and stores it; the caller removes the upload's file
+    fn finish_session(
+        &self,
+        name: &str,
+        path: &Path,
+        digest: &str,
+        body: &mut dyn Read,
+    ) -> Result<Response, StoreError> {
+        let layout = match self.layout(name)? {
+            Some(layout) => layout,
+            None => return Ok(name_unknown()),
+        };
+        append(path, body)?;
+        let size = fs::metadata(path)?.len();
+        finish_upload(&layout, name, File::open(path)?, size, digest)
+    }
+
+    // copies the blob if `from` has it
+    fn mount(&self, name: &str, digest: &str, from: &str) -> Result<Option<Response>, StoreError> {
+        let digest = match digest.parse::<Digest>() {
+            Ok(digest) => digest,
+            Err(_) => return Ok(None),
+        };
+        let source = match self.layout(from)? {
+            Some(source) if source.contains(&digest)? => source,
+            _ => return Ok(None),
+        };
+        let layout = OciLayout::create(self.root.join(name))?;
+        if !layout.contains(&digest)? {
+            let mut writer = layout.writer()?;
+            std::io::copy(&mut source.reader(&digest)?, &mut writer)?;
+            writer.commit()?;
+        }
+        Ok(Some(blob_created(name, &digest)))
+    }
+
+    fn tags(&self, request: &Request, name: &str) -> Result<Response, StoreError> {
+        let layout = match self.layout(name)? {
+            Some(layout) => layout,
+            None => return Ok(name_unknown()),
+        };
+        let mut tags = layout.tags()?;
+        tags.sort();
+        tags.dedup();
+        Ok(tags_page(request, name, &tags))
+    }
+
+    fn referrers(
+        &self,
+        request: &Request,
+        name: &str,
+        digest: &str,
+    ) -> Result<Response, StoreError> {
+        let digest = match digest.parse::<Digest>() {
+            Ok(digest) => digest,
+            Err(_) => return Ok(digest_invalid("invalid digest")),
+        };
+        let layout = match self.layout(name)? {
+            Some(layout) => layout,
+            None => return Ok(name_unknown()),
+        };
+        let mut referrers = Vec::new();
+        // deleting a referrer leaves its entry behind
+        for descriptor in layout.referrers(&digest, None)? {
+            if layout.contains(&descriptor.digest)? {
+                referrers.push(descriptor);
+            }
+        }
+        Ok(referrers_index(request, referrers))
+    }
+}
+
+impl Transport for LayoutRegistry {
+    fn send(&self, request: Request) -> Result<Response, RegistryError> {
+        Ok(self.handle(&request))
+    }
+}
+
+/// A [`LayoutRegistry`] listening for HTTP requests, which it stops doing when dropped.
+pub struct RegistryServer {
+    address: SocketAddr,
+    server: Arc<tiny_http::Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RegistryServer {
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The URL to give [`RegistryClient`](crate::registry::RegistryClient), such as
+    /// `http://127.0.0.1:5000`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+}
+
+impl std::fmt::Debug for RegistryServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RegistryServer")
+            .field("address", &self.address)
+            .finish()
+    }
+}
+
+impl Drop for RegistryServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// the distribution spec's lowercase path components, which also keeps names from escaping the
+// root or reaching into the `blobs` of another repository's layout
+fn valid_name(name: &str) -> bool {
+    name.split('/').all(|component| {
+        let bytes = component.as_bytes();
+        !bytes.is_empty()
+            && component != BLOBS_DIR
+            && bytes[0].is_ascii_alphanumeric()
+            && bytes[bytes.len() - 1].is_ascii_alphanumeric()
+            && bytes
+                .iter()
+                .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || b"._-".contains(x))
+    })
+}
+
+// the media type of a blob if it's a manifest or an index
+fn manifest_media_type(data: &[u8]) -> Option<String> {
+    match declared_media_type(data).as_str() {
+        media_type if is_image_manifest(media_type) || is_image_index(media_type) => {
+            Some(media_type.to_string())
+        }
+        "" if parse_image_manifest(&mut &data[..]).is_ok() => {
+            Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string())
+        }
+        "" if parse_image_index(&mut &data[..]).is_ok() => Some(MEDIA_TYPE_IMAGE_INDEX.to_string()),
+        _ => None,
+    }
+}
+
+// records a pushed manifest in `index.json`, untagged if pushed by digest so that it stays
+// referenced
+fn record(layout: &OciLayout, tag: Option<&str>, descriptor: Descriptor) -> Result<(), StoreError> {
+    layout.update_index(|index| {
+        match tag {
+            Some(tag) => {
+                index
+                    .manifests
+                    .retain(|x| x.digest != descriptor.digest || ref_name(x).is_some());
+                set_ref_name(index, tag, descriptor);
+            }
+            None => {
+                if !index
+                    .manifests
+                    .iter()
+                    .any(|x| x.digest == descriptor.digest)
+                {
+                    index.manifests.push(descriptor);
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+// returns how much was appended
+fn append(path: &Path, body: &mut dyn Read) -> std::io::Result<u64> {
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    let received = std::io::copy(body, &mut file)?;
+    file.flush()?;
+    Ok(received)
+}
+
+// stores the upload only if it matches `digest`
+fn finish_upload<R: Read>(
+    layout: &OciLayout,
+    name: &str,
+    data: R,
+    size: u64,
+    digest: &str,
+) -> Result<Response, StoreError> {
+    let digest = match digest.parse::<Digest>() {
+        Ok(digest) => digest,
+        Err(_) => return Ok(digest_invalid("invalid digest")),
+    };
+    let mut reader = match VerifyingReader::new(data, &digest, size) {
+        Ok(reader) => reader,
+        Err(e) => return Ok(digest_invalid(&e.to_string())),
+    };
+    let mut writer = layout.writer()?;
+    if let Err(e) = std::io::copy(&mut reader, &mut writer) {
+        return match VerificationError::from_io_error(&e) {
+            Some(e) => Ok(digest_invalid(&e.to_string())),
+            None => Err(e.into()),
+        };
+    }
+    writer.commit()?;
+    Ok(blob_created(name, &digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::foreign::tests::foreign_layer;
+    use crate::layer::{ForeignLayerPolicy, MEDIA_TYPE_DOCKER_FOREIGN_LAYER};
+    use crate::registry::{HttpTransport, RegistryClient};
+    use crate::store::tests::image_config;
+    use crate::store::{copy_image, put_json, MemoryBlobStore};
+    use crate::v1::{
+        Manifest, EMPTY_JSON, MEDIA_TYPE_DOCKER_MANIFEST, MEDIA_TYPE_IMAGE_LAYER_GZIP,
+    };
+
+    fn descriptor(media_type: &str, data: &[u8]) -> Descriptor {
+        Descriptor {
+            media_type: media_type.to_string(),
+            digest: Digest::sha256(data),
+            size: data.len() as u64,
+            urls: None,
+            artifact_type: None,
+            annotations: None,
+            platform: None,
+        }
+    }
+
+    fn image_manifest(config: &[u8], layer: &[u8]) -> Manifest {
+        Manifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config: descriptor(MEDIA_TYPE_IMAGE_CONFIG, config),
+            layers: vec![descriptor(MEDIA_TYPE_IMAGE_LAYER_GZIP, layer)],
+            artifact_type: None,
+            subject: None,
+            annotations: None,
+        }
+    }
+
+    fn push_image<T: Transport>(client: &RegistryClient<T>, repository: &str) -> Descriptor {
+        let config = serde_json::to_vec(&image_config()).unwrap();
+        client.push_blob(repository, &config).unwrap();
+        client
+            .push_blob_chunked(repository, &b"layer content"[..], 4)
+            .unwrap();
+        let manifest = image_manifest(&config, b"layer content");
+        client.push_manifest(repository, "v1", &manifest).unwrap()
+    }
+
+    #[test]
+    fn stores_pushed_images_in_layouts() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LayoutRegistry::new(dir.path()).unwrap();
+        let client = RegistryClient::new("http://localhost", &registry).unwrap();
+        let pushed = push_image(&client, "library/app");
+
+        let (pulled, manifest, config) = client.pull_image("library/app", "v1").unwrap();
+        assert_eq!(pulled, pushed);
+        assert_eq!(manifest.layers[0].size, 13);
+        assert_eq!(config, image_config());
+
+        let layout = registry.layout("library/app").unwrap().unwrap();
+        assert_eq!(layout.root(), dir.path().join("library/app"));
+        assert_eq!(layout.resolve("v1").unwrap().unwrap().digest, pushed.digest);
+        assert_eq!(layout.list().unwrap().len(), 3);
+        // nothing of unfinished uploads is left behind
+        assert!(fs::read_dir(layout.root()).unwrap().all(|x| !x
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with('.')));
+        assert!(registry.layout("other").unwrap().is_none());
+    }
+
+    #[test]
+    fn accepts_manifests_without_their_foreign_layers() {
+        let source = MemoryBlobStore::new();
+        let config = put_json(&source, MEDIA_TYPE_DOCKER_CONFIG, &image_config()).unwrap();
+        let layer = descriptor(MEDIA_TYPE_IMAGE_LAYER_GZIP, b"layer content");
+        source.put(b"layer content").unwrap();
+        let mut foreign = foreign_layer(b"windows", &["https://example.com/windows"]);
+        foreign.media_type = MEDIA_TYPE_DOCKER_FOREIGN_LAYER.to_string();
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string()),
+            config,
+            layers: vec![foreign.clone(), layer],
+            artifact_type: None,
+            subject: None,
+            annotations: None,
+        };
+        let image = put_json(&source, MEDIA_TYPE_DOCKER_MANIFEST, &manifest).unwrap();
+
+        // mirrors the image, leaving its foreign layer out
+        let staging = MemoryBlobStore::new();
+        let skipped = copy_image(&source, &staging, &image, &ForeignLayerPolicy::Skip).unwrap();
+        assert_eq!(skipped, vec![foreign]);
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LayoutRegistry::new(dir.path()).unwrap();
+        let client = RegistryClient::new("http://localhost", &registry).unwrap();
+        for digest in staging.list().unwrap() {
+            if digest != image.digest {
+                client
+                    .push_blob("windows", &staging.get(&digest).unwrap())
+                    .unwrap();
+            }
+        }
+        let data = staging.get(&image.digest).unwrap();
+        client
+            .put_manifest("windows", "ltsc2022", MEDIA_TYPE_DOCKER_MANIFEST, &data)
+            .unwrap();
+
+        let (pulled, _) = client.get_manifest("windows", "ltsc2022").unwrap();
+        assert_eq!(pulled.digest, image.digest);
+        assert_eq!(pulled.media_type, MEDIA_TYPE_DOCKER_MANIFEST);
+    }
+
+    #[test]
+    fn tags_deletes_and_lists() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LayoutRegistry::new(dir.path()).unwrap();
+        let client = RegistryClient::new("http://localhost", &registry).unwrap();
+        let pushed = push_image(&client, "app");
+        let (_, manifest) = client.pull_manifest("app", "v1").unwrap();
+
+        // pushing by digest keeps a single, untagged entry
+        client
+            .push_manifest("app", &pushed.digest.to_string(), &manifest)
+            .unwrap();
+        for tag in &["v2", "v3", "latest"] {
+            client.push_manifest("app", tag, &manifest).unwrap();
+        }
+        let layout = registry.layout("app").unwrap().unwrap();
+        assert_eq!(layout.index().unwrap().manifests.len(), 4);
+        assert_eq!(
+            client.list_tags_paginated("app", Some(3)).unwrap(),
+            vec!["latest", "v1", "v2", "v3"]
+        );
+
+        client.delete_tag("app", "v2").unwrap();
+        assert_eq!(client.list_tags("app").unwrap(), vec!["latest", "v1", "v3"]);
+        assert!(client.head_manifest("app", "v3").unwrap().is_some());
+
+        client.delete_manifest("app", &pushed.digest).unwrap();
+        assert_eq!(client.list_tags("app").unwrap(), Vec::<String>::new());
+        assert!(client
+            .head_manifest("app", &pushed.digest.to_string())
+            .unwrap()
+            .is_none());
+
+        // blobs that aren't manifests aren't served as ones
+        let layer = &manifest.layers[0].digest;
+        assert!(client
+            .head_manifest("app", &layer.to_string())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn serves_referrers() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = LayoutRegistry::new(dir.path()).unwrap();
+        let client = RegistryClient::new("http://localhost", &registry).unwrap();
+        let subject = push_image(&client, "app");
+        client.push_blob("app", EMPTY_JSON).unwrap();
+
+        let signature = Manifest {
+            schema_version: 2,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config: Descriptor::empty(),
+            layers: vec![],
+            artifact_type: Some("application/vnd.example.signature".to_string()),
+            subject: Some(subject.clone()),
+            annotations: None,
+        };
+        let digest = Digest::sha256(&serde_json::to_vec(&signature).unwrap());
+        client
+            .push_manifest("app", &digest.to_string(), &signature)
+            .unwrap();
+
+        let referrers = client.referrers("app", &subject.digest, None).unwrap();
+        assert_eq!(referrers.len(), 1);
+        assert_eq!(referrers[0].digest, digest);
+        assert_eq!(
+            referrers[0].artifact_type.as_deref(),
+            Some("application/vnd.example.signature")
+        );
+        let layout = registry.layout("app").unwrap().unwrap();
+        assert_eq!(layout.referrers(&subject.digest, None).unwrap(), referrers);
+        assert!(client
+            .referrers("app", &subject.digest, Some("application/vnd.example.sbom"))
+            .unwrap()
+            .is_empty());
+
+        client.delete_manifest("app", &digest).unwrap();
+        assert!(client
+            .referrers("app", &subject.digest, None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn serves_over_http() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = LayoutRegistry::new(dir.path())
+            .unwrap()
+            .serve("127.0.0.1:0")
+            .unwrap();
+        let client = RegistryClient::new(&server.url(), HttpTransport::new()).unwrap();
+        client.ping().unwrap();
+        let pushed = push_image(&client, "library/app");
+
+        let (pulled, manifest, config) = client.pull_image("library/app", "v1").unwrap();
+        assert_eq!(pulled, pushed);
+        assert_eq!(config, image_config());
+        assert_eq!(
+            client
+                .head_manifest("library/app", "v1")
+                .unwrap()
+                .unwrap()
+                .size,
+            pushed.size
+        );
+        assert_eq!(
+            client
+                .head_blob("library/app", &manifest.layers[0].digest)
+                .unwrap(),
+            Some(13)
+        );
+        let mut layer = Vec::new();
+        client
+            .get_blob("library/app", &manifest.layers[0])
+            .unwrap()
+            .read_to_end(&mut layer)
+            .unwrap();
+        assert_eq!(layer, b"layer content");
+        assert_eq!(client.list_tags("library/app").unwrap(), vec!["v1"]);
+
+        // blobs aren't held to the limit on other bodies
+        let blob = vec![7; MAX_BODY_SIZE as usize + 1];
+        let digest = client.push_blob("library/app", &blob).unwrap();
+        assert_eq!(
+            client.head_blob("library/app", &digest).unwrap(),
+            Some(blob.len() as u64)
+        );
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        fn registry() -> (tempfile::TempDir, LayoutRegistry) {
+            let dir = tempfile::tempdir().unwrap();
+            let registry = LayoutRegistry::new(dir.path()).unwrap();
+            (dir, registry)
+        }
+
+        fn error_code(error: RegistryError) -> String {
+            match error {
+                RegistryError::Status { errors, .. } => errors[0].code.clone(),
+                error => panic!("unexpected error: {}", error),
+            }
+        }
+
+        #[test]
+        fn rejects_invalid_image_configs() {
+            let (_dir, registry) = registry();
+            let client = RegistryClient::new("http://localhost", &registry).unwrap();
+            let config = br#"{"architecture": "amd64"}"#;
+            client.push_blob("app", config).unwrap();
+            client.push_blob("app", b"layer").unwrap();
+
+            let err = client
+                .push_manifest("app", "v1", &image_manifest(config, b"layer"))
+                .unwrap_err();
+            assert_eq!(error_code(err), "MANIFEST_INVALID");
+
+            // configs are validated beyond their shape
+            let config = br#"{"architecture": "wasm", "os": "linux", "rootfs": {"type": "layers", "diff_ids": []}}"#;
+            client.push_blob("app", config).unwrap();
+            let err = client
+                .push_manifest("app", "v1", &image_manifest(config, b"layer"))
+                .unwrap_err();
+            assert_eq!(error_code(err), "MANIFEST_INVALID");
+
+            // and so are Docker's
+            let mut manifest = image_manifest(config, b"layer");
+            manifest.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string());
+            manifest.config.media_type = MEDIA_TYPE_DOCKER_CONFIG.to_string();
+            let err = client.push_manifest("app", "v1", &manifest).unwrap_err();
+            assert_eq!(error_code(err), "MANIFEST_INVALID");
+            assert!(client.list_tags("app").unwrap().is_empty());
+        }
+
+        #[test]
+        fn rejects_manifests_with_missing_blobs() {
+            let (_dir, registry) = registry();
+            let client = RegistryClient::new("http://localhost", &registry).unwrap();
+            let config = serde_json::to_vec(&image_config()).unwrap();
+            client.push_blob("app", &config).unwrap();
+
+            let err = client
+                .push_manifest("app", "v1", &image_manifest(&config, b"layer"))
+                .unwrap_err();
+            assert_eq!(error_code(err), "MANIFEST_BLOB_UNKNOWN");
+
+            // only foreign layers may be missing, whether or not others have URLs
+            let mut manifest = image_manifest(&config, b"layer");
+            manifest.layers[0].urls = Some(vec!["https://example.com/layer".to_string()]);
+            let err = client.push_manifest("app", "v1", &manifest).unwrap_err();
+            assert_eq!(error_code(err), "MANIFEST_BLOB_UNKNOWN");
+            client.push_blob("app", b"layer").unwrap();
+            let mut manifest = image_manifest(b"{}", b"layer");
+            manifest.config.urls = Some(vec!["https://example.com/config".to_string()]);
+            let err = client.push_manifest("app", "v1", &manifest).unwrap_err();
+            assert_eq!(error_code(err), "MANIFEST_BLOB_UNKNOWN");
+
+            let err = client
+                .put_manifest("app", "v1", MEDIA_TYPE_IMAGE_MANIFEST, b"{}")
+                .unwrap_err();
+            assert_eq!(error_code(err), "MANIFEST_INVALID");
+        }
+
+        #[test]
+        fn rejects_uploads_not_matching_their_digest() {
+            let (_dir, registry) = registry();
+            let mut request = Request::new(
+                Method::Post,
+                url::Url::parse(&format!(
+                    "http://localhost/v2/app/blobs/uploads/?digest={}",
+                    Digest::sha256(b"other")
+                ))
+                .unwrap(),
+            );
+            request.body = b"data".to_vec();
+            let response = registry.send(request).unwrap();
+            assert_eq!(response.status, 400);
+
+            let url = url::Url::parse("http://localhost/v2/app/blobs/uploads/").unwrap();
+            let response = registry.send(Request::new(Method::Post, url)).unwrap();
+            let location = response.header("Location").unwrap();
+            let mut url = url::Url::parse("http://localhost")
+                .unwrap()
+                .join(location)
+                .unwrap();
+            url.query_pairs_mut()
+                .append_pair("digest", &Digest::sha256(b"other").to_string());
+            let mut request = Request::new(Method::Put, url);
+            request.body = b"data".to_vec();
+            let response = registry.send(request).unwrap();
+            assert_eq!(response.status, 400);
+
+            let layout = registry.layout("app").unwrap().unwrap();
+            assert!(layout.list().unwrap().is_empty());
+            // neither upload leaves its file behind
+            assert!(fs::read_dir(layout.root()).unwrap().all(|x| !x
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with(".upload-")));
+        }
+
+        #[test]
+        fn rejects_large_manifests() {
+            let dir = tempfile::tempdir().unwrap();
+            let server = LayoutRegistry::new(dir.path())
+                .unwrap()
+                .serve("127.0.0.1:0")
+                .unwrap();
+            let client = RegistryClient::new(&server.url(), HttpTransport::new()).unwrap();
+            let mut manifest = image_manifest(b"{}", b"layer");
+            manifest.annotations = Some(
+                vec![("large".to_string(), "x".repeat(MAX_BODY_SIZE as usize))]
+                    .into_iter()
+                    .collect(),
+            );
+            let error = client.push_manifest("app", "v1", &manifest).unwrap_err();
+            assert_eq!(error_code(error), "SIZE_INVALID");
+        }
+
+        #[test]
+        fn rejects_invalid_repository_names() {
+            let (_dir, registry) = registry();
+            let client = RegistryClient::new("http://localhost", &registry).unwrap();
+            for name in &["App", "app/.hidden", "app//x", "app/blobs"] {
+                let err = client.push_blob(name, b"data").unwrap_err();
+                assert_eq!(error_code(err), "NAME_INVALID", "{}", name);
+            }
+        }
+    }
+}
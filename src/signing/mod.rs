@@ -0,0 +1,810 @@
+//! Signing manifests in a layout the way cosign does, with a "simple signing" payload naming
+//! the manifest's digest, so images can be signed and verified offline.
+
+use std::fmt::Display;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::rand::SystemRandom;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1,
+    ECDSA_P256_SHA256_ASN1_SIGNING, ED25519,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::layout::{referrers_tag, OciLayout};
+use crate::store::{put_json, read_manifest, BlobStore, StoreError};
+use crate::v1::{
+    is_image_index, is_image_manifest, Descriptor, Digest, Manifest, EMPTY_JSON,
+    MEDIA_TYPE_IMAGE_INDEX, MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+/// The media type of the layers holding simple signing payloads.
+pub const MEDIA_TYPE_SIMPLE_SIGNING: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+/// The artifact type of signatures stored as referrers.
+pub const ARTIFACT_TYPE_SIGNATURE: &str = "application/vnd.dev.cosign.artifact.sig.v1+json";
+/// The layer annotation holding the base64-encoded signature of the layer's payload.
+pub const ANNOTATION_SIGNATURE: &str = "dev.cosignproject.cosign/signature";
+/// The `critical.type` of simple signing payloads for container images.
+pub const SIMPLE_SIGNING_TYPE: &str = "cosign container image signature";
+
+const PEM_PRIVATE_KEY: &str = "PRIVATE KEY";
+const PEM_PUBLIC_KEY: &str = "PUBLIC KEY";
+
+// the DER of a SubjectPublicKeyInfo up to the key itself, which has a fixed size for both
+const SPKI_ED25519: &[u8] = &[
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+const SPKI_ECDSA_P256: &[u8] = &[
+    0x30, 0x59, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01, 0x06, 0x08, 0x2a,
+    0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x03, 0x42, 0x00,
+];
+
+/// The cosign tag schema for signatures: `sha256-<hex>.sig` for the manifest at `digest`.
+pub fn signature_tag(digest: &Digest) -> String {
+    format!("{}.sig", referrers_tag(digest))
+}
+
+/// The "simple signing" payload that is signed, binding the signature to a manifest digest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimpleSigning {
+    pub critical: Critical,
+    pub optional: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Critical {
+    pub identity: Identity,
+    pub image: Image,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    #[serde(rename = "docker-reference")]
+    pub docker_reference: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Image {
+    #[serde(rename = "docker-manifest-digest")]
+    pub docker_manifest_digest: Digest,
+}
+
+impl SimpleSigning {
+    /// The payload for the manifest at `digest`, known as `docker_reference`, such as
+    /// `registry.example.com/app`.
+    pub fn new(docker_reference: &str, digest: &Digest) -> Self {
+        SimpleSigning {
+            critical: Critical {
+                identity: Identity {
+                    docker_reference: docker_reference.to_string(),
+                },
+                image: Image {
+                    docker_manifest_digest: digest.clone(),
+                },
+                kind: SIMPLE_SIGNING_TYPE.to_string(),
+            },
+            optional: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The key couldn't be read, or isn't an ed25519 or ECDSA P-256 key.
+    Key(String),
+    /// No signature of the manifest verifies with the key.
+    Unverified {
+        digest: Digest,
+        checked: usize,
+    },
+    Store(StoreError),
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SignatureError::Key(message) => write!(f, "invalid key: {}", message),
+            SignatureError::Unverified { digest, checked } => write!(
+                f,
+                "none of the {} signatures of `{}` verify with the key",
+                checked, digest
+            ),
+            SignatureError::Store(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SignatureError::Store(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<StoreError> for SignatureError {
+    fn from(error: StoreError) -> Self {
+        SignatureError::Store(error)
+    }
+}
+
+impl From<serde_json::Error> for SignatureError {
+    fn from(error: serde_json::Error) -> Self {
+        SignatureError::Store(StoreError::Serialize(error))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAlgorithm {
+    Ed25519,
+    EcdsaP256,
+}
+
+enum KeyPairs {
+    Ed25519(Ed25519KeyPair),
+    EcdsaP256(EcdsaKeyPair),
+}
+
+/// A private key to sign with, read from an unencrypted PKCS#8 document.
+pub struct SigningKey {
+    pair: KeyPairs,
+    rng: SystemRandom,
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("public_key", &self.public_key())
+            .finish()
+    }
+}
+
+impl SigningKey {
+    pub fn from_pkcs8(der: &[u8]) -> Result<Self, SignatureError> {
+        let rng = SystemRandom::new();
+        let pair = match Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+            Ok(pair) => KeyPairs::Ed25519(pair),
+            Err(_) => EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, der, &rng)
+                .map(KeyPairs::EcdsaP256)
+                .map_err(|x| {
+                    SignatureError::Key(format!("not an ed25519 or ECDSA P-256 key: {}", x))
+                })?,
+        };
+        Ok(SigningKey { pair, rng })
+    }
+
+    /// Reads a `PRIVATE KEY` PEM block, as written by `openssl genpkey`.
+    pub fn from_pem(pem: &str) -> Result<Self, SignatureError> {
+        Self::from_pkcs8(&pem_decode(pem, PEM_PRIVATE_KEY)?)
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        match &self.pair {
+            KeyPairs::Ed25519(pair) => PublicKey {
+                algorithm: KeyAlgorithm::Ed25519,
+                bytes: pair.public_key().as_ref().to_vec(),
+            },
+            KeyPairs::EcdsaP256(pair) => PublicKey {
+                algorithm: KeyAlgorithm::EcdsaP256,
+                bytes: pair.public_key().as_ref().to_vec(),
+            },
+        }
+    }
+
+    /// Signs `payload`; ECDSA signatures are ASN.1 encoded over its SHA-256, as cosign's are.
+    pub fn sign(&self, payload: &[u8]) -> Result<Vec<u8>, SignatureError> {
+        match &self.pair {
+            KeyPairs::Ed25519(pair) => Ok(pair.sign(payload).as_ref().to_vec()),
+            KeyPairs::EcdsaP256(pair) => pair
+                .sign(&self.rng, payload)
+                .map(|x| x.as_ref().to_vec())
+                .map_err(|_| SignatureError::Key("failed to sign".to_string())),
+        }
+    }
+}
+
+/// A public key to verify signatures with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicKey {
+    algorithm: KeyAlgorithm,
+    bytes: Vec<u8>,
+}
+
+impl PublicKey {
+    /// Reads a DER-encoded SubjectPublicKeyInfo of an ed25519 or ECDSA P-256 key.
+    pub fn from_spki_der(der: &[u8]) -> Result<Self, SignatureError> {
+        let (algorithm, bytes) = if let Some(bytes) = der.strip_prefix(SPKI_ED25519) {
+            (KeyAlgorithm::Ed25519, bytes)
+        } else if let Some(bytes) = der.strip_prefix(SPKI_ECDSA_P256) {
+            (KeyAlgorithm::EcdsaP256, bytes)
+        } else {
+            return Err(SignatureError::Key(
+                "not an ed25519 or ECDSA P-256 public key".to_string(),
+            ));
+        };
+        let expected = match algorithm {
+            KeyAlgorithm::Ed25519 => 32,
+            KeyAlgorithm::EcdsaP256 => 65,
+        };
+        if bytes.len() != expected {
+            return Err(SignatureError::Key(format!(
+                "expected a {} byte public key but found {}",
+                expected,
+                bytes.len()
+            )));
+        }
+        Ok(PublicKey {
+            algorithm,
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    /// Reads a `PUBLIC KEY` PEM block, like the `cosign.pub` written by `cosign generate-key-pair`.
+    pub fn from_pem(pem: &str) -> Result<Self, SignatureError> {
+        Self::from_spki_der(&pem_decode(pem, PEM_PUBLIC_KEY)?)
+    }
+
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    pub fn to_spki_der(&self) -> Vec<u8> {
+        let prefix = match self.algorithm {
+            KeyAlgorithm::Ed25519 => SPKI_ED25519,
+            KeyAlgorithm::EcdsaP256 => SPKI_ECDSA_P256,
+        };
+        [prefix, &self.bytes].concat()
+    }
+
+    pub fn to_pem(&self) -> String {
+        pem_encode(PEM_PUBLIC_KEY, &self.to_spki_der())
+    }
+
+    pub fn verify(&self, payload: &[u8], signature: &[u8]) -> bool {
+        let algorithm: &dyn ring::signature::VerificationAlgorithm = match self.algorithm {
+            KeyAlgorithm::Ed25519 => &ED25519,
+            KeyAlgorithm::EcdsaP256 => &ECDSA_P256_SHA256_ASN1,
+        };
+        UnparsedPublicKey::new(algorithm, &self.bytes)
+            .verify(payload, signature)
+            .is_ok()
+    }
+}
+
+/// Where signatures are stored in a layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureLocation {
+    /// In a manifest tagged `sha256-<hex>.sig`, with a layer per signature, as cosign does.
+    Tag,
+    /// In a manifest of its own, attached to the signed manifest as a referrer.
+    Referrer,
+}
+
+/// Signs the manifest `reference` names in `layout`, a tag or a digest, storing the signature
+/// at `location` and returning the descriptor of the manifest holding it.
+///
+/// The payload identifies the image as `docker_reference`; verification only relies on the
+/// digest it names.
+pub fn sign_manifest(
+    layout: &OciLayout,
+    reference: &str,
+    docker_reference: &str,
+    key: &SigningKey,
+    location: SignatureLocation,
+) -> Result<Descriptor, SignatureError> {
+    let subject = resolve_manifest(layout, reference)?;
+    let payload = serde_json::to_vec(&SimpleSigning::new(docker_reference, &subject.digest))?;
+    let signature = STANDARD.encode(key.sign(&payload)?);
+
+    let mut layer = Descriptor {
+        media_type: MEDIA_TYPE_SIMPLE_SIGNING.to_string(),
+        digest: layout.put(&payload)?,
+        size: payload.len() as u64,
+        urls: None,
+        annotations: None,
+        platform: None,
+        artifact_type: None,
+    };
+    layer
+        .annotations
+        .get_or_insert_with(Default::default)
+        .insert(ANNOTATION_SIGNATURE.to_string(), signature);
+    layout.put(EMPTY_JSON)?;
+
+    match location {
+        SignatureLocation::Tag => {
+            let tag = signature_tag(&subject.digest);
+            let mut manifest = match layout.resolve(&tag)? {
+                Some(existing) => read_manifest(layout, &existing.digest)?,
+                None => signature_manifest(None),
+            };
+            if !manifest.layers.contains(&layer) {
+                manifest.layers.push(layer);
+            }
+            let descriptor = put_json(layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)?;
+            layout.tag(&tag, descriptor.clone())?;
+            Ok(descriptor)
+        }
+        SignatureLocation::Referrer => {
+            let mut manifest = signature_manifest(Some(subject));
+            manifest.layers.push(layer);
+            Ok(layout.attach(&manifest)?)
+        }
+    }
+}
+
+/// Verifies the signatures of the manifest `reference` names in `layout` with `key`, returning
+/// the payloads of those that are valid.
+///
+/// Signatures are looked up both under the `.sig` tag and among the referrers of the manifest.
+/// A signature only counts if it verifies and its payload names the manifest's digest, so one
+/// copied over from another image is rejected. One whose payload is missing from the layout
+/// doesn't count either.
+pub fn verify_manifest(
+    layout: &OciLayout,
+    reference: &str,
+    key: &PublicKey,
+) -> Result<Vec<SimpleSigning>, SignatureError> {
+    let digest = resolve_manifest(layout, reference)?.digest;
+    let mut manifests = vec![];
+    if let Some(tagged) = layout.resolve(&signature_tag(&digest))? {
+        manifests.push(tagged.digest);
+    }
+    for referrer in layout.referrers(&digest, Some(ARTIFACT_TYPE_SIGNATURE))? {
+        manifests.push(referrer.digest);
+    }
+
+    let mut checked = 0;
+    let mut verified = vec![];
+    for manifest in manifests {
+        for layer in read_manifest(layout, &manifest)?.layers {
+            let signature = match layer
+                .annotations
+                .as_ref()
+                .and_then(|x| x.get(ANNOTATION_SIGNATURE))
+            {
+                Some(signature) if layer.media_type == MEDIA_TYPE_SIMPLE_SIGNING => signature,
+                _ => continue,
+            };
+            checked += 1;
+            let signature = match STANDARD.decode(signature) {
+                Ok(signature) => signature,
+                Err(_) => continue,
+            };
+            let payload = match layout.get(&layer.digest) {
+                Ok(payload) => payload,
+                // other signatures may still verify without it
+                Err(StoreError::NotFound(_)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if !key.verify(&payload, &signature) {
+                continue;
+            }
+            match serde_json::from_slice::<SimpleSigning>(&payload) {
+                Ok(payload)
+                    if payload.critical.kind == SIMPLE_SIGNING_TYPE
+                        && payload.critical.image.docker_manifest_digest == digest =>
+                {
+                    verified.push(payload)
+                }
+                _ => continue,
+            }
+        }
+    }
+    if verified.is_empty() {
+        return Err(SignatureError::Unverified { digest, checked });
+    }
+    Ok(verified)
+}
+
+fn signature_manifest(subject: Option<Descriptor>) -> Manifest {
+    Manifest {
+        schema_version: 2,
+        media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+        artifact_type: subject
+            .as_ref()
+            .map(|_| ARTIFACT_TYPE_SIGNATURE.to_string()),
+        config: Descriptor::empty(),
+        layers: vec![],
+        subject,
+        annotations: None,
+    }
+}
+
+// a tag in `index.json`, or the digest of a manifest in the layout's blobs
+fn resolve_manifest(layout: &OciLayout, reference: &str) -> Result<Descriptor, SignatureError> {
+    if let Some(mut descriptor) = layout.resolve(reference)? {
+        descriptor.annotations = None;
+        return Ok(descriptor);
+    }
+    let digest: Digest = reference.parse().map_err(|_| {
+        StoreError::Invalid(format!(
+            "`{}` is neither a tag nor a digest in the layout",
+            reference
+        ))
+    })?;
+    let data = layout.get(&digest)?;
+    // anything that isn't JSON isn't a manifest either
+    let value: serde_json::Value = serde_json::from_slice(&data).unwrap_or_default();
+    let media_type = match value.get("mediaType").and_then(|x| x.as_str()) {
+        Some(declared) if is_image_manifest(declared) || is_image_index(declared) => declared,
+        // OCI's documents don't have to declare it
+        None if value.get("manifests").is_some() => MEDIA_TYPE_IMAGE_INDEX,
+        None if value.get("config").is_some() && value.get("layers").is_some() => {
+            MEDIA_TYPE_IMAGE_MANIFEST
+        }
+        _ => {
+            return Err(StoreError::Invalid(format!(
+                "`{}` is neither an image manifest nor an index",
+                digest
+            ))
+            .into())
+        }
+    };
+    Ok(Descriptor {
+        media_type: media_type.to_string(),
+        size: data.len() as u64,
+        digest,
+        urls: None,
+        annotations: None,
+        platform: None,
+        artifact_type: None,
+    })
+}
+
+fn pem_decode(pem: &str, label: &str) -> Result<Vec<u8>, SignatureError> {
+    let begin = format!("-----BEGIN {}-----", label);
+    let end = format!("-----END {}-----", label);
+    let body = pem
+        .split_once(&begin)
+        .and_then(|(_, rest)| rest.split_once(&end))
+        .map(|(body, _)| body)
+        .ok_or_else(|| SignatureError::Key(format!("no `{}` PEM block", label)))?;
+    let body: String = body.split_whitespace().collect();
+    STANDARD
+        .decode(body)
+        .map_err(|x| SignatureError::Key(format!("invalid PEM: {}", x)))
+}
+
+fn pem_encode(label: &str, der: &[u8]) -> String {
+    let encoded = STANDARD.encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{image_config, put_image};
+    use crate::v1::{MEDIA_TYPE_DOCKER_MANIFEST, MEDIA_TYPE_DOCKER_MANIFEST_LIST};
+
+    fn ed25519_key() -> SigningKey {
+        let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        SigningKey::from_pkcs8(document.as_ref()).unwrap()
+    }
+
+    fn p256_key() -> SigningKey {
+        let document =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &SystemRandom::new())
+                .unwrap();
+        let pem = pem_encode(PEM_PRIVATE_KEY, document.as_ref());
+        SigningKey::from_pem(&pem).unwrap()
+    }
+
+    fn tag_image(layout: &OciLayout, tag: &str, created_by: &str) -> Digest {
+        let mut config = image_config();
+        config.author = Some(created_by.to_string());
+        let descriptor = put_image(layout, &config, vec![]);
+        layout.tag(tag, descriptor.clone()).unwrap();
+        descriptor.digest
+    }
+
+    #[test]
+    fn serializes_the_payload_like_cosign() {
+        let digest = Digest::sha256(b"manifest");
+        let payload = SimpleSigning::new("registry.example.com/app", &digest);
+        assert_eq!(
+            serde_json::to_string(&payload).unwrap(),
+            format!(
+                "{{\"critical\":{{\"identity\":{{\"docker-reference\":\"registry.example.com/app\"}},\
+                 \"image\":{{\"docker-manifest-digest\":\"{}\"}},\
+                 \"type\":\"cosign container image signature\"}},\"optional\":null}}",
+                digest
+            )
+        );
+        assert_eq!(
+            signature_tag(&digest),
+            format!("sha256-{}.sig", digest.encoded())
+        );
+    }
+
+    #[test]
+    fn round_trips_public_keys() {
+        for key in &[ed25519_key(), p256_key()] {
+            let public_key = key.public_key();
+            let pem = public_key.to_pem();
+            assert!(pem.starts_with("-----BEGIN PUBLIC KEY-----\n"));
+            assert_eq!(PublicKey::from_pem(&pem).unwrap(), public_key);
+
+            let signature = key.sign(b"payload").unwrap();
+            assert!(public_key.verify(b"payload", &signature));
+            assert!(!public_key.verify(b"tampered", &signature));
+        }
+    }
+
+    #[test]
+    fn signs_and_verifies_under_the_signature_tag() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let digest = tag_image(&layout, "latest", "app");
+        let ed25519 = ed25519_key();
+        let p256 = p256_key();
+
+        let first = sign_manifest(
+            &layout,
+            "latest",
+            "registry.example.com/app",
+            &ed25519,
+            SignatureLocation::Tag,
+        )
+        .unwrap();
+        let second = sign_manifest(
+            &layout,
+            &digest.to_string(),
+            "registry.example.com/app",
+            &p256,
+            SignatureLocation::Tag,
+        )
+        .unwrap();
+
+        assert_eq!(
+            layout
+                .resolve(&signature_tag(&digest))
+                .unwrap()
+                .unwrap()
+                .digest,
+            second.digest
+        );
+        assert_eq!(
+            read_manifest(&layout, &first.digest).unwrap().layers.len(),
+            1
+        );
+        assert_eq!(
+            read_manifest(&layout, &second.digest).unwrap().layers.len(),
+            2
+        );
+        for key in &[ed25519, p256] {
+            let verified = verify_manifest(&layout, "latest", &key.public_key()).unwrap();
+            assert_eq!(
+                verified,
+                vec![SimpleSigning::new("registry.example.com/app", &digest)]
+            );
+        }
+    }
+
+    #[test]
+    fn signs_docker_manifest_lists_by_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let digest = tag_image(&layout, "latest", "app");
+        let list = format!(
+            r#"{{"schemaVersion":2,"mediaType":"{}","manifests":[{{"mediaType":"{}","digest":"{}","size":1}}]}}"#,
+            MEDIA_TYPE_DOCKER_MANIFEST_LIST, MEDIA_TYPE_DOCKER_MANIFEST, digest
+        );
+        let list = layout.put(list.as_bytes()).unwrap();
+
+        let signature = sign_manifest(
+            &layout,
+            &list.to_string(),
+            "registry.example.com/app",
+            &ed25519_key(),
+            SignatureLocation::Referrer,
+        )
+        .unwrap();
+        let subject = read_manifest(&layout, &signature.digest)
+            .unwrap()
+            .subject
+            .unwrap();
+        assert_eq!(subject.digest, list);
+        assert_eq!(subject.media_type, MEDIA_TYPE_DOCKER_MANIFEST_LIST);
+    }
+
+    #[test]
+    fn signs_and_verifies_as_a_referrer() {
+        let dir = tempfile::tempdir().unwrap();
+        let layout = OciLayout::create(dir.path()).unwrap();
+        let digest = tag_image(&layout, "latest", "app");
+        let key = p256_key();
+
+        let signature = sign_manifest(
+            &layout,
+            "latest",
+            "registry.example.com/app",
+            &key,
+            SignatureLocation::Referrer,
+        )
+        .unwrap();
+        assert_eq!(
+            signature.artifact_type.as_deref(),
+            Some(ARTIFACT_TYPE_SIGNATURE)
+        );
+        let manifest = read_manifest(&layout, &signature.digest).unwrap();
+        assert_eq!(manifest.subject.unwrap().digest, digest);
+        assert_eq!(manifest.config, Descriptor::empty());
+        assert!(layout.contains(&manifest.config.digest).unwrap());
+        assert!(layout.resolve(&signature_tag(&digest)).unwrap().is_none());
+
+        let verified = verify_manifest(&layout, "latest", &key.public_key()).unwrap();
+        assert_eq!(verified.len(), 1);
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn skips_signatures_with_missing_payloads() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            tag_image(&layout, "latest", "app");
+            let key = p256_key();
+            let mut payloads = vec![];
+            for (name, location) in &[
+                ("registry.example.com/app", SignatureLocation::Tag),
+                ("mirror.example.com/app", SignatureLocation::Referrer),
+            ] {
+                let signature = sign_manifest(&layout, "latest", name, &key, *location).unwrap();
+                let manifest = read_manifest(&layout, &signature.digest).unwrap();
+                payloads.push(manifest.layers.last().unwrap().digest.clone());
+            }
+
+            layout.delete(&payloads[0]).unwrap();
+            let verified = verify_manifest(&layout, "latest", &key.public_key()).unwrap();
+            assert_eq!(verified.len(), 1);
+            assert_eq!(
+                verified[0].critical.identity.docker_reference,
+                "mirror.example.com/app"
+            );
+
+            layout.delete(&payloads[1]).unwrap();
+            assert!(matches!(
+                verify_manifest(&layout, "latest", &key.public_key()),
+                Err(SignatureError::Unverified { checked: 2, .. })
+            ));
+        }
+
+        #[test]
+        fn rejects_signatures_by_another_key() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            let digest = tag_image(&layout, "latest", "app");
+            sign_manifest(
+                &layout,
+                "latest",
+                "registry.example.com/app",
+                &ed25519_key(),
+                SignatureLocation::Tag,
+            )
+            .unwrap();
+
+            match verify_manifest(&layout, "latest", &ed25519_key().public_key()) {
+                Err(SignatureError::Unverified {
+                    digest: unverified,
+                    checked,
+                }) => {
+                    assert_eq!(unverified, digest);
+                    assert_eq!(checked, 1);
+                }
+                x => panic!("expected the signature to be rejected, got {:?}", x),
+            }
+        }
+
+        #[test]
+        fn rejects_unsigned_manifests() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            tag_image(&layout, "latest", "app");
+            assert!(matches!(
+                verify_manifest(&layout, "latest", &p256_key().public_key()),
+                Err(SignatureError::Unverified { checked: 0, .. })
+            ));
+        }
+
+        #[test]
+        fn rejects_signatures_of_other_manifests() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            let signed = tag_image(&layout, "signed", "app");
+            let unsigned = tag_image(&layout, "unsigned", "malware");
+            let key = ed25519_key();
+            let signature = sign_manifest(
+                &layout,
+                "signed",
+                "registry.example.com/app",
+                &key,
+                SignatureLocation::Tag,
+            )
+            .unwrap();
+            verify_manifest(&layout, "signed", &key.public_key()).unwrap();
+
+            // a valid signature copied over to another image doesn't vouch for it
+            layout.untag(&signature_tag(&signed)).unwrap();
+            layout.tag(&signature_tag(&unsigned), signature).unwrap();
+            assert!(matches!(
+                verify_manifest(&layout, "unsigned", &key.public_key()),
+                Err(SignatureError::Unverified { checked: 1, .. })
+            ));
+        }
+
+        #[test]
+        fn rejects_tampered_payloads() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            let digest = tag_image(&layout, "latest", "app");
+            let key = p256_key();
+            let signature = sign_manifest(
+                &layout,
+                "latest",
+                "registry.example.com/app",
+                &key,
+                SignatureLocation::Tag,
+            )
+            .unwrap();
+
+            let mut manifest = read_manifest(&layout, &signature.digest).unwrap();
+            let payload =
+                serde_json::to_vec(&SimpleSigning::new("evil.example.com/app", &digest)).unwrap();
+            manifest.layers[0].digest = layout.put(&payload).unwrap();
+            manifest.layers[0].size = payload.len() as u64;
+            let tampered = put_json(&layout, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+            layout.tag(&signature_tag(&digest), tampered).unwrap();
+
+            assert!(matches!(
+                verify_manifest(&layout, "latest", &key.public_key()),
+                Err(SignatureError::Unverified { checked: 1, .. })
+            ));
+        }
+
+        #[test]
+        fn refuses_to_sign_what_isnt_a_manifest() {
+            let dir = tempfile::tempdir().unwrap();
+            let layout = OciLayout::create(dir.path()).unwrap();
+            let key = ed25519_key();
+            for blob in &[&b"not json"[..], br#"{"architecture": "amd64"}"#] {
+                let digest = layout.put(blob).unwrap();
+                let result = sign_manifest(
+                    &layout,
+                    &digest.to_string(),
+                    "registry.example.com/app",
+                    &key,
+                    SignatureLocation::Referrer,
+                );
+                assert!(
+                    matches!(result, Err(SignatureError::Store(StoreError::Invalid(_)))),
+                    "{:?}",
+                    result
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_other_keys() {
+            assert!(matches!(
+                SigningKey::from_pkcs8(b"not a key"),
+                Err(SignatureError::Key(_))
+            ));
+            assert!(matches!(
+                PublicKey::from_pem("-----BEGIN PUBLIC KEY-----\nAAAA\n-----END PUBLIC KEY-----\n"),
+                Err(SignatureError::Key(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::layer::foreign::rejected;
+use crate::layer::{fetch_layer, is_foreign_layer, ForeignLayerPolicy};
+use crate::store::{read_index, read_manifest, BlobStore, StoreError};
+use crate::v1::{is_image_index, is_image_manifest, Descriptor, ParseError, VerifyingReader};
+
+/// Copies the manifest or index `descriptor` points to from `source` to `destination`, along
+/// with everything it references, and returns the foreign layers the policy skipped.
+///
+/// Blobs are verified as they're copied, and those the destination already has are left
+/// alone. Manifests and indexes, OCI's or Docker's, are only copied once everything they
+/// reference is there; other kinds of manifests are an error, as what they reference is unknown.
+pub fn copy_image<S: BlobStore + ?Sized, D: BlobStore + ?Sized>(
+    source: &S,
+    destination: &D,
+    descriptor: &Descriptor,
+    policy: &ForeignLayerPolicy,
+) -> Result<Vec<Descriptor>, StoreError> {
+    let mut skipped = vec![];
+    copy(source, destination, descriptor, policy, &mut skipped)?;
+    Ok(skipped)
+}
+
+fn copy<S: BlobStore + ?Sized, D: BlobStore + ?Sized>(
+    source: &S,
+    destination: &D,
+    descriptor: &Descriptor,
+    policy: &ForeignLayerPolicy,
+    skipped: &mut Vec<Descriptor>,
+) -> Result<(), StoreError> {
+    if destination.contains(&descriptor.digest)? {
+        return Ok(());
+    }
+    match descriptor.media_type.as_str() {
+        media_type if is_image_index(media_type) => {
+            for manifest in read_index(source, &descriptor.digest)?.manifests {
+                copy(source, destination, &manifest, policy, skipped)?;
+            }
+        }
+        media_type if is_image_manifest(media_type) => {
+            let manifest = read_manifest(source, &descriptor.digest)?;
+            copy_blob(source, destination, &manifest.config)?;
+            for layer in &manifest.layers {
+                if !is_foreign_layer(layer) {
+                    copy_blob(source, destination, layer)?;
+                    continue;
+                }
+                match policy {
+                    ForeignLayerPolicy::Reject => return Err(rejected(layer)),
+                    _ if destination.contains(&layer.digest)? => {}
+                    ForeignLayerPolicy::Skip => skipped.push(layer.clone()),
+                    ForeignLayerPolicy::Fetch(_) if source.contains(&layer.digest)? => {
+                        copy_blob(source, destination, layer)?
+                    }
+                    ForeignLayerPolicy::Fetch(fetcher) => {
+                        fetch_layer(destination, fetcher.as_ref(), layer)?
+                    }
+                }
+            }
+        }
+        // such as Docker's schema 1 manifests
+        media_type if media_type.contains(".manifest.") || media_type.contains(".index.") => {
+            return Err(StoreError::Invalid(format!(
+                "can't copy `{}`: unsupported manifest media type `{}`",
+                descriptor.digest, media_type
+            )))
+        }
+        _ => {}
+    }
+    copy_blob(source, destination, descriptor)
+}
+
+fn copy_blob<S: BlobStore + ?Sized, D: BlobStore + ?Sized>(
+    source: &S,
+    destination: &D,
+    descriptor: &Descriptor,
+) -> Result<(), StoreError> {
+    if destination.contains(&descriptor.digest)? {
+        return Ok(());
+    }
+    let mut reader =
+        VerifyingReader::for_descriptor(source.reader(&descriptor.digest)?, descriptor)
+            .map_err(ParseError::from)?;
+    let mut writer = destination.writer()?;
+    std::io::copy(&mut reader, &mut writer)?;
+    writer.commit()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::foreign::tests::{foreign_layer, StaticFetcher};
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::{put_json, MemoryBlobStore};
+    use crate::v1::{
+        Digest, Index, MEDIA_TYPE_DOCKER_CONFIG, MEDIA_TYPE_DOCKER_MANIFEST,
+        MEDIA_TYPE_DOCKER_MANIFEST_LIST, MEDIA_TYPE_IMAGE_LAYER,
+    };
+
+    // an image with a regular layer and a foreign one, which is only in the store if `stored`
+    fn image_with_foreign_layer(store: &MemoryBlobStore, stored: bool) -> (Descriptor, Descriptor) {
+        let layer = Descriptor {
+            media_type: MEDIA_TYPE_IMAGE_LAYER.to_string(),
+            digest: store.put(b"layer").unwrap(),
+            size: 5,
+            urls: None,
+            annotations: None,
+            platform: None,
+            artifact_type: None,
+        };
+        let foreign = foreign_layer(b"foreign", &["https://example.com/foreign"]);
+        if stored {
+            store.put(b"foreign").unwrap();
+        }
+        let descriptor = put_image(store, &image_config(), vec![layer, foreign.clone()]);
+        (descriptor, foreign)
+    }
+
+    #[test]
+    fn copies_images_without_foreign_layers() {
+        let source = MemoryBlobStore::new();
+        let destination = MemoryBlobStore::new();
+        let (image, foreign) = image_with_foreign_layer(&source, true);
+
+        let skipped = copy_image(&source, &destination, &image, &ForeignLayerPolicy::Skip).unwrap();
+        assert_eq!(skipped, vec![foreign.clone()]);
+        assert_eq!(destination.list().unwrap().len(), 3);
+        assert!(!destination.contains(&foreign.digest).unwrap());
+        assert!(read_manifest(&destination, &image.digest).is_ok());
+    }
+
+    #[test]
+    fn walks_docker_manifest_lists() {
+        let source = MemoryBlobStore::new();
+        let destination = MemoryBlobStore::new();
+        let (image, foreign) = image_with_foreign_layer(&source, true);
+        // as Windows images are usually distributed
+        let mut manifest = read_manifest(&source, &image.digest).unwrap();
+        manifest.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string());
+        manifest.config = put_json(&source, MEDIA_TYPE_DOCKER_CONFIG, &image_config()).unwrap();
+        let manifest = put_json(&source, MEDIA_TYPE_DOCKER_MANIFEST, &manifest).unwrap();
+        let list = Index {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            manifests: vec![manifest.clone()],
+            media_type: Some(MEDIA_TYPE_DOCKER_MANIFEST_LIST.to_string()),
+            annotations: None,
+        };
+        let list = put_json(&source, MEDIA_TYPE_DOCKER_MANIFEST_LIST, &list).unwrap();
+
+        let skipped = copy_image(&source, &destination, &list, &ForeignLayerPolicy::Skip).unwrap();
+        assert_eq!(skipped, vec![foreign.clone()]);
+        assert!(!destination.contains(&foreign.digest).unwrap());
+        // the list, the manifest, its config and the regular layer
+        assert_eq!(destination.list().unwrap().len(), 4);
+        let destination = MemoryBlobStore::new();
+        assert!(matches!(
+            copy_image(&source, &destination, &list, &ForeignLayerPolicy::Reject),
+            Err(StoreError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn fetches_foreign_layers_the_source_doesnt_have() {
+        let source = MemoryBlobStore::new();
+        let destination = MemoryBlobStore::new();
+        let (image, foreign) = image_with_foreign_layer(&source, false);
+        let mut fetcher = StaticFetcher::default();
+        fetcher.content.insert(
+            "https://example.com/foreign".to_string(),
+            b"foreign".to_vec(),
+        );
+
+        let policy = ForeignLayerPolicy::Fetch(Box::new(fetcher));
+        assert!(copy_image(&source, &destination, &image, &policy)
+            .unwrap()
+            .is_empty());
+        assert_eq!(destination.get(&foreign.digest).unwrap(), b"foreign");
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_foreign_layers() {
+            let source = MemoryBlobStore::new();
+            let destination = MemoryBlobStore::new();
+            let (image, _) = image_with_foreign_layer(&source, true);
+
+            assert!(matches!(
+                copy_image(&source, &destination, &image, &ForeignLayerPolicy::Reject),
+                Err(StoreError::Invalid(_))
+            ));
+            // the manifest never lands without its layers
+            assert!(!destination.contains(&image.digest).unwrap());
+        }
+
+        #[test]
+        fn rejects_unknown_manifest_media_types() {
+            let source = MemoryBlobStore::new();
+            let destination = MemoryBlobStore::new();
+            let (mut image, _) = image_with_foreign_layer(&source, true);
+            image.media_type = "application/vnd.docker.distribution.manifest.v1+json".to_string();
+
+            assert!(matches!(
+                copy_image(&source, &destination, &image, &ForeignLayerPolicy::Skip),
+                Err(StoreError::Invalid(_))
+            ));
+            assert!(destination.list().unwrap().is_empty());
+        }
+
+        #[test]
+        fn fails_on_missing_layers() {
+            let source = MemoryBlobStore::new();
+            let destination = MemoryBlobStore::new();
+            let (image, _) = image_with_foreign_layer(&source, true);
+            source.delete(&Digest::sha256(b"layer")).unwrap();
+
+            assert!(matches!(
+                copy_image(&source, &destination, &image, &ForeignLayerPolicy::Skip),
+                Err(StoreError::NotFound(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::store::{BlobStore, BlobWriter, StoreError};
+use crate::v1::{Digest, Digester};
+
+static INGEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Stores blobs on disk as `<root>/<algorithm>/<encoded>`, which is the layout of the `blobs`
+/// directory of an OCI image layout.
+#[derive(Debug, Clone)]
+pub struct FsBlobStore {
+    root: PathBuf,
+    ingest_dir: PathBuf,
+}
+
+impl FsBlobStore {
+    /// Uses `root` for blobs and for partially written ones, which are kept as dotfiles.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref().to_path_buf();
+        FsBlobStore {
+            ingest_dir: root.clone(),
+            root,
+        }
+    }
+
+    /// Keeps partially written blobs in `ingest_dir` instead of `root`. Both should be on the
+    /// same filesystem so that committing a blob is an atomic rename.
+    pub fn with_ingest_dir<P: AsRef<Path>, Q: AsRef<Path>>(root: P, ingest_dir: Q) -> Self {
+        FsBlobStore {
+            root: root.as_ref().to_path_buf(),
+            ingest_dir: ingest_dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn blob_path(&self, digest: &Digest) -> PathBuf {
+        self.root.join(digest.algorithm()).join(digest.encoded())
+    }
+}
+
+impl BlobStore for FsBlobStore {
+    fn stat(&self, digest: &Digest) -> Result<Option<u64>, StoreError> {
+        match fs::metadata(self.blob_path(digest)) {
+            Ok(metadata) => Ok(Some(metadata.len())),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn reader(&self, digest: &Digest) -> Result<Box<dyn Read + '_>, StoreError> {
+        match File::open(self.blob_path(digest)) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                Err(StoreError::NotFound(digest.clone()))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn writer(&self) -> Result<Box<dyn BlobWriter + '_>, StoreError> {
+        fs::create_dir_all(&self.ingest_dir)?;
+        let path = self.ingest_dir.join(format!(
+            ".ingest-{}-{}",
+            std::process::id(),
+            INGEST_COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok(Box::new(FsBlobWriter {
+            store: self,
+            path,
+            file: Some(BufWriter::new(file)),
+            digester: Digester::sha256(),
+            size: 0,
+        }))
+    }
+
+    fn delete(&self, digest: &Digest) -> Result<bool, StoreError> {
+        match fs::remove_file(self.blob_path(digest)) {
+            Ok(()) => Ok(true),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(false),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<Digest>, StoreError> {
+        let mut digests = Vec::new();
+        let algorithms = match fs::read_dir(&self.root) {
+            Ok(algorithms) => algorithms,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(digests),
+            Err(error) => return Err(error.into()),
+        };
+
+        for algorithm in algorithms {
+            let algorithm = algorithm?;
+            if !algorithm.file_type()?.is_dir() {
+                continue;
+            }
+            let algorithm_name = algorithm.file_name().to_string_lossy().to_string();
+            for blob in fs::read_dir(algorithm.path())? {
+                let blob_name = blob?.file_name().to_string_lossy().to_string();
+                // anything that isn't named like a digest isn't a blob of ours
+                if let Ok(digest) = Digest::new(&algorithm_name, &blob_name) {
+                    digests.push(digest);
+                }
+            }
+        }
+
+        digests.sort();
+        Ok(digests)
+    }
+}
+
+struct FsBlobWriter<'a> {
+    store: &'a FsBlobStore,
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+    digester: Digester,
+    size: u64,
+}
+
+impl<'a> Write for FsBlobWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.as_mut().unwrap().write(buf)?;
+        self.digester.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl<'a> BlobWriter for FsBlobWriter<'a> {
+    fn commit(mut self: Box<Self>) -> Result<(Digest, u64), StoreError> {
+        let file = self.file.take().unwrap();
+        let result = self.persist(file);
+        // `Drop` no longer sees the file, so it's up to us to not leave it behind
+        if result.is_err() {
+            let _ = fs::remove_file(&self.path);
+        }
+        result
+    }
+}
+
+impl<'a> FsBlobWriter<'a> {
+    fn persist(&self, file: BufWriter<File>) -> Result<(Digest, u64), StoreError> {
+        file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        let digest = self.digester.clone().finalize();
+        let target = self.store.blob_path(&digest);
+        if target.exists() {
+            fs::remove_file(&self.path)?;
+        } else {
+            fs::create_dir_all(target.parent().unwrap())?;
+            fs::rename(&self.path, &target)?;
+        }
+        Ok((digest, self.size))
+    }
+}
+
+impl<'a> Drop for FsBlobWriter<'a> {
+    fn drop(&mut self) {
+        // only still set if the writer was never committed
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::*;
+    use crate::v1::{ParseError, VerificationError};
+
+    #[test]
+    fn honours_blob_store_contract() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_blob_store_contract(&FsBlobStore::new(dir.path()));
+    }
+
+    #[test]
+    fn resolves_image_config() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_resolves_image_config(&FsBlobStore::new(dir.path()));
+    }
+
+    #[test]
+    fn stores_blobs_by_algorithm_and_encoded_digest() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsBlobStore::new(dir.path());
+        let digest = store.put(b"{}").unwrap();
+
+        let path = dir
+            .path()
+            .join("sha256")
+            .join("44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a");
+        assert_eq!(store.blob_path(&digest), path);
+        assert_eq!(fs::read(path).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn cleans_up_abandoned_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let ingest = tempfile::tempdir().unwrap();
+        let store = FsBlobStore::with_ingest_dir(dir.path(), ingest.path());
+
+        let mut writer = store.writer().unwrap();
+        writer.write_all(b"abandoned").unwrap();
+        assert_eq!(fs::read_dir(ingest.path()).unwrap().count(), 1);
+        drop(writer);
+
+        assert_eq!(fs::read_dir(ingest.path()).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn cleans_up_failed_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let ingest = tempfile::tempdir().unwrap();
+        let store = FsBlobStore::with_ingest_dir(dir.path(), ingest.path());
+        // where the directory for sha256 blobs would go
+        fs::write(dir.path().join("sha256"), b"").unwrap();
+
+        let mut writer = store.writer().unwrap();
+        writer.write_all(b"failed").unwrap();
+        assert!(writer.commit().is_err());
+
+        assert_eq!(fs::read_dir(ingest.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn refuses_to_parse_tampered_blobs() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsBlobStore::new(dir.path());
+        let descriptor =
+            crate::store::put_json(&store, crate::v1::MEDIA_TYPE_IMAGE_CONFIG, &image_config())
+                .unwrap();
+        let path = store.blob_path(&descriptor.digest);
+        let tampered = fs::read_to_string(&path).unwrap().replace("amd64", "arm64");
+        fs::write(&path, tampered).unwrap();
+
+        match crate::store::read_image_config(&store, &descriptor.digest) {
+            Err(StoreError::Parse(ParseError::Verification(
+                VerificationError::DigestMismatch { expected, .. },
+            ))) => assert_eq!(expected, descriptor.digest),
+            result => panic!("Received unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn lists_nothing_for_missing_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FsBlobStore::new(dir.path().join("missing"));
+        assert!(store.list().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read, Write};
+use std::sync::{Arc, RwLock};
+
+use crate::store::{BlobStore, BlobWriter, StoreError};
+use crate::v1::{Digest, Digester};
+
+/// Keeps every blob in memory. Meant for tests and short-lived tooling.
+#[derive(Debug, Default)]
+pub struct MemoryBlobStore {
+    blobs: RwLock<BTreeMap<Digest, Arc<Vec<u8>>>>,
+}
+
+impl MemoryBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BlobStore for MemoryBlobStore {
+    fn stat(&self, digest: &Digest) -> Result<Option<u64>, StoreError> {
+        let blobs = self.blobs.read().unwrap();
+        Ok(blobs.get(digest).map(|x| x.len() as u64))
+    }
+
+    fn reader(&self, digest: &Digest) -> Result<Box<dyn Read + '_>, StoreError> {
+        let blobs = self.blobs.read().unwrap();
+        match blobs.get(digest) {
+            Some(blob) => Ok(Box::new(Cursor::new(SharedBlob(blob.clone())))),
+            None => Err(StoreError::NotFound(digest.clone())),
+        }
+    }
+
+    fn writer(&self) -> Result<Box<dyn BlobWriter + '_>, StoreError> {
+        Ok(Box::new(MemoryBlobWriter {
+            store: self,
+            data: Vec::new(),
+            digester: Digester::sha256(),
+        }))
+    }
+
+    fn delete(&self, digest: &Digest) -> Result<bool, StoreError> {
+        let mut blobs = self.blobs.write().unwrap();
+        Ok(blobs.remove(digest).is_some())
+    }
+
+    fn list(&self) -> Result<Vec<Digest>, StoreError> {
+        let blobs = self.blobs.read().unwrap();
+        Ok(blobs.keys().cloned().collect())
+    }
+}
+
+// lets readers outlive the lock without copying the blob
+struct SharedBlob(Arc<Vec<u8>>);
+impl AsRef<[u8]> for SharedBlob {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+struct MemoryBlobWriter<'a> {
+    store: &'a MemoryBlobStore,
+    data: Vec<u8>,
+    digester: Digester,
+}
+
+impl<'a> Write for MemoryBlobWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.digester.update(buf);
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> BlobWriter for MemoryBlobWriter<'a> {
+    fn commit(self: Box<Self>) -> Result<(Digest, u64), StoreError> {
+        let MemoryBlobWriter {
+            store,
+            data,
+            digester,
+        } = *self;
+        let digest = digester.finalize();
+        let size = data.len() as u64;
+        let mut blobs = store.blobs.write().unwrap();
+        blobs
+            .entry(digest.clone())
+            .or_insert_with(|| Arc::new(data));
+        Ok((digest, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::*;
+
+    #[test]
+    fn honours_blob_store_contract() {
+        assert_blob_store_contract(&MemoryBlobStore::new());
+    }
+
+    #[test]
+    fn resolves_image_config() {
+        assert_resolves_image_config(&MemoryBlobStore::new());
+    }
+}
@@ -0,0 +1,307 @@
+use std::fmt::Display;
+use std::io::{Read, Write};
+
+use crate::v1::{
+    parse_image_config, parse_image_config_with_descriptor, parse_image_index,
+    parse_image_manifest, Descriptor, Digest, ImageConfig, Index, Manifest, ParseError,
+    VerifyingReader, MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+use serde::Serialize;
+
+mod copy;
+pub use copy::copy_image;
+
+mod filesystem;
+pub use filesystem::FsBlobStore;
+
+mod memory;
+pub use memory::MemoryBlobStore;
+
+mod multi_platform;
+pub use multi_platform::{assemble_index, split_index};
+
+mod rebase;
+pub use rebase::rebase_image;
+
+/// Content-addressable storage for blobs, keyed by their digest.
+///
+/// Implementations must be safe to share between threads; every operation takes `&self`.
+pub trait BlobStore: Send + Sync {
+    /// Returns the size of the blob, or `None` if it isn't in the store.
+    fn stat(&self, digest: &Digest) -> Result<Option<u64>, StoreError>;
+
+    fn reader(&self, digest: &Digest) -> Result<Box<dyn Read + '_>, StoreError>;
+
+    /// Starts writing a new blob, whose digest is only known once it's committed.
+    fn writer(&self) -> Result<Box<dyn BlobWriter + '_>, StoreError>;
+
+    /// Removes the blob, returning whether it was in the store.
+    fn delete(&self, digest: &Digest) -> Result<bool, StoreError>;
+
+    fn list(&self) -> Result<Vec<Digest>, StoreError>;
+
+    fn get(&self, digest: &Digest) -> Result<Vec<u8>, StoreError> {
+        let mut data = Vec::new();
+        self.reader(digest)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn put(&self, data: &[u8]) -> Result<Digest, StoreError> {
+        let mut writer = self.writer()?;
+        writer.write_all(data)?;
+        Ok(writer.commit()?.0)
+    }
+
+    fn contains(&self, digest: &Digest) -> Result<bool, StoreError> {
+        Ok(self.stat(digest)?.is_some())
+    }
+}
+
+/// Streams a blob into a [`BlobStore`], hashing it on the way in.
+///
+/// Nothing becomes visible in the store until `commit` is called; dropping the writer discards
+/// whatever was written.
+pub trait BlobWriter: Write {
+    /// Stores the blob, returning its digest and size.
+    fn commit(self: Box<Self>) -> Result<(Digest, u64), StoreError>;
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    NotFound(Digest),
+    DigestMismatch { expected: Digest, actual: Digest },
+    SizeMismatch { expected: u64, actual: u64 },
+    Io(std::io::Error),
+    Parse(ParseError),
+    Serialize(serde_json::error::Error),
+    Invalid(String),
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            StoreError::NotFound(digest) => write!(f, "blob `{}` not found", digest),
+            StoreError::DigestMismatch { expected, actual } => write!(
+                f,
+                "digest mismatch: expected `{}` but content hashes to `{}`",
+                expected, actual
+            ),
+            StoreError::SizeMismatch { expected, actual } => write!(
+                f,
+                "size mismatch: expected {} bytes but found {}",
+                expected, actual
+            ),
+            StoreError::Io(error) => write!(f, "{}", error),
+            StoreError::Parse(error) => write!(f, "{}", error),
+            StoreError::Serialize(error) => write!(f, "failed to serialize: {}", error),
+            StoreError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StoreError::Io(error) => Some(error),
+            StoreError::Parse(error) => Some(error),
+            StoreError::Serialize(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(error: std::io::Error) -> Self {
+        StoreError::Io(error)
+    }
+}
+
+impl From<ParseError> for StoreError {
+    fn from(error: ParseError) -> Self {
+        StoreError::Parse(error)
+    }
+}
+
+impl From<serde_json::error::Error> for StoreError {
+    fn from(error: serde_json::error::Error) -> Self {
+        StoreError::Serialize(error)
+    }
+}
+
+/// Serializes `value` and stores it, returning a descriptor for it with the given media type.
+pub fn put_json<S: BlobStore + ?Sized, T: Serialize>(
+    store: &S,
+    media_type: &str,
+    value: &T,
+) -> Result<Descriptor, StoreError> {
+    let data = serde_json::to_vec(value)?;
+    let digest = store.put(&data)?;
+    Ok(Descriptor {
+        media_type: media_type.to_string(),
+        digest,
+        size: data.len() as u64,
+        urls: None,
+        artifact_type: None,
+        annotations: None,
+        platform: None,
+    })
+}
+
+// blobs are addressed by digest, so whatever comes back has to hash to it
+fn verified_reader<'a, S: BlobStore + ?Sized>(
+    store: &'a S,
+    digest: &Digest,
+) -> Result<VerifyingReader<Box<dyn Read + 'a>>, StoreError> {
+    let size = store
+        .stat(digest)?
+        .ok_or_else(|| StoreError::NotFound(digest.clone()))?;
+    let reader = store.reader(digest)?;
+    Ok(VerifyingReader::new(reader, digest, size).map_err(ParseError::from)?)
+}
+
+pub fn read_manifest<S: BlobStore + ?Sized>(
+    store: &S,
+    digest: &Digest,
+) -> Result<Manifest, StoreError> {
+    Ok(parse_image_manifest(&mut verified_reader(store, digest)?)?)
+}
+
+pub fn read_index<S: BlobStore + ?Sized>(store: &S, digest: &Digest) -> Result<Index, StoreError> {
+    Ok(parse_image_index(&mut verified_reader(store, digest)?)?)
+}
+
+pub fn read_image_config<S: BlobStore + ?Sized>(
+    store: &S,
+    digest: &Digest,
+) -> Result<ImageConfig, StoreError> {
+    Ok(parse_image_config(&mut verified_reader(store, digest)?)?)
+}
+
+/// Follows an image manifest to the `ImageConfig` it references, checking the config against
+/// the manifest's descriptor.
+pub fn resolve_image_config<S: BlobStore + ?Sized>(
+    store: &S,
+    manifest_digest: &Digest,
+) -> Result<(Manifest, ImageConfig), StoreError> {
+    let manifest = read_manifest(store, manifest_digest)?;
+    let mut reader = store.reader(&manifest.config.digest)?;
+    let config = parse_image_config_with_descriptor(&mut reader, &manifest.config)?;
+    Ok((manifest, config))
+}
+
+// for changes that store OCI manifests and layers, which don't belong in a Docker manifest
+pub(crate) fn check_oci_manifest(digest: &Digest, manifest: &Manifest) -> Result<(), StoreError> {
+    match manifest.media_type.as_deref() {
+        None | Some(MEDIA_TYPE_IMAGE_MANIFEST) => Ok(()),
+        Some(media_type) => Err(StoreError::Invalid(format!(
+            "`{}` is a `{}` rather than an OCI image manifest; transcode it first",
+            digest, media_type
+        ))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::v1::{
+        Architecture, RootFS, RootFSType, MEDIA_TYPE_IMAGE_CONFIG, MEDIA_TYPE_IMAGE_MANIFEST, OS,
+    };
+
+    pub(crate) fn image_config() -> ImageConfig {
+        ImageConfig {
+            architecture: Architecture::Amd64,
+            os: OS::Linux,
+            variant: None,
+            os_version: None,
+            os_features: None,
+            rootfs: RootFS {
+                _type: RootFSType::Layers,
+                diff_ids: vec![],
+            },
+            created: None,
+            author: None,
+            config: None,
+            history: None,
+        }
+    }
+
+    // stores an image manifest of `config` and `layers`, which have to be stored already
+    pub(crate) fn put_image<S: BlobStore + ?Sized, C: Serialize>(
+        store: &S,
+        config: &C,
+        layers: Vec<Descriptor>,
+    ) -> Descriptor {
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: Some(MEDIA_TYPE_IMAGE_MANIFEST.to_string()),
+            config: put_json(store, MEDIA_TYPE_IMAGE_CONFIG, config).unwrap(),
+            layers,
+            annotations: None,
+        };
+        put_json(store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap()
+    }
+
+    // exercises the contract every `BlobStore` implementation has to honour
+    pub(crate) fn assert_blob_store_contract<S: BlobStore>(store: &S) {
+        let digest = store.put(b"hello world").unwrap();
+        assert_eq!(digest, Digest::sha256(b"hello world"));
+        assert_eq!(store.stat(&digest).unwrap(), Some(11));
+        assert_eq!(store.get(&digest).unwrap(), b"hello world");
+        assert!(store.contains(&digest).unwrap());
+
+        // putting the same content again is a no-op
+        assert_eq!(store.put(b"hello world").unwrap(), digest);
+        assert_eq!(store.list().unwrap(), vec![digest.clone()]);
+
+        let mut writer = store.writer().unwrap();
+        writer.write_all(b"streamed ").unwrap();
+        writer.write_all(b"content").unwrap();
+        let (streamed, size) = writer.commit().unwrap();
+        assert_eq!(streamed, Digest::sha256(b"streamed content"));
+        assert_eq!(size, 16);
+
+        let mut reader = store.reader(&streamed).unwrap();
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "streamed content");
+        drop(reader);
+
+        // uncommitted writes never show up
+        let mut writer = store.writer().unwrap();
+        writer.write_all(b"abandoned").unwrap();
+        drop(writer);
+        assert_eq!(store.list().unwrap().len(), 2);
+
+        assert!(store.delete(&digest).unwrap());
+        assert!(!store.delete(&digest).unwrap());
+        assert_eq!(store.stat(&digest).unwrap(), None);
+        match store.get(&digest) {
+            Err(StoreError::NotFound(missing)) => assert_eq!(missing, digest),
+            result => panic!("Received unexpected result: {:?}", result),
+        }
+    }
+
+    pub(crate) fn assert_resolves_image_config<S: BlobStore>(store: &S) {
+        let config = image_config();
+        let config_descriptor = put_json(store, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap();
+        let manifest = Manifest {
+            schema_version: 2,
+            artifact_type: None,
+            subject: None,
+            media_type: None,
+            config: config_descriptor,
+            layers: vec![],
+            annotations: None,
+        };
+        let manifest_descriptor =
+            put_json(store, crate::v1::MEDIA_TYPE_IMAGE_MANIFEST, &manifest).unwrap();
+
+        let (resolved_manifest, resolved_config) =
+            resolve_image_config(store, &manifest_descriptor.digest).unwrap();
+        assert_eq!(resolved_manifest, manifest);
+        assert_eq!(resolved_config, config);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::store::{put_json, read_index, resolve_image_config, BlobStore, StoreError};
+use crate::v1::{is_image_manifest, Descriptor, Digest, Index, Platform, MEDIA_TYPE_IMAGE_INDEX};
+
+/// Builds and stores an index of single-platform images, taking each one's platform from its
+/// `ImageConfig`. Fails if two of them are for the same platform, as compared by
+/// [`Platform::normalized`].
+pub fn assemble_index<S: BlobStore + ?Sized>(
+    store: &S,
+    manifests: &[Descriptor],
+) -> Result<(Descriptor, Index), StoreError> {
+    let mut entries: Vec<Descriptor> = Vec::with_capacity(manifests.len());
+    for descriptor in manifests {
+        check_image_manifest(descriptor)?;
+        let platform = platform_of(store, &descriptor.digest)?;
+        let normalized = platform.normalized();
+        if entries
+            .iter()
+            .filter_map(|x| x.platform.as_ref())
+            .any(|x| x.normalized() == normalized)
+        {
+            return Err(StoreError::Invalid(format!(
+                "more than one image for platform `{}`",
+                platform
+            )));
+        }
+        let mut entry = descriptor.clone();
+        entry.platform = Some(platform);
+        entries.push(entry);
+    }
+
+    let index = Index {
+        schema_version: 2,
+        artifact_type: None,
+        subject: None,
+        manifests: entries,
+        media_type: Some(MEDIA_TYPE_IMAGE_INDEX.to_string()),
+        annotations: None,
+    };
+    let descriptor = put_json(store, MEDIA_TYPE_IMAGE_INDEX, &index)?;
+    Ok((descriptor, index))
+}
+
+/// Lists the single-platform images of an index or Docker manifest list, each with its platform
+/// set. Images that the index doesn't record a platform for get the one of their `ImageConfig`.
+/// Fails if the index lists anything other than image manifests, like nested indexes.
+pub fn split_index<S: BlobStore + ?Sized>(
+    store: &S,
+    index_digest: &Digest,
+) -> Result<Vec<Descriptor>, StoreError> {
+    let index = read_index(store, index_digest)?;
+    let mut images = Vec::new();
+    for mut descriptor in index.manifests {
+        check_image_manifest(&descriptor)?;
+        if descriptor.platform.is_none() {
+            descriptor.platform = Some(platform_of(store, &descriptor.digest)?);
+        }
+        images.push(descriptor);
+    }
+    Ok(images)
+}
+
+fn check_image_manifest(descriptor: &Descriptor) -> Result<(), StoreError> {
+    if is_image_manifest(&descriptor.media_type) {
+        Ok(())
+    } else {
+        Err(StoreError::Invalid(format!(
+            "`{}` is a `{}` rather than an image manifest",
+            descriptor.digest, descriptor.media_type
+        )))
+    }
+}
+
+fn platform_of<S: BlobStore + ?Sized>(
+    store: &S,
+    manifest_digest: &Digest,
+) -> Result<Platform, StoreError> {
+    let (_, config) = resolve_image_config(store, manifest_digest)?;
+    Ok(config.platform())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::MemoryBlobStore;
+    use crate::v1::{
+        Architecture, MEDIA_TYPE_DOCKER_MANIFEST, MEDIA_TYPE_DOCKER_MANIFEST_LIST, OS,
+    };
+
+    fn image_for(store: &MemoryBlobStore, architecture: Architecture, variant: &str) -> Descriptor {
+        let mut config = image_config();
+        config.architecture = architecture;
+        if !variant.is_empty() {
+            config.variant = Some(variant.to_string());
+        }
+        put_image(store, &config, vec![])
+    }
+
+    #[test]
+    fn assembles_index_with_platforms() {
+        let store = MemoryBlobStore::new();
+        let amd64 = image_for(&store, Architecture::Amd64, "");
+        let arm64 = image_for(&store, Architecture::Arm64, "v8");
+        let arm = image_for(&store, Architecture::Arm, "v7");
+
+        let (descriptor, index) =
+            assemble_index(&store, &[amd64.clone(), arm64.clone(), arm]).unwrap();
+        assert_eq!(descriptor.media_type, MEDIA_TYPE_IMAGE_INDEX);
+        assert_eq!(read_index(&store, &descriptor.digest).unwrap(), index);
+
+        let platforms: Vec<String> = index
+            .manifests
+            .iter()
+            .map(|x| x.platform.as_ref().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            platforms,
+            vec!["linux/amd64", "linux/arm64/v8", "linux/arm/v7"]
+        );
+        assert_eq!(index.manifests[0].digest, amd64.digest);
+        assert_eq!(index.manifests[1].size, arm64.size);
+        assert_eq!(index.manifests[1].platform.as_ref().unwrap().os, OS::Linux);
+    }
+
+    #[test]
+    fn rejects_duplicate_platforms() {
+        let store = MemoryBlobStore::new();
+        let arm64 = image_for(&store, Architecture::Arm64, "v8");
+        let mut other = arm64.clone();
+        other.annotations = Some(Default::default());
+
+        let err = assemble_index(&store, &[arm64.clone(), other]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "more than one image for platform `linux/arm64/v8`"
+        );
+
+        // arm64 without a variant is v8
+        let unset = image_for(&store, Architecture::Arm64, "");
+        let err = assemble_index(&store, &[unset, arm64.clone()]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "more than one image for platform `linux/arm64/v8`"
+        );
+
+        // a different variant is a different platform
+        let v9 = image_for(&store, Architecture::Arm64, "v9");
+        assert!(assemble_index(&store, &[arm64, v9]).is_ok());
+    }
+
+    #[test]
+    fn rejects_non_manifests() {
+        let store = MemoryBlobStore::new();
+        let amd64 = image_for(&store, Architecture::Amd64, "");
+        let (index, _) = assemble_index(&store, &[amd64]).unwrap();
+        assert!(matches!(
+            assemble_index(&store, &[index]),
+            Err(StoreError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn splits_index_into_images() {
+        let store = MemoryBlobStore::new();
+        let amd64 = image_for(&store, Architecture::Amd64, "");
+        let arm64 = image_for(&store, Architecture::Arm64, "v8");
+        let (descriptor, mut index) =
+            assemble_index(&store, &[amd64.clone(), arm64.clone()]).unwrap();
+
+        let images = split_index(&store, &descriptor.digest).unwrap();
+        assert_eq!(images, index.manifests);
+
+        // platforms missing from the index are looked up
+        for manifest in &mut index.manifests {
+            manifest.platform = None;
+        }
+        let descriptor = put_json(&store, MEDIA_TYPE_IMAGE_INDEX, &index).unwrap();
+        let images = split_index(&store, &descriptor.digest).unwrap();
+        assert_eq!(images[0].digest, amd64.digest);
+        assert_eq!(
+            images[1].platform.as_ref().unwrap().to_string(),
+            "linux/arm64/v8"
+        );
+    }
+
+    #[test]
+    fn splits_docker_manifest_lists() {
+        let store = MemoryBlobStore::new();
+        let amd64 = image_for(&store, Architecture::Amd64, "");
+        let (_, mut index) = assemble_index(&store, std::slice::from_ref(&amd64)).unwrap();
+        index.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST_LIST.to_string());
+        index.manifests[0].media_type = MEDIA_TYPE_DOCKER_MANIFEST.to_string();
+        let descriptor = put_json(&store, MEDIA_TYPE_DOCKER_MANIFEST_LIST, &index).unwrap();
+
+        let images = split_index(&store, &descriptor.digest).unwrap();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].digest, amd64.digest);
+        assert_eq!(images[0].media_type, MEDIA_TYPE_DOCKER_MANIFEST);
+    }
+
+    #[test]
+    fn refuses_to_split_indexes_of_other_things() {
+        let store = MemoryBlobStore::new();
+        let amd64 = image_for(&store, Architecture::Amd64, "");
+        let (nested, mut index) = assemble_index(&store, &[amd64]).unwrap();
+        index.manifests.push(nested);
+        let descriptor = put_json(&store, MEDIA_TYPE_IMAGE_INDEX, &index).unwrap();
+
+        assert!(matches!(
+            split_index(&store, &descriptor.digest),
+            Err(StoreError::Invalid(_))
+        ));
+    }
+}
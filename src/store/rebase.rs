@@ -0,0 +1,274 @@
+use crate::store::{check_oci_manifest, put_json, resolve_image_config, BlobStore, StoreError};
+use crate::v1::{
+    Descriptor, Digest, ImageConfig, Manifest, ANNOTATION_BASE_IMAGE_DIGEST,
+    ANNOTATION_BASE_IMAGE_NAME, MEDIA_TYPE_IMAGE_MANIFEST,
+};
+
+/// Moves an image built on `old_base` onto `new_base`, storing the new config and manifest and
+/// returning the manifest's descriptor. All three are image manifests in `store`.
+///
+/// The layers of the old base are swapped for those of the new one, and what the image added
+/// to the old base's config is derived from the new base's, following the same rules as a
+/// `FROM`. The manifest records `new_base_name` and the new base's digest in the
+/// `org.opencontainers.image.base.*` annotations. The new base has to be for the image's OS,
+/// architecture and variant. Docker manifests are rejected, as their layers and those of an
+/// OCI base don't mix; transcode them to OCI first.
+pub fn rebase_image<S: BlobStore + ?Sized>(
+    store: &S,
+    image: &Digest,
+    old_base: &Digest,
+    new_base: &Digest,
+    new_base_name: &str,
+) -> Result<Descriptor, StoreError> {
+    let (mut manifest, config) = resolve_image(store, image)?;
+    let (_, old_config) = resolve_image(store, old_base)?;
+    let (new_manifest, new_config) = resolve_image(store, new_base)?;
+
+    let base_layers = old_config.rootfs.diff_ids.len();
+    if !config
+        .rootfs
+        .diff_ids
+        .starts_with(&old_config.rootfs.diff_ids)
+    {
+        return Err(StoreError::Invalid(format!(
+            "image `{}` isn't built on `{}`: their layers differ",
+            image, old_base
+        )));
+    }
+    if let (Some(history), Some(old_history)) = (&config.history, &old_config.history) {
+        if !history.starts_with(old_history) {
+            return Err(StoreError::Invalid(format!(
+                "image `{}` isn't built on `{}`: their history differs",
+                image, old_base
+            )));
+        }
+    }
+
+    // a base for another platform would leave the image's own layers unable to run
+    let platform = config.platform().normalized();
+    let new_platform = new_config.platform().normalized();
+    if (&platform.os, &platform.architecture, &platform.variant)
+        != (
+            &new_platform.os,
+            &new_platform.architecture,
+            &new_platform.variant,
+        )
+    {
+        return Err(StoreError::Invalid(format!(
+            "image `{}` is for {} but `{}` is for {}",
+            image, platform, new_base, new_platform
+        )));
+    }
+
+    let rebased = config.strip_base(&old_config).derive_from(&new_config);
+    manifest.layers.splice(..base_layers, new_manifest.layers);
+    manifest.config = put_json(store, &manifest.config.media_type, &rebased)?;
+    let annotations = manifest.annotations.get_or_insert_with(Default::default);
+    annotations.insert(
+        ANNOTATION_BASE_IMAGE_NAME.to_string(),
+        new_base_name.to_string(),
+    );
+    annotations.insert(
+        ANNOTATION_BASE_IMAGE_DIGEST.to_string(),
+        new_base.to_string(),
+    );
+    put_json(store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)
+}
+
+// the layers are spliced by position, so they have to line up with the diff IDs
+fn resolve_image<S: BlobStore + ?Sized>(
+    store: &S,
+    digest: &Digest,
+) -> Result<(Manifest, ImageConfig), StoreError> {
+    let (manifest, config) = resolve_image_config(store, digest)?;
+    check_oci_manifest(digest, &manifest)?;
+    if manifest.layers.len() != config.rootfs.diff_ids.len() {
+        return Err(StoreError::Invalid(format!(
+            "manifest `{}` has {} layers but its config lists {} diff IDs",
+            digest,
+            manifest.layers.len(),
+            config.rootfs.diff_ids.len()
+        )));
+    }
+    Ok((manifest, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::compression::tests::tar_of;
+    use crate::layer::{verify_diff_ids, write_layer, Compression};
+    use crate::store::tests::{image_config, put_image};
+    use crate::store::{read_manifest, MemoryBlobStore};
+    use crate::v1::{
+        Architecture, Config, EnvVar, History, WellKnownAnnotations, MEDIA_TYPE_DOCKER_MANIFEST,
+        MEDIA_TYPE_IMAGE_CONFIG,
+    };
+    use std::collections::HashMap;
+
+    fn env_var(var_name: &str, var_value: &str) -> EnvVar {
+        EnvVar {
+            var_name: var_name.to_string(),
+            var_value: var_value.to_string(),
+        }
+    }
+
+    fn runtime(env: Vec<EnvVar>, labels: &[(&str, &str)]) -> Config {
+        Config {
+            user: None,
+            exposed_ports: None,
+            env: Some(env),
+            entrypoint: None,
+            cmd: Some(vec!["sh".to_string()]),
+            volumes: None,
+            working_dir: None,
+            labels: Some(
+                labels
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+            stop_signal: None,
+        }
+    }
+
+    // stores an image with a layer per file, derived from `base` if there is one
+    fn build_image(
+        store: &MemoryBlobStore,
+        base: Option<&Digest>,
+        files: &[&str],
+        runtime: Config,
+    ) -> Digest {
+        let mut config = image_config();
+        config.config = Some(runtime);
+        let mut layers = vec![];
+        let mut history = vec![];
+        for file in files {
+            let tar = tar_of(&[(*file, file.as_bytes())]);
+            let layer = write_layer(store, tar.as_slice(), Compression::Gzip).unwrap();
+            config.rootfs.diff_ids.push(layer.diff_id.to_string());
+            layers.push(layer.descriptor());
+            history.push(History {
+                created: None,
+                author: None,
+                created_by: Some(format!("COPY {}", file)),
+                comment: None,
+                empty_layer: None,
+            });
+        }
+        config.history = Some(history);
+        if let Some(base) = base {
+            let (manifest, base_config) = resolve_image(store, base).unwrap();
+            config = config.derive_from(&base_config);
+            layers.splice(..0, manifest.layers);
+        }
+        put_image(store, &config, layers).digest
+    }
+
+    fn images(store: &MemoryBlobStore) -> (Digest, Digest, Digest) {
+        let old_base = build_image(
+            store,
+            None,
+            &["os-1"],
+            runtime(vec![env_var("PATH", "/bin")], &[("os", "1")]),
+        );
+        let new_base = build_image(
+            store,
+            None,
+            &["os-2", "patch"],
+            runtime(vec![env_var("PATH", "/usr/bin")], &[("os", "2")]),
+        );
+        let mut app = runtime(vec![env_var("APP", "1")], &[("app", "1")]);
+        app.cmd = None;
+        app.entrypoint = Some(vec!["/app".to_string()]);
+        let image = build_image(store, Some(&old_base), &["app"], app);
+        (image, old_base, new_base)
+    }
+
+    #[test]
+    fn rebases_images() {
+        let store = MemoryBlobStore::new();
+        let (image, old_base, new_base) = images(&store);
+
+        let rebased =
+            rebase_image(&store, &image, &old_base, &new_base, "example.com/os:2").unwrap();
+        verify_diff_ids(&store, &rebased.digest).unwrap();
+        let (manifest, config) = resolve_image_config(&store, &rebased.digest).unwrap();
+        let new_manifest = read_manifest(&store, &new_base).unwrap();
+        assert_eq!(manifest.layers[..2], new_manifest.layers[..]);
+        assert_eq!(
+            manifest.layers[2],
+            read_manifest(&store, &image).unwrap().layers[1]
+        );
+
+        let runtime = config.config.unwrap();
+        assert_eq!(
+            runtime.env.unwrap(),
+            vec![env_var("PATH", "/usr/bin"), env_var("APP", "1")]
+        );
+        let mut labels = HashMap::new();
+        labels.insert("os".to_string(), "2".to_string());
+        labels.insert("app".to_string(), "1".to_string());
+        assert_eq!(runtime.labels.unwrap(), labels);
+        assert_eq!(runtime.entrypoint, Some(vec!["/app".to_string()]));
+        assert_eq!(runtime.cmd, None);
+        let created_by: Vec<String> = config
+            .history
+            .unwrap()
+            .into_iter()
+            .filter_map(|x| x.created_by)
+            .collect();
+        assert_eq!(created_by, vec!["COPY os-2", "COPY patch", "COPY app"]);
+
+        let annotations = manifest.annotations.unwrap();
+        let annotations = WellKnownAnnotations::new(&annotations);
+        assert_eq!(annotations.base_name(), Some("example.com/os:2"));
+        assert_eq!(annotations.base_digest().unwrap(), Some(new_base));
+    }
+
+    mod with_bad_input {
+        use super::*;
+
+        #[test]
+        fn rejects_images_not_built_on_the_old_base() {
+            let store = MemoryBlobStore::new();
+            let (image, _, new_base) = images(&store);
+            assert!(matches!(
+                rebase_image(&store, &image, &new_base, &new_base, "example.com/os:2"),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+
+        #[test]
+        fn rejects_bases_for_other_platforms() {
+            let store = MemoryBlobStore::new();
+            let (image, old_base, new_base) = images(&store);
+            let (mut manifest, mut config) = resolve_image(&store, &new_base).unwrap();
+            config.architecture = Architecture::Arm64;
+            manifest.config = put_json(&store, MEDIA_TYPE_IMAGE_CONFIG, &config).unwrap();
+            let new_base = put_json(&store, MEDIA_TYPE_IMAGE_MANIFEST, &manifest)
+                .unwrap()
+                .digest;
+
+            let error =
+                rebase_image(&store, &image, &old_base, &new_base, "example.com/os:2").unwrap_err();
+            assert!(error.to_string().contains("linux/arm64"), "{}", error);
+        }
+
+        #[test]
+        fn rejects_docker_manifests() {
+            let store = MemoryBlobStore::new();
+            let (image, old_base, new_base) = images(&store);
+            let mut manifest = read_manifest(&store, &image).unwrap();
+            manifest.media_type = Some(MEDIA_TYPE_DOCKER_MANIFEST.to_string());
+            let image = put_json(&store, MEDIA_TYPE_DOCKER_MANIFEST, &manifest)
+                .unwrap()
+                .digest;
+
+            assert!(matches!(
+                rebase_image(&store, &image, &old_base, &new_base, "example.com/os:2"),
+                Err(StoreError::Invalid(_))
+            ));
+        }
+    }
+}
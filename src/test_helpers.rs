@@ -13,7 +13,7 @@ pub mod assertions {
         K: std::cmp::Eq + std::hash::Hash,
         V: std::cmp::PartialEq + std::fmt::Debug,
     {
-        assert_eq!(map.contains_key(&key), true);
+        assert!(map.contains_key(&key));
         assert_eq!(map[&key], val);
     }
 
@@ -42,8 +42,8 @@ pub mod assertions {
 
         #[test]
         fn test_assert_consists_of() {
-            assert_consists_of(vec![1, 2, 3], &vec![3, 1, 2]);
-            assert_consists_of(vec!["1", "2", "3"], &vec!["3", "1", "2"]);
+            assert_consists_of(vec![1, 2, 3], &[3, 1, 2]);
+            assert_consists_of(vec!["1", "2", "3"], &["3", "1", "2"]);
         }
     }
 }